@@ -5,6 +5,22 @@ use clap::{Parser, Subcommand};
 use colored::Colorize;
 use raco_core::config::load_config;
 use raco_core::utils::ensure_dir_exists;
+use raco_mcp::protocol::McpRequest;
+use raco_mcp::server::ServerRegistry;
+use raco_servers::stdio;
+use raco_servers::system::{SystemCommand, SystemResponse, SystemServer};
+use raco_workflow::engine::{PendingInput, WorkflowEngine};
+use raco_workflow::events::{EventKind, WorkflowEvent};
+use raco_workflow::mcp::result_text;
+use raco_workflow::persistence::WorkflowStore;
+use raco_workflow::spec::{self, SpecFormat, WorkflowSpec};
+use raco_workflow::{StepId, WorkflowId, WorkflowStatus};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 use tracing::{debug, info};
 use tracing_subscriber::EnvFilter;
 
@@ -55,18 +71,315 @@ enum Commands {
     /// Run a command on a server
     #[clap(about = "Run a command on a server")]
     Run {
-        /// Server name
+        /// Server name (resolved from the tool catalog when omitted)
         #[clap(short, long)]
-        server: String,
+        server: Option<String>,
 
-        /// Command to run
-        #[clap(short, long)]
+        /// Command to run (`-c` is taken by `--config`)
+        #[clap(long)]
         command: String,
 
-        /// Command arguments
+        /// Command arguments as KEY=VALUE pairs; values are read as JSON where possible
         #[clap(short, long)]
         args: Option<Vec<String>>,
     },
+
+    /// Serve a builtin server over MCP on stdio
+    #[clap(hide = true)]
+    Serve {
+        /// Server name
+        name: String,
+    },
+
+    /// Run and interact with workflows
+    #[clap(about = "Run and interact with workflows")]
+    Workflow {
+        /// Directory of workflow files available as sub-workflows (defaults to the
+        /// directory of the workflow file for `run`)
+        #[clap(long, global = true)]
+        library: Option<String>,
+
+        /// Workflow command
+        #[clap(subcommand)]
+        command: WorkflowCommands,
+    },
+}
+
+/// Workflow commands
+#[derive(Subcommand, Debug)]
+enum WorkflowCommands {
+    /// Run a workflow file until it completes or needs input
+    #[clap(about = "Run a workflow file until it completes or needs input")]
+    Run {
+        /// Workflow file (.yaml, .yml or .toml)
+        file: String,
+    },
+
+    /// List the prompts waiting for an answer
+    #[clap(about = "List the prompts waiting for an answer")]
+    Pending,
+
+    /// Answer a prompt and continue the workflow
+    #[clap(about = "Answer a prompt and continue the workflow")]
+    Respond {
+        /// Workflow ID
+        workflow: String,
+
+        /// Step ID or the step's name in the workflow file
+        step: String,
+
+        /// Response text
+        response: String,
+
+        /// Parse the response as JSON
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// Time allowed for the builtin servers to start and list their tools
+const SERVER_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Create a registry holding the servers that ship with RACO
+///
+/// The servers are served by this binary's `serve` command.
+async fn builtin_registry() -> Result<ServerRegistry> {
+    let registry = ServerRegistry::new();
+    let program = std::env::current_exe().context("Failed to locate the raco binary")?;
+    stdio::register_builtins(&registry, &program).await?;
+    Ok(registry)
+}
+
+/// Create a registry with the servers that ship with RACO running, and their
+/// tools in its catalog
+async fn active_registry() -> Result<ServerRegistry> {
+    let registry = ServerRegistry::new();
+    let program = std::env::current_exe().context("Failed to locate the raco binary")?;
+    stdio::activate_builtins(&registry, &program, SERVER_STARTUP_TIMEOUT)
+        .await
+        .context("Failed to start the builtin servers")?;
+    Ok(registry)
+}
+
+/// Parse `KEY=VALUE` tool arguments, reading values as JSON where they are JSON
+fn tool_arguments(args: &[String]) -> Result<serde_json::Value> {
+    let mut arguments = serde_json::Map::new();
+    for arg in args {
+        let (key, value) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Arguments must be KEY=VALUE pairs: {}", arg))?;
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        arguments.insert(key.to_string(), value);
+    }
+    Ok(serde_json::Value::Object(arguments))
+}
+
+/// Print workflow events as progress lines until the engine is dropped
+fn print_progress(mut events: broadcast::Receiver<WorkflowEvent>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut names = HashMap::new();
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            // Steps started by an earlier process are shown by ID
+            let name = |step_id: StepId| {
+                names
+                    .get(&step_id)
+                    .cloned()
+                    .unwrap_or_else(|| step_id.to_string())
+            };
+            match &event.kind {
+                EventKind::StepStarted { step_id, name } => {
+                    names.insert(*step_id, name.clone());
+                    println!("{} {}", "Running".cyan(), name);
+                }
+                EventKind::StepProgress { step_id, payload } => {
+                    println!("  {}: {}", name(*step_id), payload);
+                }
+                EventKind::StepRetrying {
+                    step_id,
+                    attempt,
+                    error,
+                    delay_secs,
+                } => {
+                    println!(
+                        "{} {} (attempt {}) in {:.1}s: {}",
+                        "Retrying".yellow(),
+                        name(*step_id),
+                        attempt,
+                        delay_secs,
+                        error
+                    );
+                }
+                EventKind::StepSkipped { step_id, reason } => {
+                    println!("{} {}: {}", "Skipped".dimmed(), name(*step_id), reason);
+                }
+                EventKind::StepCompleted { step_id, .. } => {
+                    println!("{} {}", "Completed".green(), name(*step_id));
+                }
+                EventKind::StepFailed { step_id, error } => {
+                    println!("{} {}: {}", "Failed".red(), name(*step_id), error);
+                }
+                _ => {}
+            }
+        }
+    })
+}
+
+/// Find a step by ID or by its name in the workflow file
+async fn resolve_step(engine: &WorkflowEngine, workflow: WorkflowId, step: &str) -> Result<StepId> {
+    if let Ok(id) = step.parse() {
+        return Ok(id);
+    }
+    let instance = engine
+        .get_workflow(workflow)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", workflow))?;
+    let instance = instance.lock().await;
+    match instance.spec() {
+        Some(workflow_spec) if workflow_spec.step(step).is_some() => {
+            Ok(spec::step_id(workflow, step))
+        }
+        _ => anyhow::bail!("Workflow {} has no step {}", workflow, step),
+    }
+}
+
+/// Print prompts waiting for an answer
+fn print_pending(pending: &[PendingInput]) {
+    for input in pending {
+        println!(
+            "- {} {} ({}): {}",
+            input.workflow_id.to_string().yellow(),
+            input.step_id,
+            input.step_name,
+            input.prompt
+        );
+    }
+}
+
+/// Handle workflow commands
+///
+/// Workflows are persisted in the data directory, and their events are recorded in
+/// the workflow history and printed as progress. Steps can call the tools of the
+/// builtin servers.
+async fn handle_workflow(
+    command: WorkflowCommands,
+    library: Option<&str>,
+    data_dir: &Path,
+) -> Result<()> {
+    let store = WorkflowStore::in_data_dir(data_dir);
+    let mut engine = WorkflowEngine::new().with_store(store.clone());
+    // Only commands that run steps need the servers
+    if !matches!(command, WorkflowCommands::Pending) {
+        engine = engine.with_tools(Arc::new(active_registry().await?));
+    }
+    let library = match (library, &command) {
+        (Some(library), _) => Some(PathBuf::from(library)),
+        (None, WorkflowCommands::Run { file }) => Path::new(file).parent().map(Path::to_path_buf),
+        (None, _) => None,
+    };
+    if let Some(library) = library {
+        register_library(&engine, &library).await?;
+    }
+    let recorder = store.record(engine.subscribe());
+    let progress = print_progress(engine.subscribe());
+    engine
+        .restore()
+        .await
+        .context("Failed to load saved workflows")?;
+
+    let result = run_workflow_command(&engine, command).await;
+
+    // Let the subscribers catch up with the last events
+    drop(engine);
+    recorder.await?;
+    progress.await?;
+
+    if let Some((status, pending)) = result? {
+        println!("{} {:?}", "Workflow status:".green(), status);
+        if !pending.is_empty() {
+            println!("Waiting for input:");
+            print_pending(&pending);
+        }
+    }
+    Ok(())
+}
+
+/// Make the workflow files in a directory available as sub-workflows
+async fn register_library(engine: &WorkflowEngine, dir: &Path) -> Result<()> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read workflow directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if SpecFormat::from_path(&path).is_none() {
+            continue;
+        }
+        match WorkflowSpec::from_file(&path) {
+            Ok(workflow_spec) => engine.register_workflow(workflow_spec).await,
+            Err(e) => debug!("Skipping {}: {:#}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Run a workflow command, returning the resulting status of the workflow it ran
+async fn run_workflow_command(
+    engine: &WorkflowEngine,
+    command: WorkflowCommands,
+) -> Result<Option<(WorkflowStatus, Vec<PendingInput>)>> {
+    let workflow = match command {
+        WorkflowCommands::Run { file } => {
+            let workflow_spec = WorkflowSpec::from_file(&file)?;
+            let id = engine.create_workflow_from_spec(workflow_spec).await?;
+            println!("{} {}", "Started workflow".green(), id.to_string().yellow());
+            engine.start_workflow(id).await?;
+            id
+        }
+        WorkflowCommands::Pending => {
+            let pending = engine.pending_inputs().await;
+            if pending.is_empty() {
+                println!("No prompts are waiting for an answer.");
+            } else {
+                println!("Waiting for input:");
+                print_pending(&pending);
+            }
+            return Ok(None);
+        }
+        WorkflowCommands::Respond {
+            workflow,
+            step,
+            response,
+            json,
+        } => {
+            let workflow: WorkflowId = workflow
+                .parse()
+                .with_context(|| format!("Invalid workflow ID: {workflow}"))?;
+            let step = resolve_step(engine, workflow, &step).await?;
+            let response = if json {
+                serde_json::from_str(&response).context("Response is not valid JSON")?
+            } else {
+                serde_json::Value::String(response)
+            };
+            engine.submit_input(workflow, step, response).await?;
+            workflow
+        }
+    };
+
+    let instance = engine
+        .get_workflow(workflow)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", workflow))?;
+    let status = instance.lock().await.status();
+    Ok(Some((status, engine.pending_inputs_of(workflow).await)))
 }
 
 /// Initialize logging
@@ -77,9 +390,11 @@ fn init_logging(verbose: bool) {
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
     };
 
+    // Logs go to stderr, so they never mix with the MCP stream of `serve`
     let _ = tracing_subscriber::fmt()
         .with_env_filter(env_filter)
         .with_target(true)
+        .with_writer(std::io::stderr)
         .try_init();
 }
 
@@ -111,13 +426,51 @@ async fn main() -> Result<()> {
             info!("Initializing project {} in {}", name, dir);
             println!("{} {}", "Project".green(), name.green().bold());
             println!("{} {}", "Initialized in".green(), dir.green());
+
+            let system = SystemServer::new(&dir);
+            let response = system
+                .handle_request(McpRequest::new(
+                    "system",
+                    SystemCommand::CheckProject { path: None },
+                ))
+                .await?;
+            match response.payload {
+                SystemResponse::Prerequisites { missing, .. } => {
+                    if missing.is_empty() {
+                        println!("{}", "All prerequisites are installed.".green());
+                    } else {
+                        println!("Missing prerequisites:");
+                        for requirement in missing {
+                            println!(
+                                "- {} (for {}): {}",
+                                requirement.name.yellow(),
+                                requirement.reason,
+                                requirement.hint
+                            );
+                        }
+                    }
+                }
+                SystemResponse::Error { message } => {
+                    anyhow::bail!("Failed to check prerequisites: {}", message)
+                }
+                other => anyhow::bail!("Unexpected response to check_project: {:?}", other),
+            }
             Ok(())
         }
         Commands::Servers => {
             info!("Listing available servers");
+            let registry = builtin_registry().await?;
+            let mut servers = registry.get_all_servers().await;
+            servers.sort_by(|a, b| a.name.cmp(&b.name));
+
             println!("Available servers:");
-            println!("- {}: Local filesystem server", "filesystem".yellow());
-            println!("- {}: Process management server", "process".yellow());
+            for server in servers {
+                let description = server
+                    .metadata
+                    .get("description")
+                    .map_or("", String::as_str);
+                println!("- {}: {}", server.name.yellow(), description);
+            }
             Ok(())
         }
         Commands::Run {
@@ -125,19 +478,47 @@ async fn main() -> Result<()> {
             command,
             args,
         } => {
-            let args_str = args.map_or_else(String::new, |a| a.join(" "));
-            info!(
-                "Running command {} {} on server {}",
-                command, args_str, server
-            );
+            let arguments = tool_arguments(&args.unwrap_or_default())?;
+            let registry = active_registry().await?;
+            let tool = match server {
+                Some(server) => {
+                    if registry.find_server_by_name(&server).await.is_none() {
+                        anyhow::bail!("Unknown server: {}", server);
+                    }
+                    format!("{server}/{command}")
+                }
+                None => command,
+            };
+            let entry = registry.resolve_tool(&tool).await?;
+
+            info!("Running tool {} with {}", entry.qualified_name(), arguments);
             println!(
                 "{} {} {}",
                 "Running on".green(),
-                server.yellow(),
-                command.yellow()
+                entry.server_name.yellow(),
+                entry.tool.name.yellow()
             );
+            let result = registry
+                .call_tool(&entry.qualified_name(), arguments)
+                .await?;
+            let (text, is_error) = result_text(&result);
+            if is_error {
+                anyhow::bail!("Tool {} failed: {}", entry.qualified_name(), text);
+            }
+            match result.get("structuredContent") {
+                Some(content) => println!("{}", serde_json::to_string_pretty(content)?),
+                None => println!("{text}"),
+            }
             println!("{}", "Command completed successfully.".green());
             Ok(())
         }
+        Commands::Serve { name } => {
+            let root_dir = std::env::current_dir()?;
+            stdio::serve(&name, &root_dir, tokio::io::stdin(), tokio::io::stdout()).await?;
+            Ok(())
+        }
+        Commands::Workflow { library, command } => {
+            handle_workflow(command, library.as_deref(), &config.data_dir).await
+        }
     }
 }
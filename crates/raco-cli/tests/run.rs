@@ -0,0 +1,111 @@
+//! Running tools of the builtin servers through `raco run`
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Run the CLI in `dir`, keeping its configuration and data inside `home`
+fn raco(dir: &Path, home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_raco-cli"))
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_run_tool_by_bare_name() {
+    let home = tempfile::tempdir().unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    git(repo.path(), &["init", "-q"]);
+    std::fs::write(repo.path().join("README.md"), "Hello").unwrap();
+    git(repo.path(), &["add", "README.md"]);
+    git(repo.path(), &["commit", "-q", "-m", "Add a readme"]);
+
+    // `log` is only offered by the git server
+    let output = raco(repo.path(), home.path(), &["run", "--command", "log"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Running on git log"), "{stdout}");
+    assert!(stdout.contains("Add a readme"), "{stdout}");
+
+    let output = raco(
+        repo.path(),
+        home.path(),
+        &["run", "--command", "log", "--args", "max_count=none"],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid arguments for tool log"));
+
+    // Both the git and the QEMU server offer `status`
+    let output = raco(repo.path(), home.path(), &["run", "--command", "status"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("git/status, qemu/status"));
+
+    let output = raco(
+        repo.path(),
+        home.path(),
+        &["run", "--server", "git", "--command", "status"],
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_workflow_steps_call_tools() {
+    let home = tempfile::tempdir().unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    git(repo.path(), &["init", "-q"]);
+    git(
+        repo.path(),
+        &["commit", "-q", "--allow-empty", "-m", "Start the history"],
+    );
+    std::fs::write(
+        repo.path().join("history.yaml"),
+        r"
+name: history
+steps:
+  - id: log
+    type: mcp_tool
+    params:
+      server: git
+      tool: log
+      arguments: {max_count: 1}
+      outputs: {subject: /commits/0/subject}
+",
+    )
+    .unwrap();
+
+    let output = raco(
+        repo.path(),
+        home.path(),
+        &["workflow", "run", "history.yaml"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Completed log"), "{stdout}");
+    assert!(stdout.contains("Workflow status: Completed"), "{stdout}");
+}
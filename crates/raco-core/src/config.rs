@@ -17,12 +17,18 @@ pub const DEFAULT_CONFIG_FILE: &str = "raco.toml";
 pub struct CoreConfig {
     /// Path to data directory
     pub data_dir: PathBuf,
+
+    /// URIs of the servers the web API may start when they are registered;
+    /// starting a server runs its command, so no other server is started
+    #[serde(default)]
+    pub allowed_servers: Vec<String>,
 }
 
 impl Default for CoreConfig {
     fn default() -> Self {
         Self {
             data_dir: default_data_dir(),
+            allowed_servers: Vec::new(),
         }
     }
 }
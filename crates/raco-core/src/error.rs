@@ -53,7 +53,7 @@ mod tests {
 
     #[test]
     fn test_with_context() {
-        let result: Result<(), io::Error> = Err(io::Error::new(io::ErrorKind::Other, "test error"));
+        let result: Result<(), io::Error> = Err(io::Error::other("test error"));
 
         let core_result = with_context(result, || "Context message".to_string());
 
@@ -0,0 +1,607 @@
+//! MCP server connections
+//!
+//! This module provides JSON-RPC connections to MCP servers, either by spawning
+//! a server process and talking to it over stdio or by opening a WebSocket.
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// MCP protocol version announced during the handshake
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Default timeout for requests sent over a connection
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where an MCP server can be reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum Endpoint {
+    /// Spawn a process and speak newline-delimited JSON-RPC over its stdio
+    Stdio {
+        /// Program to run
+        command: String,
+
+        /// Program arguments
+        #[serde(default)]
+        args: Vec<String>,
+    },
+
+    /// Connect to a WebSocket server
+    WebSocket {
+        /// WebSocket URL
+        url: String,
+    },
+}
+
+impl Endpoint {
+    /// Parse an endpoint from a server URI
+    ///
+    /// `ws://` and `wss://` URIs are WebSocket endpoints. Anything else is treated as
+    /// a command line, optionally prefixed with `stdio:`, split on whitespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URI does not name a command or URL
+    pub fn parse(uri: &str) -> Result<Self> {
+        let uri = uri.trim();
+        if uri.starts_with("ws://") || uri.starts_with("wss://") {
+            return Ok(Self::WebSocket {
+                url: uri.to_string(),
+            });
+        }
+
+        let command_line = uri.strip_prefix("stdio:").unwrap_or(uri);
+        let mut parts = command_line.split_whitespace().map(ToString::to_string);
+        let command = parts
+            .next()
+            .ok_or_else(|| anyhow!("Server URI '{}' does not name a command", uri))?;
+
+        Ok(Self::Stdio {
+            command,
+            args: parts.collect(),
+        })
+    }
+}
+
+/// A notification received from an MCP server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    /// Notification method, e.g. `notifications/tools/list_changed`
+    pub method: String,
+
+    /// Notification parameters
+    #[serde(default)]
+    pub params: Value,
+}
+
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>>;
+
+/// A live JSON-RPC connection to an MCP server
+#[derive(Debug)]
+pub struct Connection {
+    /// Outgoing messages, written by the writer task
+    outgoing: mpsc::UnboundedSender<String>,
+
+    /// Requests waiting for a response, keyed by request ID
+    pending: PendingRequests,
+
+    /// Next request ID
+    next_id: AtomicU64,
+
+    /// Notifications received from the server
+    notifications: broadcast::Sender<Notification>,
+
+    /// Becomes `true` once the server side of the connection is gone
+    closed: Arc<watch::Sender<bool>>,
+
+    /// Spawned server process, for stdio connections
+    child: tokio::sync::Mutex<Option<Child>>,
+
+    /// Reader and writer tasks
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Connection {
+    /// Open a connection to the given endpoint
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process cannot be spawned or the WebSocket cannot be opened
+    pub async fn open(endpoint: &Endpoint) -> Result<Self> {
+        match endpoint {
+            Endpoint::Stdio { command, args } => Self::spawn_stdio(command, args),
+            Endpoint::WebSocket { url } => Self::connect_websocket(url).await,
+        }
+    }
+
+    fn spawn_stdio(command: &str, args: &[String]) -> Result<Self> {
+        info!("Spawning MCP server: {} {}", command, args.join(" "));
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to spawn MCP server '{command}'"))?;
+
+        let mut stdin = child.stdin.take().context("Server stdin not captured")?;
+        let stdout = child.stdout.take().context("Server stdout not captured")?;
+        let stderr = child.stderr.take().context("Server stderr not captured")?;
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<String>();
+        let closed = Arc::new(watch::Sender::new(false));
+        let closed_tx = closed.clone();
+        let pending = PendingRequests::default();
+        let (notifications, _) = broadcast::channel(64);
+
+        let writer = tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                let written = async {
+                    stdin.write_all(message.as_bytes()).await?;
+                    stdin.write_all(b"\n").await?;
+                    stdin.flush().await
+                };
+                if let Err(e) = written.await {
+                    debug!("MCP server stdin closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        let dispatcher = Dispatcher {
+            outgoing: outgoing.clone(),
+            pending: pending.clone(),
+            notifications: notifications.clone(),
+        };
+        let reader = tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                dispatcher.dispatch(&line);
+            }
+            closed_tx.send_replace(true);
+            dispatcher.close();
+        });
+
+        let command_name = command.to_string();
+        let logger = tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("[{}] {}", command_name, line);
+            }
+        });
+
+        Ok(Self {
+            outgoing,
+            pending,
+            next_id: AtomicU64::new(1),
+            notifications,
+            closed,
+            child: tokio::sync::Mutex::new(Some(child)),
+            tasks: vec![writer, reader, logger],
+        })
+    }
+
+    async fn connect_websocket(url: &str) -> Result<Self> {
+        info!("Connecting to MCP server at {}", url);
+        let (stream, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .with_context(|| format!("Failed to connect to MCP server at {url}"))?;
+        let (mut sink, mut source) = stream.split();
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<String>();
+        let closed = Arc::new(watch::Sender::new(false));
+        let closed_tx = closed.clone();
+        let pending = PendingRequests::default();
+        let (notifications, _) = broadcast::channel(64);
+
+        let writer = tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                if let Err(e) = sink.send(Message::Text(message)).await {
+                    debug!("MCP WebSocket closed: {}", e);
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        let dispatcher = Dispatcher {
+            outgoing: outgoing.clone(),
+            pending: pending.clone(),
+            notifications: notifications.clone(),
+        };
+        let reader = tokio::spawn(async move {
+            while let Some(Ok(message)) = source.next().await {
+                match message {
+                    Message::Text(text) => dispatcher.dispatch(&text),
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            closed_tx.send_replace(true);
+            dispatcher.close();
+        });
+
+        Ok(Self {
+            outgoing,
+            pending,
+            next_id: AtomicU64::new(1),
+            notifications,
+            closed,
+            child: tokio::sync::Mutex::new(None),
+            tasks: vec![writer, reader],
+        })
+    }
+
+    /// Perform the MCP `initialize` handshake and return the server's answer
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server does not answer within `timeout` or rejects the request
+    pub async fn initialize(&self, timeout: Duration) -> Result<Value> {
+        let result = self
+            .request_with_timeout(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "raco", "version": crate::VERSION },
+                }),
+                timeout,
+            )
+            .await?;
+        self.notify("notifications/initialized", json!({}))?;
+        Ok(result)
+    }
+
+    /// Send a `ping` and wait for the answer
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server does not answer within `timeout`
+    pub async fn ping(&self, timeout: Duration) -> Result<()> {
+        self.request_with_timeout("ping", json!({}), timeout)
+            .await
+            .map(|_| ())
+    }
+
+    /// Send a request using the default timeout
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out or the server returns an error
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        self.request_with_timeout(method, params, DEFAULT_REQUEST_TIMEOUT)
+            .await
+    }
+
+    /// Send a request and wait at most `timeout` for the response
+    ///
+    /// If the request times out, or the returned future is dropped before the
+    /// response arrives, the server is told to stop working on it with a
+    /// `notifications/cancelled` notification.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out or the server returns an error
+    pub async fn request_with_timeout(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        lock(&self.pending).insert(id, tx);
+        // The pending map is cleared after the connection is marked closed, so
+        // checking afterwards guarantees the request cannot be left dangling
+        if self.is_closed() {
+            lock(&self.pending).remove(&id);
+            return Err(anyhow!("Connection closed"));
+        }
+
+        debug!("Sending MCP request {} ({})", method, id);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if self.outgoing.send(message.to_string()).is_err() {
+            lock(&self.pending).remove(&id);
+            return Err(anyhow!("Connection closed"));
+        }
+
+        let mut in_flight = InFlight {
+            connection: self,
+            id,
+            answered: false,
+        };
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(result)) => {
+                in_flight.answered = true;
+                result
+            }
+            Ok(Err(_)) => {
+                in_flight.answered = true;
+                Err(anyhow!("Connection closed"))
+            }
+            Err(_) => Err(anyhow!("Request {} timed out after {:?}", method, timeout)),
+        }
+    }
+
+    /// Send a notification
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection is closed
+    pub fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        self.outgoing
+            .send(message.to_string())
+            .map_err(|_| anyhow!("Connection closed"))
+    }
+
+    /// Subscribe to notifications sent by the server
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+
+    /// Check whether the server side of the connection has gone away
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Wait until the server side of the connection has gone away
+    pub async fn closed(&self) {
+        let mut closed = self.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    /// Close the connection, killing the server process if one was spawned
+    pub async fn close(&self) {
+        if let Some(mut child) = self.child.lock().await.take() {
+            if let Err(e) = child.kill().await {
+                warn!("Failed to kill MCP server process: {}", e);
+            }
+        }
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.closed.send_replace(true);
+        lock(&self.pending).clear();
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// A request waiting for its response
+///
+/// Dropping it before the response arrived withdraws the request.
+struct InFlight<'a> {
+    connection: &'a Connection,
+    id: u64,
+    answered: bool,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.answered {
+            return;
+        }
+        lock(&self.connection.pending).remove(&self.id);
+        debug!("Cancelling MCP request {}", self.id);
+        // The connection may already be gone
+        let _ = self.connection.notify(
+            "notifications/cancelled",
+            json!({ "requestId": self.id, "reason": "The client stopped waiting for the response" }),
+        );
+    }
+}
+
+/// Routes incoming messages to pending requests and notification subscribers
+struct Dispatcher {
+    outgoing: mpsc::UnboundedSender<String>,
+    pending: PendingRequests,
+    notifications: broadcast::Sender<Notification>,
+}
+
+impl Dispatcher {
+    fn dispatch(&self, text: &str) {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring non-JSON message from MCP server: {}", e);
+                return;
+            }
+        };
+
+        let method = message.get("method").and_then(Value::as_str);
+        let id = message.get("id").filter(|id| !id.is_null());
+
+        match (method, id) {
+            // Response to one of our requests
+            (None, Some(id)) => {
+                let Some(id) = id.as_u64() else {
+                    debug!("Ignoring response with foreign ID {}", id);
+                    return;
+                };
+                let Some(tx) = lock(&self.pending).remove(&id) else {
+                    debug!("Ignoring response to unknown request {}", id);
+                    return;
+                };
+                let result = if let Some(error) = message.get("error") {
+                    Err(anyhow!(
+                        "MCP error {}: {}",
+                        error.get("code").and_then(Value::as_i64).unwrap_or(0),
+                        error
+                            .get("message")
+                            .and_then(Value::as_str)
+                            .unwrap_or("unknown error")
+                    ))
+                } else {
+                    Ok(message.get("result").cloned().unwrap_or(Value::Null))
+                };
+                let _ = tx.send(result);
+            }
+            // Notification
+            (Some(method), None) => {
+                let _ = self.notifications.send(Notification {
+                    method: method.to_string(),
+                    params: message.get("params").cloned().unwrap_or(Value::Null),
+                });
+            }
+            // Request from the server; we only answer pings
+            (Some(method), Some(id)) => {
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("Method not found: {method}") },
+                    })
+                };
+                let _ = self.outgoing.send(reply.to_string());
+            }
+            (None, None) => debug!("Ignoring malformed message from MCP server"),
+        }
+    }
+
+    /// Fail every outstanding request once the connection is gone
+    fn close(&self) {
+        lock(&self.pending).clear();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A shell one-liner acting as an MCP server that answers every request with `{}`
+    pub(crate) const ECHO_SERVER: &str = r#"while IFS= read -r line; do id=$(printf '%s' "$line" | sed -n 's/^{"id":\([0-9]*\).*/\1/p'); [ -n "$id" ] && printf '{"jsonrpc":"2.0","id":%s,"result":{}}\n' "$id"; done"#;
+
+    /// Write a shell script to a temporary file and return a server URI running it
+    pub(crate) fn script_uri(script: &str) -> (String, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("raco-mcp-{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(&path, script).unwrap();
+        (format!("sh {}", path.display()), path)
+    }
+
+    pub(crate) fn echo_endpoint() -> Endpoint {
+        Endpoint::Stdio {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), ECHO_SERVER.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            Endpoint::parse("ws://localhost:9000/mcp").unwrap(),
+            Endpoint::WebSocket {
+                url: "ws://localhost:9000/mcp".to_string()
+            }
+        );
+        assert_eq!(
+            Endpoint::parse("stdio:mcp-server --root /tmp").unwrap(),
+            Endpoint::Stdio {
+                command: "mcp-server".to_string(),
+                args: vec!["--root".to_string(), "/tmp".to_string()],
+            }
+        );
+        assert!(Endpoint::parse("stdio:").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_request_and_close() {
+        let connection = Connection::open(&echo_endpoint()).await.unwrap();
+
+        assert!(connection.initialize(Duration::from_secs(5)).await.is_ok());
+        assert!(connection.ping(Duration::from_secs(5)).await.is_ok());
+        assert!(!connection.is_closed());
+
+        connection.close().await;
+        connection.closed().await;
+        assert!(connection.is_closed());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_abandoned_requests_are_cancelled() {
+        // A server that never answers and records what it receives
+        let log = std::env::temp_dir().join(format!("raco-mcp-{}.log", uuid::Uuid::new_v4()));
+        let endpoint = Endpoint::Stdio {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), format!("cat > {}", log.display())],
+        };
+        let connection = Connection::open(&endpoint).await.unwrap();
+
+        let error = connection
+            .request_with_timeout("tools/call", json!({}), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        let request = connection.request("tools/call", json!({}));
+        assert!(tokio::time::timeout(Duration::from_millis(10), request)
+            .await
+            .is_err());
+        assert!(lock(&connection.pending).is_empty());
+
+        let expected = [
+            r#""params":{"reason":"The client stopped waiting for the response","requestId":1}"#,
+            r#""requestId":2"#,
+        ];
+        let mut received = String::new();
+        for _ in 0..100 {
+            received = std::fs::read_to_string(&log).unwrap_or_default();
+            if expected.iter().all(|line| received.contains(line)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        connection.close().await;
+        let _ = std::fs::remove_file(&log);
+        assert_eq!(
+            received.matches("notifications/cancelled").count(),
+            2,
+            "{received}"
+        );
+        assert!(received.contains(expected[0]), "{received}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_request_fails_when_server_exits() {
+        let endpoint = Endpoint::Stdio {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), "exit 0".to_string()],
+        };
+        let connection = Connection::open(&endpoint).await.unwrap();
+
+        connection.closed().await;
+        assert!(connection.ping(Duration::from_secs(5)).await.is_err());
+    }
+}
@@ -4,8 +4,11 @@
 //! It includes server management, client interfaces, and protocol handlers.
 
 pub mod client;
+pub mod connection;
+pub mod lifecycle;
 pub mod protocol;
 pub mod server;
+pub mod tools;
 
 /// Current version of the RACO MCP library
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,264 @@
+//! MCP server lifecycle management
+//!
+//! This module launches registered servers, health-checks them with periodic
+//! pings and restarts them with exponential backoff when they crash.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::connection::{Connection, Endpoint};
+use crate::server::ServerTable;
+use crate::tools;
+
+/// Connection state of an MCP server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    /// Server is not running
+    #[default]
+    Stopped,
+
+    /// Server is being launched or reconnected
+    Starting,
+
+    /// Server is connected and answering health pings
+    Ready,
+
+    /// Server is connected but has missed health pings
+    Degraded,
+
+    /// Server could not be (re)started and is no longer retried
+    Failed,
+}
+
+impl ServerState {
+    /// Whether the server is being supervised
+    #[must_use]
+    pub fn is_active(self) -> bool {
+        matches!(self, Self::Starting | Self::Ready | Self::Degraded)
+    }
+}
+
+/// Timing parameters for server supervision
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleConfig {
+    /// Time allowed for the `initialize` handshake
+    pub startup_timeout: Duration,
+
+    /// Interval between health pings
+    pub health_interval: Duration,
+
+    /// Time allowed for a health ping to be answered
+    pub health_timeout: Duration,
+
+    /// Consecutive missed pings after which the server is restarted
+    pub max_missed_pings: u32,
+
+    /// Delay before the first restart attempt
+    pub initial_backoff: Duration,
+
+    /// Upper bound for the restart delay
+    pub max_backoff: Duration,
+
+    /// Consecutive failed restarts after which the server is marked failed
+    pub max_restarts: u32,
+
+    /// Uptime after which a server is considered stable and the backoff is reset
+    pub stable_after: Duration,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        Self {
+            startup_timeout: Duration::from_secs(30),
+            health_interval: Duration::from_secs(15),
+            health_timeout: Duration::from_secs(5),
+            max_missed_pings: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: 5,
+            stable_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Notification sent by servers whose tool list changed
+const TOOLS_CHANGED: &str = "notifications/tools/list_changed";
+
+/// Shared slot holding the current connection of a supervised server
+pub(crate) type ConnectionSlot = Arc<RwLock<Option<Arc<Connection>>>>;
+
+/// Why a supervised connection ended
+enum Exit {
+    /// The server went away
+    Crashed,
+
+    /// The server stopped answering health pings
+    Unresponsive,
+}
+
+/// Supervise a server until it is marked failed
+///
+/// The task owns the connection; aborting it drops the connection and kills the server.
+pub(crate) async fn supervise(
+    id: Uuid,
+    endpoint: Endpoint,
+    config: LifecycleConfig,
+    servers: Arc<ServerTable>,
+    slot: ConnectionSlot,
+) {
+    let mut failures = 0;
+    let mut backoff = config.initial_backoff;
+
+    loop {
+        servers
+            .update(id, |server| server.state = ServerState::Starting)
+            .await;
+
+        match start(&endpoint, &config).await {
+            Ok(connection) => {
+                let connection = Arc::new(connection);
+                *slot.write().await = Some(connection.clone());
+                servers
+                    .update(id, |server| {
+                        server.state = ServerState::Ready;
+                        server.last_error = None;
+                    })
+                    .await;
+                info!("MCP server {} is ready", id);
+
+                let started = Instant::now();
+                let exit = monitor(id, &connection, &config, &servers).await;
+                servers.tools.remove_server(id).await;
+                slot.write().await.take();
+                connection.close().await;
+
+                let reason = match exit {
+                    Exit::Crashed => "server exited",
+                    Exit::Unresponsive => "server stopped answering health pings",
+                };
+                warn!("MCP server {} went down: {}", id, reason);
+                servers
+                    .update(id, |server| {
+                        server.last_error = Some(reason.to_string());
+                    })
+                    .await;
+
+                if started.elapsed() >= config.stable_after {
+                    failures = 0;
+                    backoff = config.initial_backoff;
+                }
+            }
+            Err(e) => {
+                warn!("Failed to start MCP server {}: {}", id, e);
+                servers
+                    .update(id, |server| {
+                        server.last_error = Some(e.to_string());
+                    })
+                    .await;
+            }
+        }
+
+        failures += 1;
+        if failures > config.max_restarts {
+            warn!(
+                "Giving up on MCP server {} after {} restarts",
+                id,
+                failures - 1
+            );
+            servers
+                .update(id, |server| server.state = ServerState::Failed)
+                .await;
+            return;
+        }
+
+        debug!("Restarting MCP server {} in {:?}", id, backoff);
+        servers
+            .update(id, |server| {
+                server.state = ServerState::Starting;
+                server.restarts += 1;
+            })
+            .await;
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(config.max_backoff);
+    }
+}
+
+/// Open a connection and perform the MCP handshake
+async fn start(endpoint: &Endpoint, config: &LifecycleConfig) -> anyhow::Result<Connection> {
+    let connection = Connection::open(endpoint).await?;
+    connection.initialize(config.startup_timeout).await?;
+    Ok(connection)
+}
+
+/// Health-check a connection until it goes away or stops answering
+async fn monitor(
+    id: Uuid,
+    connection: &Connection,
+    config: &LifecycleConfig,
+    servers: &ServerTable,
+) -> Exit {
+    let mut missed = 0;
+    let mut notifications = connection.subscribe();
+    refresh_tools(id, connection, servers).await;
+
+    loop {
+        tokio::select! {
+            () = connection.closed() => return Exit::Crashed,
+            () = tokio::time::sleep(config.health_interval) => {}
+            notification = notifications.recv() => {
+                match notification {
+                    Ok(notification) if notification.method == TOOLS_CHANGED => {}
+                    // Missed notifications may have included a tool list change
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    _ => continue,
+                }
+                refresh_tools(id, connection, servers).await;
+                continue;
+            }
+        }
+
+        match connection.ping(config.health_timeout).await {
+            Ok(()) => {
+                if missed > 0 {
+                    info!("MCP server {} is answering pings again", id);
+                    missed = 0;
+                    servers
+                        .update(id, |server| server.state = ServerState::Ready)
+                        .await;
+                }
+            }
+            Err(_) if connection.is_closed() => return Exit::Crashed,
+            Err(e) => {
+                missed += 1;
+                warn!("MCP server {} missed health ping {}: {}", id, missed, e);
+                if missed >= config.max_missed_pings {
+                    return Exit::Unresponsive;
+                }
+                servers
+                    .update(id, |server| server.state = ServerState::Degraded)
+                    .await;
+            }
+        }
+    }
+}
+
+/// Re-read the tool list of a server into the catalog
+async fn refresh_tools(id: Uuid, connection: &Connection, servers: &ServerTable) {
+    let Some(name) = servers.name(id).await else {
+        return;
+    };
+    match tools::list_tools(connection).await {
+        Ok(tools) => {
+            debug!("MCP server {} offers {} tools", id, tools.len());
+            servers.tools.set_server_tools(id, &name, tools).await;
+        }
+        Err(e) => warn!("Failed to list tools of MCP server {}: {}", id, e),
+    }
+}
@@ -5,16 +5,41 @@
 use raco_core::error::CoreError;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use crate::connection::{Connection, Endpoint};
+use crate::lifecycle::{self, ConnectionSlot, LifecycleConfig, ServerState};
+use crate::tools::{ToolCatalog, ToolEntry};
+
+/// Interval at which [`ServerRegistry::wait_for_tools`] checks the catalog
+const TOOLS_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 /// Server registry for tracking MCP servers
 #[derive(Debug)]
 pub struct ServerRegistry {
-    /// Map of server ID to server info
-    servers: Arc<RwLock<HashMap<Uuid, ServerInfo>>>,
+    /// Registered servers, shared with the supervisors
+    table: Arc<ServerTable>,
+
+    /// Supervisors of activated servers
+    supervisors: Mutex<HashMap<Uuid, Supervisor>>,
+
+    /// Supervision timing
+    lifecycle: LifecycleConfig,
+}
+
+/// A running server supervisor
+#[derive(Debug)]
+struct Supervisor {
+    /// Supervision task; aborting it stops the server
+    task: JoinHandle<()>,
+
+    /// Current connection, if the server is up
+    connection: ConnectionSlot,
 }
 
 /// Information about an MCP server
@@ -29,39 +54,154 @@ pub struct ServerInfo {
     /// Server type
     pub server_type: String,
 
-    /// Server URI
+    /// Server URI: a command line for stdio servers, or a `ws://` URL
     pub uri: String,
 
-    /// Whether the server is currently active
+    /// Connection state of the server
     #[serde(default)]
-    pub active: bool,
+    pub state: ServerState,
+
+    /// Number of times the server has been restarted since activation
+    #[serde(default)]
+    pub restarts: u32,
+
+    /// Last error reported by the server's supervisor
+    #[serde(default)]
+    pub last_error: Option<String>,
+
+    /// Server metadata
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl ServerInfo {
+    /// Create information about a stopped server
+    pub fn new(name: &str, server_type: &str, uri: &str) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            server_type: server_type.to_string(),
+            uri: uri.to_string(),
+            state: ServerState::Stopped,
+            restarts: 0,
+            last_error: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Add a metadata entry
+    #[must_use]
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// A change to the set of registered servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RegistryEvent {
+    /// A server was registered or replaced
+    Registered {
+        /// The registered server
+        server: ServerInfo,
+    },
+
+    /// A server was unregistered
+    Unregistered {
+        /// ID of the removed server
+        id: Uuid,
+    },
+
+    /// A server's connection state changed
+    StateChanged {
+        /// Server ID
+        id: Uuid,
+
+        /// New connection state
+        state: ServerState,
+    },
+}
+
+/// Registered servers and the channel announcing changes to them
+#[derive(Debug)]
+pub(crate) struct ServerTable {
+    /// Map of server ID to server info
+    servers: RwLock<HashMap<Uuid, ServerInfo>>,
+
+    /// Change events
+    events: broadcast::Sender<RegistryEvent>,
+
+    /// Tools offered by connected servers
+    pub(crate) tools: ToolCatalog,
+}
+
+impl ServerTable {
+    /// Get the name of a server
+    pub(crate) async fn name(&self, id: Uuid) -> Option<String> {
+        self.servers.read().await.get(&id).map(|s| s.name.clone())
+    }
+
+    /// Apply a change to a server, announcing it if its state changed
+    pub(crate) async fn update(&self, id: Uuid, change: impl FnOnce(&mut ServerInfo)) {
+        let mut servers = self.servers.write().await;
+        let Some(server) = servers.get_mut(&id) else {
+            return;
+        };
+        let previous = server.state;
+        change(server);
+        if server.state != previous {
+            let _ = self.events.send(RegistryEvent::StateChanged {
+                id,
+                state: server.state,
+            });
+        }
+    }
 }
 
 impl ServerRegistry {
     /// Create a new server registry
     pub fn new() -> Self {
+        Self::with_lifecycle(LifecycleConfig::default())
+    }
+
+    /// Create a new server registry with custom supervision timing
+    pub fn with_lifecycle(lifecycle: LifecycleConfig) -> Self {
         info!("Creating new MCP server registry");
         Self {
-            servers: Arc::new(RwLock::new(HashMap::new())),
+            table: Arc::new(ServerTable {
+                servers: RwLock::new(HashMap::new()),
+                events: broadcast::channel(64).0,
+                tools: ToolCatalog::new(),
+            }),
+            supervisors: Mutex::new(HashMap::new()),
+            lifecycle,
         }
     }
 
     /// Register a new MCP server
     pub async fn register_server(&self, info: ServerInfo) -> Result<(), CoreError> {
         info!("Registering MCP server: {} ({})", info.name, info.id);
-        let mut servers = self.servers.write().await;
+        let mut servers = self.table.servers.write().await;
         if servers.contains_key(&info.id) {
             warn!("Server with ID {} already exists, replacing", info.id);
         }
-        servers.insert(info.id, info);
+        servers.insert(info.id, info.clone());
+        let _ = self
+            .table
+            .events
+            .send(RegistryEvent::Registered { server: info });
         Ok(())
     }
 
     /// Unregister an MCP server
     pub async fn unregister_server(&self, id: Uuid) -> Result<(), CoreError> {
         info!("Unregistering MCP server with ID: {}", id);
-        let mut servers = self.servers.write().await;
-        if servers.remove(&id).is_none() {
+        self.stop_supervisor(id).await;
+        let mut servers = self.table.servers.write().await;
+        if servers.remove(&id).is_some() {
+            let _ = self.table.events.send(RegistryEvent::Unregistered { id });
+        } else {
             warn!("Server with ID {} not found", id);
         }
         Ok(())
@@ -70,42 +210,205 @@ impl ServerRegistry {
     /// Get information about an MCP server
     pub async fn get_server(&self, id: Uuid) -> Option<ServerInfo> {
         debug!("Getting server info for ID: {}", id);
-        let servers = self.servers.read().await;
+        let servers = self.table.servers.read().await;
         servers.get(&id).cloned()
     }
 
     /// Get information about all MCP servers
     pub async fn get_all_servers(&self) -> Vec<ServerInfo> {
         debug!("Getting info for all servers");
-        let servers = self.servers.read().await;
+        let servers = self.table.servers.read().await;
         servers.values().cloned().collect()
     }
 
+    /// Get information about MCP servers of a specific type
+    pub async fn get_servers_by_type(&self, server_type: &str) -> Vec<ServerInfo> {
+        debug!("Getting servers of type: {}", server_type);
+        let servers = self.table.servers.read().await;
+        servers
+            .values()
+            .filter(|s| s.server_type == server_type)
+            .cloned()
+            .collect()
+    }
+
+    /// Find an MCP server by name
+    pub async fn find_server_by_name(&self, name: &str) -> Option<ServerInfo> {
+        debug!("Finding server by name: {}", name);
+        let servers = self.table.servers.read().await;
+        servers.values().find(|s| s.name == name).cloned()
+    }
+
+    /// Subscribe to registration and state change events
+    pub fn subscribe(&self) -> broadcast::Receiver<RegistryEvent> {
+        self.table.events.subscribe()
+    }
+
     /// Activate an MCP server
+    ///
+    /// Launches the server (or connects to it) in the background and keeps it
+    /// supervised until it is deactivated. Progress is reported through
+    /// [`ServerInfo::state`].
     pub async fn activate_server(&self, id: Uuid) -> Result<(), CoreError> {
         info!("Activating MCP server: {}", id);
-        let mut servers = self.servers.write().await;
-        if let Some(server) = servers.get_mut(&id) {
-            server.active = true;
-            Ok(())
-        } else {
+        let mut servers = self.table.servers.write().await;
+        let Some(server) = servers.get_mut(&id) else {
             warn!("Server with ID {} not found", id);
-            Err(CoreError::Other(format!("Server with ID {} not found", id)))
+            return Err(CoreError::Other(format!("Server with ID {} not found", id)));
+        };
+
+        let endpoint =
+            Endpoint::parse(&server.uri).map_err(|e| CoreError::Config(e.to_string()))?;
+
+        let mut supervisors = lock(&self.supervisors);
+        if supervisors
+            .get(&id)
+            .is_some_and(|supervisor| !supervisor.task.is_finished())
+        {
+            debug!("Server {} is already active", id);
+            return Ok(());
         }
+
+        server.state = ServerState::Starting;
+        server.restarts = 0;
+        server.last_error = None;
+        let _ = self.table.events.send(RegistryEvent::StateChanged {
+            id,
+            state: ServerState::Starting,
+        });
+
+        let connection = ConnectionSlot::default();
+        let task = tokio::spawn(lifecycle::supervise(
+            id,
+            endpoint,
+            self.lifecycle.clone(),
+            self.table.clone(),
+            connection.clone(),
+        ));
+        supervisors.insert(id, Supervisor { task, connection });
+        Ok(())
     }
 
-    /// Deactivate an MCP server
+    /// Deactivate an MCP server, stopping it if it is running
     pub async fn deactivate_server(&self, id: Uuid) -> Result<(), CoreError> {
         info!("Deactivating MCP server: {}", id);
-        let mut servers = self.servers.write().await;
-        if let Some(server) = servers.get_mut(&id) {
-            server.active = false;
-            Ok(())
-        } else {
+        self.stop_supervisor(id).await;
+        if self.get_server(id).await.is_none() {
             warn!("Server with ID {} not found", id);
-            Err(CoreError::Other(format!("Server with ID {} not found", id)))
+            return Err(CoreError::Other(format!("Server with ID {} not found", id)));
         }
+        self.table
+            .update(id, |server| server.state = ServerState::Stopped)
+            .await;
+        Ok(())
+    }
+
+    /// Get the live connection to a server, if it is up
+    pub async fn connection(&self, id: Uuid) -> Option<Arc<Connection>> {
+        let slot = lock(&self.supervisors).get(&id)?.connection.clone();
+        let connection = slot.read().await.clone();
+        connection
     }
+
+    /// Get the catalog of tools offered by connected servers
+    pub fn tools(&self) -> &ToolCatalog {
+        &self.table.tools
+    }
+
+    /// Wait until an activated server has listed its tools
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not active, fails, or has not listed its
+    /// tools within `timeout`
+    pub async fn wait_for_tools(&self, id: Uuid, timeout: Duration) -> Result<(), CoreError> {
+        let wait = async {
+            loop {
+                if self.table.tools.has_server(id).await {
+                    return Ok(());
+                }
+                let Some(server) = self.get_server(id).await else {
+                    return Err(CoreError::Other(format!("Server with ID {} not found", id)));
+                };
+                if !server.state.is_active() {
+                    return Err(CoreError::Other(format!(
+                        "Server {} is {:?}: {}",
+                        server.name,
+                        server.state,
+                        server.last_error.as_deref().unwrap_or("not activated")
+                    )));
+                }
+                tokio::time::sleep(TOOLS_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            CoreError::Other(format!(
+                "Server {} did not list its tools within {:?}",
+                id, timeout
+            ))
+        })?
+    }
+
+    /// Resolve a tool name, bare or qualified as `server/tool`, to its provider
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no connected server provides the tool or the name is ambiguous
+    pub async fn resolve_tool(&self, name: &str) -> Result<ToolEntry, CoreError> {
+        self.table.tools.resolve(name).await
+    }
+
+    /// Call a tool on whichever connected server provides it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tool cannot be resolved, its server is not connected,
+    /// or the `tools/call` request fails
+    pub async fn call_tool(
+        &self,
+        name: &str,
+        arguments: serde_json::Value,
+    ) -> Result<serde_json::Value, CoreError> {
+        let entry = self.resolve_tool(name).await?;
+        let connection = self.connection(entry.server_id).await.ok_or_else(|| {
+            CoreError::Other(format!("Server {} is not connected", entry.server_name))
+        })?;
+
+        debug!("Calling tool {}", entry.qualified_name());
+        connection
+            .request(
+                "tools/call",
+                serde_json::json!({ "name": entry.tool.name, "arguments": arguments }),
+            )
+            .await
+            .map_err(|e| CoreError::Other(format!("Tool {} failed: {e}", entry.qualified_name())))
+    }
+
+    async fn stop_supervisor(&self, id: Uuid) {
+        let Some(supervisor) = lock(&self.supervisors).remove(&id) else {
+            return;
+        };
+        supervisor.task.abort();
+        self.table.tools.remove_server(id).await;
+        let connection = supervisor.connection.write().await.take();
+        if let Some(connection) = connection {
+            connection.close().await;
+        }
+    }
+}
+
+impl Drop for ServerRegistry {
+    fn drop(&mut self) {
+        for supervisor in lock(&self.supervisors).values() {
+            supervisor.task.abort();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
 }
 
 impl Default for ServerRegistry {
@@ -118,16 +421,40 @@ impl Default for ServerRegistry {
 mod tests {
     use super::*;
 
+    use crate::connection::tests::{script_uri, ECHO_SERVER};
+    use std::time::Duration;
+
     fn create_test_server() -> ServerInfo {
-        ServerInfo {
-            id: Uuid::new_v4(),
-            name: "Test Server".to_string(),
-            server_type: "test".to_string(),
-            uri: "localhost:8080".to_string(),
-            active: false,
+        ServerInfo::new("Test Server", "test", "localhost:8080")
+    }
+
+    fn test_lifecycle() -> LifecycleConfig {
+        LifecycleConfig {
+            startup_timeout: Duration::from_secs(5),
+            health_interval: Duration::from_millis(50),
+            health_timeout: Duration::from_secs(1),
+            max_missed_pings: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            max_restarts: 2,
+            stable_after: Duration::from_secs(60),
         }
     }
 
+    async fn wait_for_state(registry: &ServerRegistry, id: Uuid, state: ServerState) -> ServerInfo {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let server = registry.get_server(id).await.unwrap();
+                if server.state == state {
+                    return server;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
     #[tokio::test]
     async fn test_register_server() {
         let registry = ServerRegistry::new();
@@ -147,6 +474,55 @@ mod tests {
         assert_eq!(result.unwrap().name, server.name);
     }
 
+    #[tokio::test]
+    async fn test_lookup_by_type_and_name() {
+        let registry = ServerRegistry::new();
+        let fs = ServerInfo::new("fs", "filesystem", "raco-fs").with_metadata("root", "/tmp");
+        let git = ServerInfo::new("git", "git", "raco-git");
+        let _ = registry.register_server(fs.clone()).await;
+        let _ = registry.register_server(git).await;
+
+        let by_type = registry.get_servers_by_type("filesystem").await;
+        assert_eq!(by_type.len(), 1);
+        assert_eq!(by_type[0].id, fs.id);
+        assert_eq!(by_type[0].metadata.get("root"), Some(&"/tmp".to_string()));
+
+        let by_name = registry.find_server_by_name("git").await;
+        assert_eq!(by_name.unwrap().server_type, "git");
+        assert!(registry.find_server_by_name("missing").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registry_events() {
+        let registry = ServerRegistry::new();
+        let mut events = registry.subscribe();
+        let server = create_test_server();
+        let id = server.id;
+
+        let _ = registry.register_server(server).await;
+        let _ = registry.unregister_server(id).await;
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            RegistryEvent::Registered { server } if server.id == id
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            RegistryEvent::Unregistered { id: removed } if removed == id
+        ));
+    }
+
+    #[test]
+    fn test_server_info_serialization() {
+        let server = create_test_server().with_metadata("owner", "raco");
+        let json = serde_json::to_value(&server).unwrap();
+        assert_eq!(json["state"], "stopped");
+
+        let deserialized: ServerInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized.id, server.id);
+        assert_eq!(deserialized.metadata, server.metadata);
+    }
+
     #[tokio::test]
     async fn test_unregister_server() {
         let registry = ServerRegistry::new();
@@ -158,19 +534,106 @@ mod tests {
         assert!(registry.get_server(id).await.is_none());
     }
 
+    #[cfg(unix)]
     #[tokio::test]
     async fn test_activate_deactivate_server() {
-        let registry = ServerRegistry::new();
-        let server = create_test_server();
+        let (uri, script) = script_uri(ECHO_SERVER);
+        let registry = ServerRegistry::with_lifecycle(test_lifecycle());
+        let server = ServerInfo::new("Echo Server", "test", &uri);
         let id = server.id;
         let _ = registry.register_server(server).await;
 
         assert!(registry.activate_server(id).await.is_ok());
-        let activated = registry.get_server(id).await.unwrap();
-        assert!(activated.active);
+        let ready = wait_for_state(&registry, id, ServerState::Ready).await;
+        assert!(ready.state.is_active());
+        assert!(registry.connection(id).await.is_some());
 
         assert!(registry.deactivate_server(id).await.is_ok());
         let deactivated = registry.get_server(id).await.unwrap();
-        assert!(!deactivated.active);
+        assert_eq!(deactivated.state, ServerState::Stopped);
+        assert!(registry.connection(id).await.is_none());
+
+        let _ = std::fs::remove_file(script);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_crashing_server_is_restarted_then_failed() {
+        let registry = ServerRegistry::with_lifecycle(test_lifecycle());
+        let server = ServerInfo::new("Crashing Server", "test", "sh -c exit");
+        let id = server.id;
+        let _ = registry.register_server(server).await;
+
+        assert!(registry.activate_server(id).await.is_ok());
+        let error = registry
+            .wait_for_tools(id, Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Crashing Server is Failed"));
+        let failed = wait_for_state(&registry, id, ServerState::Failed).await;
+        assert_eq!(failed.restarts, 2);
+        assert!(failed.last_error.is_some());
+    }
+
+    /// Serves one tool, announces a list change after the first `tools/list` and
+    /// serves a second tool from then on
+    const TOOL_SERVER: &str = r#"n=0
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/^{"id":\([0-9]*\).*/\1/p')
+  [ -z "$id" ] && continue
+  case "$line" in
+    *'"method":"tools/list"'*)
+      n=$((n+1))
+      if [ $n -eq 1 ]; then tools='{"name":"build"}'; else tools='{"name":"build"},{"name":"test"}'; fi
+      result="{\"tools\":[$tools]}" ;;
+    *'"method":"tools/call"'*) result='{"content":[{"type":"text","text":"done"}]}' ;;
+    *) result='{}' ;;
+  esac
+  printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$result"
+  [ $n -eq 1 ] && n=2 && printf '{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}\n'
+done"#;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tool_discovery_and_call() {
+        let (uri, script) = script_uri(TOOL_SERVER);
+        let registry = ServerRegistry::with_lifecycle(test_lifecycle());
+        let server = ServerInfo::new("cargo", "rust", &uri);
+        let id = server.id;
+        let _ = registry.register_server(server).await;
+        let _ = registry.activate_server(id).await;
+
+        // The second tool only appears after the list change notification
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while registry.resolve_tool("test").await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        registry
+            .wait_for_tools(id, Duration::from_secs(10))
+            .await
+            .unwrap();
+        let entry = registry.resolve_tool("cargo/build").await.unwrap();
+        assert_eq!(entry.server_id, id);
+        assert_eq!(registry.tools().list().await.len(), 2);
+
+        let result = registry
+            .call_tool("build", serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result["content"][0]["text"], "done");
+
+        let _ = registry.deactivate_server(id).await;
+        assert!(registry.resolve_tool("build").await.is_err());
+        let _ = std::fs::remove_file(script);
+    }
+
+    #[tokio::test]
+    async fn test_activate_unknown_server() {
+        let registry = ServerRegistry::new();
+        assert!(registry.activate_server(Uuid::new_v4()).await.is_err());
     }
 }
@@ -0,0 +1,233 @@
+//! Tool discovery across MCP servers
+//!
+//! This module aggregates the `tools/list` results of all connected servers into a
+//! searchable catalog and resolves tool names to the server providing them.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use raco_core::error::CoreError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::connection::Connection;
+
+/// Separator between server name and tool name in qualified tool names
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// A tool as advertised by an MCP server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    /// Tool name
+    pub name: String,
+
+    /// Human readable description
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// JSON Schema of the tool arguments
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+/// A tool in the catalog, together with the server providing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolEntry {
+    /// ID of the providing server
+    pub server_id: Uuid,
+
+    /// Name of the providing server
+    pub server_name: String,
+
+    /// The tool itself
+    pub tool: Tool,
+}
+
+impl ToolEntry {
+    /// Name of the tool qualified with its server, e.g. `git/status`
+    pub fn qualified_name(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.server_name, NAMESPACE_SEPARATOR, self.tool.name
+        )
+    }
+}
+
+/// Catalog of the tools offered by all connected servers
+#[derive(Debug, Default)]
+pub struct ToolCatalog {
+    /// Tools keyed by the ID of the server providing them
+    tools: RwLock<HashMap<Uuid, Vec<ToolEntry>>>,
+}
+
+impl ToolCatalog {
+    /// Create an empty catalog
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the tools provided by a server
+    pub async fn set_server_tools(&self, server_id: Uuid, server_name: &str, tools: Vec<Tool>) {
+        let entries = tools
+            .into_iter()
+            .map(|tool| ToolEntry {
+                server_id,
+                server_name: server_name.to_string(),
+                tool,
+            })
+            .collect();
+        self.tools.write().await.insert(server_id, entries);
+    }
+
+    /// Remove the tools provided by a server
+    pub async fn remove_server(&self, server_id: Uuid) {
+        self.tools.write().await.remove(&server_id);
+    }
+
+    /// Whether a server has listed its tools
+    pub async fn has_server(&self, server_id: Uuid) -> bool {
+        self.tools.read().await.contains_key(&server_id)
+    }
+
+    /// All tools, sorted by qualified name
+    pub async fn list(&self) -> Vec<ToolEntry> {
+        let tools = self.tools.read().await;
+        let mut entries: Vec<_> = tools.values().flatten().cloned().collect();
+        entries.sort_by_key(ToolEntry::qualified_name);
+        entries
+    }
+
+    /// Tools whose name or description contains `query`, ignoring case
+    pub async fn search(&self, query: &str) -> Vec<ToolEntry> {
+        let query = query.to_lowercase();
+        self.list()
+            .await
+            .into_iter()
+            .filter(|entry| {
+                entry.tool.name.to_lowercase().contains(&query)
+                    || entry
+                        .tool
+                        .description
+                        .as_ref()
+                        .is_some_and(|d| d.to_lowercase().contains(&query))
+            })
+            .collect()
+    }
+
+    /// Resolve a tool name to the entry providing it
+    ///
+    /// Names of the form `server/tool` select a specific server. Bare tool names
+    /// resolve only if exactly one server provides a tool of that name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no server provides the tool or a bare name is ambiguous
+    pub async fn resolve(&self, name: &str) -> Result<ToolEntry, CoreError> {
+        let tools = self.tools.read().await;
+        let mut candidates: Vec<_> = tools
+            .values()
+            .flatten()
+            .filter(|entry| match name.split_once(NAMESPACE_SEPARATOR) {
+                Some((server, tool)) => entry.server_name == server && entry.tool.name == tool,
+                None => entry.tool.name == name,
+            })
+            .collect();
+
+        match candidates.len() {
+            0 => Err(CoreError::Other(format!("Unknown tool: {name}"))),
+            1 => Ok(candidates.remove(0).clone()),
+            _ => {
+                let mut names: Vec<_> = candidates.iter().map(|e| e.qualified_name()).collect();
+                names.sort();
+                Err(CoreError::Other(format!(
+                    "Tool {name} is provided by several servers, use one of: {}",
+                    names.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+/// Fetch the complete tool list of a server, following pagination cursors
+///
+/// # Errors
+///
+/// Returns an error if a `tools/list` request fails or returns malformed tools
+pub async fn list_tools(connection: &Connection) -> Result<Vec<Tool>> {
+    let mut tools = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let params = cursor.map_or_else(|| json!({}), |cursor| json!({ "cursor": cursor }));
+        let mut result = connection.request("tools/list", params).await?;
+
+        let page: Vec<Tool> = match result["tools"].take() {
+            Value::Null => Vec::new(),
+            page => serde_json::from_value(page).context("Malformed tools/list response")?,
+        };
+        tools.extend(page);
+
+        cursor = result["nextCursor"].as_str().map(ToString::to_string);
+        if cursor.is_none() {
+            return Ok(tools);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str, description: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            input_schema: json!({ "type": "object" }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_unique_and_qualified_names() {
+        let catalog = ToolCatalog::new();
+        let fs = Uuid::new_v4();
+        let git = Uuid::new_v4();
+        catalog
+            .set_server_tools(
+                fs,
+                "fs",
+                vec![tool("read", "Read a file"), tool("status", "Disk usage")],
+            )
+            .await;
+        catalog
+            .set_server_tools(git, "git", vec![tool("status", "Working tree status")])
+            .await;
+
+        assert_eq!(catalog.resolve("read").await.unwrap().server_id, fs);
+        assert_eq!(catalog.resolve("git/status").await.unwrap().server_id, git);
+        assert!(catalog.resolve("status").await.is_err());
+        assert!(catalog.resolve("missing").await.is_err());
+
+        catalog.remove_server(fs).await;
+        assert_eq!(catalog.resolve("status").await.unwrap().server_id, git);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let catalog = ToolCatalog::new();
+        catalog
+            .set_server_tools(
+                Uuid::new_v4(),
+                "git",
+                vec![tool("diff", "Show changes"), tool("log", "Commit history")],
+            )
+            .await;
+
+        let found = catalog.search("CHANGES").await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].qualified_name(), "git/diff");
+        assert_eq!(catalog.list().await.len(), 2);
+    }
+}
@@ -0,0 +1,720 @@
+//! Debug MCP server
+//!
+//! This module provides an MCP server implementation for debugging host binaries with GDB.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use raco_mcp::protocol::{McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info};
+
+use crate::gdb::{self, GdbSession};
+use crate::{sandbox, ServerResult};
+
+/// Debugger executables a session may attach with
+pub const DEBUGGERS: &[&str] = &["gdb", "gdb-multiarch"];
+
+/// Time to wait for the program to stop after resuming it
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Debug server for handling GDB sessions
+#[derive(Debug)]
+pub struct DebugServer {
+    /// Project root; debugged programs are confined to it
+    root_dir: PathBuf,
+
+    /// Server ID
+    id: String,
+
+    /// Active debug session
+    session: Mutex<Option<GdbSession>>,
+}
+
+/// Debug command types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum DebugCommand {
+    /// Load a program into a new debug session, replacing the active one
+    #[serde(rename = "launch")]
+    Launch {
+        /// Path of the program
+        program: String,
+
+        /// Program arguments
+        #[serde(default)]
+        args: Vec<String>,
+    },
+
+    /// Attach to a remote target, e.g. the GDB stub of an emulator, replacing the active session
+    #[serde(rename = "attach")]
+    Attach {
+        /// Remote target, e.g. `localhost:1234`
+        target: String,
+
+        /// Path of the program running on the target, for symbols
+        #[serde(default)]
+        program: Option<String>,
+
+        /// Debugger executable, `gdb` (the default) or `gdb-multiarch` for cross targets
+        #[serde(default)]
+        gdb: Option<String>,
+    },
+
+    /// Insert a breakpoint
+    #[serde(rename = "break")]
+    Break {
+        /// Location, e.g. `main.rs:12` or a function name
+        location: String,
+
+        /// Only stop when this expression is true
+        #[serde(default)]
+        condition: Option<String>,
+    },
+
+    /// Delete a breakpoint
+    #[serde(rename = "delete_breakpoint")]
+    DeleteBreakpoint {
+        /// Breakpoint number
+        number: u32,
+    },
+
+    /// Start the program and run until it stops
+    #[serde(rename = "run")]
+    Run,
+
+    /// Continue until the program stops
+    #[serde(rename = "continue")]
+    Continue,
+
+    /// Step over the current source line
+    #[serde(rename = "next")]
+    Next,
+
+    /// Step into the current source line
+    #[serde(rename = "step")]
+    Step,
+
+    /// Run until the current function returns
+    #[serde(rename = "finish")]
+    Finish,
+
+    /// Show the call stack
+    #[serde(rename = "backtrace")]
+    Backtrace,
+
+    /// Show the local variables of a frame
+    #[serde(rename = "locals")]
+    Locals {
+        /// Frame level (defaults to the innermost frame)
+        #[serde(default)]
+        frame: Option<u32>,
+    },
+
+    /// Evaluate an expression
+    #[serde(rename = "evaluate")]
+    Evaluate {
+        /// Expression in the program's language
+        expression: String,
+
+        /// Frame level (defaults to the innermost frame)
+        #[serde(default)]
+        frame: Option<u32>,
+    },
+
+    /// End the debug session
+    #[serde(rename = "terminate")]
+    Terminate,
+}
+
+/// A stack frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame {
+    /// Frame level, 0 being the innermost
+    pub level: u32,
+
+    /// Function name
+    pub function: Option<String>,
+
+    /// Source file
+    pub file: Option<String>,
+
+    /// Source line
+    pub line: Option<u32>,
+
+    /// Program counter
+    pub address: Option<String>,
+}
+
+impl Frame {
+    /// Convert an MI frame tuple
+    pub fn from_mi(frame: &Value) -> Self {
+        Self {
+            level: number(&frame["level"]).unwrap_or(0),
+            function: string(&frame["func"]),
+            file: string(&frame["fullname"]).or_else(|| string(&frame["file"])),
+            line: number(&frame["line"]),
+            address: string(&frame["addr"]),
+        }
+    }
+}
+
+/// A breakpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Breakpoint number
+    pub number: u32,
+
+    /// Function containing the breakpoint
+    pub function: Option<String>,
+
+    /// Source file
+    pub file: Option<String>,
+
+    /// Source line
+    pub line: Option<u32>,
+}
+
+/// A variable and its value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variable {
+    /// Variable name
+    pub name: String,
+
+    /// Value as printed by the debugger
+    pub value: Option<String>,
+}
+
+/// Why and where the program stopped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StopEvent {
+    /// Stop reason, e.g. `breakpoint-hit`, `end-stepping-range` or `exited-normally`
+    pub reason: String,
+
+    /// Frame the program stopped in, if it is still alive
+    pub frame: Option<Frame>,
+
+    /// Exit code, if the program exited
+    pub exit_code: Option<i32>,
+
+    /// Signal name, if the program received a signal
+    pub signal: Option<String>,
+
+    /// Debugger console output produced while running
+    pub output: String,
+}
+
+impl StopEvent {
+    /// Convert the results of an MI `*stopped` record
+    pub fn from_mi(stopped: &Value, output: String) -> Self {
+        let reason = string(&stopped["reason"]).unwrap_or_else(|| "unknown".to_string());
+        let exit_code = match reason.as_str() {
+            "exited-normally" => Some(0),
+            // GDB prints exit codes in octal
+            "exited" => stopped["exit-code"]
+                .as_str()
+                .and_then(|code| i32::from_str_radix(code, 8).ok()),
+            _ => None,
+        };
+
+        Self {
+            frame: stopped.get("frame").map(Frame::from_mi),
+            exit_code,
+            signal: string(&stopped["signal-name"]),
+            output,
+            reason,
+        }
+    }
+
+    /// Whether the program has ended
+    pub fn exited(&self) -> bool {
+        self.reason.starts_with("exited")
+    }
+}
+
+/// Debug response types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DebugResponse {
+    /// Launch response
+    #[serde(rename = "launched")]
+    Launched {
+        /// Path of the loaded program
+        program: String,
+    },
+
+    /// Attach response
+    #[serde(rename = "attached")]
+    Attached {
+        /// Remote target
+        target: String,
+    },
+
+    /// Breakpoint response
+    #[serde(rename = "breakpoint")]
+    Breakpoint(Breakpoint),
+
+    /// Breakpoint deletion response
+    #[serde(rename = "breakpoint_deleted")]
+    BreakpointDeleted {
+        /// Breakpoint number
+        number: u32,
+    },
+
+    /// Execution response
+    #[serde(rename = "stopped")]
+    Stopped(StopEvent),
+
+    /// Backtrace response
+    #[serde(rename = "backtrace")]
+    Backtrace {
+        /// Frames, innermost first
+        frames: Vec<Frame>,
+    },
+
+    /// Locals response
+    #[serde(rename = "variables")]
+    Variables {
+        /// Local variables
+        variables: Vec<Variable>,
+    },
+
+    /// Evaluation response
+    #[serde(rename = "value")]
+    Value {
+        /// Evaluated expression
+        expression: String,
+
+        /// Value as printed by the debugger
+        value: String,
+    },
+
+    /// Termination response
+    #[serde(rename = "terminated")]
+    Terminated,
+
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+impl DebugServer {
+    /// Create a new debug server for programs under the given root directory
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        let root_dir = root_dir.as_ref().to_path_buf();
+        info!(
+            "Creating debug server with root directory: {}",
+            root_dir.display()
+        );
+        Self {
+            root_dir,
+            id: uuid::Uuid::new_v4().to_string(),
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Get the server ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Handle an MCP request
+    pub async fn handle_request(
+        &self,
+        request: McpRequest<DebugCommand>,
+    ) -> ServerResult<McpResponse<DebugResponse>> {
+        debug!("Handling debug request: {:?}", request);
+
+        let response = match request.payload {
+            DebugCommand::Launch { program, args } => self.handle_launch(program, args).await,
+            DebugCommand::Attach {
+                target,
+                program,
+                gdb,
+            } => self.handle_attach(target, program, gdb).await,
+            DebugCommand::Break {
+                location,
+                condition,
+            } => self.handle_break(location, condition).await,
+            DebugCommand::DeleteBreakpoint { number } => {
+                self.handle_delete_breakpoint(number).await
+            }
+            DebugCommand::Run => self.handle_resume("-exec-run").await,
+            DebugCommand::Continue => self.handle_resume("-exec-continue").await,
+            DebugCommand::Next => self.handle_resume("-exec-next").await,
+            DebugCommand::Step => self.handle_resume("-exec-step").await,
+            DebugCommand::Finish => self.handle_resume("-exec-finish").await,
+            DebugCommand::Backtrace => self.handle_backtrace().await,
+            DebugCommand::Locals { frame } => self.handle_locals(frame).await,
+            DebugCommand::Evaluate { expression, frame } => {
+                self.handle_evaluate(expression, frame).await
+            }
+            DebugCommand::Terminate => self.handle_terminate().await,
+        };
+
+        let response = match response {
+            Ok(payload) => McpResponse {
+                command: request.command,
+                payload,
+                status: ResponseStatus::success(),
+                request_id: request.request_id,
+            },
+            Err(e) => {
+                error!("Error handling debug request: {}", e);
+                McpResponse {
+                    command: request.command,
+                    payload: create_error_response(&e.to_string()),
+                    status: ResponseStatus::error(1, &e.to_string()),
+                    request_id: request.request_id,
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn handle_launch(
+        &self,
+        program: String,
+        args: Vec<String>,
+    ) -> Result<DebugResponse, anyhow::Error> {
+        let path = sandbox::resolve_path(&self.root_dir, &program)?;
+        if !path.is_file() {
+            return Err(anyhow!("Program not found: {}", program));
+        }
+
+        let session = GdbSession::launch(&path, &args).await?;
+        if let Some(previous) = self.session.lock().await.replace(session) {
+            previous.quit().await;
+        }
+
+        Ok(DebugResponse::Launched {
+            program: path.display().to_string(),
+        })
+    }
+
+    async fn handle_attach(
+        &self,
+        target: String,
+        program: Option<String>,
+        gdb: Option<String>,
+    ) -> Result<DebugResponse, anyhow::Error> {
+        let program = program
+            .map(|program| sandbox::resolve_path(&self.root_dir, &program))
+            .transpose()?;
+        let gdb = gdb.as_deref().unwrap_or("gdb");
+        if !DEBUGGERS.contains(&gdb) {
+            return Err(anyhow!(
+                "Unsupported debugger: {} (use one of: {})",
+                gdb,
+                DEBUGGERS.join(", ")
+            ));
+        }
+
+        let session = GdbSession::attach(gdb, program.as_deref(), &target).await?;
+        if let Some(previous) = self.session.lock().await.replace(session) {
+            previous.quit().await;
+        }
+
+        Ok(DebugResponse::Attached { target })
+    }
+
+    async fn handle_break(
+        &self,
+        location: String,
+        condition: Option<String>,
+    ) -> Result<DebugResponse, anyhow::Error> {
+        let mut command = "-break-insert".to_string();
+        if let Some(condition) = condition {
+            command.push_str(&format!(" -c {}", gdb::quote(&condition)));
+        }
+        command.push_str(&format!(" {}", gdb::quote(&location)));
+
+        let record = self.command(&command).await?;
+        let bkpt = &record.results["bkpt"];
+        Ok(DebugResponse::Breakpoint(Breakpoint {
+            number: number(&bkpt["number"]).context("GDB did not report a breakpoint number")?,
+            function: string(&bkpt["func"]),
+            file: string(&bkpt["fullname"]).or_else(|| string(&bkpt["file"])),
+            line: number(&bkpt["line"]),
+        }))
+    }
+
+    async fn handle_delete_breakpoint(&self, number: u32) -> Result<DebugResponse, anyhow::Error> {
+        self.command(&format!("-break-delete {number}")).await?;
+        Ok(DebugResponse::BreakpointDeleted { number })
+    }
+
+    async fn handle_resume(&self, command: &str) -> Result<DebugResponse, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard.as_mut().ok_or_else(no_session)?;
+
+        session.command(command).await?;
+        let stopped = session.wait_for_stop(STOP_TIMEOUT).await?;
+        let output = session.take_console();
+        Ok(DebugResponse::Stopped(StopEvent::from_mi(&stopped, output)))
+    }
+
+    async fn handle_backtrace(&self) -> Result<DebugResponse, anyhow::Error> {
+        let record = self.command("-stack-list-frames").await?;
+        let frames = record.results["stack"]
+            .as_array()
+            .map(|frames| frames.iter().map(Frame::from_mi).collect())
+            .unwrap_or_default();
+        Ok(DebugResponse::Backtrace { frames })
+    }
+
+    async fn handle_locals(&self, frame: Option<u32>) -> Result<DebugResponse, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard.as_mut().ok_or_else(no_session)?;
+
+        select_frame(session, frame).await?;
+        let record = session
+            .command("-stack-list-variables --all-values")
+            .await?;
+        let variables = record.results["variables"]
+            .as_array()
+            .map(|variables| {
+                variables
+                    .iter()
+                    .filter_map(|variable| {
+                        Some(Variable {
+                            name: string(&variable["name"])?,
+                            value: string(&variable["value"]),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(DebugResponse::Variables { variables })
+    }
+
+    async fn handle_evaluate(
+        &self,
+        expression: String,
+        frame: Option<u32>,
+    ) -> Result<DebugResponse, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard.as_mut().ok_or_else(no_session)?;
+
+        select_frame(session, frame).await?;
+        let record = session
+            .command(&format!(
+                "-data-evaluate-expression {}",
+                gdb::quote(&expression)
+            ))
+            .await?;
+        let value = string(&record.results["value"]).unwrap_or_default();
+        Ok(DebugResponse::Value { expression, value })
+    }
+
+    async fn handle_terminate(&self) -> Result<DebugResponse, anyhow::Error> {
+        let session = self.session.lock().await.take().ok_or_else(no_session)?;
+        session.quit().await;
+        Ok(DebugResponse::Terminated)
+    }
+
+    /// Run an MI command in the active session
+    async fn command(&self, command: &str) -> Result<gdb::Record, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard.as_mut().ok_or_else(no_session)?;
+        session.command(command).await
+    }
+}
+
+async fn select_frame(session: &mut GdbSession, frame: Option<u32>) -> Result<(), anyhow::Error> {
+    session
+        .command(&format!("-stack-select-frame {}", frame.unwrap_or(0)))
+        .await?;
+    Ok(())
+}
+
+fn no_session() -> anyhow::Error {
+    anyhow!("No debug session, launch a program first")
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(ToString::to_string)
+}
+
+fn number(value: &Value) -> Option<u32> {
+    value.as_str().and_then(|value| value.parse().ok())
+}
+
+// Helper function to create an error response for the appropriate command type
+fn create_error_response(error: &str) -> DebugResponse {
+    DebugResponse::Error {
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PROGRAM: &str = r#"fn square(x: i32) -> i32 {
+    let result = x * x;
+    result
+}
+
+fn main() {
+    let value = 7;
+    println!("{}", square(value));
+}
+"#;
+
+    async fn request(server: &DebugServer, command: DebugCommand) -> DebugResponse {
+        let response = server
+            .handle_request(McpRequest::new("debug", command))
+            .await
+            .unwrap();
+        response.payload
+    }
+
+    #[test]
+    fn test_server_creation() {
+        let server = DebugServer::new(".");
+        assert!(!server.id().is_empty());
+    }
+
+    #[test]
+    fn test_stop_event_from_mi() {
+        let stopped = json!({
+            "reason": "breakpoint-hit",
+            "bkptno": "1",
+            "frame": {"addr": "0x5555", "func": "prog::square", "file": "main.rs", "fullname": "/tmp/main.rs", "line": "2"}
+        });
+        let event = StopEvent::from_mi(&stopped, String::new());
+        assert_eq!(event.reason, "breakpoint-hit");
+        assert!(!event.exited());
+        let frame = event.frame.unwrap();
+        assert_eq!(frame.function.as_deref(), Some("prog::square"));
+        assert_eq!(frame.file.as_deref(), Some("/tmp/main.rs"));
+        assert_eq!(frame.line, Some(2));
+
+        let exited = StopEvent::from_mi(
+            &json!({"reason": "exited", "exit-code": "012"}),
+            String::new(),
+        );
+        assert!(exited.exited());
+        assert_eq!(exited.exit_code, Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_requires_session() {
+        let server = DebugServer::new(".");
+        let response = request(&server, DebugCommand::Backtrace).await;
+        assert!(matches!(response, DebugResponse::Error { .. }));
+
+        let response = request(
+            &server,
+            DebugCommand::Launch {
+                program: "../outside".to_string(),
+                args: vec![],
+            },
+        )
+        .await;
+        assert!(matches!(response, DebugResponse::Error { .. }));
+
+        let response = request(
+            &server,
+            DebugCommand::Attach {
+                target: "localhost:1234".to_string(),
+                program: None,
+                gdb: Some("/bin/sh".to_string()),
+            },
+        )
+        .await;
+        let DebugResponse::Error { message } = response else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert!(message.contains("Unsupported debugger"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs gdb"]
+    async fn test_debug_session() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), PROGRAM).unwrap();
+        let status = std::process::Command::new("rustc")
+            .args(["-g", "-C", "opt-level=0", "-o", "prog", "main.rs"])
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let server = DebugServer::new(dir.path());
+        let response = request(
+            &server,
+            DebugCommand::Launch {
+                program: "prog".to_string(),
+                args: vec![],
+            },
+        )
+        .await;
+        assert!(matches!(response, DebugResponse::Launched { .. }));
+
+        let response = request(
+            &server,
+            DebugCommand::Break {
+                location: "main.rs:2".to_string(),
+                condition: None,
+            },
+        )
+        .await;
+        let DebugResponse::Breakpoint(breakpoint) = response else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert_eq!(breakpoint.line, Some(2));
+
+        let DebugResponse::Stopped(event) = request(&server, DebugCommand::Run).await else {
+            panic!("program did not stop");
+        };
+        assert_eq!(event.reason, "breakpoint-hit");
+        assert!(event.frame.unwrap().function.unwrap().contains("square"));
+
+        let response = request(
+            &server,
+            DebugCommand::Evaluate {
+                expression: "x".to_string(),
+                frame: None,
+            },
+        )
+        .await;
+        assert!(matches!(response, DebugResponse::Value { ref value, .. } if value == "7"));
+
+        request(&server, DebugCommand::Next).await;
+        let DebugResponse::Variables { variables } =
+            request(&server, DebugCommand::Locals { frame: None }).await
+        else {
+            panic!("no variables");
+        };
+        assert!(variables
+            .iter()
+            .any(|v| v.name == "result" && v.value.as_deref() == Some("49")));
+
+        let DebugResponse::Backtrace { frames } = request(&server, DebugCommand::Backtrace).await
+        else {
+            panic!("no backtrace");
+        };
+        assert!(frames.len() >= 2);
+        assert_eq!(frames[0].level, 0);
+
+        let DebugResponse::Stopped(event) = request(&server, DebugCommand::Continue).await else {
+            panic!("program did not exit");
+        };
+        assert!(event.exited());
+        assert_eq!(event.exit_code, Some(0));
+
+        let response = request(&server, DebugCommand::Terminate).await;
+        assert!(matches!(response, DebugResponse::Terminated));
+    }
+}
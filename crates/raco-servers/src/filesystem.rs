@@ -3,17 +3,17 @@
 //! This module provides an MCP server implementation for filesystem operations.
 
 use raco_mcp::protocol::{FileInfo, McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use tracing::{debug, error, info};
 
-use crate::ServerResult;
+use crate::{sandbox, ServerResult};
 
 /// Filesystem server for handling filesystem operations
 #[derive(Debug)]
 pub struct FilesystemServer {
     /// Root directory for filesystem operations
-    #[allow(dead_code)]
     root_dir: PathBuf,
 
     /// Server ID
@@ -21,7 +21,7 @@ pub struct FilesystemServer {
 }
 
 /// Filesystem command types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum FilesystemCommand {
     /// List files in a directory
@@ -171,18 +171,20 @@ impl FilesystemServer {
     // Implementation of the handlers will go here in a real implementation
     async fn handle_list(
         &self,
-        _path: String,
+        path: String,
         _recursive: bool,
     ) -> Result<FilesystemResponse, anyhow::Error> {
+        sandbox::resolve_path(&self.root_dir, &path)?;
         // This is a placeholder - actual implementation would list files
         Ok(FilesystemResponse::List { files: vec![] })
     }
 
     async fn handle_read(
         &self,
-        _path: String,
+        path: String,
         _encoding: Option<String>,
     ) -> Result<FilesystemResponse, anyhow::Error> {
+        sandbox::resolve_path(&self.root_dir, &path)?;
         // This is a placeholder - actual implementation would read the file
         Ok(FilesystemResponse::Read {
             content: "".to_string(),
@@ -192,19 +194,21 @@ impl FilesystemServer {
 
     async fn handle_write(
         &self,
-        _path: String,
+        path: String,
         _content: String,
         _append: bool,
     ) -> Result<FilesystemResponse, anyhow::Error> {
+        sandbox::resolve_path(&self.root_dir, &path)?;
         // This is a placeholder - actual implementation would write to the file
         Ok(FilesystemResponse::Write { bytes_written: 0 })
     }
 
     async fn handle_delete(
         &self,
-        _path: String,
+        path: String,
         _recursive: bool,
     ) -> Result<FilesystemResponse, anyhow::Error> {
+        sandbox::resolve_path(&self.root_dir, &path)?;
         // This is a placeholder - actual implementation would delete the file/directory
         Ok(FilesystemResponse::Delete { success: true })
     }
@@ -253,4 +257,20 @@ mod tests {
             panic!("Expected List response");
         }
     }
+
+    #[test]
+    fn test_path_outside_root_is_rejected() {
+        let server = FilesystemServer::new(".");
+
+        let request = McpRequest::new(
+            "filesystem.read",
+            FilesystemCommand::Read {
+                path: "../outside.txt".to_string(),
+                encoding: None,
+            },
+        );
+
+        let response = block_on(server.handle_request(request)).unwrap();
+        assert!(!response.status.is_success());
+    }
 }
@@ -0,0 +1,521 @@
+//! GDB machine interface
+//!
+//! This module drives `gdb --interpreter=mi` and parses its output records. It is
+//! shared by the servers that debug host binaries and emulated targets.
+
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Default time to wait for a command's result record
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Kind of an MI output record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// `^` result of a command
+    Result,
+
+    /// `*` change of the execution state
+    Exec,
+
+    /// `+` progress of a long-running operation
+    Status,
+
+    /// `=` other notification
+    Notify,
+
+    /// `~` console output
+    Console,
+
+    /// `@` output of the debugged program
+    Target,
+
+    /// `&` GDB log output
+    Log,
+}
+
+/// A parsed MI output record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Command token the record answers, if any
+    pub token: Option<u64>,
+
+    /// Record kind
+    pub kind: RecordKind,
+
+    /// Record class, e.g. `done`, `error` or `stopped`; empty for stream records
+    pub class: String,
+
+    /// Record results as a JSON object, or the text of a stream record
+    pub results: Value,
+}
+
+impl Record {
+    /// Error message of an `^error` result record
+    pub fn error_message(&self) -> Option<&str> {
+        (self.kind == RecordKind::Result && self.class == "error")
+            .then(|| self.results["msg"].as_str().unwrap_or("unknown error"))
+    }
+}
+
+/// Parse one line of MI output
+///
+/// Returns `None` for the `(gdb)` prompt and lines that are not MI records.
+pub fn parse_record(line: &str) -> Option<Record> {
+    let line = line.trim_end();
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    let token = line[..digits].parse().ok();
+    let rest = &line[digits..];
+    let marker = rest.chars().next()?;
+    let body = &rest[marker.len_utf8()..];
+
+    let kind = match marker {
+        '^' => RecordKind::Result,
+        '*' => RecordKind::Exec,
+        '+' => RecordKind::Status,
+        '=' => RecordKind::Notify,
+        '~' => RecordKind::Console,
+        '@' => RecordKind::Target,
+        '&' => RecordKind::Log,
+        _ => return None,
+    };
+
+    if matches!(
+        kind,
+        RecordKind::Console | RecordKind::Target | RecordKind::Log
+    ) {
+        let mut parser = Parser::new(body);
+        let text = parser.c_string().unwrap_or_else(|| body.to_string());
+        return Some(Record {
+            token,
+            kind,
+            class: String::new(),
+            results: Value::String(text),
+        });
+    }
+
+    let (class, results) = body.split_once(',').unwrap_or((body, ""));
+    let mut parser = Parser::new(results);
+    Some(Record {
+        token,
+        kind,
+        class: class.to_string(),
+        results: Value::Object(parser.results()),
+    })
+}
+
+/// Quote a string as an MI c-string
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Recursive-descent parser for MI result lists
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// `result ("," result)*`, stopping at a closing bracket
+    fn results(&mut self) -> Map<String, Value> {
+        let mut map = Map::new();
+        while let Some((name, value)) = self.result() {
+            map.insert(name, value);
+            if self.peek() == Some(',') {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        map
+    }
+
+    /// `variable "=" value`
+    fn result(&mut self) -> Option<(String, Value)> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '=' {
+                break;
+            }
+            if matches!(c, ',' | '}' | ']' | '"' | '{' | '[') {
+                return None;
+            }
+            self.bump();
+        }
+        let name = self.input[start..self.pos].to_string();
+        if self.bump() != Some('=') || name.is_empty() {
+            return None;
+        }
+        Some((name, self.value()?))
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        match self.peek()? {
+            '"' => self.c_string().map(Value::String),
+            '{' => {
+                self.bump();
+                let map = self.results();
+                (self.bump() == Some('}')).then_some(Value::Object(map))
+            }
+            '[' => {
+                self.bump();
+                let mut items = Vec::new();
+                while self.peek() != Some(']') {
+                    // Lists hold either bare values or `name=value` results;
+                    // the names of list results are redundant and dropped
+                    let item = if matches!(self.peek()?, '"' | '{' | '[') {
+                        self.value()?
+                    } else {
+                        self.result()?.1
+                    };
+                    items.push(item);
+                    if self.peek() == Some(',') {
+                        self.bump();
+                    }
+                }
+                self.bump();
+                Some(Value::Array(items))
+            }
+            _ => None,
+        }
+    }
+
+    fn c_string(&mut self) -> Option<String> {
+        if self.bump()? != '"' {
+            return None;
+        }
+        let mut text = String::new();
+        loop {
+            match self.bump()? {
+                '"' => return Some(text),
+                '\\' => match self.bump()? {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+/// A running GDB session in MI mode
+#[derive(Debug)]
+pub struct GdbSession {
+    /// GDB process
+    child: Child,
+
+    /// GDB standard input
+    stdin: ChildStdin,
+
+    /// Records parsed from GDB's standard output
+    records: mpsc::UnboundedReceiver<Record>,
+
+    /// Asynchronous records received while waiting for command results
+    pending: VecDeque<Record>,
+
+    /// Console output collected since the last call to [`GdbSession::take_console`]
+    console: String,
+
+    /// Next command token
+    next_token: u64,
+}
+
+impl GdbSession {
+    /// Launch GDB on a program
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if GDB cannot be started
+    pub async fn launch<S: AsRef<OsStr>>(program: S, args: &[String]) -> Result<Self> {
+        let mut command = Command::new("gdb");
+        command.args(["--interpreter=mi2", "--quiet", "--nx"]);
+        if args.is_empty() {
+            command.arg(program);
+        } else {
+            command.arg("--args").arg(program).args(args);
+        }
+        Self::spawn(command).await
+    }
+
+    /// Start `gdb` and attach it to a remote target such as a GDB stub
+    ///
+    /// `program` provides the symbols of the code running on the target, and `gdb`
+    /// selects the debugger executable, e.g. `gdb-multiarch` for cross targets.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if GDB cannot be started or cannot connect to `target`
+    pub async fn attach(gdb: &str, program: Option<&Path>, target: &str) -> Result<Self> {
+        let mut command = Command::new(gdb);
+        command.args(["--interpreter=mi2", "--quiet", "--nx"]);
+        if let Some(program) = program {
+            command.arg(program);
+        }
+
+        let mut session = Self::spawn(command).await?;
+        session
+            .command(&format!("-target-select remote {}", quote(target)))
+            .await
+            .with_context(|| format!("Failed to connect to {target}"))?;
+        // The target halts on connection; that stop is not a response to a resume
+        session.pending.clear();
+        Ok(session)
+    }
+
+    async fn spawn(mut command: Command) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to start gdb")?;
+
+        let stdin = child.stdin.take().context("gdb stdin not captured")?;
+        let stdout = child.stdout.take().context("gdb stdout not captured")?;
+
+        let (tx, records) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(record) = parse_record(&line) {
+                    if tx.send(record).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut session = Self {
+            child,
+            stdin,
+            records,
+            pending: VecDeque::new(),
+            console: String::new(),
+            next_token: 1,
+        };
+        session.command("-gdb-set confirm off").await?;
+        Ok(session)
+    }
+
+    /// Run an MI command and return its result record
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if GDB exits, does not answer in time or reports `^error`
+    pub async fn command(&mut self, command: &str) -> Result<Record> {
+        self.command_with_timeout(command, DEFAULT_TIMEOUT).await
+    }
+
+    /// Run an MI command and wait at most `timeout` for its result record
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if GDB exits, does not answer in time or reports `^error`
+    pub async fn command_with_timeout(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<Record> {
+        let token = self.next_token;
+        self.next_token += 1;
+
+        debug!("gdb <- {}{}", token, command);
+        self.stdin
+            .write_all(format!("{token}{command}\n").as_bytes())
+            .await
+            .context("gdb is not running")?;
+        self.stdin.flush().await?;
+
+        let record = tokio::time::timeout(timeout, async {
+            loop {
+                let record = self.next_record().await?;
+                if record.kind == RecordKind::Result && record.token == Some(token) {
+                    return Ok::<_, anyhow::Error>(record);
+                }
+                self.keep(record);
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("gdb did not answer {} within {:?}", command, timeout))??;
+
+        if let Some(message) = record.error_message() {
+            bail!("{}", message);
+        }
+        Ok(record)
+    }
+
+    /// Wait for the inferior to stop and return the `*stopped` record's results
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if GDB exits or the inferior does not stop within `timeout`
+    pub async fn wait_for_stop(&mut self, timeout: Duration) -> Result<Value> {
+        if let Some(index) = self.pending.iter().position(is_stop) {
+            if let Some(record) = self.pending.remove(index) {
+                return Ok(record.results);
+            }
+        }
+
+        tokio::time::timeout(timeout, async {
+            loop {
+                let record = self.next_record().await?;
+                if is_stop(&record) {
+                    return Ok::<_, anyhow::Error>(record.results);
+                }
+                self.keep(record);
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Program did not stop within {:?}", timeout))?
+    }
+
+    /// Take the console and program output collected so far
+    pub fn take_console(&mut self) -> String {
+        std::mem::take(&mut self.console)
+    }
+
+    /// Quit GDB, killing the inferior
+    pub async fn quit(mut self) {
+        let _ = self
+            .command_with_timeout("-gdb-exit", Duration::from_secs(2))
+            .await;
+        let _ = self.child.kill().await;
+    }
+
+    async fn next_record(&mut self) -> Result<Record> {
+        self.records
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("gdb exited"))
+    }
+
+    fn keep(&mut self, record: Record) {
+        match record.kind {
+            RecordKind::Console | RecordKind::Target => {
+                if let Some(text) = record.results.as_str() {
+                    self.console.push_str(text);
+                }
+            }
+            RecordKind::Exec => self.pending.push_back(record),
+            _ => {}
+        }
+    }
+}
+
+fn is_stop(record: &Record) -> bool {
+    record.kind == RecordKind::Exec && record.class == "stopped"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_result_record() {
+        let record = parse_record(
+            r#"12^done,bkpt={number="1",type="breakpoint",file="main.rs",line="3",thread-groups=["i1"]}"#,
+        )
+        .unwrap();
+        assert_eq!(record.token, Some(12));
+        assert_eq!(record.kind, RecordKind::Result);
+        assert_eq!(record.class, "done");
+        assert_eq!(
+            record.results,
+            json!({"bkpt": {"number": "1", "type": "breakpoint", "file": "main.rs", "line": "3", "thread-groups": ["i1"]}})
+        );
+    }
+
+    #[test]
+    fn test_parse_list_of_results() {
+        let record = parse_record(
+            r#"^done,stack=[frame={level="0",func="inner"},frame={level="1",func="main"}]"#,
+        )
+        .unwrap();
+        assert_eq!(record.token, None);
+        assert_eq!(
+            record.results["stack"],
+            json!([{"level": "0", "func": "inner"}, {"level": "1", "func": "main"}])
+        );
+    }
+
+    #[test]
+    fn test_parse_async_and_stream_records() {
+        let stopped = parse_record(
+            r#"*stopped,reason="signal-received",signal-name="SIGSEGV",frame={addr="0x1",func="crash",args=[]}"#,
+        )
+        .unwrap();
+        assert_eq!(stopped.kind, RecordKind::Exec);
+        assert_eq!(stopped.class, "stopped");
+        assert_eq!(stopped.results["signal-name"], "SIGSEGV");
+        assert_eq!(stopped.results["frame"]["args"], json!([]));
+
+        let console = parse_record(r#"~"Hello \"world\"\n""#).unwrap();
+        assert_eq!(console.kind, RecordKind::Console);
+        assert_eq!(console.results, json!("Hello \"world\"\n"));
+
+        assert!(parse_record("(gdb) ").is_none());
+    }
+
+    #[test]
+    fn test_non_ascii_output() {
+        // The inferior shares gdb's stdout, so any program output can show up
+        assert!(parse_record("état: prêt").is_none());
+        assert!(parse_record("42€").is_none());
+
+        let console = parse_record(r#"~"état\n""#).unwrap();
+        assert_eq!(console.results, json!("état\n"));
+    }
+
+    #[test]
+    fn test_error_record() {
+        let record = parse_record(r#"3^error,msg="No symbol \"x\" in current context.""#).unwrap();
+        assert_eq!(
+            record.error_message(),
+            Some("No symbol \"x\" in current context.")
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+}
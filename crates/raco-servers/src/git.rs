@@ -0,0 +1,911 @@
+//! Git MCP server
+//!
+//! This module provides an MCP server implementation for Git operations on the project root.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use raco_mcp::protocol::{McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+use crate::{sandbox, ServerResult};
+
+/// Field separator used in `git log` output
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+/// Record separator used in `git log` output
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// Git server for handling repository operations
+#[derive(Debug)]
+pub struct GitServer {
+    /// Repository root; all paths are confined to it
+    root_dir: PathBuf,
+
+    /// Server ID
+    id: String,
+}
+
+/// Stash operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StashAction {
+    /// Stash the working tree changes
+    Push,
+
+    /// Apply and drop the latest stash
+    Pop,
+
+    /// List stashes
+    List,
+}
+
+/// Git command types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum GitCommand {
+    /// Show the working tree status
+    #[serde(rename = "status")]
+    Status,
+
+    /// Show changes
+    #[serde(rename = "diff")]
+    Diff {
+        /// Show staged instead of unstaged changes
+        #[serde(default)]
+        staged: bool,
+
+        /// Restrict the diff to one path
+        #[serde(default)]
+        path: Option<String>,
+    },
+
+    /// Show commit history
+    #[serde(rename = "log")]
+    Log {
+        /// Maximum number of commits
+        #[serde(default)]
+        max_count: Option<usize>,
+
+        /// Only commits by this author
+        #[serde(default)]
+        author: Option<String>,
+
+        /// Only commits newer than this date
+        #[serde(default)]
+        since: Option<String>,
+
+        /// Only commits whose message matches this pattern
+        #[serde(default)]
+        grep: Option<String>,
+
+        /// Only commits touching this path
+        #[serde(default)]
+        path: Option<String>,
+    },
+
+    /// Show who last changed each line of a file
+    #[serde(rename = "blame")]
+    Blame {
+        /// File to blame
+        path: String,
+
+        /// First line (1-based)
+        #[serde(default)]
+        start_line: Option<usize>,
+
+        /// Last line (inclusive)
+        #[serde(default)]
+        end_line: Option<usize>,
+    },
+
+    /// Create a branch
+    #[serde(rename = "create_branch")]
+    CreateBranch {
+        /// Branch name
+        name: String,
+
+        /// Commit to start from (defaults to `HEAD`)
+        #[serde(default)]
+        start_point: Option<String>,
+    },
+
+    /// Switch to a branch
+    #[serde(rename = "switch_branch")]
+    SwitchBranch {
+        /// Branch name
+        name: String,
+
+        /// Create the branch before switching
+        #[serde(default)]
+        create: bool,
+    },
+
+    /// Record a commit
+    #[serde(rename = "commit")]
+    Commit {
+        /// Commit message
+        message: String,
+
+        /// Paths to stage before committing
+        #[serde(default)]
+        paths: Vec<String>,
+
+        /// Stage all tracked changes before committing
+        #[serde(default)]
+        all: bool,
+    },
+
+    /// Manage stashes
+    #[serde(rename = "stash")]
+    Stash {
+        /// Stash operation
+        action: StashAction,
+
+        /// Message for `push`
+        #[serde(default)]
+        message: Option<String>,
+    },
+
+    /// Create a linked worktree
+    #[serde(rename = "worktree")]
+    Worktree {
+        /// Directory for the worktree
+        path: String,
+
+        /// Branch to check out in the worktree
+        branch: String,
+
+        /// Create the branch
+        #[serde(default)]
+        create_branch: bool,
+    },
+}
+
+/// A changed file in the working tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusEntry {
+    /// File path, relative to the repository root
+    pub path: String,
+
+    /// Status in the index (`M`, `A`, `D`, `R`, `?`, ...)
+    pub index: char,
+
+    /// Status in the working tree
+    pub worktree: char,
+}
+
+/// A commit in the history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitInfo {
+    /// Full commit hash
+    pub hash: String,
+
+    /// Author name
+    pub author: String,
+
+    /// Author email
+    pub email: String,
+
+    /// Author date (ISO 8601)
+    pub date: String,
+
+    /// First line of the commit message
+    pub subject: String,
+}
+
+/// A line of `git blame` output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameLine {
+    /// Line number in the current file
+    pub line: usize,
+
+    /// Commit that last changed the line
+    pub commit: String,
+
+    /// Author of that commit
+    pub author: String,
+
+    /// Line content
+    pub content: String,
+}
+
+/// Git response types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum GitResponse {
+    /// Status response
+    #[serde(rename = "status")]
+    Status {
+        /// Current branch, if not detached
+        branch: Option<String>,
+
+        /// Changed files
+        entries: Vec<StatusEntry>,
+    },
+
+    /// Diff response
+    #[serde(rename = "diff")]
+    Diff {
+        /// Unified diff
+        diff: String,
+    },
+
+    /// Log response
+    #[serde(rename = "log")]
+    Log {
+        /// Matching commits, newest first
+        commits: Vec<CommitInfo>,
+    },
+
+    /// Blame response
+    #[serde(rename = "blame")]
+    Blame {
+        /// Blamed lines
+        lines: Vec<BlameLine>,
+    },
+
+    /// Branch response
+    #[serde(rename = "branch")]
+    Branch {
+        /// Branch that was created or checked out
+        name: String,
+    },
+
+    /// Commit response
+    #[serde(rename = "commit")]
+    Commit {
+        /// Hash of the new commit
+        hash: String,
+    },
+
+    /// Stash response
+    #[serde(rename = "stash")]
+    Stash {
+        /// Output of the stash command
+        output: String,
+    },
+
+    /// Worktree response
+    #[serde(rename = "worktree")]
+    Worktree {
+        /// Absolute path of the new worktree
+        path: String,
+    },
+
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+impl GitServer {
+    /// Create a new Git server operating on the given repository root
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        let root_dir = root_dir.as_ref().to_path_buf();
+        info!(
+            "Creating git server with root directory: {}",
+            root_dir.display()
+        );
+        Self {
+            root_dir,
+            id: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Get the server ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Handle an MCP request
+    pub async fn handle_request(
+        &self,
+        request: McpRequest<GitCommand>,
+    ) -> ServerResult<McpResponse<GitResponse>> {
+        debug!("Handling git request: {:?}", request);
+
+        let response = match request.payload {
+            GitCommand::Status => self.handle_status().await,
+            GitCommand::Diff { staged, path } => self.handle_diff(staged, path).await,
+            GitCommand::Log {
+                max_count,
+                author,
+                since,
+                grep,
+                path,
+            } => self.handle_log(max_count, author, since, grep, path).await,
+            GitCommand::Blame {
+                path,
+                start_line,
+                end_line,
+            } => self.handle_blame(path, start_line, end_line).await,
+            GitCommand::CreateBranch { name, start_point } => {
+                self.handle_create_branch(name, start_point).await
+            }
+            GitCommand::SwitchBranch { name, create } => {
+                self.handle_switch_branch(name, create).await
+            }
+            GitCommand::Commit {
+                message,
+                paths,
+                all,
+            } => self.handle_commit(message, paths, all).await,
+            GitCommand::Stash { action, message } => self.handle_stash(action, message).await,
+            GitCommand::Worktree {
+                path,
+                branch,
+                create_branch,
+            } => self.handle_worktree(path, branch, create_branch).await,
+        };
+
+        let response = match response {
+            Ok(payload) => McpResponse {
+                command: request.command,
+                payload,
+                status: ResponseStatus::success(),
+                request_id: request.request_id,
+            },
+            Err(e) => {
+                error!("Error handling git request: {}", e);
+                McpResponse {
+                    command: request.command,
+                    payload: create_error_response(&e.to_string()),
+                    status: ResponseStatus::error(1, &e.to_string()),
+                    request_id: request.request_id,
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn handle_status(&self) -> Result<GitResponse, anyhow::Error> {
+        let output = self
+            .git(&[
+                "status",
+                "--porcelain=v1",
+                "--branch",
+                "--untracked-files=all",
+            ])
+            .await?;
+
+        let mut branch = None;
+        let mut entries = Vec::new();
+        for line in output.lines() {
+            if let Some(header) = line.strip_prefix("## ") {
+                let name = header.split("...").next().unwrap_or(header);
+                let name = name.strip_prefix("No commits yet on ").unwrap_or(name);
+                if !name.starts_with("HEAD ") {
+                    branch = Some(name.to_string());
+                }
+                continue;
+            }
+
+            let mut chars = line.chars();
+            let (Some(index), Some(worktree)) = (chars.next(), chars.next()) else {
+                continue;
+            };
+            let path = line.get(3..).unwrap_or_default();
+            // Renames are reported as `old -> new`
+            let path = path.rsplit(" -> ").next().unwrap_or(path);
+            entries.push(StatusEntry {
+                path: path.to_string(),
+                index,
+                worktree,
+            });
+        }
+
+        Ok(GitResponse::Status { branch, entries })
+    }
+
+    async fn handle_diff(
+        &self,
+        staged: bool,
+        path: Option<String>,
+    ) -> Result<GitResponse, anyhow::Error> {
+        let mut args = vec!["diff".to_string()];
+        if staged {
+            args.push("--cached".to_string());
+        }
+        args.push("--".to_string());
+        if let Some(path) = path {
+            args.push(self.repo_path(&path)?);
+        }
+
+        let diff = self.git(&args).await?;
+        Ok(GitResponse::Diff { diff })
+    }
+
+    async fn handle_log(
+        &self,
+        max_count: Option<usize>,
+        author: Option<String>,
+        since: Option<String>,
+        grep: Option<String>,
+        path: Option<String>,
+    ) -> Result<GitResponse, anyhow::Error> {
+        let mut args = vec![
+            "log".to_string(),
+            format!("--format=%H{FIELD_SEPARATOR}%an{FIELD_SEPARATOR}%ae{FIELD_SEPARATOR}%aI{FIELD_SEPARATOR}%s{RECORD_SEPARATOR}"),
+        ];
+        if let Some(max_count) = max_count {
+            args.push(format!("--max-count={max_count}"));
+        }
+        if let Some(author) = author {
+            args.push(format!("--author={author}"));
+        }
+        if let Some(since) = since {
+            args.push(format!("--since={since}"));
+        }
+        if let Some(grep) = grep {
+            args.push(format!("--grep={grep}"));
+        }
+        args.push("--".to_string());
+        if let Some(path) = path {
+            args.push(self.repo_path(&path)?);
+        }
+
+        let output = self.git(&args).await?;
+        let commits = output
+            .split(RECORD_SEPARATOR)
+            .map(str::trim)
+            .filter(|record| !record.is_empty())
+            .filter_map(|record| {
+                let mut fields = record.split(FIELD_SEPARATOR);
+                Some(CommitInfo {
+                    hash: fields.next()?.to_string(),
+                    author: fields.next()?.to_string(),
+                    email: fields.next()?.to_string(),
+                    date: fields.next()?.to_string(),
+                    subject: fields.next().unwrap_or_default().to_string(),
+                })
+            })
+            .collect();
+
+        Ok(GitResponse::Log { commits })
+    }
+
+    async fn handle_blame(
+        &self,
+        path: String,
+        start_line: Option<usize>,
+        end_line: Option<usize>,
+    ) -> Result<GitResponse, anyhow::Error> {
+        let mut args = vec!["blame".to_string(), "--line-porcelain".to_string()];
+        match (start_line, end_line) {
+            (Some(start), Some(end)) => args.push(format!("-L{start},{end}")),
+            (Some(start), None) => args.push(format!("-L{start},")),
+            (None, Some(end)) => args.push(format!("-L1,{end}")),
+            (None, None) => {}
+        }
+        args.push("--".to_string());
+        args.push(self.repo_path(&path)?);
+
+        let output = self.git(&args).await?;
+        Ok(GitResponse::Blame {
+            lines: parse_line_porcelain(&output),
+        })
+    }
+
+    async fn handle_create_branch(
+        &self,
+        name: String,
+        start_point: Option<String>,
+    ) -> Result<GitResponse, anyhow::Error> {
+        check_ref_name(&name)?;
+        let mut args = vec!["branch".to_string(), name.clone()];
+        if let Some(start_point) = start_point {
+            check_ref_name(&start_point)?;
+            args.push(start_point);
+        }
+
+        self.git(&args).await?;
+        Ok(GitResponse::Branch { name })
+    }
+
+    async fn handle_switch_branch(
+        &self,
+        name: String,
+        create: bool,
+    ) -> Result<GitResponse, anyhow::Error> {
+        check_ref_name(&name)?;
+        let mut args = vec!["switch".to_string()];
+        if create {
+            args.push("--create".to_string());
+        }
+        args.push(name.clone());
+
+        self.git(&args).await?;
+        Ok(GitResponse::Branch { name })
+    }
+
+    async fn handle_commit(
+        &self,
+        message: String,
+        paths: Vec<String>,
+        all: bool,
+    ) -> Result<GitResponse, anyhow::Error> {
+        if message.trim().is_empty() {
+            bail!("Commit message must not be empty");
+        }
+
+        if !paths.is_empty() {
+            let mut args = vec!["add".to_string(), "--".to_string()];
+            for path in &paths {
+                args.push(self.repo_path(path)?);
+            }
+            self.git(&args).await?;
+        }
+
+        let mut args = vec!["commit".to_string(), "--message".to_string(), message];
+        if all {
+            args.push("--all".to_string());
+        }
+        self.git(&args).await?;
+
+        let hash = self.git(&["rev-parse", "HEAD"]).await?;
+        Ok(GitResponse::Commit {
+            hash: hash.trim().to_string(),
+        })
+    }
+
+    async fn handle_stash(
+        &self,
+        action: StashAction,
+        message: Option<String>,
+    ) -> Result<GitResponse, anyhow::Error> {
+        let mut args = vec!["stash".to_string()];
+        match action {
+            StashAction::Push => {
+                args.push("push".to_string());
+                if let Some(message) = message {
+                    args.push("--message".to_string());
+                    args.push(message);
+                }
+            }
+            StashAction::Pop => args.push("pop".to_string()),
+            StashAction::List => args.push("list".to_string()),
+        }
+
+        let output = self.git(&args).await?;
+        Ok(GitResponse::Stash { output })
+    }
+
+    async fn handle_worktree(
+        &self,
+        path: String,
+        branch: String,
+        create_branch: bool,
+    ) -> Result<GitResponse, anyhow::Error> {
+        check_ref_name(&branch)?;
+        let worktree = sandbox::resolve_path(&self.root_dir, &path)?;
+        let worktree_arg = worktree.to_string_lossy().to_string();
+
+        let mut args = vec!["worktree".to_string(), "add".to_string()];
+        if create_branch {
+            args.extend(["-b".to_string(), branch, worktree_arg]);
+        } else {
+            args.extend([worktree_arg, branch]);
+        }
+
+        self.git(&args).await?;
+        Ok(GitResponse::Worktree {
+            path: worktree.to_string_lossy().to_string(),
+        })
+    }
+
+    /// Confine a path to the repository root and return it relative to the root
+    fn repo_path(&self, path: &str) -> Result<String, anyhow::Error> {
+        let relative = sandbox::relative_path(&self.root_dir, path)?;
+        if relative.as_os_str().is_empty() {
+            return Ok(".".to_string());
+        }
+        Ok(relative.to_string_lossy().to_string())
+    }
+
+    /// Run git in the repository root and return its standard output
+    async fn git<S: AsRef<str>>(&self, args: &[S]) -> Result<String, anyhow::Error> {
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        debug!("Running git {}", args.join(" "));
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.root_dir)
+            .args(&args)
+            .output()
+            .await
+            .context("Failed to run git")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.first().copied().unwrap_or_default(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// Reject reference names that git would interpret as options
+fn check_ref_name(name: &str) -> Result<(), anyhow::Error> {
+    if name.is_empty() || name.starts_with('-') {
+        bail!("Invalid reference name: {name}");
+    }
+    Ok(())
+}
+
+/// Parse `git blame --line-porcelain` output
+fn parse_line_porcelain(output: &str) -> Vec<BlameLine> {
+    let mut lines = Vec::new();
+    let mut commit = String::new();
+    let mut line = 0;
+    let mut author = String::new();
+
+    for row in output.lines() {
+        if let Some(content) = row.strip_prefix('\t') {
+            lines.push(BlameLine {
+                line,
+                commit: commit.clone(),
+                author: author.clone(),
+                content: content.to_string(),
+            });
+        } else if let Some(name) = row.strip_prefix("author ") {
+            author = name.to_string();
+        } else {
+            let mut fields = row.split(' ');
+            let hash = fields.next().unwrap_or_default();
+            if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                commit = hash.to_string();
+                line = fields.nth(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+            }
+        }
+    }
+
+    lines
+}
+
+// Helper function to create an error response for the appropriate command type
+fn create_error_response(error: &str) -> GitResponse {
+    GitResponse::Error {
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a throwaway repository with one commit
+    async fn create_test_repo() -> (tempfile::TempDir, GitServer) {
+        let dir = tempfile::tempdir().unwrap();
+        let server = GitServer::new(dir.path());
+        server
+            .git(&["init", "--initial-branch=main"])
+            .await
+            .unwrap();
+        server.git(&["config", "user.name", "Test"]).await.unwrap();
+        server
+            .git(&["config", "user.email", "test@example.com"])
+            .await
+            .unwrap();
+
+        std::fs::write(dir.path().join("README.md"), "hello\n").unwrap();
+        let response = request(
+            &server,
+            GitCommand::Commit {
+                message: "Initial commit".to_string(),
+                paths: vec!["README.md".to_string()],
+                all: false,
+            },
+        )
+        .await;
+        assert!(matches!(response, GitResponse::Commit { .. }));
+
+        (dir, server)
+    }
+
+    async fn request(server: &GitServer, command: GitCommand) -> GitResponse {
+        let response = server
+            .handle_request(McpRequest::new("git", command))
+            .await
+            .unwrap();
+        response.payload
+    }
+
+    #[test]
+    fn test_server_creation() {
+        let server = GitServer::new(".");
+        assert!(!server.id().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status_and_diff() {
+        let (dir, server) = create_test_repo().await;
+        std::fs::write(dir.path().join("README.md"), "hello\nworld\n").unwrap();
+        std::fs::write(dir.path().join("new.txt"), "new\n").unwrap();
+
+        let GitResponse::Status { branch, entries } = request(&server, GitCommand::Status).await
+        else {
+            panic!("Expected Status response");
+        };
+        assert_eq!(branch.as_deref(), Some("main"));
+        assert!(entries.contains(&StatusEntry {
+            path: "README.md".to_string(),
+            index: ' ',
+            worktree: 'M',
+        }));
+        assert!(entries
+            .iter()
+            .any(|e| e.path == "new.txt" && e.index == '?'));
+
+        let GitResponse::Diff { diff } = request(
+            &server,
+            GitCommand::Diff {
+                staged: false,
+                path: Some("README.md".to_string()),
+            },
+        )
+        .await
+        else {
+            panic!("Expected Diff response");
+        };
+        assert!(diff.contains("+world"));
+
+        let GitResponse::Diff { diff } = request(
+            &server,
+            GitCommand::Diff {
+                staged: true,
+                path: None,
+            },
+        )
+        .await
+        else {
+            panic!("Expected Diff response");
+        };
+        assert!(diff.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_commit_log_and_blame() {
+        let (dir, server) = create_test_repo().await;
+        std::fs::write(dir.path().join("README.md"), "hello\nworld\n").unwrap();
+
+        let response = request(
+            &server,
+            GitCommand::Commit {
+                message: "Add world".to_string(),
+                paths: vec![],
+                all: true,
+            },
+        )
+        .await;
+        let GitResponse::Commit { hash } = response else {
+            panic!("Expected Commit response");
+        };
+
+        let GitResponse::Log { commits } = request(
+            &server,
+            GitCommand::Log {
+                max_count: Some(5),
+                author: None,
+                since: None,
+                grep: Some("world".to_string()),
+                path: None,
+            },
+        )
+        .await
+        else {
+            panic!("Expected Log response");
+        };
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].hash, hash);
+        assert_eq!(commits[0].subject, "Add world");
+        assert_eq!(commits[0].author, "Test");
+
+        let GitResponse::Blame { lines } = request(
+            &server,
+            GitCommand::Blame {
+                path: "README.md".to_string(),
+                start_line: Some(2),
+                end_line: None,
+            },
+        )
+        .await
+        else {
+            panic!("Expected Blame response");
+        };
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line, 2);
+        assert_eq!(lines[0].content, "world");
+        assert_eq!(lines[0].commit, hash);
+    }
+
+    #[tokio::test]
+    async fn test_branches_stash_and_worktree() {
+        let (dir, server) = create_test_repo().await;
+
+        let response = request(
+            &server,
+            GitCommand::SwitchBranch {
+                name: "feature".to_string(),
+                create: true,
+            },
+        )
+        .await;
+        assert!(matches!(response, GitResponse::Branch { .. }));
+        let GitResponse::Status { branch, .. } = request(&server, GitCommand::Status).await else {
+            panic!("Expected Status response");
+        };
+        assert_eq!(branch.as_deref(), Some("feature"));
+
+        std::fs::write(dir.path().join("README.md"), "changed\n").unwrap();
+        request(
+            &server,
+            GitCommand::Stash {
+                action: StashAction::Push,
+                message: Some("wip".to_string()),
+            },
+        )
+        .await;
+        let GitResponse::Stash { output } = request(
+            &server,
+            GitCommand::Stash {
+                action: StashAction::List,
+                message: None,
+            },
+        )
+        .await
+        else {
+            panic!("Expected Stash response");
+        };
+        assert!(output.contains("wip"));
+
+        let response = request(
+            &server,
+            GitCommand::Worktree {
+                path: "trees/review".to_string(),
+                branch: "review".to_string(),
+                create_branch: true,
+            },
+        )
+        .await;
+        let GitResponse::Worktree { path } = response else {
+            panic!("Expected Worktree response");
+        };
+        assert!(Path::new(&path).join("README.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_and_option_injection() {
+        let (_dir, server) = create_test_repo().await;
+
+        let response = request(
+            &server,
+            GitCommand::Blame {
+                path: "../../etc/passwd".to_string(),
+                start_line: None,
+                end_line: None,
+            },
+        )
+        .await;
+        assert!(matches!(response, GitResponse::Error { .. }));
+
+        let response = request(
+            &server,
+            GitCommand::CreateBranch {
+                name: "--delete".to_string(),
+                start_point: None,
+            },
+        )
+        .await;
+        assert!(matches!(response, GitResponse::Error { .. }));
+    }
+}
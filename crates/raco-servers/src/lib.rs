@@ -2,9 +2,16 @@
 //!
 //! This crate provides implementations of various MCP servers for RACO.
 
+pub mod debug;
 pub mod filesystem;
+pub mod gdb;
+pub mod git;
 pub mod process;
-pub mod registry;
+pub mod qemu;
+pub mod rust;
+pub mod sandbox;
+pub mod stdio;
+pub mod system;
 
 use raco_core::error::CoreError;
 use thiserror::Error;
@@ -3,6 +3,7 @@
 //! This module provides an MCP server implementation for process management.
 
 use raco_mcp::protocol::{McpRequest, McpResponse, ProcessInfo, ResponseStatus};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tracing::{debug, error, info};
@@ -45,7 +46,7 @@ struct ProcessHandle {
 }
 
 /// Process command types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum ProcessCommand {
     /// Start a new process
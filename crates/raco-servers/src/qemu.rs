@@ -0,0 +1,977 @@
+//! QEMU MCP server
+//!
+//! This module provides an MCP server implementation for running Zephyr images in QEMU.
+//! Images boot with the GDB stub enabled so the debug server can attach to them.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use raco_mcp::protocol::{McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, error, info};
+
+use crate::{sandbox, ServerResult};
+
+/// Default port of the GDB stub
+pub const DEFAULT_GDB_PORT: u16 = 1234;
+
+/// Default time to wait for console output
+const DEFAULT_CONSOLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between checks of the console while waiting for output
+const CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// QEMU settings of a Zephyr `qemu_*` board
+#[derive(Debug, Clone, Copy)]
+struct BoardPreset {
+    /// Zephyr board name
+    board: &'static str,
+
+    /// Architecture suffix of the `qemu-system-*` binary
+    arch: &'static str,
+
+    /// Machine model
+    machine: &'static str,
+
+    /// CPU model
+    cpu: Option<&'static str>,
+
+    /// Additional arguments
+    args: &'static [&'static str],
+}
+
+/// Presets for the Zephyr QEMU boards
+const BOARDS: &[BoardPreset] = &[
+    BoardPreset {
+        board: "qemu_cortex_m3",
+        arch: "arm",
+        machine: "lm3s6965evb",
+        cpu: Some("cortex-m3"),
+        args: &[],
+    },
+    BoardPreset {
+        board: "qemu_cortex_a53",
+        arch: "aarch64",
+        machine: "virt",
+        cpu: Some("cortex-a53"),
+        args: &[],
+    },
+    BoardPreset {
+        board: "qemu_x86",
+        arch: "i386",
+        machine: "q35",
+        cpu: Some("qemu32,+nx,+pae"),
+        args: &[],
+    },
+    BoardPreset {
+        board: "qemu_x86_64",
+        arch: "x86_64",
+        machine: "q35",
+        cpu: Some("qemu64,+x2apic"),
+        args: &[],
+    },
+    BoardPreset {
+        board: "qemu_riscv32",
+        arch: "riscv32",
+        machine: "virt",
+        cpu: None,
+        args: &["-bios", "none"],
+    },
+    BoardPreset {
+        board: "qemu_riscv64",
+        arch: "riscv64",
+        machine: "virt",
+        cpu: None,
+        args: &["-bios", "none"],
+    },
+];
+
+/// QEMU server for handling emulated targets
+#[derive(Debug)]
+pub struct QemuServer {
+    /// Project root; images are confined to it
+    root_dir: PathBuf,
+
+    /// QEMU executable replacing `qemu-system-<arch>`
+    qemu: Option<PathBuf>,
+
+    /// Server ID
+    id: String,
+
+    /// Running emulator
+    instance: Mutex<Option<QemuInstance>>,
+}
+
+/// A running emulator
+#[derive(Debug)]
+struct QemuInstance {
+    /// QEMU process
+    child: Child,
+
+    /// GDB stub port
+    gdb_port: u16,
+
+    /// Captured console
+    console: Arc<Console>,
+}
+
+/// Output of the emulated UART
+#[derive(Debug)]
+pub struct Console {
+    /// Everything printed so far
+    text: std::sync::Mutex<String>,
+
+    /// Messages of QEMU itself, printed on its stderr
+    diagnostics: std::sync::Mutex<String>,
+
+    /// Chunks as they are printed
+    chunks: broadcast::Sender<String>,
+
+    /// Set when the emulator closed both its output streams
+    finished: AtomicBool,
+}
+
+impl Console {
+    fn new() -> Self {
+        let (chunks, _) = broadcast::channel(256);
+        Self {
+            text: std::sync::Mutex::new(String::new()),
+            diagnostics: std::sync::Mutex::new(String::new()),
+            chunks,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Output from byte `offset` on, and the offset of its end
+    pub fn read(&self, offset: usize) -> (String, usize) {
+        let text = lock(&self.text);
+        let start = (offset..=text.len())
+            .find(|&i| text.is_char_boundary(i))
+            .unwrap_or(text.len());
+        (text[start..].to_string(), text.len())
+    }
+
+    /// Messages QEMU printed on its stderr, e.g. why it failed to start
+    pub fn diagnostics(&self) -> String {
+        lock(&self.diagnostics).clone()
+    }
+
+    /// Subscribe to output printed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.chunks.subscribe()
+    }
+
+    /// Whether the emulator closed its output
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    fn push(&self, chunk: String) {
+        lock(&self.text).push_str(&chunk);
+        let _ = self.chunks.send(chunk);
+    }
+
+    /// Copy the UART output and QEMU's diagnostics into the console until both
+    /// streams end
+    fn capture<O, E>(self: &Arc<Self>, stdout: O, stderr: E)
+    where
+        O: AsyncRead + Unpin + Send + 'static,
+        E: AsyncRead + Unpin + Send + 'static,
+    {
+        let console = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::join!(
+                read_text(stdout, |chunk| console.push(chunk)),
+                read_text(stderr, |chunk| lock(&console.diagnostics).push_str(&chunk)),
+            );
+            console.finished.store(true, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Read a stream as text until it ends, passing each decoded chunk to `sink`
+async fn read_text<R: AsyncRead + Unpin>(mut stream: R, mut sink: impl FnMut(String)) {
+    let mut decoder = Utf8Decoder::default();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let chunk = decoder.decode(&buf[..n]);
+                if !chunk.is_empty() {
+                    sink(chunk);
+                }
+            }
+        }
+    }
+    let rest = decoder.finish();
+    if !rest.is_empty() {
+        sink(rest);
+    }
+}
+
+/// Decoder of UTF-8 text arriving in chunks
+///
+/// A character split across two chunks is held back until its remaining bytes
+/// arrive; invalid bytes become U+FFFD.
+#[derive(Debug, Default)]
+struct Utf8Decoder {
+    /// Bytes of an incomplete character at the end of the last chunk
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let mut text = String::new();
+        let mut rest = self.pending.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        // The chunk ends inside a character
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        text
+    }
+
+    /// Decode what is left once the stream ended
+    fn finish(self) -> String {
+        String::from_utf8_lossy(&self.pending).into_owned()
+    }
+}
+
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// How to boot an image
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BootOptions {
+    /// Zephyr board, e.g. `qemu_cortex_m3`, selecting architecture, machine and CPU
+    #[serde(default)]
+    pub board: Option<String>,
+
+    /// Architecture suffix of the `qemu-system-*` binary, overriding the board
+    #[serde(default)]
+    pub arch: Option<String>,
+
+    /// Machine model, overriding the board
+    #[serde(default)]
+    pub machine: Option<String>,
+
+    /// CPU model, overriding the board
+    #[serde(default)]
+    pub cpu: Option<String>,
+
+    /// GDB stub port (defaults to 1234)
+    #[serde(default)]
+    pub gdb_port: Option<u16>,
+
+    /// Halt the CPU until a debugger continues it
+    #[serde(default)]
+    pub wait_for_debugger: bool,
+
+    /// Additional QEMU arguments, limited to `-m`, `-smp`, `-icount`, `-rtc`, `-d`,
+    /// `-no-reboot` and `-no-shutdown`
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+}
+
+impl BootOptions {
+    /// QEMU executable and arguments for booting `image`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the board is unknown, no machine is selected, the
+    /// architecture is not a plain name or an extra argument is not allowed
+    pub fn command_line(&self, image: &Path) -> Result<(String, Vec<String>), anyhow::Error> {
+        let preset = match &self.board {
+            Some(board) => Some(
+                BOARDS
+                    .iter()
+                    .find(|preset| preset.board == board)
+                    .ok_or_else(|| anyhow!("Unknown board: {}", board))?,
+            ),
+            None => None,
+        };
+
+        let arch = self
+            .arch
+            .as_deref()
+            .or(preset.map(|p| p.arch))
+            .unwrap_or("arm");
+        if arch.is_empty() || !arch.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid architecture: {}", arch);
+        }
+        let qemu = format!("qemu-system-{arch}");
+        let machine = self
+            .machine
+            .as_deref()
+            .or(preset.map(|p| p.machine))
+            .context("Select a board or a machine")?;
+
+        let mut args = vec!["-machine".to_string(), machine.to_string()];
+        if let Some(cpu) = self.cpu.as_deref().or(preset.and_then(|p| p.cpu)) {
+            args.extend(["-cpu".to_string(), cpu.to_string()]);
+        }
+        args.extend(
+            ["-nographic", "-monitor", "none", "-serial", "stdio"]
+                .into_iter()
+                .map(String::from),
+        );
+        if let Some(preset) = preset {
+            args.extend(preset.args.iter().map(|arg| arg.to_string()));
+        }
+        args.extend([
+            "-gdb".to_string(),
+            format!("tcp::{}", self.gdb_port.unwrap_or(DEFAULT_GDB_PORT)),
+        ]);
+        if self.wait_for_debugger {
+            args.push("-S".to_string());
+        }
+        args.extend(["-kernel".to_string(), image.display().to_string()]);
+        check_extra_args(&self.extra_args)?;
+        args.extend(self.extra_args.iter().cloned());
+
+        Ok((qemu, args))
+    }
+}
+
+/// QEMU options a boot may add, and whether they take a value
+///
+/// Options naming files, sockets or programs are left out: they would reach
+/// outside the project root.
+pub const EXTRA_OPTIONS: &[(&str, bool)] = &[
+    ("-m", true),
+    ("-smp", true),
+    ("-icount", true),
+    ("-rtc", true),
+    ("-d", true),
+    ("-no-reboot", false),
+    ("-no-shutdown", false),
+];
+
+fn check_extra_args(args: &[String]) -> Result<(), anyhow::Error> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (_, takes_value) = EXTRA_OPTIONS
+            .iter()
+            .find(|(option, _)| option == arg)
+            .ok_or_else(|| anyhow!("QEMU option not allowed: {}", arg))?;
+        if *takes_value && args.next().is_none() {
+            bail!("QEMU option {} needs a value", arg);
+        }
+    }
+    Ok(())
+}
+
+/// QEMU command types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum QemuCommand {
+    /// Boot an image, replacing the running emulator
+    #[serde(rename = "boot")]
+    Boot {
+        /// Path of the image, e.g. `build/zephyr/zephyr.elf`
+        image: String,
+
+        /// Boot options
+        #[serde(default, flatten)]
+        options: BootOptions,
+    },
+
+    /// Read the console output
+    #[serde(rename = "read_console")]
+    ReadConsole {
+        /// Byte offset to read from, as returned by the previous read
+        #[serde(default)]
+        offset: usize,
+
+        /// Wait until the output from `offset` on contains this text
+        #[serde(default)]
+        wait_for: Option<String>,
+
+        /// Seconds to wait (defaults to 10)
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+    },
+
+    /// Show whether the emulator is running
+    #[serde(rename = "status")]
+    Status,
+
+    /// Stop the emulator
+    #[serde(rename = "stop")]
+    Stop,
+}
+
+/// QEMU response types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum QemuResponse {
+    /// Boot response
+    #[serde(rename = "booted")]
+    Booted {
+        /// QEMU process ID
+        pid: Option<u32>,
+
+        /// Target for the debug server's `attach`, e.g. `localhost:1234`
+        gdb_target: String,
+
+        /// Command line QEMU was started with
+        command: Vec<String>,
+    },
+
+    /// Console response
+    #[serde(rename = "console")]
+    Console {
+        /// Output from the requested offset on
+        output: String,
+
+        /// Offset to continue reading from
+        offset: usize,
+
+        /// Whether the emulator is still running
+        running: bool,
+    },
+
+    /// Status response
+    #[serde(rename = "status")]
+    Status {
+        /// Whether the emulator is running
+        running: bool,
+
+        /// Exit code, if the emulator exited
+        exit_code: Option<i32>,
+
+        /// Target for the debug server's `attach`, if an emulator was booted
+        gdb_target: Option<String>,
+
+        /// Messages QEMU printed on its stderr
+        #[serde(default)]
+        diagnostics: String,
+    },
+
+    /// Stop response
+    #[serde(rename = "stopped")]
+    Stopped {
+        /// Exit code, if the emulator exited on its own
+        exit_code: Option<i32>,
+    },
+
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+impl QemuServer {
+    /// Create a new QEMU server for images under the given root directory
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        let root_dir = root_dir.as_ref().to_path_buf();
+        info!(
+            "Creating QEMU server with root directory: {}",
+            root_dir.display()
+        );
+        Self {
+            root_dir,
+            qemu: None,
+            id: uuid::Uuid::new_v4().to_string(),
+            instance: Mutex::new(None),
+        }
+    }
+
+    /// Run the given executable instead of `qemu-system-<arch>`
+    pub fn with_qemu<P: Into<PathBuf>>(mut self, qemu: P) -> Self {
+        self.qemu = Some(qemu.into());
+        self
+    }
+
+    /// Get the server ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Console of the running emulator
+    pub async fn console(&self) -> Option<Arc<Console>> {
+        self.instance
+            .lock()
+            .await
+            .as_ref()
+            .map(|instance| Arc::clone(&instance.console))
+    }
+
+    /// Handle an MCP request
+    pub async fn handle_request(
+        &self,
+        request: McpRequest<QemuCommand>,
+    ) -> ServerResult<McpResponse<QemuResponse>> {
+        debug!("Handling QEMU request: {:?}", request);
+
+        let response = match request.payload {
+            QemuCommand::Boot { image, options } => self.handle_boot(image, options).await,
+            QemuCommand::ReadConsole {
+                offset,
+                wait_for,
+                timeout_secs,
+            } => {
+                self.handle_read_console(offset, wait_for, timeout_secs)
+                    .await
+            }
+            QemuCommand::Status => self.handle_status().await,
+            QemuCommand::Stop => self.handle_stop().await,
+        };
+
+        let response = match response {
+            Ok(payload) => McpResponse {
+                command: request.command,
+                payload,
+                status: ResponseStatus::success(),
+                request_id: request.request_id,
+            },
+            Err(e) => {
+                error!("Error handling QEMU request: {}", e);
+                McpResponse {
+                    command: request.command,
+                    payload: create_error_response(&e.to_string()),
+                    status: ResponseStatus::error(1, &e.to_string()),
+                    request_id: request.request_id,
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn handle_boot(
+        &self,
+        image: String,
+        options: BootOptions,
+    ) -> Result<QemuResponse, anyhow::Error> {
+        let path = sandbox::resolve_path(&self.root_dir, &image)?;
+        if !path.is_file() {
+            bail!("Image not found: {}", image);
+        }
+        let (qemu, args) = options.command_line(&path)?;
+        let qemu = self
+            .qemu
+            .as_ref()
+            .map_or(qemu, |program| program.display().to_string());
+
+        let mut instance = self.instance.lock().await;
+        if let Some(mut previous) = instance.take() {
+            let _ = previous.child.kill().await;
+        }
+
+        info!("Booting {} with {} {:?}", image, qemu, args);
+        let mut child = Command::new(&qemu)
+            .args(&args)
+            .current_dir(&self.root_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {qemu}"))?;
+
+        let console = Arc::new(Console::new());
+        if let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) {
+            console.capture(stdout, stderr);
+        }
+
+        let gdb_port = options.gdb_port.unwrap_or(DEFAULT_GDB_PORT);
+        let pid = child.id();
+        *instance = Some(QemuInstance {
+            child,
+            gdb_port,
+            console,
+        });
+
+        let mut command = vec![qemu];
+        command.extend(args);
+        Ok(QemuResponse::Booted {
+            pid,
+            gdb_target: gdb_target(gdb_port),
+            command,
+        })
+    }
+
+    async fn handle_read_console(
+        &self,
+        offset: usize,
+        wait_for: Option<String>,
+        timeout_secs: Option<u64>,
+    ) -> Result<QemuResponse, anyhow::Error> {
+        let console = self
+            .console()
+            .await
+            .ok_or_else(|| anyhow!("No emulator has been booted"))?;
+
+        if let Some(pattern) = &wait_for {
+            let timeout = timeout_secs.map_or(DEFAULT_CONSOLE_TIMEOUT, Duration::from_secs);
+            let found = tokio::time::timeout(timeout, async {
+                loop {
+                    if console.read(offset).0.contains(pattern.as_str()) {
+                        return true;
+                    }
+                    if console.is_finished() {
+                        return false;
+                    }
+                    tokio::time::sleep(CONSOLE_POLL_INTERVAL).await;
+                }
+            })
+            .await;
+
+            match found {
+                Ok(true) => {}
+                Ok(false) => bail!(
+                    "Emulator exited before printing {:?}: {}",
+                    pattern,
+                    console.diagnostics().trim()
+                ),
+                Err(_) => bail!("{:?} did not appear within {:?}", pattern, timeout),
+            }
+        }
+
+        let (output, offset) = console.read(offset);
+        Ok(QemuResponse::Console {
+            output,
+            offset,
+            running: !console.is_finished(),
+        })
+    }
+
+    async fn handle_status(&self) -> Result<QemuResponse, anyhow::Error> {
+        let mut instance = self.instance.lock().await;
+        let Some(instance) = instance.as_mut() else {
+            return Ok(QemuResponse::Status {
+                running: false,
+                exit_code: None,
+                gdb_target: None,
+                diagnostics: String::new(),
+            });
+        };
+
+        let status = instance.child.try_wait()?;
+        Ok(QemuResponse::Status {
+            running: status.is_none(),
+            exit_code: status.and_then(|status| status.code()),
+            gdb_target: Some(gdb_target(instance.gdb_port)),
+            diagnostics: instance.console.diagnostics(),
+        })
+    }
+
+    async fn handle_stop(&self) -> Result<QemuResponse, anyhow::Error> {
+        let mut instance = self
+            .instance
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| anyhow!("No emulator has been booted"))?;
+
+        let exit_code = match instance.child.try_wait()? {
+            Some(status) => status.code(),
+            None => {
+                instance.child.kill().await?;
+                None
+            }
+        };
+        Ok(QemuResponse::Stopped { exit_code })
+    }
+}
+
+fn gdb_target(port: u16) -> String {
+    format!("localhost:{port}")
+}
+
+// Helper function to create an error response for the appropriate command type
+fn create_error_response(error: &str) -> QemuResponse {
+    QemuResponse::Error {
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::AsyncWriteExt;
+
+    /// Stand-in for QEMU that prints a Zephyr boot banner and its arguments, and a
+    /// warning of its own
+    const FAKE_QEMU: &str = r#"#!/bin/sh
+echo "*** Booting Zephyr OS build v3.6.0 ***"
+echo "args: $*"
+echo "qemu: warning: fake emulator" >&2
+sleep 30
+"#;
+
+    async fn request(server: &QemuServer, command: QemuCommand) -> QemuResponse {
+        let response = server
+            .handle_request(McpRequest::new("qemu", command))
+            .await
+            .unwrap();
+        response.payload
+    }
+
+    #[test]
+    fn test_server_creation() {
+        let server = QemuServer::new(".");
+        assert!(!server.id().is_empty());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let options = BootOptions {
+            board: Some("qemu_cortex_m3".to_string()),
+            wait_for_debugger: true,
+            ..Default::default()
+        };
+        let (qemu, args) = options
+            .command_line(Path::new("/project/zephyr.elf"))
+            .unwrap();
+        assert_eq!(qemu, "qemu-system-arm");
+        assert_eq!(
+            args,
+            [
+                "-machine",
+                "lm3s6965evb",
+                "-cpu",
+                "cortex-m3",
+                "-nographic",
+                "-monitor",
+                "none",
+                "-serial",
+                "stdio",
+                "-gdb",
+                "tcp::1234",
+                "-S",
+                "-kernel",
+                "/project/zephyr.elf"
+            ]
+        );
+
+        let options = BootOptions {
+            board: Some("qemu_riscv32".to_string()),
+            gdb_port: Some(4321),
+            machine: Some("sifive_e".to_string()),
+            ..Default::default()
+        };
+        let (qemu, args) = options.command_line(Path::new("zephyr.elf")).unwrap();
+        assert_eq!(qemu, "qemu-system-riscv32");
+        assert!(args.windows(2).any(|w| w == ["-machine", "sifive_e"]));
+        assert!(args.windows(2).any(|w| w == ["-bios", "none"]));
+        assert!(args.windows(2).any(|w| w == ["-gdb", "tcp::4321"]));
+        assert!(!args.contains(&"-S".to_string()));
+
+        let unknown = BootOptions {
+            board: Some("nrf52840dk".to_string()),
+            ..Default::default()
+        };
+        assert!(unknown.command_line(Path::new("zephyr.elf")).is_err());
+        assert!(BootOptions::default()
+            .command_line(Path::new("zephyr.elf"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_command_line_is_restricted() {
+        let image = Path::new("zephyr.elf");
+        let options = |arch: &str, extra_args: &[&str]| BootOptions {
+            arch: Some(arch.to_string()),
+            machine: Some("virt".to_string()),
+            extra_args: extra_args.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        };
+
+        let (qemu, args) = options("aarch64", &["-m", "64M", "-no-reboot"])
+            .command_line(image)
+            .unwrap();
+        assert_eq!(qemu, "qemu-system-aarch64");
+        assert!(args.ends_with(&[
+            "-m".to_string(),
+            "64M".to_string(),
+            "-no-reboot".to_string()
+        ]));
+
+        assert!(options("../../bin/sh", &[]).command_line(image).is_err());
+        assert!(options("", &[]).command_line(image).is_err());
+        assert!(options("arm", &["-drive", "file=/etc/passwd"])
+            .command_line(image)
+            .is_err());
+        assert!(options("arm", &["-m"]).command_line(image).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_boot_and_console() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("zephyr.elf"), b"\x7fELF").unwrap();
+        let fake_qemu = dir.path().join("qemu");
+        std::fs::write(&fake_qemu, FAKE_QEMU).unwrap();
+
+        std::fs::set_permissions(&fake_qemu, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let server = QemuServer::new(dir.path()).with_qemu(&fake_qemu);
+        let response = request(
+            &server,
+            QemuCommand::Boot {
+                image: "zephyr.elf".to_string(),
+                options: BootOptions {
+                    machine: Some("lm3s6965evb".to_string()),
+                    ..Default::default()
+                },
+            },
+        )
+        .await;
+        let QemuResponse::Booted { gdb_target, .. } = response else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert_eq!(gdb_target, "localhost:1234");
+
+        let response = request(
+            &server,
+            QemuCommand::ReadConsole {
+                offset: 0,
+                wait_for: Some("args:".to_string()),
+                timeout_secs: Some(5),
+            },
+        )
+        .await;
+        let QemuResponse::Console {
+            output,
+            offset,
+            running,
+        } = response
+        else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert!(output.contains("*** Booting Zephyr OS"));
+        assert!(output.contains("-kernel"));
+        assert!(running);
+
+        let response = request(
+            &server,
+            QemuCommand::ReadConsole {
+                offset,
+                wait_for: None,
+                timeout_secs: None,
+            },
+        )
+        .await;
+        assert!(matches!(response, QemuResponse::Console { ref output, .. } if output.is_empty()));
+
+        let response = request(&server, QemuCommand::Status).await;
+        let QemuResponse::Status {
+            running,
+            diagnostics,
+            ..
+        } = response
+        else {
+            panic!("unexpected response: {response:?}");
+        };
+        assert!(running);
+        assert_eq!(diagnostics, "qemu: warning: fake emulator\n");
+        assert!(!server
+            .console()
+            .await
+            .unwrap()
+            .read(0)
+            .0
+            .contains("warning"));
+
+        let response = request(&server, QemuCommand::Stop).await;
+        assert!(matches!(response, QemuResponse::Stopped { .. }));
+        let response = request(&server, QemuCommand::Status).await;
+        assert!(matches!(
+            response,
+            QemuResponse::Status { running: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_utf8_decoder() {
+        let text = "Zephyr ✓ boot";
+        let bytes = text.as_bytes();
+        let split = text.find('✓').unwrap() + 1;
+
+        let mut decoder = Utf8Decoder::default();
+        let first = decoder.decode(&bytes[..split]);
+        assert_eq!(first, "Zephyr ");
+        assert_eq!(first + &decoder.decode(&bytes[split..]), text);
+
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"a\xffb\xe2\x9c"), "a\u{fffd}b");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+    }
+
+    #[tokio::test]
+    async fn test_console_finishes_after_both_streams() {
+        let console = Arc::new(Console::new());
+        let (stdout, mut uart) = tokio::io::duplex(64);
+        let (stderr, qemu) = tokio::io::duplex(64);
+        console.capture(stdout, stderr);
+
+        drop(qemu);
+        uart.write_all("ready ✓".as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!console.is_finished());
+
+        drop(uart);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !console.is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(console.read(0).0, "ready ✓");
+        assert!(console.diagnostics().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_image_outside_root_is_rejected() {
+        let server = QemuServer::new("/project");
+        let response = request(
+            &server,
+            QemuCommand::Boot {
+                image: "../zephyr.elf".to_string(),
+                options: BootOptions::default(),
+            },
+        )
+        .await;
+        assert!(matches!(response, QemuResponse::Error { .. }));
+    }
+}
@@ -0,0 +1,811 @@
+//! Rust toolchain MCP server
+//!
+//! This module provides an MCP server that runs cargo on the project root and turns
+//! its `--message-format=json` output into structured diagnostics and test results.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use raco_mcp::protocol::{McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+use crate::ServerResult;
+
+/// Rust server for running cargo on a project
+#[derive(Debug)]
+pub struct RustServer {
+    /// Project root containing `Cargo.toml`
+    root_dir: PathBuf,
+
+    /// Server ID
+    id: String,
+}
+
+/// Options shared by the cargo commands
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CargoOptions {
+    /// Package to operate on (defaults to the whole workspace)
+    #[serde(default)]
+    pub package: Option<String>,
+
+    /// Include tests, examples and benches
+    #[serde(default)]
+    pub all_targets: bool,
+
+    /// Features to enable
+    #[serde(default)]
+    pub features: Vec<String>,
+
+    /// Build in release mode
+    #[serde(default)]
+    pub release: bool,
+}
+
+/// Rust command types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum RustCommand {
+    /// Run `cargo check`
+    #[serde(rename = "check")]
+    Check {
+        /// Cargo options
+        #[serde(default, flatten)]
+        options: CargoOptions,
+    },
+
+    /// Run `cargo build`
+    #[serde(rename = "build")]
+    Build {
+        /// Cargo options
+        #[serde(default, flatten)]
+        options: CargoOptions,
+    },
+
+    /// Run `cargo test`
+    #[serde(rename = "test")]
+    Test {
+        /// Cargo options
+        #[serde(default, flatten)]
+        options: CargoOptions,
+
+        /// Only run tests whose name contains this string
+        #[serde(default)]
+        filter: Option<String>,
+    },
+
+    /// Run `cargo clippy`
+    #[serde(rename = "clippy")]
+    Clippy {
+        /// Cargo options
+        #[serde(default, flatten)]
+        options: CargoOptions,
+
+        /// Treat warnings as errors
+        #[serde(default)]
+        deny_warnings: bool,
+    },
+
+    /// Run `cargo fmt`
+    #[serde(rename = "fmt")]
+    Fmt {
+        /// Only report unformatted files instead of rewriting them
+        #[serde(default)]
+        check: bool,
+    },
+}
+
+/// Severity of a compiler diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticLevel {
+    /// Compilation error
+    Error,
+
+    /// Warning
+    Warning,
+
+    /// Additional note
+    Note,
+
+    /// Help message
+    Help,
+
+    /// Internal compiler error
+    #[serde(rename = "error: internal compiler error")]
+    InternalError,
+
+    /// Any other level
+    #[serde(other)]
+    Other,
+}
+
+/// A source location
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    /// File path, relative to the project root
+    pub file: String,
+
+    /// First line (1-based)
+    pub line_start: usize,
+
+    /// Last line (1-based, inclusive)
+    pub line_end: usize,
+
+    /// First column (1-based)
+    pub column_start: usize,
+
+    /// Column after the last character (1-based)
+    pub column_end: usize,
+
+    /// Label attached to the span
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A machine-applicable fix proposed by the compiler
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    /// Explanation of the fix
+    pub message: String,
+
+    /// Span to replace
+    pub span: Span,
+
+    /// Replacement text
+    pub replacement: String,
+
+    /// How confident the compiler is, e.g. `MachineApplicable`
+    #[serde(default)]
+    pub applicability: Option<String>,
+}
+
+/// A compiler or clippy diagnostic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Severity
+    pub level: DiagnosticLevel,
+
+    /// Diagnostic message
+    pub message: String,
+
+    /// Error or lint code, e.g. `E0308` or `clippy::needless_return`
+    #[serde(default)]
+    pub code: Option<String>,
+
+    /// Primary location, if the diagnostic has one (linker errors do not)
+    #[serde(default)]
+    pub span: Option<Span>,
+
+    /// Proposed fixes
+    #[serde(default)]
+    pub suggestions: Vec<Suggestion>,
+
+    /// Human readable rendering, as printed by cargo
+    #[serde(default)]
+    pub rendered: Option<String>,
+}
+
+/// Outcome of a single test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestOutcome {
+    /// Test passed
+    Passed,
+
+    /// Test failed
+    Failed,
+
+    /// Test was ignored
+    Ignored,
+}
+
+/// Result of a single test
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestResult {
+    /// Test path, e.g. `tests::it_works`
+    pub name: String,
+
+    /// Outcome
+    pub outcome: TestOutcome,
+}
+
+/// Rust response types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RustResponse {
+    /// Response to check, build and clippy
+    #[serde(rename = "build")]
+    Build {
+        /// Whether cargo succeeded
+        success: bool,
+
+        /// Diagnostics emitted while compiling
+        diagnostics: Vec<Diagnostic>,
+
+        /// Error output of cargo, when it failed without explaining why in diagnostics
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stderr: Option<String>,
+    },
+
+    /// Test response
+    #[serde(rename = "test")]
+    Test {
+        /// Whether all tests passed
+        success: bool,
+
+        /// Diagnostics emitted while compiling the tests
+        diagnostics: Vec<Diagnostic>,
+
+        /// Individual test results
+        tests: Vec<TestResult>,
+
+        /// Error output of cargo, when it failed without a diagnostic or failed test
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stderr: Option<String>,
+    },
+
+    /// Format response
+    #[serde(rename = "fmt")]
+    Fmt {
+        /// Whether all files are formatted
+        success: bool,
+
+        /// Files that are (or were) not formatted
+        files: Vec<String>,
+
+        /// Error output of rustfmt, when it failed without listing a file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stderr: Option<String>,
+    },
+
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+impl RustServer {
+    /// Create a new Rust server for the project at `root_dir`
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        let root_dir = root_dir.as_ref().to_path_buf();
+        info!(
+            "Creating rust server with root directory: {}",
+            root_dir.display()
+        );
+        Self {
+            root_dir,
+            id: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Get the server ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Handle an MCP request
+    pub async fn handle_request(
+        &self,
+        request: McpRequest<RustCommand>,
+    ) -> ServerResult<McpResponse<RustResponse>> {
+        debug!("Handling rust request: {:?}", request);
+
+        let response = match request.payload {
+            RustCommand::Check { options } => self.handle_build("check", &options, &[]).await,
+            RustCommand::Build { options } => self.handle_build("build", &options, &[]).await,
+            RustCommand::Clippy {
+                options,
+                deny_warnings,
+            } => {
+                let extra: &[&str] = if deny_warnings {
+                    &["--", "-D", "warnings"]
+                } else {
+                    &[]
+                };
+                self.handle_build("clippy", &options, extra).await
+            }
+            RustCommand::Test { options, filter } => self.handle_test(&options, filter).await,
+            RustCommand::Fmt { check } => self.handle_fmt(check).await,
+        };
+
+        let response = match response {
+            Ok(payload) => McpResponse {
+                command: request.command,
+                payload,
+                status: ResponseStatus::success(),
+                request_id: request.request_id,
+            },
+            Err(e) => {
+                error!("Error handling rust request: {}", e);
+                McpResponse {
+                    command: request.command,
+                    payload: create_error_response(&e.to_string()),
+                    status: ResponseStatus::error(1, &e.to_string()),
+                    request_id: request.request_id,
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn handle_build(
+        &self,
+        subcommand: &str,
+        options: &CargoOptions,
+        extra: &[&str],
+    ) -> Result<RustResponse, anyhow::Error> {
+        let mut args = cargo_args(subcommand, options)?;
+        args.extend(extra.iter().map(ToString::to_string));
+
+        let output = self.cargo(&args).await?;
+        let parsed = parse_cargo_output(&output.stdout);
+        Ok(RustResponse::Build {
+            success: output.success,
+            stderr: output.unexplained_error(!parsed.diagnostics.is_empty()),
+            diagnostics: parsed.diagnostics,
+        })
+    }
+
+    async fn handle_test(
+        &self,
+        options: &CargoOptions,
+        filter: Option<String>,
+    ) -> Result<RustResponse, anyhow::Error> {
+        let mut args = cargo_args("test", options)?;
+        if let Some(filter) = filter {
+            check_value("filter", &filter)?;
+            args.push("--".to_string());
+            args.push(filter);
+        }
+
+        let output = self.cargo(&args).await?;
+        let parsed = parse_cargo_output(&output.stdout);
+        let explained = !parsed.diagnostics.is_empty()
+            || parsed
+                .tests
+                .iter()
+                .any(|test| test.outcome == TestOutcome::Failed);
+        Ok(RustResponse::Test {
+            success: output.success,
+            stderr: output.unexplained_error(explained),
+            diagnostics: parsed.diagnostics,
+            tests: parsed.tests,
+        })
+    }
+
+    async fn handle_fmt(&self, check: bool) -> Result<RustResponse, anyhow::Error> {
+        let mut args = vec!["fmt".to_string(), "--all".to_string(), "--".to_string()];
+        if check {
+            args.push("--check".to_string());
+        }
+        // List the files that are (or were) not formatted
+        args.push("--files-with-diff".to_string());
+
+        let output = self.cargo(&args).await?;
+        let files: Vec<String> = output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|file| {
+                Path::new(file)
+                    .strip_prefix(&self.root_dir)
+                    .map_or_else(|_| file.to_string(), |p| p.to_string_lossy().to_string())
+            })
+            .collect();
+
+        Ok(RustResponse::Fmt {
+            success: output.success,
+            stderr: output.unexplained_error(!files.is_empty()),
+            files,
+        })
+    }
+
+    /// Run cargo in the project root
+    async fn cargo(&self, args: &[String]) -> Result<CargoOutput, anyhow::Error> {
+        debug!("Running cargo {}", args.join(" "));
+        let output = Command::new("cargo")
+            .args(args)
+            .current_dir(&self.root_dir)
+            .output()
+            .await
+            .context("Failed to run cargo")?;
+
+        Ok(CargoOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+/// Exit status and output of a cargo run
+struct CargoOutput {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+impl CargoOutput {
+    /// Standard error if cargo failed and the parsed output does not explain why,
+    /// e.g. because the manifest is broken
+    fn unexplained_error(&self, explained: bool) -> Option<String> {
+        (!self.success && !explained).then(|| self.stderr.trim().to_string())
+    }
+}
+
+/// Reject values that cargo or the test harness would take for options
+fn check_value(name: &str, value: &str) -> Result<(), anyhow::Error> {
+    if value.starts_with('-') {
+        bail!("Invalid {}: {} (must not start with '-')", name, value);
+    }
+    Ok(())
+}
+
+/// Build the argument list for a cargo subcommand emitting JSON messages
+fn cargo_args(subcommand: &str, options: &CargoOptions) -> Result<Vec<String>, anyhow::Error> {
+    let mut args = vec![subcommand.to_string(), "--message-format=json".to_string()];
+    match &options.package {
+        Some(package) => {
+            check_value("package", package)?;
+            args.extend(["--package".to_string(), package.clone()]);
+        }
+        None => args.push("--workspace".to_string()),
+    }
+    if options.all_targets {
+        args.push("--all-targets".to_string());
+    }
+    if !options.features.is_empty() {
+        for feature in &options.features {
+            check_value("feature", feature)?;
+        }
+        args.extend(["--features".to_string(), options.features.join(",")]);
+    }
+    if options.release {
+        args.push("--release".to_string());
+    }
+    Ok(args)
+}
+
+/// Diagnostics and test results found in cargo output
+#[derive(Debug, Default)]
+struct ParsedOutput {
+    diagnostics: Vec<Diagnostic>,
+    tests: Vec<TestResult>,
+}
+
+/// A cargo JSON message
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    message: Option<RustcDiagnostic>,
+}
+
+/// A diagnostic as emitted by rustc
+#[derive(Deserialize)]
+struct RustcDiagnostic {
+    message: String,
+    code: Option<RustcCode>,
+    level: DiagnosticLevel,
+    spans: Vec<RustcSpan>,
+    children: Vec<RustcDiagnostic>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RustcCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct RustcSpan {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+    label: Option<String>,
+    suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
+}
+
+impl RustcSpan {
+    fn to_span(&self) -> Span {
+        Span {
+            file: self.file_name.clone(),
+            line_start: self.line_start,
+            line_end: self.line_end,
+            column_start: self.column_start,
+            column_end: self.column_end,
+            label: self.label.clone(),
+        }
+    }
+}
+
+impl RustcDiagnostic {
+    /// Convert to a diagnostic
+    ///
+    /// Warnings and notes without a source location are summaries such as "generated
+    /// 1 warning" and are dropped, like the "aborting due to" error summary. Other
+    /// errors without a location, e.g. from the linker, are kept.
+    fn into_diagnostic(self) -> Option<Diagnostic> {
+        let span = self
+            .spans
+            .iter()
+            .find(|s| s.is_primary)
+            .map(RustcSpan::to_span);
+        let is_error = matches!(
+            self.level,
+            DiagnosticLevel::Error | DiagnosticLevel::InternalError
+        );
+        if span.is_none() && (!is_error || self.message.starts_with("aborting due to")) {
+            return None;
+        }
+
+        let suggestions = std::iter::once(&self)
+            .chain(&self.children)
+            .flat_map(|d| d.spans.iter().map(move |s| (d, s)))
+            .filter_map(|(d, s)| {
+                Some(Suggestion {
+                    message: d.message.clone(),
+                    span: s.to_span(),
+                    replacement: s.suggested_replacement.clone()?,
+                    applicability: s.suggestion_applicability.clone(),
+                })
+            })
+            .collect();
+
+        Some(Diagnostic {
+            level: self.level,
+            message: self.message,
+            code: self.code.map(|c| c.code),
+            span,
+            suggestions,
+            rendered: self.rendered,
+        })
+    }
+}
+
+/// Parse cargo's standard output: JSON messages interleaved with libtest output
+fn parse_cargo_output(stdout: &str) -> ParsedOutput {
+    let mut parsed = ParsedOutput::default();
+
+    for line in stdout.lines() {
+        if line.starts_with('{') {
+            if let Ok(message) = serde_json::from_str::<CargoMessage>(line) {
+                if message.reason == "compiler-message" {
+                    if let Some(diagnostic) =
+                        message.message.and_then(RustcDiagnostic::into_diagnostic)
+                    {
+                        if !parsed.diagnostics.contains(&diagnostic) {
+                            parsed.diagnostics.push(diagnostic);
+                        }
+                    }
+                }
+                continue;
+            }
+        }
+
+        if let Some(test) = parse_test_line(line) {
+            parsed.tests.push(test);
+        }
+    }
+
+    parsed
+}
+
+/// Parse a libtest line of the form `test path::name ... ok`
+fn parse_test_line(line: &str) -> Option<TestResult> {
+    let rest = line.strip_prefix("test ")?;
+    let (name, outcome) = rest.rsplit_once(" ... ")?;
+    let outcome = match outcome.trim() {
+        "ok" => TestOutcome::Passed,
+        "FAILED" => TestOutcome::Failed,
+        o if o.starts_with("ignored") => TestOutcome::Ignored,
+        _ => return None,
+    };
+    Some(TestResult {
+        name: name.trim().to_string(),
+        outcome,
+    })
+}
+
+// Helper function to create an error response for the appropriate command type
+fn create_error_response(error: &str) -> RustResponse {
+    RustResponse::Error {
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPILER_MESSAGE: &str = r#"{"reason":"compiler-message","package_id":"demo 0.1.0","manifest_path":"/p/Cargo.toml","target":{"name":"demo"},"message":{"rendered":"error[E0308]: mismatched types\n","children":[{"children":[],"code":null,"level":"help","message":"try using a conversion method","rendered":null,"spans":[{"byte_end":40,"byte_start":38,"column_end":20,"column_start":18,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":null,"line_end":2,"line_start":2,"suggested_replacement":"\"1\".to_string()","suggestion_applicability":"MaybeIncorrect","text":[]}]}],"code":{"code":"E0308","explanation":"..."},"level":"error","message":"mismatched types","spans":[{"byte_end":40,"byte_start":38,"column_end":20,"column_start":18,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":"expected `String`, found `&str`","line_end":2,"line_start":2,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}]}}"#;
+
+    #[test]
+    fn test_server_creation() {
+        let server = RustServer::new(".");
+        assert!(!server.id().is_empty());
+    }
+
+    #[test]
+    fn test_parse_compiler_message() {
+        let summary = r#"{"reason":"compiler-message","package_id":"demo","target":{},"message":{"rendered":"error: aborting due to 1 previous error\n","children":[],"code":null,"level":"error","message":"aborting due to 1 previous error","spans":[]}}"#;
+        let finished = r#"{"reason":"build-finished","success":false}"#;
+        let output = [COMPILER_MESSAGE, summary, finished].join("\n");
+
+        let parsed = parse_cargo_output(&output);
+        assert_eq!(parsed.diagnostics.len(), 1);
+
+        let diagnostic = &parsed.diagnostics[0];
+        assert_eq!(diagnostic.level, DiagnosticLevel::Error);
+        assert_eq!(diagnostic.code.as_deref(), Some("E0308"));
+        let span = diagnostic.span.as_ref().unwrap();
+        assert_eq!(span.file, "src/main.rs");
+        assert_eq!(span.line_start, 2);
+        assert_eq!(span.column_start, 18);
+        assert_eq!(diagnostic.suggestions.len(), 1);
+        assert_eq!(diagnostic.suggestions[0].replacement, "\"1\".to_string()");
+        assert_eq!(
+            diagnostic.suggestions[0].applicability.as_deref(),
+            Some("MaybeIncorrect")
+        );
+    }
+
+    #[test]
+    fn test_parse_linker_error() {
+        let linker = r#"{"reason":"compiler-message","package_id":"demo","target":{},"message":{"rendered":"error: linking with `cc` failed: exit status: 1\n","children":[{"children":[],"code":null,"level":"note","message":"undefined reference to `missing'","rendered":null,"spans":[]}],"code":null,"level":"error","message":"linking with `cc` failed: exit status: 1","spans":[]}}"#;
+        let summary = r#"{"reason":"compiler-message","package_id":"demo","target":{},"message":{"rendered":"warning: `demo` (bin \"demo\") generated 1 warning\n","children":[],"code":null,"level":"warning","message":"`demo` (bin \"demo\") generated 1 warning","spans":[]}}"#;
+
+        let parsed = parse_cargo_output(&[linker, summary].join("\n"));
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let diagnostic = &parsed.diagnostics[0];
+        assert_eq!(diagnostic.level, DiagnosticLevel::Error);
+        assert_eq!(
+            diagnostic.message,
+            "linking with `cc` failed: exit status: 1"
+        );
+        assert!(diagnostic.span.is_none());
+    }
+
+    #[test]
+    fn test_options_are_not_flags() {
+        let options = CargoOptions {
+            package: Some("--manifest-path=/etc/Cargo.toml".to_string()),
+            ..Default::default()
+        };
+        assert!(cargo_args("check", &options).is_err());
+
+        let options = CargoOptions {
+            package: Some("demo".to_string()),
+            features: vec!["serde".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            cargo_args("check", &options).unwrap(),
+            [
+                "check",
+                "--message-format=json",
+                "--package",
+                "demo",
+                "--features",
+                "serde"
+            ]
+        );
+        assert!(check_value("filter", "--logfile=/tmp/out").is_err());
+        assert!(check_value("filter", "tests::parse").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reports_cargo_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "[package\n").unwrap();
+
+        let server = RustServer::new(dir.path());
+        let response = server
+            .handle_request(McpRequest::new(
+                "rust.check",
+                RustCommand::Check {
+                    options: CargoOptions::default(),
+                },
+            ))
+            .await
+            .unwrap();
+
+        let RustResponse::Build {
+            success,
+            diagnostics,
+            stderr,
+        } = response.payload
+        else {
+            panic!("Expected Build response");
+        };
+        assert!(!success);
+        assert!(diagnostics.is_empty());
+        assert!(stderr.unwrap().contains("Cargo.toml"));
+    }
+
+    #[test]
+    fn test_parse_test_results() {
+        let output = "\
+running 3 tests
+test tests::passes ... ok
+test tests::fails ... FAILED
+test tests::slow ... ignored, takes too long
+{\"reason\":\"build-finished\",\"success\":false}
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out";
+
+        let parsed = parse_cargo_output(output);
+        assert_eq!(
+            parsed.tests,
+            vec![
+                TestResult {
+                    name: "tests::passes".to_string(),
+                    outcome: TestOutcome::Passed
+                },
+                TestResult {
+                    name: "tests::fails".to_string(),
+                    outcome: TestOutcome::Failed
+                },
+                TestResult {
+                    name: "tests::slow".to_string(),
+                    outcome: TestOutcome::Ignored
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_reports_diagnostics() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/main.rs"),
+            "fn main() {\n    let s: String = \"1\";\n    println!(\"{s}\");\n}\n",
+        )
+        .unwrap();
+
+        let server = RustServer::new(dir.path());
+        let response = server
+            .handle_request(McpRequest::new(
+                "rust.check",
+                RustCommand::Check {
+                    options: CargoOptions::default(),
+                },
+            ))
+            .await
+            .unwrap();
+
+        let RustResponse::Build {
+            success,
+            diagnostics,
+            ..
+        } = response.payload
+        else {
+            panic!("Expected Build response");
+        };
+        assert!(!success);
+        let error = diagnostics
+            .iter()
+            .find(|d| d.level == DiagnosticLevel::Error)
+            .unwrap();
+        assert_eq!(error.code.as_deref(), Some("E0308"));
+        let span = error.span.as_ref().unwrap();
+        assert_eq!(span.file, "src/main.rs");
+        assert_eq!(span.line_start, 2);
+    }
+}
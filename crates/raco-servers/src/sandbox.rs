@@ -0,0 +1,100 @@
+//! Path sandboxing
+//!
+//! This module confines paths received in requests to a server's root directory.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::{ServerError, ServerResult};
+
+/// Resolve a request path against `root`, rejecting paths that escape it
+///
+/// Relative paths are joined onto `root`; absolute paths must already lie inside it.
+/// `..` components are resolved lexically, so the check does not require the
+/// path to exist.
+///
+/// # Errors
+///
+/// Returns [`ServerError::NotSupported`] if the path lies outside `root`
+pub fn resolve_path(root: &Path, path: &str) -> ServerResult<PathBuf> {
+    let root = normalize(root);
+    let candidate = normalize(&root.join(path));
+
+    if candidate.starts_with(&root) {
+        Ok(candidate)
+    } else {
+        Err(ServerError::NotSupported(format!(
+            "Path {} is outside of {}",
+            path,
+            root.display()
+        )))
+    }
+}
+
+/// Resolve a request path and return it relative to `root`
+///
+/// # Errors
+///
+/// Returns [`ServerError::NotSupported`] if the path lies outside `root`
+pub fn relative_path(root: &Path, path: &str) -> ServerResult<PathBuf> {
+    let resolved = resolve_path(root, path)?;
+    Ok(resolved
+        .strip_prefix(normalize(root))
+        .map(Path::to_path_buf)
+        .unwrap_or_default())
+}
+
+/// Lexically normalize a path, making it absolute against the current directory
+fn normalize(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .unwrap_or_else(|_| PathBuf::from("/"))
+            .join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new("/project");
+
+        assert_eq!(
+            resolve_path(root, "src/main.rs").unwrap(),
+            PathBuf::from("/project/src/main.rs")
+        );
+        assert_eq!(
+            resolve_path(root, "src/../Cargo.toml").unwrap(),
+            PathBuf::from("/project/Cargo.toml")
+        );
+        assert_eq!(resolve_path(root, ".").unwrap(), PathBuf::from("/project"));
+        assert!(resolve_path(root, "../etc/passwd").is_err());
+        assert!(resolve_path(root, "/etc/passwd").is_err());
+        assert!(resolve_path(root, "/project/src").is_ok());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let root = Path::new("/project");
+        assert_eq!(
+            relative_path(root, "/project/src/lib.rs").unwrap(),
+            PathBuf::from("src/lib.rs")
+        );
+        assert_eq!(relative_path(root, ".").unwrap(), PathBuf::new());
+    }
+}
@@ -0,0 +1,523 @@
+//! MCP front for the builtin servers
+//!
+//! Every builtin server can run as an MCP server speaking newline-delimited
+//! JSON-RPC over stdio, so it can be activated in a
+//! [`ServerRegistry`](raco_mcp::server::ServerRegistry) like any other server. Its
+//! commands are offered as tools named after the command type: the git server's
+//! `log` tool takes the fields of [`GitCommand::Log`](crate::git::GitCommand::Log)
+//! as its arguments and returns the [`GitResponse`](crate::git::GitResponse) as
+//! structured content.
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use raco_mcp::connection::PROTOCOL_VERSION;
+use raco_mcp::protocol::{McpRequest, McpResponse};
+use raco_mcp::server::{ServerInfo, ServerRegistry};
+use schemars::generate::SchemaSettings;
+use schemars::{JsonSchema, Schema};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::debug::{DebugCommand, DebugServer};
+use crate::filesystem::{FilesystemCommand, FilesystemServer};
+use crate::git::{GitCommand, GitServer};
+use crate::process::{ProcessCommand, ProcessServer};
+use crate::qemu::{QemuCommand, QemuServer};
+use crate::rust::{RustCommand, RustServer};
+use crate::system::{SystemCommand, SystemServer};
+use crate::{ServerError, ServerResult};
+
+/// JSON-RPC error code for unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// JSON-RPC error code for malformed parameters
+const INVALID_PARAMS: i64 = -32602;
+
+/// A server that ships with RACO
+#[derive(Debug, Clone, Copy)]
+pub struct Builtin {
+    /// Server name
+    pub name: &'static str,
+
+    /// Human readable description
+    pub description: &'static str,
+
+    /// JSON Schema of the server's commands, one tagged variant per tool
+    pub commands: fn() -> Schema,
+}
+
+/// A tool offered by a builtin server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tool {
+    /// Tool name, the command type
+    pub name: String,
+
+    /// Human readable description
+    pub description: String,
+
+    /// JSON Schema of the arguments, the fields of the command
+    #[serde(rename = "inputSchema")]
+    pub input_schema: Value,
+}
+
+impl Builtin {
+    /// The tools the server offers, one for each variant of its command type
+    pub fn tools(&self) -> Vec<Tool> {
+        let commands = (self.commands)();
+        let variants = commands
+            .get("oneOf")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        variants
+            .iter()
+            .filter_map(|variant| {
+                let mut input_schema = variant.clone();
+                let name = input_schema
+                    .get_mut("properties")?
+                    .as_object_mut()?
+                    .remove("type")?["const"]
+                    .as_str()?
+                    .to_string();
+                if let Some(required) = input_schema
+                    .get_mut("required")
+                    .and_then(Value::as_array_mut)
+                {
+                    required.retain(|field| field != "type");
+                }
+                let description = input_schema
+                    .as_object_mut()?
+                    .remove("description")
+                    .and_then(|description| description.as_str().map(String::from))
+                    .unwrap_or_default();
+                Some(Tool {
+                    name,
+                    description,
+                    input_schema,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Schema of a command type with the schemas of its fields inlined
+fn commands<T: JsonSchema>() -> Schema {
+    SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>()
+}
+
+/// The servers that ship with RACO
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "debug",
+        description: "GDB debugging server",
+        commands: commands::<DebugCommand>,
+    },
+    Builtin {
+        name: "filesystem",
+        description: "Local filesystem server",
+        commands: commands::<FilesystemCommand>,
+    },
+    Builtin {
+        name: "git",
+        description: "Git repository server",
+        commands: commands::<GitCommand>,
+    },
+    Builtin {
+        name: "process",
+        description: "Process management server",
+        commands: commands::<ProcessCommand>,
+    },
+    Builtin {
+        name: "qemu",
+        description: "QEMU emulator server for Zephyr images",
+        commands: commands::<QemuCommand>,
+    },
+    Builtin {
+        name: "rust",
+        description: "Rust toolchain server",
+        commands: commands::<RustCommand>,
+    },
+    Builtin {
+        name: "system",
+        description: "Development environment scanner",
+        commands: commands::<SystemCommand>,
+    },
+];
+
+/// Look up a builtin server by name
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Register the builtin servers in a registry, returning their IDs
+///
+/// Each server is launched as `<program> serve <name>`, where `program` is a
+/// binary that hands its `serve` command to [`serve`].
+///
+/// # Errors
+///
+/// Returns an error if the path of `program` contains whitespace, which the
+/// command line of a server cannot express
+pub async fn register_builtins(
+    registry: &ServerRegistry,
+    program: &Path,
+) -> ServerResult<Vec<Uuid>> {
+    let program = program.display().to_string();
+    if program.contains(char::is_whitespace) {
+        return Err(ServerError::General(format!(
+            "Cannot launch the builtin servers from '{program}', whose path contains whitespace"
+        )));
+    }
+
+    let mut ids = Vec::with_capacity(BUILTINS.len());
+    for builtin in BUILTINS {
+        let info = ServerInfo::new(
+            builtin.name,
+            builtin.name,
+            &format!("{program} serve {}", builtin.name),
+        )
+        .with_metadata("description", builtin.description);
+        ids.push(info.id);
+        registry.register_server(info).await?;
+    }
+    Ok(ids)
+}
+
+/// Register and activate the builtin servers, waiting until they listed their tools
+///
+/// # Errors
+///
+/// Returns an error if a server cannot be registered, fails to start, or has not
+/// listed its tools within `timeout`
+pub async fn activate_builtins(
+    registry: &ServerRegistry,
+    program: &Path,
+    timeout: Duration,
+) -> ServerResult<()> {
+    let ids = register_builtins(registry, program).await?;
+    for &id in &ids {
+        registry.activate_server(id).await?;
+    }
+    for id in ids {
+        registry.wait_for_tools(id, timeout).await?;
+    }
+    Ok(())
+}
+
+/// A running builtin server
+#[derive(Debug)]
+enum Server {
+    Debug(DebugServer),
+    Filesystem(FilesystemServer),
+    Git(GitServer),
+    Process(Mutex<ProcessServer>),
+    Qemu(QemuServer),
+    Rust(RustServer),
+    System(SystemServer),
+}
+
+impl Server {
+    fn new(name: &str, root_dir: &Path) -> ServerResult<Self> {
+        Ok(match name {
+            "debug" => Self::Debug(DebugServer::new(root_dir)),
+            "filesystem" => Self::Filesystem(FilesystemServer::new(root_dir)),
+            "git" => Self::Git(GitServer::new(root_dir)),
+            "process" => Self::Process(Mutex::new(ProcessServer::new())),
+            "qemu" => Self::Qemu(QemuServer::new(root_dir)),
+            "rust" => Self::Rust(RustServer::new(root_dir)),
+            "system" => Self::System(SystemServer::new(root_dir)),
+            name => return Err(ServerError::ServerNotFound(name.to_string())),
+        })
+    }
+
+    /// Run a command, returning the response payload or an error message
+    async fn call(&self, tool: &str, arguments: Value) -> Result<Value, String> {
+        match self {
+            Self::Debug(server) => dispatch(tool, arguments, |r| server.handle_request(r)).await,
+            Self::Filesystem(server) => {
+                dispatch(tool, arguments, |r| server.handle_request(r)).await
+            }
+            Self::Git(server) => dispatch(tool, arguments, |r| server.handle_request(r)).await,
+            Self::Process(server) => {
+                let mut server = server.lock().await;
+                dispatch(tool, arguments, |r| server.handle_request(r)).await
+            }
+            Self::Qemu(server) => dispatch(tool, arguments, |r| server.handle_request(r)).await,
+            Self::Rust(server) => dispatch(tool, arguments, |r| server.handle_request(r)).await,
+            Self::System(server) => dispatch(tool, arguments, |r| server.handle_request(r)).await,
+        }
+    }
+}
+
+/// Turn tool arguments into a command, run it and unwrap the response
+async fn dispatch<C, R, F, Fut>(tool: &str, arguments: Value, handle: F) -> Result<Value, String>
+where
+    C: DeserializeOwned,
+    R: Serialize,
+    F: FnOnce(McpRequest<C>) -> Fut,
+    Fut: Future<Output = ServerResult<McpResponse<R>>>,
+{
+    let mut command = match arguments {
+        Value::Object(fields) => fields,
+        Value::Null => Map::new(),
+        _ => return Err("Tool arguments must be an object".to_string()),
+    };
+    command.insert("type".to_string(), Value::String(tool.to_string()));
+    let command = serde_json::from_value(Value::Object(command))
+        .map_err(|e| format!("Invalid arguments for tool {tool}: {e}"))?;
+
+    let response = handle(McpRequest::new(tool, command))
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status.code != 0 {
+        return Err(response.status.message);
+    }
+    let payload = serde_json::to_value(response.payload).map_err(|e| e.to_string())?;
+    if payload["type"] == "error" {
+        return Err(payload["message"]
+            .as_str()
+            .unwrap_or("Unknown error")
+            .to_string());
+    }
+    Ok(payload)
+}
+
+/// Answer a JSON-RPC request, returning its result or an error code and message
+async fn answer(
+    builtin: &Builtin,
+    tools: &[Tool],
+    server: &Server,
+    method: &str,
+    params: Value,
+) -> Result<Value, (i64, String)> {
+    match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": params
+                .get("protocolVersion")
+                .cloned()
+                .unwrap_or_else(|| PROTOCOL_VERSION.into()),
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": format!("raco-{}", builtin.name), "version": crate::VERSION },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools })),
+        "tools/call" => {
+            let tool = params["name"]
+                .as_str()
+                .ok_or_else(|| (INVALID_PARAMS, "Missing tool name".to_string()))?;
+            if !tools.iter().any(|known| known.name == tool) {
+                return Err((INVALID_PARAMS, format!("Unknown tool: {tool}")));
+            }
+            let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+            Ok(match server.call(tool, arguments).await {
+                Ok(payload) => json!({
+                    "content": [{ "type": "text", "text": payload.to_string() }],
+                    "structuredContent": payload,
+                    "isError": false,
+                }),
+                Err(message) => json!({
+                    "content": [{ "type": "text", "text": message }],
+                    "isError": true,
+                }),
+            })
+        }
+        method => Err((METHOD_NOT_FOUND, format!("Method not found: {method}"))),
+    }
+}
+
+/// Serve a builtin server over newline-delimited JSON-RPC until `input` ends
+///
+/// Requests are answered concurrently, so health pings are answered while a long
+/// tool call such as `cargo build` is running.
+///
+/// # Errors
+///
+/// Returns an error if `name` is not a builtin server or `input` cannot be read
+pub async fn serve<R, W>(name: &str, root_dir: &Path, input: R, mut output: W) -> ServerResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let builtin = builtin(name).ok_or_else(|| ServerError::ServerNotFound(name.to_string()))?;
+    let server = Arc::new(Server::new(builtin.name, root_dir)?);
+    let tools: Arc<[Tool]> = builtin.tools().into();
+
+    let (responses, mut outgoing) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        while let Some(response) = outgoing.recv().await {
+            let written = async {
+                output.write_all(response.to_string().as_bytes()).await?;
+                output.write_all(b"\n").await?;
+                output.flush().await
+            };
+            if let Err(e) = written.await {
+                debug!("MCP client stopped reading: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(input).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| ServerError::General(e.to_string()))?
+    {
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Ignoring malformed message: {}", e);
+                continue;
+            }
+        };
+        // Notifications need no answer
+        let (Some(id), Some(method)) = (message.get("id"), message["method"].as_str()) else {
+            continue;
+        };
+        let (id, method) = (id.clone(), method.to_string());
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let server = Arc::clone(&server);
+        let tools = Arc::clone(&tools);
+        let responses = responses.clone();
+        tokio::spawn(async move {
+            let response = match answer(builtin, &tools, &server, &method, params).await {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            let _ = responses.send(response);
+        });
+    }
+
+    drop(responses);
+    let _ = writer.await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_serve_filesystem() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_input, server_output) = tokio::io::split(server);
+        let root = dir.path().to_path_buf();
+        let serving =
+            tokio::spawn(
+                async move { serve("filesystem", &root, server_input, server_output).await },
+            );
+
+        let (client_input, mut client_output) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_input).lines();
+        let request = |id: u64, method: &str, params: Value| {
+            let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+            format!("{message}\n")
+        };
+        for message in [
+            request(
+                1,
+                "initialize",
+                json!({ "protocolVersion": PROTOCOL_VERSION }),
+            ),
+            request(2, "tools/list", json!({})),
+            request(
+                3,
+                "tools/call",
+                json!({ "name": "read", "arguments": { "path": "notes.txt" } }),
+            ),
+            request(4, "tools/call", json!({ "name": "read", "arguments": {} })),
+            request(5, "tools/call", json!({ "name": "status" })),
+            request(6, "resources/list", json!({})),
+        ] {
+            client_output.write_all(message.as_bytes()).await.unwrap();
+        }
+
+        let mut responses = std::collections::HashMap::new();
+        while responses.len() < 6 {
+            let line = lines.next_line().await.unwrap().unwrap();
+            let response: Value = serde_json::from_str(&line).unwrap();
+            responses.insert(response["id"].as_u64().unwrap(), response);
+        }
+
+        assert_eq!(responses[&1]["result"]["protocolVersion"], PROTOCOL_VERSION);
+        let tools = responses[&2]["result"]["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 4);
+        assert_eq!(tools[1]["name"], "read");
+
+        let read = &responses[&3]["result"];
+        assert_eq!(read["isError"], false);
+        assert_eq!(read["structuredContent"]["type"], "read");
+
+        let invalid = &responses[&4]["result"];
+        assert_eq!(invalid["isError"], true);
+        assert!(invalid["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid arguments for tool read"));
+
+        assert_eq!(responses[&5]["error"]["code"], INVALID_PARAMS);
+        assert_eq!(responses[&6]["error"]["code"], METHOD_NOT_FOUND);
+
+        client_output.shutdown().await.unwrap();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_tool_schemas() {
+        let tools = builtin("qemu").unwrap().tools();
+        let names: Vec<_> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names, ["boot", "read_console", "status", "stop"]);
+        assert_eq!(
+            tools[0].description,
+            "Boot an image, replacing the running emulator"
+        );
+
+        // The arguments are the fields of the command, flattened options included
+        let boot = jsonschema::validator_for(&tools[0].input_schema).unwrap();
+        assert!(boot.is_valid(&json!({ "image": "zephyr.elf", "board": "qemu_x86" })));
+        assert!(!boot.is_valid(&json!({ "board": "qemu_x86" })));
+        assert!(!boot.is_valid(&json!({ "image": "zephyr.elf", "gdb_port": "1234" })));
+        assert!(tools[0].input_schema["properties"].get("type").is_none());
+
+        for builtin in BUILTINS {
+            let tools = builtin.tools();
+            assert!(!tools.is_empty(), "{} has no tools", builtin.name);
+            for tool in tools {
+                assert!(
+                    !tool.description.is_empty(),
+                    "{} is undocumented",
+                    tool.name
+                );
+                assert_eq!(tool.input_schema["type"], "object");
+                assert!(jsonschema::validator_for(&tool.input_schema).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert!(BUILTINS
+            .iter()
+            .all(|builtin| Server::new(builtin.name, Path::new(".")).is_ok()));
+        assert!(builtin("git").is_some());
+        assert!(builtin("telnet").is_none());
+    }
+}
@@ -0,0 +1,878 @@
+//! System MCP server
+//!
+//! This module provides an MCP server implementation that inventories the development
+//! environment and reports which prerequisites of a project are missing.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use futures::future::join_all;
+use raco_mcp::protocol::{McpRequest, McpResponse, ResponseStatus};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+use crate::{sandbox, ServerResult};
+
+/// Tools looked up by a scan
+const TOOLS: &[&str] = &[
+    "rustup",
+    "cargo",
+    "rustc",
+    "gcc",
+    "clang",
+    "make",
+    "cmake",
+    "ninja",
+    "python3",
+    "west",
+    "git",
+    "gdb",
+    "gdb-multiarch",
+    "qemu-system-arm",
+    "qemu-system-aarch64",
+    "qemu-system-i386",
+    "qemu-system-x86_64",
+    "qemu-system-riscv32",
+    "qemu-system-riscv64",
+];
+
+/// Environment variables reported by a scan
+const ENV_VARS: &[&str] = &[
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "RUSTUP_TOOLCHAIN",
+    "CARGO_BUILD_TARGET",
+    "CC",
+    "CXX",
+    "VIRTUAL_ENV",
+    "ZEPHYR_BASE",
+    "ZEPHYR_SDK_INSTALL_DIR",
+    "ZEPHYR_TOOLCHAIN_VARIANT",
+];
+
+/// Time a tool may take to report its version
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// System server for inspecting the development environment
+#[derive(Debug)]
+pub struct SystemServer {
+    /// Project root; checked projects are confined to it
+    root_dir: PathBuf,
+
+    /// Server ID
+    id: String,
+
+    /// Directories searched for tools, defaulting to `PATH`
+    search_path: Option<OsString>,
+}
+
+/// System command types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SystemCommand {
+    /// Inventory the installed tools, OS and environment
+    #[serde(rename = "scan")]
+    Scan,
+
+    /// Report the prerequisites of a project and which of them are missing
+    #[serde(rename = "check_project")]
+    CheckProject {
+        /// Project directory (defaults to the root directory)
+        #[serde(default)]
+        path: Option<String>,
+    },
+}
+
+/// Operating system details
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsInfo {
+    /// Operating system, e.g. `linux` or `macos`
+    pub os: String,
+
+    /// CPU architecture, e.g. `x86_64`
+    pub arch: String,
+
+    /// Distribution name, e.g. `Debian GNU/Linux 12 (bookworm)`
+    pub name: Option<String>,
+
+    /// Kernel release
+    pub kernel: Option<String>,
+}
+
+/// An installed tool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolInfo {
+    /// Executable name
+    pub name: String,
+
+    /// Location of the executable, if installed
+    pub path: Option<String>,
+
+    /// First line of `--version` output
+    pub version: Option<String>,
+}
+
+/// Rust toolchains managed by rustup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RustInfo {
+    /// Installed toolchains
+    pub toolchains: Vec<String>,
+
+    /// Default toolchain
+    pub default_toolchain: Option<String>,
+
+    /// Installed targets of the active toolchain
+    pub targets: Vec<String>,
+
+    /// Binaries installed with `cargo install`
+    pub cargo_binaries: Vec<String>,
+}
+
+/// A prerequisite of a project
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Requirement {
+    /// What is required, e.g. `cmake` or `rust target thumbv7em-none-eabihf`
+    pub name: String,
+
+    /// Project file that requires it
+    pub reason: String,
+
+    /// How to install it
+    pub hint: String,
+}
+
+/// System response types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SystemResponse {
+    /// Scan response
+    #[serde(rename = "inventory")]
+    Inventory {
+        /// Operating system
+        os: OsInfo,
+
+        /// Looked up tools, installed or not
+        tools: Vec<ToolInfo>,
+
+        /// Rust toolchains
+        rust: RustInfo,
+
+        /// Relevant environment variables that are set
+        env: BTreeMap<String, String>,
+    },
+
+    /// Project check response
+    #[serde(rename = "prerequisites")]
+    Prerequisites {
+        /// Prerequisites that are installed
+        satisfied: Vec<Requirement>,
+
+        /// Prerequisites that are missing
+        missing: Vec<Requirement>,
+    },
+
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        /// Error message
+        message: String,
+    },
+}
+
+/// Something a project needs, satisfied by any of several checks
+#[derive(Debug)]
+struct Need {
+    requirement: Requirement,
+    check: Check,
+}
+
+#[derive(Debug)]
+enum Check {
+    /// Any of these executables is installed
+    AnyTool(&'static [&'static str]),
+
+    /// A rustup toolchain of this channel is installed
+    Toolchain(String),
+
+    /// This target is installed for the active toolchain
+    Target(String),
+}
+
+impl SystemServer {
+    /// Create a new system server for projects under the given root directory
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Self {
+        let root_dir = root_dir.as_ref().to_path_buf();
+        info!(
+            "Creating system server with root directory: {}",
+            root_dir.display()
+        );
+        Self {
+            root_dir,
+            id: uuid::Uuid::new_v4().to_string(),
+            search_path: None,
+        }
+    }
+
+    /// Search tools in the given directories instead of `PATH`
+    pub fn with_search_path<S: Into<OsString>>(mut self, search_path: S) -> Self {
+        self.search_path = Some(search_path.into());
+        self
+    }
+
+    /// Get the server ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Handle an MCP request
+    pub async fn handle_request(
+        &self,
+        request: McpRequest<SystemCommand>,
+    ) -> ServerResult<McpResponse<SystemResponse>> {
+        debug!("Handling system request: {:?}", request);
+
+        let response = match request.payload {
+            SystemCommand::Scan => self.handle_scan().await,
+            SystemCommand::CheckProject { path } => self.handle_check_project(path).await,
+        };
+
+        let response = match response {
+            Ok(payload) => McpResponse {
+                command: request.command,
+                payload,
+                status: ResponseStatus::success(),
+                request_id: request.request_id,
+            },
+            Err(e) => {
+                error!("Error handling system request: {}", e);
+                McpResponse {
+                    command: request.command,
+                    payload: create_error_response(&e.to_string()),
+                    status: ResponseStatus::error(1, &e.to_string()),
+                    request_id: request.request_id,
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn handle_scan(&self) -> Result<SystemResponse, anyhow::Error> {
+        let tools = join_all(TOOLS.iter().map(|name| self.probe(name))).await;
+        let rust = self.rust_info(&self.root_dir).await;
+
+        let env = ENV_VARS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect();
+
+        Ok(SystemResponse::Inventory {
+            os: self.os_info().await,
+            tools,
+            rust,
+            env,
+        })
+    }
+
+    async fn handle_check_project(
+        &self,
+        path: Option<String>,
+    ) -> Result<SystemResponse, anyhow::Error> {
+        let project = sandbox::resolve_path(&self.root_dir, path.as_deref().unwrap_or("."))?;
+        if !project.is_dir() {
+            anyhow::bail!("Project directory not found: {}", project.display());
+        }
+
+        let needs = project_needs(&project);
+        let rust = if needs
+            .iter()
+            .any(|need| !matches!(need.check, Check::AnyTool(_)))
+        {
+            self.rust_info(&project).await
+        } else {
+            RustInfo::default()
+        };
+
+        let mut satisfied = Vec::new();
+        let mut missing = Vec::new();
+        for need in needs {
+            let installed = match &need.check {
+                Check::AnyTool(tools) => tools.iter().any(|tool| self.find_tool(tool).is_some()),
+                Check::Toolchain(channel) => rust
+                    .toolchains
+                    .iter()
+                    .any(|toolchain| is_channel(toolchain, channel)),
+                Check::Target(target) => rust.targets.contains(target),
+            };
+            if installed {
+                satisfied.push(need.requirement);
+            } else {
+                missing.push(need.requirement);
+            }
+        }
+
+        Ok(SystemResponse::Prerequisites { satisfied, missing })
+    }
+
+    async fn os_info(&self) -> OsInfo {
+        let name = tokio::fs::read_to_string("/etc/os-release")
+            .await
+            .ok()
+            .and_then(|release| {
+                release.lines().find_map(|line| {
+                    line.strip_prefix("PRETTY_NAME=")
+                        .map(|name| name.trim_matches('"').to_string())
+                })
+            });
+        let kernel = match self.find_tool("uname") {
+            Some(uname) => run(&uname, &["-r"]).await,
+            None => None,
+        }
+        .and_then(|output| output.lines().next().map(ToString::to_string));
+
+        OsInfo {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            name,
+            kernel,
+        }
+    }
+
+    /// Toolchains and the targets of the toolchain active in `dir`, which a
+    /// `rust-toolchain.toml` there selects
+    async fn rust_info(&self, dir: &Path) -> RustInfo {
+        let mut info = RustInfo::default();
+
+        if let Some(rustup) = self.find_tool("rustup") {
+            for line in run_rustup(&rustup, dir, &["toolchain", "list"])
+                .await
+                .unwrap_or_default()
+                .lines()
+            {
+                let Some(name) = line.split_whitespace().next() else {
+                    continue;
+                };
+                if line.contains("default") {
+                    info.default_toolchain = Some(name.to_string());
+                }
+                info.toolchains.push(name.to_string());
+            }
+
+            info.targets = run_rustup(&rustup, dir, &["target", "list", "--installed"])
+                .await
+                .unwrap_or_default()
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect();
+        }
+
+        if let Some(cargo) = self.find_tool("cargo") {
+            let list = run(&cargo, &["install", "--list"])
+                .await
+                .unwrap_or_default();
+            info.cargo_binaries = installed_binaries(&list);
+        }
+
+        info
+    }
+
+    async fn probe(&self, name: &str) -> ToolInfo {
+        let path = self.find_tool(name);
+        let version = match &path {
+            Some(path) => run(path, &["--version"]).await.and_then(|output| {
+                output
+                    .lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty())
+                    .map(ToString::to_string)
+            }),
+            None => None,
+        };
+
+        ToolInfo {
+            name: name.to_string(),
+            path: path.map(|path| path.display().to_string()),
+            version,
+        }
+    }
+
+    /// Locate an executable in the search path
+    fn find_tool(&self, name: &str) -> Option<PathBuf> {
+        let search_path = self
+            .search_path
+            .clone()
+            .or_else(|| std::env::var_os("PATH"))?;
+        std::env::split_paths(&search_path)
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    }
+}
+
+/// Prerequisites implied by the files of a project
+fn project_needs(project: &Path) -> Vec<Need> {
+    let mut needs = Vec::new();
+    let mut need = |name: &str, reason: &str, hint: &str, check: Check| {
+        needs.push(Need {
+            requirement: Requirement {
+                name: name.to_string(),
+                reason: reason.to_string(),
+                hint: hint.to_string(),
+            },
+            check,
+        });
+    };
+    let exists = |file: &str| project.join(file).exists();
+
+    if exists("Cargo.toml") {
+        need(
+            "cargo",
+            "Cargo.toml",
+            "Install Rust with rustup from https://rustup.rs",
+            Check::AnyTool(&["cargo"]),
+        );
+    }
+
+    for file in ["rust-toolchain.toml", "rust-toolchain"] {
+        let Ok(text) = std::fs::read_to_string(project.join(file)) else {
+            continue;
+        };
+        let (channel, targets) = parse_toolchain_file(&text);
+        need(
+            "rustup",
+            file,
+            "Install rustup from https://rustup.rs",
+            Check::AnyTool(&["rustup"]),
+        );
+        if let Some(channel) = channel {
+            need(
+                &format!("rust toolchain {channel}"),
+                file,
+                &format!("rustup toolchain install {channel}"),
+                Check::Toolchain(channel),
+            );
+        }
+        for target in targets {
+            need(
+                &format!("rust target {target}"),
+                file,
+                &format!("rustup target add {target}"),
+                Check::Target(target),
+            );
+        }
+        break;
+    }
+
+    if let Ok(text) = std::fs::read_to_string(project.join(".cargo/config.toml")) {
+        if let Some(target) = toml::from_str::<toml::Value>(&text)
+            .ok()
+            .and_then(|config| {
+                config
+                    .get("build")?
+                    .get("target")?
+                    .as_str()
+                    .map(String::from)
+            })
+        {
+            need(
+                &format!("rust target {target}"),
+                ".cargo/config.toml",
+                &format!("rustup target add {target}"),
+                Check::Target(target),
+            );
+        }
+    }
+
+    let zephyr = ["west.yml", "zephyr/module.yml", "prj.conf"]
+        .into_iter()
+        .find(|file| exists(file));
+    if let Some(file) = zephyr {
+        need(
+            "west",
+            file,
+            "pip3 install --user west",
+            Check::AnyTool(&["west"]),
+        );
+        need(
+            "python3",
+            file,
+            "Install Python 3 with your package manager",
+            Check::AnyTool(&["python3"]),
+        );
+        need(
+            "ninja",
+            file,
+            "Install ninja-build with your package manager",
+            Check::AnyTool(&["ninja"]),
+        );
+    }
+
+    if let Some(file) = zephyr.or(exists("CMakeLists.txt").then_some("CMakeLists.txt")) {
+        need(
+            "cmake",
+            file,
+            "Install CMake with your package manager",
+            Check::AnyTool(&["cmake"]),
+        );
+    }
+
+    let c_build = if exists("Makefile") {
+        Some("Makefile")
+    } else if zephyr.is_none() && exists("CMakeLists.txt") {
+        Some("CMakeLists.txt")
+    } else {
+        None
+    };
+    if let Some(file) = c_build {
+        need(
+            "C compiler",
+            file,
+            "Install gcc or clang with your package manager",
+            Check::AnyTool(&["gcc", "clang", "cc"]),
+        );
+    }
+    if exists("Makefile") {
+        need(
+            "make",
+            "Makefile",
+            "Install make with your package manager",
+            Check::AnyTool(&["make"]),
+        );
+    }
+
+    // Zephyr projects already require Python
+    if let Some(file) = ["pyproject.toml", "requirements.txt"]
+        .into_iter()
+        .find(|file| zephyr.is_none() && exists(file))
+    {
+        need(
+            "python3",
+            file,
+            "Install Python 3 with your package manager",
+            Check::AnyTool(&["python3"]),
+        );
+    }
+
+    needs
+}
+
+/// Channel and targets of a `rust-toolchain.toml` or legacy `rust-toolchain` file
+fn parse_toolchain_file(text: &str) -> (Option<String>, Vec<String>) {
+    match toml::from_str::<toml::Value>(text) {
+        Ok(file) => {
+            let toolchain = file.get("toolchain");
+            let channel = toolchain
+                .and_then(|t| t.get("channel"))
+                .and_then(|c| c.as_str())
+                .map(String::from);
+            let targets = toolchain
+                .and_then(|t| t.get("targets"))
+                .and_then(|t| t.as_array())
+                .map(|targets| {
+                    targets
+                        .iter()
+                        .filter_map(|target| target.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+            (channel, targets)
+        }
+        // The legacy format holds just the channel name
+        Err(_) => (
+            text.lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(String::from),
+            Vec::new(),
+        ),
+    }
+}
+
+/// Run a tool and return its output, or `None` if it fails
+async fn run(program: &Path, args: &[&str]) -> Option<String> {
+    capture(Command::new(program).args(args)).await
+}
+
+/// Run rustup in a directory, so it reports on the toolchain active there, without
+/// letting it install that toolchain if it is missing
+async fn run_rustup(rustup: &Path, dir: &Path, args: &[&str]) -> Option<String> {
+    capture(
+        Command::new(rustup)
+            .args(args)
+            .current_dir(dir)
+            .env("RUSTUP_AUTO_INSTALL", "0"),
+    )
+    .await
+}
+
+/// Output of a command, or `None` if it fails
+async fn capture(command: &mut Command) -> Option<String> {
+    let output = tokio::time::timeout(
+        PROBE_TIMEOUT,
+        command.stdin(Stdio::null()).kill_on_drop(true).output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // Some tools print their version to stderr
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        Some(String::from_utf8_lossy(&output.stderr).into_owned())
+    } else {
+        Some(stdout.into_owned())
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+// Helper function to create an error response for the appropriate command type
+fn create_error_response(error: &str) -> SystemResponse {
+    SystemResponse::Error {
+        message: error.to_string(),
+    }
+}
+
+/// Whether a rustup toolchain is exactly the given channel
+///
+/// rustup names toolchains after their channel followed by the host triple, e.g.
+/// `1.78-x86_64-unknown-linux-gnu`. Triples start with the architecture, never with
+/// a digit, which tells `nightly-x86_64-…` apart from `nightly-2024-01-01-x86_64-…`.
+fn is_channel(toolchain: &str, channel: &str) -> bool {
+    toolchain == channel
+        || toolchain
+            .strip_prefix(channel)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|host| host.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// Binary names from `cargo install --list`: each package is an unindented
+/// `name vX.Y.Z:` line followed by its binaries, one per indented line.
+fn installed_binaries(list: &str) -> Vec<String> {
+    list.lines()
+        .filter(|line| line.starts_with(char::is_whitespace))
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    async fn request(server: &SystemServer, command: SystemCommand) -> SystemResponse {
+        let response = server
+            .handle_request(McpRequest::new("system", command))
+            .await
+            .unwrap();
+        response.payload
+    }
+
+    #[test]
+    fn test_server_creation() {
+        let server = SystemServer::new(".");
+        assert!(!server.id().is_empty());
+    }
+
+    #[test]
+    fn test_parse_toolchain_file() {
+        let (channel, targets) = parse_toolchain_file(
+            "[toolchain]\nchannel = \"1.78\"\ntargets = [\"thumbv7em-none-eabihf\", \"riscv32imac-unknown-none-elf\"]\n",
+        );
+        assert_eq!(channel.as_deref(), Some("1.78"));
+        assert_eq!(
+            targets,
+            ["thumbv7em-none-eabihf", "riscv32imac-unknown-none-elf"]
+        );
+
+        let (channel, targets) = parse_toolchain_file("nightly-2024-01-01\n");
+        assert_eq!(channel.as_deref(), Some("nightly-2024-01-01"));
+        assert!(targets.is_empty());
+    }
+
+    #[test]
+    fn test_installed_binaries() {
+        let list =
+            "probe-rs-tools v0.24.0:\n    cargo-embed\n    probe-rs\nripgrep v14.0.0:\n    rg\n";
+        assert_eq!(installed_binaries(list), ["cargo-embed", "probe-rs", "rg"]);
+        assert!(installed_binaries("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let server = SystemServer::new(".");
+        let SystemResponse::Inventory { os, tools, .. } =
+            request(&server, SystemCommand::Scan).await
+        else {
+            panic!("scan failed");
+        };
+        assert_eq!(os.os, std::env::consts::OS);
+        assert_eq!(tools.len(), TOOLS.len());
+
+        // The tests themselves run under cargo
+        let cargo = tools.iter().find(|tool| tool.name == "cargo").unwrap();
+        assert!(cargo.path.is_some());
+        assert!(cargo.version.as_deref().unwrap().starts_with("cargo"));
+    }
+
+    #[test]
+    fn test_is_channel() {
+        assert!(is_channel("stable-x86_64-unknown-linux-gnu", "stable"));
+        assert!(is_channel("1.78-aarch64-apple-darwin", "1.78"));
+        assert!(is_channel(
+            "nightly-2024-01-01-x86_64-unknown-linux-gnu",
+            "nightly-2024-01-01"
+        ));
+        assert!(is_channel("my-toolchain", "my-toolchain"));
+        assert!(!is_channel("1.78.0-x86_64-unknown-linux-gnu", "1.7"));
+        assert!(!is_channel("1.78.0-x86_64-unknown-linux-gnu", "1.78"));
+        assert!(!is_channel(
+            "nightly-2024-01-01-x86_64-unknown-linux-gnu",
+            "nightly"
+        ));
+        assert!(!is_channel("stable-x86_64-unknown-linux-gnu", "stab"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        std::fs::write(bin.join("west"), "#!/bin/sh\necho 'West version: v1.2.0'\n").unwrap();
+        std::fs::set_permissions(bin.join("west"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let project = dir.path().join("app");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(project.join("Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        std::fs::write(project.join("west.yml"), "manifest: {}\n").unwrap();
+        std::fs::write(
+            project.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"stable\"\ntargets = [\"thumbv7m-none-eabi\"]\n",
+        )
+        .unwrap();
+
+        let server = SystemServer::new(dir.path()).with_search_path(&bin);
+        let response = request(
+            &server,
+            SystemCommand::CheckProject {
+                path: Some("app".to_string()),
+            },
+        )
+        .await;
+        let SystemResponse::Prerequisites { satisfied, missing } = response else {
+            panic!("unexpected response: {response:?}");
+        };
+
+        let names = |requirements: &[Requirement]| {
+            requirements
+                .iter()
+                .map(|r| r.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&satisfied), ["west"]);
+        let missing_names = names(&missing);
+        for name in [
+            "cargo",
+            "rustup",
+            "rust toolchain stable",
+            "rust target thumbv7m-none-eabi",
+            "cmake",
+            "ninja",
+        ] {
+            assert!(missing_names.contains(&name.to_string()), "{name} missing");
+        }
+        let target = missing
+            .iter()
+            .find(|r| r.name == "rust target thumbv7m-none-eabi")
+            .unwrap();
+        assert_eq!(target.hint, "rustup target add thumbv7m-none-eabi");
+        assert_eq!(target.reason, "rust-toolchain.toml");
+
+        let response = request(
+            &server,
+            SystemCommand::CheckProject {
+                path: Some("../elsewhere".to_string()),
+            },
+        )
+        .await;
+        assert!(matches!(response, SystemResponse::Error { .. }));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_project_toolchain() {
+        // A rustup that lists the target only for the project's toolchain
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        std::fs::write(
+            bin.join("rustup"),
+            "#!/bin/sh\ncase \"$1\" in\n  toolchain) echo '1.78-x86_64-unknown-linux-gnu (default)';;\n  target) [ -f rust-toolchain.toml ] && echo thumbv7m-none-eabi;;\nesac\nexit 0\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(bin.join("rustup"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let server = SystemServer::new(dir.path()).with_search_path(&bin);
+        let check = |channel: &'static str| {
+            let project = dir.path().join(channel);
+            std::fs::create_dir(&project).unwrap();
+            std::fs::write(
+                project.join("rust-toolchain.toml"),
+                format!(
+                    "[toolchain]\nchannel = \"{channel}\"\ntargets = [\"thumbv7m-none-eabi\"]\n"
+                ),
+            )
+            .unwrap();
+            let server = &server;
+            async move {
+                let response = request(
+                    server,
+                    SystemCommand::CheckProject {
+                        path: Some(channel.to_string()),
+                    },
+                )
+                .await;
+                let SystemResponse::Prerequisites { satisfied, .. } = response else {
+                    panic!("unexpected response: {response:?}");
+                };
+                satisfied.into_iter().map(|r| r.name).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            check("1.78").await,
+            [
+                "rustup",
+                "rust toolchain 1.78",
+                "rust target thumbv7m-none-eabi"
+            ]
+        );
+        assert_eq!(
+            check("1.7").await,
+            ["rustup", "rust target thumbv7m-none-eabi"]
+        );
+    }
+}
@@ -2,39 +2,41 @@
 
 use anyhow::{Context, Result};
 use axum::{
-    extract::State,
+    extract::{Path, State},
     http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
+use futures::Stream;
 use raco_core::config::load_config;
 use raco_core::utils::ensure_dir_exists;
+use raco_mcp::server::{ServerInfo, ServerRegistry};
+use raco_servers::stdio;
+use raco_workflow::engine::{PendingInput, WorkflowEngine};
+use raco_workflow::persistence::WorkflowStore;
+use raco_workflow::{StepId, WorkflowId, WorkflowStatus};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tower_http::cors::{Any, CorsLayer};
+use std::time::Duration;
+use tokio::sync::broadcast;
 use tower_http::trace::TraceLayer;
 use tracing::{debug, info, warn};
 use tracing_subscriber::EnvFilter;
 
+/// Time allowed for the builtin servers to start and list their tools
+const SERVER_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Application state
 #[derive(Debug, Clone)]
 struct AppState {
-    #[allow(dead_code)]
     config: Arc<raco_core::config::CoreConfig>,
-    server_registry: Arc<RwLock<raco_servers::registry::ServerRegistry>>,
-}
-
-/// Server info response
-#[derive(Debug, Serialize, Deserialize)]
-struct ServerInfoResponse {
-    id: String,
-    name: String,
-    server_type: String,
-    uri: String,
-    active: bool,
+    server_registry: Arc<ServerRegistry>,
+    workflows: Arc<WorkflowEngine>,
 }
 
 /// API error response
@@ -48,9 +50,11 @@ fn init_logging() {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,raco_web=debug"));
 
+    // Logs go to stderr, so they never mix with the MCP stream of `serve`
     let _ = tracing_subscriber::fmt()
         .with_env_filter(env_filter)
         .with_target(true)
+        .with_writer(std::io::stderr)
         .try_init();
 }
 
@@ -59,6 +63,16 @@ async fn main() -> Result<()> {
     // Initialize logging
     init_logging();
 
+    // `raco-web serve <name>` runs one of the builtin servers over MCP on stdio
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, name] = args.as_slice() {
+        if command == "serve" {
+            let root_dir = std::env::current_dir()?;
+            stdio::serve(name, &root_dir, tokio::io::stdin(), tokio::io::stdout()).await?;
+            return Ok(());
+        }
+    }
+
     info!("Starting RACO Web server");
 
     // Load configuration
@@ -69,25 +83,57 @@ async fn main() -> Result<()> {
 
     debug!("Using data directory: {}", config.data_dir.display());
 
+    // Start the builtin servers, which this binary serves, so that workflow steps
+    // can call their tools
+    let server_registry = Arc::new(ServerRegistry::new());
+    let program = std::env::current_exe().context("Failed to locate the raco-web binary")?;
+    stdio::activate_builtins(&server_registry, &program, SERVER_STARTUP_TIMEOUT)
+        .await
+        .context("Failed to start the builtin servers")?;
+
+    // Reload saved workflows and continue the ones that were interrupted
+    let store = WorkflowStore::in_data_dir(&config.data_dir);
+    let workflows = Arc::new(
+        WorkflowEngine::new()
+            .with_store(store.clone())
+            .with_tools(server_registry.clone()),
+    );
+    store.record(workflows.subscribe());
+    for id in workflows.restore().await? {
+        let workflows = Arc::clone(&workflows);
+        tokio::spawn(async move {
+            if let Err(e) = workflows.resume_workflow(id).await {
+                warn!("Error resuming workflow {}: {:#}", id, e);
+            }
+        });
+    }
+
     // Create application state
     let app_state = AppState {
         config: Arc::new(config),
-        server_registry: Arc::new(RwLock::new(raco_servers::registry::ServerRegistry::new())),
+        server_registry,
+        workflows,
     };
 
-    // CORS configuration
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
     // Build application router
     let app = Router::new()
         .route("/", get(root_handler))
         .route("/api/servers", get(list_servers))
         .route("/api/servers", post(register_server))
+        .route("/api/workflows/events", get(workflow_events))
+        .route("/api/workflows/pending", get(list_pending_inputs))
+        .route(
+            "/api/workflows/:workflow_id/steps/:step_id/input",
+            post(submit_input),
+        )
+        .route(
+            "/api/workflows/:workflow_id/steps/:step_id/cancel",
+            post(cancel_step),
+        )
+        .route("/api/workflows/:workflow_id/cancel", post(cancel_workflow))
+        .route("/api/workflows/:workflow_id/pause", post(pause_workflow))
+        .route("/api/workflows/:workflow_id/resume", post(resume_workflow))
         .layer(TraceLayer::new_for_http())
-        .layer(cors)
         .with_state(app_state);
 
     // Start the server
@@ -108,36 +154,8 @@ async fn root_handler() -> impl IntoResponse {
 }
 
 /// List servers handler
-async fn list_servers(
-    State(state): State<AppState>,
-) -> Result<Json<Vec<ServerInfoResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let registry = state.server_registry.read().await;
-
-    match registry.get_all_servers().await {
-        Ok(servers) => {
-            let response = servers
-                .into_iter()
-                .map(|s| ServerInfoResponse {
-                    id: s.id.to_string(),
-                    name: s.name,
-                    server_type: s.server_type,
-                    uri: s.uri,
-                    active: s.active,
-                })
-                .collect();
-
-            Ok(Json(response))
-        }
-        Err(e) => {
-            warn!("Error listing servers: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to list servers: {}", e),
-                }),
-            ))
-        }
-    }
+async fn list_servers(State(state): State<AppState>) -> Json<Vec<ServerInfo>> {
+    Json(state.server_registry.get_all_servers().await)
 }
 
 /// Register server request
@@ -146,26 +164,44 @@ struct RegisterServerRequest {
     name: String,
     server_type: String,
     uri: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
 }
 
 /// Register server handler
+///
+/// Registers the server. Starting a server runs its command, so only servers whose
+/// URI is listed in the `allowed_servers` configuration are started, which lets
+/// workflow steps use their tools.
 async fn register_server(
     State(state): State<AppState>,
     Json(request): Json<RegisterServerRequest>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let registry = state.server_registry.write().await;
-
-    let server_info = raco_servers::registry::ServerInfo {
-        id: uuid::Uuid::new_v4(),
-        name: request.name,
-        server_type: request.server_type,
-        uri: request.uri,
-        active: false,
-        metadata: std::collections::HashMap::new(),
+    let server_info = ServerInfo {
+        metadata: request.metadata,
+        ..ServerInfo::new(&request.name, &request.server_type, &request.uri)
     };
 
-    match registry.register_server(server_info).await {
-        Ok(_) => Ok(StatusCode::CREATED),
+    let id = server_info.id;
+    let allowed = state
+        .config
+        .allowed_servers
+        .iter()
+        .any(|uri| uri.trim() == request.uri.trim());
+    let registered = async {
+        state.server_registry.register_server(server_info).await?;
+        if allowed {
+            state.server_registry.activate_server(id).await
+        } else {
+            info!(
+                "Not starting server {}: its URI is not in allowed_servers",
+                request.name
+            );
+            Ok(())
+        }
+    };
+    match registered.await {
+        Ok(()) => Ok(StatusCode::CREATED),
         Err(e) => {
             warn!("Error registering server: {}", e);
             Err((
@@ -177,3 +213,178 @@ async fn register_server(
         }
     }
 }
+
+/// Workflow events handler
+///
+/// Streams every workflow and step transition as a server-sent event named after
+/// the kind of transition.
+async fn workflow_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = futures::stream::unfold(state.workflows.subscribe(), |mut receiver| async {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let data = serde_json::to_value(&event).unwrap_or_default();
+                    let name = data["event"].as_str().unwrap_or("message").to_string();
+                    let event = Event::default().event(name).data(data.to_string());
+                    return Some((Ok(event), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Event stream skipped {} workflow events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// List pending inputs handler
+async fn list_pending_inputs(State(state): State<AppState>) -> Json<Vec<PendingInput>> {
+    Json(state.workflows.pending_inputs().await)
+}
+
+/// Submit input request
+#[derive(Debug, Deserialize)]
+struct SubmitInputRequest {
+    response: serde_json::Value,
+}
+
+/// State of a workflow after a request changed it
+#[derive(Debug, Serialize)]
+struct WorkflowStateResponse {
+    status: WorkflowStatus,
+    pending: Vec<PendingInput>,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn not_found(workflow_id: WorkflowId) -> ApiError {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("Workflow {} not found", workflow_id),
+        }),
+    )
+}
+
+fn bad_request(e: &anyhow::Error) -> ApiError {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: format!("{:#}", e),
+        }),
+    )
+}
+
+/// Get the state of a workflow
+async fn workflow_state(
+    state: &AppState,
+    workflow_id: WorkflowId,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    let instance = state
+        .workflows
+        .get_workflow(workflow_id)
+        .await
+        .ok_or_else(|| not_found(workflow_id))?;
+    let status = instance.lock().await.status();
+    Ok(Json(WorkflowStateResponse {
+        status,
+        pending: state.workflows.pending_inputs_of(workflow_id).await,
+    }))
+}
+
+/// Submit input handler
+///
+/// Responds once the workflow has completed or needs input again.
+async fn submit_input(
+    State(state): State<AppState>,
+    Path((workflow_id, step_id)): Path<(WorkflowId, StepId)>,
+    Json(request): Json<SubmitInputRequest>,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    if state.workflows.get_workflow(workflow_id).await.is_none() {
+        return Err(not_found(workflow_id));
+    }
+
+    if let Err(e) = state
+        .workflows
+        .submit_input(workflow_id, step_id, request.response)
+        .await
+    {
+        warn!("Error submitting input: {:#}", e);
+        return Err(bad_request(&e));
+    }
+
+    workflow_state(&state, workflow_id).await
+}
+
+/// Cancel step handler
+async fn cancel_step(
+    State(state): State<AppState>,
+    Path((workflow_id, step_id)): Path<(WorkflowId, StepId)>,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    if state.workflows.get_workflow(workflow_id).await.is_none() {
+        return Err(not_found(workflow_id));
+    }
+
+    if let Err(e) = state.workflows.cancel_step(workflow_id, step_id).await {
+        warn!("Error cancelling step: {:#}", e);
+        return Err(bad_request(&e));
+    }
+
+    workflow_state(&state, workflow_id).await
+}
+
+/// Cancel workflow handler
+async fn cancel_workflow(
+    State(state): State<AppState>,
+    Path(workflow_id): Path<WorkflowId>,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    if state.workflows.get_workflow(workflow_id).await.is_none() {
+        return Err(not_found(workflow_id));
+    }
+
+    if let Err(e) = state.workflows.cancel_workflow(workflow_id).await {
+        warn!("Error cancelling workflow: {:#}", e);
+        return Err(bad_request(&e));
+    }
+
+    workflow_state(&state, workflow_id).await
+}
+
+/// Pause workflow handler
+async fn pause_workflow(
+    State(state): State<AppState>,
+    Path(workflow_id): Path<WorkflowId>,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    if state.workflows.get_workflow(workflow_id).await.is_none() {
+        return Err(not_found(workflow_id));
+    }
+
+    if let Err(e) = state.workflows.pause_workflow(workflow_id).await {
+        warn!("Error pausing workflow: {:#}", e);
+        return Err(bad_request(&e));
+    }
+
+    workflow_state(&state, workflow_id).await
+}
+
+/// Resume workflow handler
+///
+/// Responds once the workflow has completed or needs input again.
+async fn resume_workflow(
+    State(state): State<AppState>,
+    Path(workflow_id): Path<WorkflowId>,
+) -> Result<Json<WorkflowStateResponse>, ApiError> {
+    if state.workflows.get_workflow(workflow_id).await.is_none() {
+        return Err(not_found(workflow_id));
+    }
+
+    if let Err(e) = state.workflows.resume_workflow(workflow_id).await {
+        warn!("Error resuming workflow: {:#}", e);
+        return Err(bad_request(&e));
+    }
+
+    workflow_state(&state, workflow_id).await
+}
@@ -0,0 +1,626 @@
+//! Model-driven tool use
+//!
+//! [`AgentStep`] hands a language model a task together with the tools of the
+//! connected MCP servers, calls the tools the model asks for and feeds the results
+//! back until the model gives a final answer:
+//!
+//! ```yaml
+//! - id: fix
+//!   type: agent
+//!   depends_on: [test]
+//!   params:
+//!     prompt: "Make these tests pass: {{ steps.test.output.failures }}"
+//!     servers: [filesystem, cargo]
+//!     max_iterations: 20
+//!     token_budget: 200000
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::mcp::{result_text, ToolProvider};
+use crate::model::{
+    ChatRequest, Message, ModelProvider, TokenUsage, ToolDefinition, DEFAULT_MAX_TOKENS,
+};
+use crate::steps::{Step, StepContext, StepResult};
+use crate::template::Template;
+use crate::{StepId, StepStatus};
+
+/// Default number of model requests after which an agent step gives up
+pub const DEFAULT_MAX_AGENT_ITERATIONS: u32 = 10;
+
+/// Instructions an agent step gives the model
+pub const AGENT_SYSTEM_PROMPT: &str = "You are a software engineering agent. \
+Use the tools you are given to complete the task. When the task is done, answer \
+with a summary of what you did and call no more tools.";
+
+/// Separator between server and tool in the tool names the model sees
+///
+/// Model APIs do not allow [`raco_mcp::tools::NAMESPACE_SEPARATOR`] in tool names.
+const MODEL_NAMESPACE_SEPARATOR: &str = "__";
+
+/// Longest tool name the model APIs accept
+const MAX_MODEL_TOOL_NAME: usize = 64;
+
+/// A step that lets a language model use MCP tools to complete a task
+///
+/// Every iteration sends the conversation to the model and calls the tools it asks
+/// for; failing tool calls are reported back to the model rather than failing the
+/// step. The step completes with the first answer that calls no tools and fails
+/// once it runs out of iterations or exceeds its token budget.
+///
+/// The step outputs `{"answer", "iterations", "tool_calls", "model", "usage"}`,
+/// where `tool_calls` lists every call with its qualified tool name.
+#[derive(Debug)]
+pub struct AgentStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Template for the task
+    prompt: Template,
+
+    /// Servers whose tools the model may use; all if empty
+    servers: Vec<String>,
+
+    /// Model to use instead of the model of the engine
+    model: Option<Arc<dyn ModelProvider>>,
+
+    /// Maximum number of model requests
+    max_iterations: u32,
+
+    /// Maximum number of tokens all model requests may consume together
+    token_budget: Option<u64>,
+
+    /// Maximum number of tokens per model response
+    max_tokens: u32,
+}
+
+impl AgentStep {
+    /// Create a step working on the task described by `prompt`
+    #[must_use]
+    pub const fn new(id: StepId, name: String, description: String, prompt: Template) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            prompt,
+            servers: Vec::new(),
+            model: None,
+            max_iterations: DEFAULT_MAX_AGENT_ITERATIONS,
+            token_budget: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
+        }
+    }
+
+    /// Offer only the tools of the named servers
+    #[must_use]
+    pub fn with_servers(mut self, servers: Vec<String>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// Use the given model instead of the model of the engine
+    #[must_use]
+    pub fn with_model(mut self, model: Arc<dyn ModelProvider>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Give up after the given number of model requests
+    #[must_use]
+    pub fn with_max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations.max(1);
+        self
+    }
+
+    /// Give up once the model requests consumed more tokens than `token_budget`
+    #[must_use]
+    pub const fn with_token_budget(mut self, token_budget: u64) -> Self {
+        self.token_budget = Some(token_budget);
+        self
+    }
+
+    /// Limit the number of tokens per model response
+    #[must_use]
+    pub const fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Tools the model may call, with the qualified names they stand for
+    async fn tools(
+        &self,
+        tools: &dyn ToolProvider,
+    ) -> (Vec<ToolDefinition>, HashMap<String, String>) {
+        let mut definitions = Vec::new();
+        let mut names = HashMap::new();
+        for entry in tools.list_tools().await {
+            if !self.servers.is_empty() && !self.servers.contains(&entry.server_name) {
+                continue;
+            }
+            let name = model_tool_name(&entry.server_name, &entry.tool.name);
+            if names.contains_key(&name) {
+                warn!(
+                    "Not offering tool {} to the model: another tool is named {}",
+                    entry.qualified_name(),
+                    name
+                );
+                continue;
+            }
+            let input_schema = if entry.tool.input_schema.is_null() {
+                json!({ "type": "object", "properties": {} })
+            } else {
+                entry.tool.input_schema.clone()
+            };
+            definitions.push(ToolDefinition {
+                name: name.clone(),
+                description: entry.tool.description.clone().unwrap_or_default(),
+                input_schema,
+            });
+            names.insert(name, entry.qualified_name());
+        }
+        (definitions, names)
+    }
+}
+
+/// Name of a tool as the model sees it, e.g. `git__status`
+///
+/// Model APIs only accept names matching `^[a-zA-Z0-9_-]{1,64}$`, so other
+/// characters become `_` and long names are cut short.
+fn model_tool_name(server: &str, tool: &str) -> String {
+    let mut name: String = format!("{server}{MODEL_NAMESPACE_SEPARATOR}{tool}")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name.truncate(MAX_MODEL_TOOL_NAME);
+    name
+}
+
+#[async_trait]
+impl Step for AgentStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<Value> {
+        None
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        Some(json!({
+            "type": "object",
+            "required": ["answer", "iterations", "tool_calls", "usage"],
+            "properties": {
+                "answer": { "type": "string" },
+                "iterations": { "type": "integer" },
+                "tool_calls": { "type": "array", "items": { "type": "object" } },
+                "model": { "type": "string" },
+                "usage": { "type": "object" }
+            }
+        }))
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let model = self
+            .model
+            .as_ref()
+            .or(context.model.as_ref())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Step {} needs a language model; set its 'model' parameter or give the engine one",
+                    self.name
+                )
+            })?;
+        let tools = context.tools.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Step {} needs MCP servers, but none are available",
+                self.name
+            )
+        })?;
+
+        let (definitions, names) = self.tools(tools.as_ref()).await;
+        let prompt = match self.prompt.render(&context)? {
+            Value::String(prompt) => prompt,
+            prompt => prompt.to_string(),
+        };
+        let mut request = ChatRequest {
+            system: Some(AGENT_SYSTEM_PROMPT.to_string()),
+            messages: vec![Message::User { content: prompt }],
+            tools: definitions,
+            max_tokens: self.max_tokens,
+        };
+        let mut usage = TokenUsage::default();
+        let mut calls = Vec::new();
+
+        for iteration in 1..=self.max_iterations {
+            let response = model.chat(&request).await?;
+            usage += response.usage;
+            if let Some(budget) = self.token_budget.filter(|budget| usage.total() > *budget) {
+                bail!(
+                    "Step {} used {} tokens, more than its budget of {}",
+                    self.name,
+                    usage.total(),
+                    budget
+                );
+            }
+
+            if response.tool_calls.is_empty() {
+                return Ok(StepResult {
+                    output: json!({
+                        "answer": response.content,
+                        "iterations": iteration,
+                        "tool_calls": calls,
+                        "model": model.model(),
+                        "usage": usage,
+                    }),
+                    status: StepStatus::Completed,
+                    error: None,
+                });
+            }
+
+            request.messages.push(Message::Assistant {
+                content: response.content,
+                tool_calls: response.tool_calls.clone(),
+            });
+            for call in response.tool_calls {
+                context.cancel.check()?;
+                let (content, is_error) = match (names.get(&call.name), &call.error) {
+                    (Some(_), Some(error)) => (
+                        format!("Invalid arguments for tool {}: {}", call.name, error),
+                        true,
+                    ),
+                    (Some(tool), None) => {
+                        context.progress.report(json!({
+                            "iteration": iteration,
+                            "tool": tool,
+                            "usage": usage,
+                        }));
+                        match tools.call_tool(tool, call.arguments.clone()).await {
+                            Ok(result) => result_text(&result),
+                            Err(e) => (format!("{e:#}"), true),
+                        }
+                    }
+                    (None, _) => (format!("Unknown tool: {}", call.name), true),
+                };
+                calls.push(json!({
+                    "tool": names.get(&call.name).unwrap_or(&call.name),
+                    "arguments": call.arguments,
+                    "is_error": is_error,
+                }));
+                request.messages.push(Message::Tool {
+                    call_id: call.id,
+                    content,
+                    is_error,
+                });
+            }
+        }
+
+        bail!(
+            "Step {} gave no final answer within {} iterations",
+            self.name,
+            self.max_iterations
+        )
+    }
+
+    fn validate_input(&self, _input: &Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::events::Progress;
+    use crate::model::{ChatResponse, ToolCall};
+    use raco_mcp::tools::{Tool, ToolEntry};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    /// Model that calls `git__status` until it has seen `answer_after` results
+    ///
+    /// Each turn it also calls an unknown tool and sends unreadable arguments.
+    #[derive(Debug)]
+    struct ScriptedModel {
+        answer_after: usize,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedModel {
+        fn new(answer_after: usize) -> Arc<Self> {
+            Arc::new(Self {
+                answer_after,
+                requests: Mutex::default(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl ModelProvider for ScriptedModel {
+        fn model(&self) -> &str {
+            "scripted"
+        }
+
+        async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+            self.requests.lock().unwrap().push(request.clone());
+            let results = request
+                .messages
+                .iter()
+                .filter(|message| matches!(message, Message::Tool { .. }))
+                .count();
+            let tool_calls = if results >= self.answer_after {
+                Vec::new()
+            } else {
+                vec![
+                    ToolCall {
+                        id: format!("{results}-a"),
+                        name: "git__status".to_string(),
+                        arguments: json!({ "path": "src" }),
+                        error: None,
+                    },
+                    ToolCall {
+                        id: format!("{results}-b"),
+                        name: "git__push".to_string(),
+                        arguments: json!({}),
+                        error: None,
+                    },
+                    ToolCall {
+                        id: format!("{results}-c"),
+                        name: "git__status".to_string(),
+                        arguments: json!("{\"path\":"),
+                        error: Some("Arguments are not valid JSON".to_string()),
+                    },
+                ]
+            };
+            Ok(ChatResponse {
+                content: if tool_calls.is_empty() {
+                    "All clean"
+                } else {
+                    ""
+                }
+                .to_string(),
+                tool_calls,
+                usage: TokenUsage {
+                    input_tokens: 8,
+                    output_tokens: 2,
+                },
+            })
+        }
+    }
+
+    #[derive(Debug)]
+    struct StatusTool;
+
+    #[async_trait]
+    impl ToolProvider for StatusTool {
+        async fn list_tools(&self) -> Vec<ToolEntry> {
+            ["git", "fs"]
+                .into_iter()
+                .map(|server| ToolEntry {
+                    server_id: Uuid::nil(),
+                    server_name: server.to_string(),
+                    tool: Tool {
+                        name: "status".to_string(),
+                        description: Some("Show the status".to_string()),
+                        input_schema: Value::Null,
+                    },
+                })
+                .collect()
+        }
+
+        async fn resolve_tool(&self, name: &str) -> Result<ToolEntry> {
+            bail!("Unknown tool: {}", name)
+        }
+
+        async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value> {
+            Ok(json!({ "content": [{ "type": "text", "text": format!("{name} {arguments}") }] }))
+        }
+    }
+
+    /// Tools of the given servers and names
+    #[derive(Debug)]
+    struct Catalog(Vec<(&'static str, String)>);
+
+    #[async_trait]
+    impl ToolProvider for Catalog {
+        async fn list_tools(&self) -> Vec<ToolEntry> {
+            self.0
+                .iter()
+                .map(|(server, tool)| ToolEntry {
+                    server_id: Uuid::nil(),
+                    server_name: (*server).to_string(),
+                    tool: Tool {
+                        name: tool.clone(),
+                        description: None,
+                        input_schema: Value::Null,
+                    },
+                })
+                .collect()
+        }
+
+        async fn resolve_tool(&self, name: &str) -> Result<ToolEntry> {
+            bail!("Unknown tool: {}", name)
+        }
+
+        async fn call_tool(&self, name: &str, _arguments: Value) -> Result<Value> {
+            bail!("Unknown tool: {}", name)
+        }
+    }
+
+    fn step() -> AgentStep {
+        let prompt =
+            Template::new(json!("Check {{ input.repo }}"), &|_| bail!("No steps")).unwrap();
+        AgentStep::new(Uuid::new_v4(), "Agent".to_string(), String::new(), prompt)
+            .with_servers(vec!["git".to_string()])
+    }
+
+    fn context(model: &Arc<ScriptedModel>) -> StepContext {
+        StepContext {
+            input: json!({ "repo": "raco" }),
+            previous_outputs: HashMap::new(),
+            global: HashMap::new(),
+            progress: Progress::default(),
+            workflows: None,
+            tools: Some(Arc::new(StatusTool)),
+            model: Some(Arc::clone(model) as Arc<dyn ModelProvider>),
+            cancel: CancelToken::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_uses_tools() {
+        let model = ScriptedModel::new(2);
+        let result = step().execute(context(&model)).await.unwrap();
+
+        assert_eq!(result.status, StepStatus::Completed);
+        assert_eq!(result.output["answer"], "All clean");
+        assert_eq!(result.output["iterations"], 2);
+        assert_eq!(
+            result.output["usage"],
+            json!({ "input_tokens": 16, "output_tokens": 4 })
+        );
+        assert_eq!(
+            result.output["tool_calls"],
+            json!([
+                { "tool": "git/status", "arguments": { "path": "src" }, "is_error": false },
+                { "tool": "git__push", "arguments": {}, "is_error": true },
+                { "tool": "git/status", "arguments": "{\"path\":", "is_error": true }
+            ])
+        );
+
+        let requests = model.requests.lock().unwrap();
+        assert_eq!(requests[0].tools.len(), 1);
+        assert_eq!(requests[0].tools[0].name, "git__status");
+        assert_eq!(requests[0].tools[0].input_schema["type"], "object");
+        assert_eq!(
+            requests[0].messages,
+            [Message::User {
+                content: "Check raco".to_string()
+            }]
+        );
+        assert_eq!(
+            requests[1].messages[2..],
+            [
+                Message::Tool {
+                    call_id: "0-a".to_string(),
+                    content: r#"git/status {"path":"src"}"#.to_string(),
+                    is_error: false
+                },
+                Message::Tool {
+                    call_id: "0-b".to_string(),
+                    content: "Unknown tool: git__push".to_string(),
+                    is_error: true
+                },
+                Message::Tool {
+                    call_id: "0-c".to_string(),
+                    content: "Invalid arguments for tool git__status: Arguments are not valid JSON"
+                        .to_string(),
+                    is_error: true
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_model_tool_names() {
+        let catalog = Catalog(vec![
+            ("my server", "read.file".to_string()),
+            ("my-server", "read file".to_string()),
+            ("git", "x".repeat(80)),
+        ]);
+        let step = AgentStep::new(
+            Uuid::new_v4(),
+            "Agent".to_string(),
+            String::new(),
+            Template::new(json!("x"), &|_| bail!("No steps")).unwrap(),
+        );
+        let (definitions, names) = step.tools(&catalog).await;
+
+        let valid = |name: &str| {
+            (1..=64).contains(&name.len())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        assert!(definitions.iter().all(|tool| valid(&tool.name)));
+        assert_eq!(definitions.len(), 3);
+        assert_eq!(definitions[0].name, "my_server__read_file");
+        assert_eq!(definitions[1].name, "my-server__read_file");
+        assert_eq!(definitions[2].name.len(), 64);
+        assert_eq!(names["my_server__read_file"], "my server/read.file");
+
+        // Tools whose names collide once sanitized are offered once
+        let catalog = Catalog(vec![
+            ("fs", "read.file".to_string()),
+            ("fs", "read_file".to_string()),
+        ]);
+        let (definitions, names) = step.tools(&catalog).await;
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(names["fs__read_file"], "fs/read.file");
+    }
+
+    #[tokio::test]
+    async fn test_agent_limits() {
+        let model = ScriptedModel::new(usize::MAX);
+
+        let error = step()
+            .with_max_iterations(3)
+            .execute(context(&model))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Step Agent gave no final answer within 3 iterations"
+        );
+
+        let error = step()
+            .with_token_budget(15)
+            .execute(context(&model))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Step Agent used 20 tokens, more than its budget of 15"
+        );
+
+        // A final answer over the budget fails as well
+        let error = step()
+            .with_token_budget(5)
+            .execute(context(&ScriptedModel::new(0)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Step Agent used 10 tokens, more than its budget of 5"
+        );
+
+        let mut context = context(&model);
+        context.tools = None;
+        assert!(step().execute(context).await.is_err());
+    }
+}
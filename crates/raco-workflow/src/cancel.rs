@@ -0,0 +1,132 @@
+//! Cooperative cancellation
+//!
+//! Every running step receives a [`CancelToken`] in its context. The engine signals
+//! the token when the step or its workflow is cancelled; the step should then stop
+//! what it is doing and return. Steps that keep running are dropped once the grace
+//! period of the engine has passed, which also kills processes they spawned with
+//! `kill_on_drop` and withdraws MCP requests they were waiting on.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::watch;
+
+/// Default time a cancelled step gets to stop by itself before it is dropped
+pub const DEFAULT_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Signal asking a step or workflow to stop
+///
+/// Clones share the signal. A token created with [`CancelToken::child`] is also
+/// cancelled when its parent is, but cancelling it leaves the parent untouched.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Whether the token itself was cancelled
+    cancelled: watch::Sender<bool>,
+
+    /// Token whose cancellation also cancels this one
+    parent: Option<CancelToken>,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            cancelled: watch::Sender::new(false),
+            parent: None,
+        }
+    }
+}
+
+impl CancelToken {
+    /// Create a token that is not cancelled
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token that is cancelled together with this one
+    #[must_use]
+    pub fn child(&self) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cancelled: watch::Sender::new(false),
+                parent: Some(self.clone()),
+            }),
+        }
+    }
+
+    /// Cancel the token and all of its children
+    pub fn cancel(&self) {
+        self.inner.cancelled.send_replace(true);
+    }
+
+    /// Check if the token or one of its ancestors was cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.cancelled.borrow() || self.inner.parent.as_ref().is_some_and(Self::is_cancelled)
+    }
+
+    /// Fail if the token was cancelled
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token or one of its ancestors was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(anyhow!("Cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait until the token or one of its ancestors is cancelled
+    pub async fn cancelled(&self) {
+        let mut waits = Vec::new();
+        let mut token = Some(self);
+        while let Some(current) = token {
+            let mut cancelled = current.inner.cancelled.subscribe();
+            waits.push(Box::pin(async move {
+                // The sender lives as long as the token, which outlives this wait
+                let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+            }));
+            token = current.inner.parent.as_ref();
+        }
+        futures::future::select_all(waits).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_children_follow_their_parent() {
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let other = parent.child();
+
+        other.cancel();
+        assert!(other.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(child.check().is_ok());
+
+        let waiting = tokio::spawn({
+            let child = child.clone();
+            async move { child.cancelled().await }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!waiting.is_finished());
+
+        parent.cancel();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(child.check().is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Conditions on dependencies
+//!
+//! A condition on a dependency tests the output of the preceding step. When it does
+//! not hold, the dependent step is skipped instead of run.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Test of a value in the output of a step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    /// JSON pointer to the tested value, e.g. `/summary/failed`; empty for the whole output
+    #[serde(default)]
+    pub path: String,
+
+    /// What the value must satisfy
+    #[serde(flatten)]
+    pub test: Test,
+}
+
+/// What a tested value must satisfy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Test {
+    /// The value equals the given value
+    Equals(Value),
+
+    /// The value is missing or differs from the given value
+    NotEquals(Value),
+
+    /// The value is one of the given values
+    OneOf(Vec<Value>),
+
+    /// The value is present (`true`) or missing (`false`)
+    Exists(bool),
+
+    /// The value is truthy (`true`) or falsy (`false`)
+    ///
+    /// Missing values, `null`, `false`, `0`, empty strings, arrays and objects are
+    /// falsy; everything else is truthy.
+    Truthy(bool),
+}
+
+impl Condition {
+    /// Create a condition on the value at `path`
+    #[must_use]
+    pub fn new(path: impl Into<String>, test: Test) -> Self {
+        Self {
+            path: path.into(),
+            test,
+        }
+    }
+
+    /// Check if the path is a valid JSON pointer
+    #[must_use]
+    pub fn has_valid_path(&self) -> bool {
+        self.path.is_empty() || self.path.starts_with('/')
+    }
+
+    /// Check if the condition holds for the output of a step
+    #[must_use]
+    pub fn holds(&self, output: &Value) -> bool {
+        let value = output.pointer(&self.path);
+        match &self.test {
+            Test::Equals(expected) => value == Some(expected),
+            Test::NotEquals(expected) => value != Some(expected),
+            Test::OneOf(expected) => value.is_some_and(|value| expected.contains(value)),
+            Test::Exists(expected) => value.is_some() == *expected,
+            Test::Truthy(expected) => value.is_some_and(is_truthy) == *expected,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        match &self.test {
+            Test::Equals(value) => write!(f, "{path} equals {value}"),
+            Test::NotEquals(value) => write!(f, "{path} does not equal {value}"),
+            Test::OneOf(values) => write!(f, "{path} is one of {}", Value::from(values.clone())),
+            Test::Exists(true) => write!(f, "{path} exists"),
+            Test::Exists(false) => write!(f, "{path} does not exist"),
+            Test::Truthy(true) => write!(f, "{path} is truthy"),
+            Test::Truthy(false) => write!(f, "{path} is falsy"),
+        }
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_holds() {
+        let output = json!({"passed": false, "failures": ["a"], "exit_code": 101});
+
+        assert!(Condition::new("/passed", Test::Equals(json!(false))).holds(&output));
+        assert!(!Condition::new("/passed", Test::NotEquals(json!(false))).holds(&output));
+        assert!(Condition::new("/missing", Test::NotEquals(json!(1))).holds(&output));
+        assert!(
+            Condition::new("/exit_code", Test::OneOf(vec![json!(1), json!(101)])).holds(&output)
+        );
+        assert!(Condition::new("/failures/0", Test::Exists(true)).holds(&output));
+        assert!(Condition::new("/failures/1", Test::Exists(false)).holds(&output));
+        assert!(Condition::new("/failures", Test::Truthy(true)).holds(&output));
+        assert!(Condition::new("/passed", Test::Truthy(false)).holds(&output));
+        assert!(Condition::new("", Test::Truthy(true)).holds(&output));
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let condition: Condition = serde_yaml::from_str("path: /passed\nequals: false\n").unwrap();
+        assert_eq!(
+            condition,
+            Condition::new("/passed", Test::Equals(json!(false)))
+        );
+        assert_eq!(condition.to_string(), "/passed equals false");
+
+        let condition: Condition = toml::from_str("truthy = true").unwrap();
+        assert_eq!(condition.to_string(), "/ is truthy");
+        assert!(condition.has_valid_path());
+        assert!(!Condition::new("passed", Test::Exists(true)).has_valid_path());
+    }
+}
@@ -4,17 +4,38 @@
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use petgraph::algo::has_path_connecting;
 use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Direction;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, RwLock};
-use tracing::{debug, info};
-
-use crate::steps::Step;
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex, Notify, RwLock};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::cancel::{CancelToken, DEFAULT_GRACE_PERIOD};
+use crate::condition::Condition;
+use crate::events::{EventKind, Progress, WorkflowEvent, EVENT_CAPACITY};
+use crate::mcp::ToolProvider;
+use crate::model::ModelProvider;
+use crate::persistence::{StepSnapshot, WorkflowSnapshot, WorkflowStore};
+use crate::policy::{StepAttempt, StepPolicy};
+use crate::registry::StepRegistry;
+use crate::schema;
+use crate::spec::WorkflowSpec;
+use crate::steps::{Rework, Step, StepContext, StepResult, FEEDBACK_KEY};
+use crate::subworkflow::{ParentStep, SubWorkflows, MAX_WORKFLOW_DEPTH};
+use crate::template::Template;
+use crate::validation::Diagnostic;
 use crate::{StepId, StepStatus, WorkflowId, WorkflowStatus};
 
+/// Default number of steps a workflow runs at the same time
+pub const DEFAULT_MAX_PARALLEL_STEPS: usize = 4;
+
 /// A workflow definition
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WorkflowDefinition {
@@ -33,6 +54,58 @@ pub struct WorkflowDefinition {
 
     /// Dependencies between steps (`from_step_id`, `to_step_id`)
     pub dependencies: Vec<(StepId, StepId)>,
+
+    /// Execution policies of steps that do not use the default policy
+    #[serde(default)]
+    pub policies: HashMap<StepId, StepPolicy>,
+
+    /// Conditions on dependencies (`from_step_id`, `to_step_id`, condition)
+    ///
+    /// A step whose conditions do not all hold is skipped, and so is a step whose
+    /// dependencies were all skipped.
+    #[serde(default)]
+    pub conditions: Vec<(StepId, StepId, Condition)>,
+
+    /// Inputs of the steps that declare one, rendered before the step runs
+    ///
+    /// The rendered value replaces the workflow input in the context of the step.
+    #[serde(skip)]
+    pub inputs: HashMap<StepId, Template>,
+}
+
+/// Start and completion time of a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StepTimes {
+    /// Start time of the latest run
+    pub started_at: Option<DateTime<Utc>>,
+
+    /// Completion time of the latest run
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A step waiting for a human response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingInput {
+    /// Workflow the step belongs to
+    pub workflow_id: WorkflowId,
+
+    /// Step waiting for the response
+    pub step_id: StepId,
+
+    /// Name of the step
+    pub step_name: String,
+
+    /// Question to show to the human
+    pub prompt: String,
+
+    /// Everything the step reported while suspending, e.g. the data to review
+    pub request: Value,
+
+    /// Schema the response must match
+    pub response_schema: Option<Value>,
+
+    /// Time the step started waiting
+    pub requested_at: Option<DateTime<Utc>>,
 }
 
 /// A workflow instance
@@ -41,18 +114,40 @@ pub struct WorkflowInstance {
     /// The workflow definition
     definition: WorkflowDefinition,
 
+    /// Workflow file the instance was created from, if any
+    spec: Option<WorkflowSpec>,
+
     /// Current status of the workflow
     status: WorkflowStatus,
 
+    /// Steps of the workflow, taken from the definition
+    steps: HashMap<StepId, Arc<dyn Step>>,
+
     /// Status of each step
     steps_statuses: HashMap<StepId, StepStatus>,
 
+    /// Output of each finished step
+    outputs: HashMap<StepId, Value>,
+
+    /// Error message of each failed step
+    errors: HashMap<StepId, String>,
+
+    /// Start and completion time of each step
+    step_times: HashMap<StepId, StepTimes>,
+
+    /// Input passed to every step
+    input: Value,
+
+    /// Context shared by all steps
+    global: HashMap<String, Value>,
+
+    /// Reason the workflow failed
+    error: Option<String>,
+
     /// Workflow execution graph
-    #[allow(dead_code)]
     graph: DiGraph<StepId, ()>,
 
     /// Mapping from step ID to graph node index
-    #[allow(dead_code)]
     node_map: HashMap<StepId, NodeIndex>,
 
     /// Creation time
@@ -67,6 +162,39 @@ pub struct WorkflowInstance {
     /// Dependencies between steps (`from_step_id`, `to_step_id`)
     #[allow(dead_code)]
     dependencies: Vec<(StepId, StepId)>,
+
+    /// Channel the instance publishes its events on
+    events: broadcast::Sender<WorkflowEvent>,
+
+    /// Steps that only run in place of a failed step
+    handlers: HashSet<StepId>,
+
+    /// Failed step each running or finished failure handler stands in for
+    fallbacks: HashMap<StepId, StepId>,
+
+    /// Attempts of each step
+    attempts: HashMap<StepId, Vec<StepAttempt>>,
+
+    /// Step of another workflow this instance runs for, if it is a sub-workflow
+    parent: Option<ParentStep>,
+
+    /// Sub-workflows started by each step, oldest first
+    children: HashMap<StepId, Vec<WorkflowId>>,
+
+    /// Steps interrupted by a restart that pick up their latest sub-workflow again
+    reattach: HashSet<StepId>,
+
+    /// Signalled when the workflow is cancelled; the tokens of running steps derive from it
+    cancel: CancelToken,
+
+    /// Tokens of the running steps
+    step_cancels: HashMap<StepId, CancelToken>,
+
+    /// Wakes the call scheduling the steps when the workflow is resumed
+    wake: Arc<Notify>,
+
+    /// Whether a call is scheduling the steps of the instance
+    driven: bool,
 }
 
 impl WorkflowInstance {
@@ -75,20 +203,19 @@ impl WorkflowInstance {
     /// # Errors
     ///
     /// Returns an error if the workflow definition is invalid or has circular dependencies
-    pub fn new(definition: WorkflowDefinition) -> Result<Self> {
-        let step_ids: HashSet<_> = definition.steps.iter().map(|step| step.id()).collect();
-
-        // Validate dependencies
-        for (from, to) in &definition.dependencies {
-            if !step_ids.contains(from) {
-                return Err(anyhow::anyhow!(
-                    "Invalid dependency: step {} not found",
-                    from
-                ));
-            }
-            if !step_ids.contains(to) {
-                return Err(anyhow::anyhow!("Invalid dependency: step {} not found", to));
-            }
+    pub fn new(mut definition: WorkflowDefinition) -> Result<Self> {
+        let errors: Vec<_> = definition
+            .validate()
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Invalid workflow {}: {}",
+                definition.name,
+                errors.join("; ")
+            ));
         }
 
         // Create graph
@@ -118,19 +245,58 @@ impl WorkflowInstance {
         // Clone dependencies before moving definition into the struct
         let dependencies = definition.dependencies.clone();
 
+        let handlers = definition
+            .policies
+            .values()
+            .filter_map(|policy| policy.on_failure)
+            .collect();
+
+        // Steps run on their own tasks and are shared with them
+        let steps = std::mem::take(&mut definition.steps)
+            .into_iter()
+            .map(|step| (step.id(), Arc::from(step)))
+            .collect();
+
         Ok(Self {
             definition,
+            spec: None,
             status: WorkflowStatus::Pending,
+            steps,
             steps_statuses,
+            outputs: HashMap::new(),
+            errors: HashMap::new(),
+            step_times: HashMap::new(),
+            input: Value::Object(serde_json::Map::new()),
+            global: HashMap::new(),
+            error: None,
             graph,
             node_map,
             created_at: Utc::now(),
             started_at: None,
             completed_at: None,
             dependencies,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            handlers,
+            fallbacks: HashMap::new(),
+            attempts: HashMap::new(),
+            parent: None,
+            children: HashMap::new(),
+            reattach: HashSet::new(),
+            cancel: CancelToken::new(),
+            step_cancels: HashMap::new(),
+            wake: Arc::new(Notify::new()),
+            driven: false,
         })
     }
 
+    /// Publish an event about this instance
+    fn emit(&self, kind: EventKind) {
+        // Nobody may be listening
+        let _ = self
+            .events
+            .send(WorkflowEvent::new(self.definition.id, kind));
+    }
+
     /// Get the workflow ID
     #[must_use]
     pub fn id(&self) -> WorkflowId {
@@ -155,6 +321,266 @@ impl WorkflowInstance {
         &self.steps_statuses
     }
 
+    /// Get the output of a finished step
+    #[must_use]
+    pub fn step_output(&self, step_id: StepId) -> Option<&Value> {
+        self.outputs.get(&step_id)
+    }
+
+    /// Get the error message of a failed step
+    #[must_use]
+    pub fn step_error(&self, step_id: StepId) -> Option<&str> {
+        self.errors.get(&step_id).map(String::as_str)
+    }
+
+    /// Get the start and completion time of a step
+    #[must_use]
+    pub fn step_times(&self, step_id: StepId) -> StepTimes {
+        self.step_times.get(&step_id).copied().unwrap_or_default()
+    }
+
+    /// Get the attempts made to run a step, oldest first
+    #[must_use]
+    pub fn attempts(&self, step_id: StepId) -> &[StepAttempt] {
+        self.attempts.get(&step_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the step this instance runs for, if it is a sub-workflow
+    #[must_use]
+    pub const fn parent(&self) -> Option<ParentStep> {
+        self.parent
+    }
+
+    /// Get the sub-workflows a step started, oldest first
+    #[must_use]
+    pub fn children(&self, step_id: StepId) -> &[WorkflowId] {
+        self.children.get(&step_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the outputs of the completed steps by the IDs the workflow file uses
+    ///
+    /// Empty for workflows that were not created from a workflow file.
+    #[must_use]
+    pub fn named_outputs(&self) -> serde_json::Map<String, Value> {
+        let Some(spec) = &self.spec else {
+            return serde_json::Map::new();
+        };
+        spec.steps
+            .iter()
+            .map(|step| (&step.id, crate::spec::step_id(self.definition.id, &step.id)))
+            .filter(|(_, id)| self.steps_statuses.get(id) == Some(&StepStatus::Completed))
+            .filter_map(|(key, id)| Some((key.clone(), self.outputs.get(&id)?.clone())))
+            .collect()
+    }
+
+    /// Get the declared input of a step, if any
+    #[must_use]
+    pub fn input(&self, step_id: StepId) -> Option<&Template> {
+        self.definition.inputs.get(&step_id)
+    }
+
+    /// Get the execution policy of a step
+    #[must_use]
+    pub fn policy(&self, step_id: StepId) -> StepPolicy {
+        self.definition
+            .policies
+            .get(&step_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Steps waiting for a human response, in graph order
+    ///
+    /// Steps waiting for a sub-workflow are left out; the sub-workflow lists its own.
+    /// Finished workflows wait for nothing.
+    #[must_use]
+    pub fn pending_inputs(&self) -> Vec<PendingInput> {
+        if self.is_finished() {
+            return Vec::new();
+        }
+        self.graph
+            .node_indices()
+            .map(|node| self.graph[node])
+            .filter(|id| self.steps_statuses[id] == StepStatus::WaitingForInput)
+            // Steps waiting for a sub-workflow are answered by the sub-workflow
+            .filter(|id| !self.children.contains_key(id))
+            .map(|id| {
+                let step = &self.steps[&id];
+                let request = self.outputs.get(&id).cloned().unwrap_or_default();
+                let prompt = request
+                    .get("prompt")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .or_else(|| step.human_input_prompt())
+                    .unwrap_or_else(|| format!("Input needed for step {}", step.name()));
+                PendingInput {
+                    workflow_id: self.definition.id,
+                    step_id: id,
+                    step_name: step.name().to_string(),
+                    prompt,
+                    request,
+                    response_schema: step.response_schema(),
+                    requested_at: self.step_times(id).started_at,
+                }
+            })
+            .collect()
+    }
+
+    /// Check if the workflow completed, failed or was cancelled
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            WorkflowStatus::Completed | WorkflowStatus::Failed | WorkflowStatus::Cancelled
+        )
+    }
+
+    /// Complete a step waiting for input with a human response
+    fn accept_input(&mut self, step_id: StepId, response: Value) -> Result<()> {
+        if self.is_finished() {
+            return Err(anyhow::anyhow!(
+                "Workflow {} already finished with status {:?}",
+                self.definition.id,
+                self.status
+            ));
+        }
+        let step = self
+            .steps
+            .get(&step_id)
+            .ok_or_else(|| anyhow::anyhow!("Step {} not found", step_id))?;
+        if self.steps_statuses[&step_id] != StepStatus::WaitingForInput {
+            return Err(anyhow::anyhow!(
+                "Step {} is not waiting for input",
+                step.name()
+            ));
+        }
+
+        let request = self.outputs.get(&step_id).cloned().unwrap_or_default();
+        let output = step.accept_human_input(&request, response)?;
+        if let Some(output_schema) = step.output_schema() {
+            schema::check(
+                &output_schema,
+                &output,
+                &format!("output of step {}", step.name()),
+            )?;
+        }
+        self.finish_step(
+            step_id,
+            Ok(StepResult {
+                output,
+                status: StepStatus::Completed,
+                error: None,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Get the reason the workflow failed
+    #[must_use]
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Get the workflow file the instance was created from
+    #[must_use]
+    pub fn spec(&self) -> Option<&WorkflowSpec> {
+        self.spec.as_ref()
+    }
+
+    /// Capture the state of the instance for persistence
+    #[must_use]
+    pub fn snapshot(&self) -> WorkflowSnapshot {
+        let steps = self
+            .steps_statuses
+            .iter()
+            .map(|(id, status)| {
+                let times = self.step_times(*id);
+                let snapshot = StepSnapshot {
+                    status: *status,
+                    output: self.outputs.get(id).cloned(),
+                    error: self.errors.get(id).cloned(),
+                    started_at: times.started_at,
+                    completed_at: times.completed_at,
+                    attempts: self.attempts(*id).to_vec(),
+                    fallback_for: self.fallbacks.get(id).copied(),
+                    children: self.children(*id).to_vec(),
+                };
+                (*id, snapshot)
+            })
+            .collect();
+
+        WorkflowSnapshot {
+            id: self.definition.id,
+            name: self.definition.name.clone(),
+            description: self.definition.description.clone(),
+            spec: self.spec.clone(),
+            parent: self.parent,
+            status: self.status,
+            error: self.error.clone(),
+            input: self.input.clone(),
+            global: self.global.clone(),
+            steps,
+            created_at: self.created_at,
+            started_at: self.started_at,
+            completed_at: self.completed_at,
+        }
+    }
+
+    /// Rebuild an instance from a definition and a snapshot of its state
+    ///
+    /// Steps that were running when the snapshot was taken are reset to pending, so
+    /// the workflow resumes after the last completed step.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the definition is invalid
+    pub fn restore(definition: WorkflowDefinition, snapshot: WorkflowSnapshot) -> Result<Self> {
+        let mut instance = Self::new(definition)?;
+        instance.spec = snapshot.spec;
+        instance.parent = snapshot.parent;
+        instance.status = snapshot.status;
+        instance.error = snapshot.error;
+        instance.input = snapshot.input;
+        instance.global = snapshot.global;
+        instance.created_at = snapshot.created_at;
+        instance.started_at = snapshot.started_at;
+        instance.completed_at = snapshot.completed_at;
+
+        for (id, step) in snapshot.steps {
+            let mut times = StepTimes {
+                started_at: step.started_at,
+                completed_at: step.completed_at,
+            };
+            let status = if step.status == StepStatus::Running {
+                times = StepTimes::default();
+                if !step.children.is_empty() {
+                    instance.reattach.insert(id);
+                }
+                StepStatus::Pending
+            } else {
+                if let Some(output) = step.output {
+                    instance.outputs.insert(id, output);
+                }
+                if let Some(error) = step.error {
+                    instance.errors.insert(id, error);
+                }
+                step.status
+            };
+            instance.steps_statuses.insert(id, status);
+            instance.step_times.insert(id, times);
+            if !step.attempts.is_empty() {
+                instance.attempts.insert(id, step.attempts);
+            }
+            if let Some(failed) = step.fallback_for {
+                instance.fallbacks.insert(id, failed);
+            }
+            if !step.children.is_empty() {
+                instance.children.insert(id, step.children);
+            }
+        }
+
+        Ok(instance)
+    }
+
     /// Get the creation time
     #[must_use]
     pub fn created_at(&self) -> DateTime<Utc> {
@@ -172,13 +598,382 @@ impl WorkflowInstance {
     pub fn completed_at(&self) -> Option<DateTime<Utc>> {
         self.completed_at
     }
+
+    /// IDs of the direct predecessors of a step
+    fn predecessors(&self, step_id: StepId) -> impl Iterator<Item = StepId> + '_ {
+        self.graph
+            .neighbors_directed(self.node_map[&step_id], Direction::Incoming)
+            .map(|node| self.graph[node])
+    }
+
+    /// Pending steps whose predecessors have all completed or were skipped, in graph order
+    ///
+    /// Failure handlers are only ready once a step failed over to them.
+    fn ready_steps(&self) -> Vec<StepId> {
+        self.graph
+            .node_indices()
+            .map(|node| self.graph[node])
+            .filter(|id| self.steps_statuses[id] == StepStatus::Pending)
+            .filter(|id| !self.handlers.contains(id) || self.fallbacks.contains_key(id))
+            .filter(|id| {
+                self.predecessors(*id).all(|pred| {
+                    matches!(
+                        self.steps_statuses[&pred],
+                        StepStatus::Completed | StepStatus::Skipped
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Skip the ready steps that must not run, until no more steps are skipped
+    fn skip_steps(&mut self) {
+        loop {
+            let skipped: Vec<_> = self
+                .ready_steps()
+                .into_iter()
+                .filter_map(|id| Some((id, self.skip_reason(id)?)))
+                .collect();
+            if skipped.is_empty() {
+                return;
+            }
+            for (step_id, reason) in skipped {
+                info!("Skipping step {}: {}", self.steps[&step_id].name(), reason);
+                self.steps_statuses.insert(step_id, StepStatus::Skipped);
+                self.emit(EventKind::StepSkipped { step_id, reason });
+            }
+        }
+    }
+
+    /// Reason a ready step must not run, if any
+    fn skip_reason(&self, step_id: StepId) -> Option<String> {
+        let unmet = self
+            .definition
+            .conditions
+            .iter()
+            .filter(|(_, to, _)| *to == step_id)
+            .find(|(from, _, condition)| {
+                self.steps_statuses[from] == StepStatus::Skipped
+                    || !condition.holds(self.outputs.get(from).unwrap_or(&Value::Null))
+            });
+        if let Some((from, _, condition)) = unmet {
+            return Some(format!(
+                "condition on step {} not met: {}",
+                self.steps[from].name(),
+                condition
+            ));
+        }
+
+        let mut predecessors = self.predecessors(step_id).peekable();
+        (predecessors.peek().is_some()
+            && predecessors.all(|pred| self.steps_statuses[&pred] == StepStatus::Skipped))
+        .then(|| "all of its dependencies were skipped".to_string())
+    }
+
+    /// Mark a step as running and build its execution context
+    fn begin_step(&mut self, step_id: StepId) -> (Arc<dyn Step>, StepContext) {
+        self.steps_statuses.insert(step_id, StepStatus::Running);
+        self.step_times.insert(
+            step_id,
+            StepTimes {
+                started_at: Some(Utc::now()),
+                completed_at: None,
+            },
+        );
+        let mut previous_outputs: HashMap<_, _> = self
+            .predecessors(step_id)
+            .filter_map(|pred| Some((pred, self.outputs.get(&pred)?.clone())))
+            .collect();
+        // A failure handler sees the failure as the output of the failed step
+        if let Some(&failed) = self.fallbacks.get(&step_id) {
+            previous_outputs.insert(
+                failed,
+                serde_json::json!({
+                    "step": self.steps[&failed].name(),
+                    "error": self.errors.get(&failed),
+                    "attempts": self.attempts(failed).len(),
+                }),
+            );
+        }
+
+        let cancel = self.cancel.child();
+        self.step_cancels.insert(step_id, cancel.clone());
+        let context = StepContext {
+            input: self.input.clone(),
+            previous_outputs,
+            global: self.global.clone(),
+            progress: Progress::new(self.events.clone(), self.definition.id, step_id),
+            workflows: None,
+            tools: None,
+            model: None,
+            cancel,
+        };
+        let step = Arc::clone(&self.steps[&step_id]);
+        self.emit(EventKind::StepStarted {
+            step_id,
+            name: step.name().to_string(),
+        });
+        (step, context)
+    }
+
+    /// Record the outcome of a step
+    fn finish_step(&mut self, step_id: StepId, result: Result<StepResult>) {
+        let (mut status, output, mut error) = match result {
+            Ok(result) => (result.status, result.output, result.error),
+            Err(e) => (StepStatus::Failed, Value::Null, Some(format!("{e:#}"))),
+        };
+
+        let rework = if status == StepStatus::Completed {
+            self.steps[&step_id].rework(&output)
+        } else {
+            None
+        };
+        if let Some(rework) = &rework {
+            if !self.node_map.contains_key(&rework.step)
+                || !has_path_connecting(
+                    &self.graph,
+                    self.node_map[&rework.step],
+                    self.node_map[&step_id],
+                    None,
+                )
+            {
+                status = StepStatus::Failed;
+                error = Some(format!(
+                    "Cannot run step {} again because it does not precede this step",
+                    rework.step
+                ));
+            }
+        }
+
+        self.steps_statuses.insert(step_id, status);
+        if status != StepStatus::WaitingForInput {
+            self.step_times.entry(step_id).or_default().completed_at = Some(Utc::now());
+        }
+        // A step waiting for input keeps the request it made as its output
+        self.outputs.insert(step_id, output);
+        if let Some(error) = error {
+            self.errors.insert(step_id, error);
+        }
+        self.emit_step_event(step_id);
+
+        if status == StepStatus::Failed
+            && matches!(
+                self.status,
+                WorkflowStatus::Running | WorkflowStatus::Paused
+            )
+        {
+            if let Some(handler) = self.failure_handler(step_id) {
+                info!(
+                    "Step {} failed, falling back to step {}",
+                    self.steps[&step_id].name(),
+                    self.steps[&handler].name()
+                );
+                self.fallbacks.insert(handler, step_id);
+                return;
+            }
+
+            let name = self.steps[&step_id].name().to_string();
+            let reason = self
+                .errors
+                .get(&step_id)
+                .map_or("unknown error", String::as_str);
+            let error = format!("Step {name} failed: {reason}");
+            self.error = Some(error.clone());
+            self.status = WorkflowStatus::Failed;
+            self.emit(EventKind::WorkflowFailed { error });
+            return;
+        }
+
+        if status == StepStatus::Completed {
+            if let Some(&failed) = self.fallbacks.get(&step_id) {
+                self.recover(failed, step_id);
+            }
+        }
+        if let Some(rework) = rework {
+            self.rework(step_id, rework);
+        }
+    }
+
+    /// Unused failure handler of a step, if it has one
+    fn failure_handler(&self, step_id: StepId) -> Option<StepId> {
+        let handler = self.definition.policies.get(&step_id)?.on_failure?;
+        (self.steps_statuses.get(&handler) == Some(&StepStatus::Pending)
+            && !self.fallbacks.contains_key(&handler))
+        .then_some(handler)
+    }
+
+    /// Complete a failed step with the output of its failure handler
+    fn recover(&mut self, failed: StepId, handler: StepId) {
+        let output = self.outputs.get(&handler).cloned().unwrap_or_default();
+        self.steps_statuses.insert(failed, StepStatus::Completed);
+        self.outputs.insert(failed, output);
+        self.errors.remove(&failed);
+        self.emit_step_event(failed);
+    }
+
+    /// Record the attempts a step made
+    fn record_attempts(&mut self, step_id: StepId, attempts: Vec<StepAttempt>) {
+        self.attempts.entry(step_id).or_default().extend(attempts);
+    }
+
+    /// Publish the outcome of a finished step
+    fn emit_step_event(&self, step_id: StepId) {
+        let output = || self.outputs.get(&step_id).cloned().unwrap_or_default();
+        let kind = match self.steps_statuses[&step_id] {
+            StepStatus::Completed => EventKind::StepCompleted {
+                step_id,
+                output: output(),
+            },
+            StepStatus::Failed => EventKind::StepFailed {
+                step_id,
+                error: self
+                    .errors
+                    .get(&step_id)
+                    .cloned()
+                    .unwrap_or_else(|| "unknown error".to_string()),
+            },
+            StepStatus::WaitingForInput => EventKind::StepWaitingForInput {
+                step_id,
+                request: output(),
+            },
+            StepStatus::Pending | StepStatus::Running | StepStatus::Skipped => return,
+        };
+        self.emit(kind);
+    }
+
+    /// Reset a step and everything between it and the requesting step to pending
+    fn rework(&mut self, requester: StepId, rework: Rework) {
+        let (from, to) = (self.node_map[&rework.step], self.node_map[&requester]);
+        let reset: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|&node| {
+                has_path_connecting(&self.graph, from, node, None)
+                    && has_path_connecting(&self.graph, node, to, None)
+            })
+            .map(|node| self.graph[node])
+            .collect();
+
+        info!(
+            "Step {} sent step {} back for rework",
+            self.steps[&requester].name(),
+            self.steps[&rework.step].name()
+        );
+        for step_id in reset {
+            self.steps_statuses.insert(step_id, StepStatus::Pending);
+            self.outputs.remove(&step_id);
+            self.errors.remove(&step_id);
+            self.step_times.remove(&step_id);
+        }
+
+        let feedback = self
+            .global
+            .entry(FEEDBACK_KEY.to_string())
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+        if let Value::Object(feedback) = feedback {
+            feedback.insert(rework.step.to_string(), rework.feedback);
+        }
+    }
+
+    /// Mark an unfinished workflow cancelled; returns whether it was unfinished
+    fn mark_cancelled(&mut self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        self.status = WorkflowStatus::Cancelled;
+        self.completed_at = Some(Utc::now());
+        self.emit(EventKind::WorkflowCancelled);
+        true
+    }
+
+    /// Latest sub-workflows of the steps that are running or waiting for input
+    fn unfinished_children(&self) -> Vec<WorkflowId> {
+        self.children
+            .iter()
+            .filter(|(step_id, _)| {
+                matches!(
+                    self.steps_statuses[*step_id],
+                    StepStatus::Running | StepStatus::WaitingForInput
+                )
+            })
+            .filter_map(|(_, children)| children.last().copied())
+            .collect()
+    }
+
+    /// Settle the workflow status once no step is running
+    fn finish(&mut self) {
+        // A paused workflow settles once it is resumed
+        if self.status == WorkflowStatus::Paused {
+            return;
+        }
+        if self.status == WorkflowStatus::Running {
+            let statuses = || self.steps_statuses.values();
+            if statuses().any(|status| *status == StepStatus::WaitingForInput) {
+                self.status = WorkflowStatus::WaitingForInput;
+                self.emit(EventKind::WorkflowWaitingForInput);
+                return;
+            }
+            // Failure handlers nobody fell back to are not needed
+            let unused: Vec<_> = self
+                .handlers
+                .iter()
+                .filter(|id| {
+                    self.steps_statuses.get(id) == Some(&StepStatus::Pending)
+                        && !self.fallbacks.contains_key(id)
+                })
+                .copied()
+                .collect();
+            for id in unused {
+                self.steps_statuses.insert(id, StepStatus::Skipped);
+            }
+
+            let statuses = || self.steps_statuses.values();
+            if statuses().any(|status| *status == StepStatus::Pending) {
+                let error = "Some steps could not run because their dependencies did not complete"
+                    .to_string();
+                self.error = Some(error.clone());
+                self.status = WorkflowStatus::Failed;
+                self.emit(EventKind::WorkflowFailed { error });
+            } else {
+                self.status = WorkflowStatus::Completed;
+                self.emit(EventKind::WorkflowCompleted);
+            }
+        }
+        self.completed_at = Some(Utc::now());
+    }
 }
 
 /// Workflow engine for managing and executing workflows
-#[derive(Debug)]
+///
+/// Clones share their instances, library and event channel.
+#[derive(Debug, Clone)]
 pub struct WorkflowEngine {
     /// Active workflow instances
     instances: Arc<RwLock<HashMap<WorkflowId, Arc<Mutex<WorkflowInstance>>>>>,
+
+    /// Maximum number of steps a workflow runs at the same time
+    max_parallel_steps: usize,
+
+    /// Step types available to workflow files
+    registry: StepRegistry,
+
+    /// Checkpoint store, if instances are persisted
+    store: Option<WorkflowStore>,
+
+    /// Channel all instances publish their events on
+    events: broadcast::Sender<WorkflowEvent>,
+
+    /// Workflow files available as sub-workflows, by name
+    library: Arc<RwLock<HashMap<String, WorkflowSpec>>>,
+
+    /// Time a cancelled step gets to stop by itself before it is dropped
+    grace_period: Duration,
+
+    /// Tools of the connected MCP servers, handed to every step
+    tools: Option<Arc<dyn ToolProvider>>,
+
+    /// Language model steps use unless they name their own
+    model: Option<Arc<dyn ModelProvider>>,
 }
 
 impl WorkflowEngine {
@@ -187,79 +982,1081 @@ impl WorkflowEngine {
         info!("Creating new workflow engine");
         Self {
             instances: Arc::new(RwLock::new(HashMap::new())),
+            max_parallel_steps: DEFAULT_MAX_PARALLEL_STEPS,
+            registry: StepRegistry::with_builtins(),
+            store: None,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            library: Arc::new(RwLock::new(HashMap::new())),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            tools: None,
+            model: None,
         }
     }
 
+    /// Subscribe to the events of all workflows
+    ///
+    /// The receiver sees every event published after this call. It closes once the
+    /// engine is dropped.
+    pub fn subscribe(&self) -> broadcast::Receiver<WorkflowEvent> {
+        self.events.subscribe()
+    }
+
+    /// Use the given step types for workflow files
+    #[must_use]
+    pub fn with_registry(mut self, registry: StepRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Checkpoint instances to the given store after every transition
+    #[must_use]
+    pub fn with_store(mut self, store: WorkflowStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Limit the number of steps a workflow runs at the same time
+    #[must_use]
+    pub fn with_max_parallel_steps(mut self, max_parallel_steps: usize) -> Self {
+        self.max_parallel_steps = max_parallel_steps.max(1);
+        self
+    }
+
+    /// Give cancelled steps the given time to stop before they are dropped
+    #[must_use]
+    pub const fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Let steps call the tools of the given MCP servers
+    #[must_use]
+    pub fn with_tools(mut self, tools: Arc<dyn ToolProvider>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    /// Let steps use the given language model unless they name their own
+    #[must_use]
+    pub fn with_model(mut self, model: Arc<dyn ModelProvider>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
     /// Create a new workflow instance from a definition
     pub async fn create_workflow(&self, definition: WorkflowDefinition) -> Result<WorkflowId> {
         let instance = WorkflowInstance::new(definition)?;
         let id = instance.id();
 
         info!("Creating workflow instance {}", id);
-        let mut instances = self.instances.write().await;
-        instances.insert(id, Arc::new(Mutex::new(instance)));
+        let name = instance.definition.name.clone();
+        self.insert(instance).await;
+        let _ = self
+            .events
+            .send(WorkflowEvent::new(id, EventKind::WorkflowCreated { name }));
 
         Ok(id)
     }
 
-    /// Get a workflow instance by ID
-    pub async fn get_workflow(&self, id: WorkflowId) -> Option<Arc<Mutex<WorkflowInstance>>> {
-        debug!("Getting workflow instance {}", id);
-        let instances = self.instances.read().await;
-        instances.get(&id).cloned()
+    /// Create a new workflow instance from a workflow file
+    ///
+    /// Unlike workflows assembled in code, these instances can be resumed after a
+    /// restart, because their steps can be rebuilt from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be instantiated or describes an invalid workflow
+    pub async fn create_workflow_from_spec(&self, spec: WorkflowSpec) -> Result<WorkflowId> {
+        self.create_instance(spec, None).await
     }
 
-    /// Start a workflow
-    pub async fn start_workflow(&self, id: WorkflowId) -> Result<()> {
-        info!("Starting workflow {}", id);
-        let instances = self.instances.read().await;
+    async fn create_instance(
+        &self,
+        spec: WorkflowSpec,
+        parent: Option<ParentStep>,
+    ) -> Result<WorkflowId> {
+        let definition = self.registry.instantiate(&spec)?;
+        let mut instance = WorkflowInstance::new(definition)?;
+        instance.spec = Some(spec);
+        instance.parent = parent;
+        // Cancelling the parent step cancels the sub-workflow
+        if let Some(parent) = parent {
+            if let Some(parent_instance) = self.get_workflow(parent.workflow_id).await {
+                if let Some(token) = parent_instance
+                    .lock()
+                    .await
+                    .step_cancels
+                    .get(&parent.step_id)
+                {
+                    instance.cancel = token.child();
+                }
+            }
+        }
+        let id = instance.id();
 
-        let instance = instances
-            .get(&id)
-            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+        info!(
+            "Creating workflow instance {} from {}",
+            id, instance.definition.name
+        );
+        let name = instance.definition.name.clone();
+        self.insert(instance).await;
+        let _ = self
+            .events
+            .send(WorkflowEvent::new(id, EventKind::WorkflowCreated { name }));
 
-        let mut instance = instance.lock().await;
-        if instance.status != WorkflowStatus::Pending {
-            return Err(anyhow::anyhow!("Workflow {} is not in pending state", id));
+        Ok(id)
+    }
+
+    async fn insert(&self, mut instance: WorkflowInstance) {
+        instance.events = self.events.clone();
+        self.checkpoint(&instance).await;
+        let mut instances = self.instances.write().await;
+        instances.insert(instance.id(), Arc::new(Mutex::new(instance)));
+    }
+
+    /// Reload the instances saved in the store
+    ///
+    /// Returns the IDs of the workflows that were running or waiting for input, which
+    /// can be continued with [`WorkflowEngine::resume_workflow`]. Paused workflows stay
+    /// paused until they are resumed explicitly. Sub-workflows are not listed: resuming
+    /// a workflow resumes the running sub-workflows of its waiting steps. Unfinished
+    /// workflows that were not created from a workflow file cannot be rebuilt and are
+    /// marked failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    pub async fn restore(&self) -> Result<Vec<WorkflowId>> {
+        let Some(store) = &self.store else {
+            return Ok(Vec::new());
+        };
+
+        let mut resumable = Vec::new();
+        for mut snapshot in store.load_all().await? {
+            let id = snapshot.id;
+            let unfinished = matches!(
+                snapshot.status,
+                WorkflowStatus::Running | WorkflowStatus::WaitingForInput | WorkflowStatus::Paused
+            );
+
+            let definition = match &snapshot.spec {
+                Some(spec) => self.registry.instantiate_with_id(spec, id),
+                None => {
+                    if unfinished {
+                        snapshot.status = WorkflowStatus::Failed;
+                        snapshot.error = Some(
+                            "Cannot resume a workflow that was not created from a workflow file"
+                                .to_string(),
+                        );
+                    }
+                    Ok(WorkflowDefinition {
+                        id,
+                        name: snapshot.name.clone(),
+                        description: snapshot.description.clone(),
+                        steps: Vec::new(),
+                        dependencies: Vec::new(),
+                        policies: HashMap::new(),
+                        conditions: Vec::new(),
+                        inputs: HashMap::new(),
+                    })
+                }
+            };
+
+            let instance = match definition
+                .and_then(|definition| WorkflowInstance::restore(definition, snapshot))
+            {
+                Ok(instance) => instance,
+                Err(e) => {
+                    warn!("Cannot restore workflow {}: {:#}", id, e);
+                    continue;
+                }
+            };
+
+            // Sub-workflows are continued by the step that started them, or by
+            // resuming their parent while the step waits
+            if instance.parent.is_none()
+                && matches!(
+                    instance.status,
+                    WorkflowStatus::Running | WorkflowStatus::WaitingForInput
+                )
+            {
+                resumable.push(id);
+            }
+            info!("Restored workflow {} with status {:?}", id, instance.status);
+            self.insert(instance).await;
         }
 
-        instance.status = WorkflowStatus::Running;
-        instance.started_at = Some(Utc::now());
+        Ok(resumable)
+    }
 
-        // For now, we'll just mark everything as completed
-        // In a real implementation, we would start executing steps based on the graph
-        for (step_id, status) in &mut instance.steps_statuses {
-            *status = StepStatus::Completed;
-            debug!("Completed step {} in workflow {}", step_id, id);
+    /// Save the state of an instance, if the engine has a store
+    async fn checkpoint(&self, instance: &WorkflowInstance) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save(&instance.snapshot()).await {
+                warn!("Failed to checkpoint workflow {}: {:#}", instance.id(), e);
+            }
         }
+    }
 
-        instance.status = WorkflowStatus::Completed;
-        instance.completed_at = Some(Utc::now());
-        info!("Completed workflow {}", id);
+    /// Make a workflow file available as a sub-workflow under its name
+    ///
+    /// Replaces any workflow registered under the same name.
+    pub async fn register_workflow(&self, spec: WorkflowSpec) {
+        info!("Registering workflow {}", spec.name);
+        let mut library = self.library.write().await;
+        library.insert(spec.name.clone(), spec);
+    }
 
-        Ok(())
+    /// Names of the workflows available as sub-workflows, sorted
+    pub async fn workflow_names(&self) -> Vec<String> {
+        let library = self.library.read().await;
+        let mut names: Vec<_> = library.keys().cloned().collect();
+        names.sort_unstable();
+        names
     }
 
-    /// Cancel a workflow
-    pub async fn cancel_workflow(&self, id: WorkflowId) -> Result<()> {
-        info!("Cancelling workflow {}", id);
-        let instances = self.instances.read().await;
+    /// Run a registered workflow as a sub-workflow of a step
+    ///
+    /// The child instance is recorded under the parent step and runs like any other
+    /// instance, so it publishes events and can wait for human input. Returns the ID
+    /// of the child and its status, which is either completed or waiting for input.
+    /// When a waiting child finishes later, the engine hands its outputs to the parent
+    /// step as the response to the parent's wait (see [`Step::accept_human_input`]).
+    /// A step that was interrupted by a restart picks up its latest sub-workflow
+    /// instead of starting a new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no workflow has the given name, sub-workflows are nested
+    /// too deeply, or the child fails or is cancelled
+    pub async fn run_child(
+        &self,
+        parent: ParentStep,
+        name: &str,
+        input: Value,
+    ) -> Result<(WorkflowId, WorkflowStatus)> {
+        let id = match self.reattach_child(parent).await {
+            Some((id, WorkflowStatus::Running)) => {
+                self.resume_workflow(id).await?;
+                id
+            }
+            Some((id, _)) => id,
+            None => self.start_child(parent, name, input).await?,
+        };
 
-        let instance = instances
-            .get(&id)
+        let instance = self
+            .get_workflow(id)
+            .await
             .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+        let instance = instance.lock().await;
+        match instance.status {
+            status @ (WorkflowStatus::Completed | WorkflowStatus::WaitingForInput) => {
+                Ok((id, status))
+            }
+            WorkflowStatus::Failed => Err(anyhow::anyhow!(
+                "Workflow {} failed: {}",
+                id,
+                instance.error().unwrap_or("unknown error")
+            )),
+            status => Err(anyhow::anyhow!(
+                "Workflow {} stopped with status {:?}",
+                id,
+                status
+            )),
+        }
+    }
 
-        let mut instance = instance.lock().await;
-        if instance.status == WorkflowStatus::Completed || instance.status == WorkflowStatus::Failed
+    /// Settle the parent step of a sub-workflow that finished while the step waited
+    async fn finish_parent(&self, child: WorkflowId) -> Result<()> {
+        let Some(instance) = self.get_workflow(child).await else {
+            return Ok(());
+        };
+        let (parent, status, outputs, error) = {
+            let instance = instance.lock().await;
+            let Some(parent) = instance.parent else {
+                return Ok(());
+            };
+            (
+                parent,
+                instance.status,
+                instance.named_outputs(),
+                instance.error.clone(),
+            )
+        };
+        let Some(parent_instance) = self.get_workflow(parent.workflow_id).await else {
+            return Ok(());
+        };
         {
-            return Err(anyhow::anyhow!("Workflow {} is already finished", id));
+            let parent_instance = parent_instance.lock().await;
+            if parent_instance.step_status(parent.step_id) != Some(StepStatus::WaitingForInput)
+                || parent_instance.children(parent.step_id).last() != Some(&child)
+            {
+                return Ok(());
+            }
+        }
+
+        let error = match status {
+            WorkflowStatus::Completed => {
+                let Err(error) = Box::pin(self.submit_input(
+                    parent.workflow_id,
+                    parent.step_id,
+                    Value::Object(outputs),
+                ))
+                .await
+                else {
+                    return Ok(());
+                };
+                // A parent step that rejected the outputs would otherwise wait forever
+                if parent_instance.lock().await.step_status(parent.step_id)
+                    != Some(StepStatus::WaitingForInput)
+                {
+                    return Err(error);
+                }
+                format!("{error:#}")
+            }
+            WorkflowStatus::Failed => format!(
+                "Workflow {} failed: {}",
+                child,
+                error.as_deref().unwrap_or("unknown error")
+            ),
+            WorkflowStatus::Cancelled => format!("Workflow {child} was cancelled"),
+            _ => return Ok(()),
+        };
+
+        self.fail_waiting_step(parent.workflow_id, parent.step_id, error)
+            .await
+    }
+
+    /// Fail a step waiting for input and continue its workflow
+    async fn fail_waiting_step(
+        &self,
+        id: WorkflowId,
+        step_id: StepId,
+        error: String,
+    ) -> Result<()> {
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        {
+            let mut instance = instance.lock().await;
+            if instance.step_status(step_id) != Some(StepStatus::WaitingForInput) {
+                return Ok(());
+            }
+            let paused = instance.status == WorkflowStatus::WaitingForInput;
+            if paused {
+                instance.status = WorkflowStatus::Running;
+                instance.emit(EventKind::WorkflowStarted);
+            }
+            instance.finish_step(step_id, Err(anyhow::anyhow!(error)));
+            self.checkpoint(&instance).await;
+            if !paused {
+                return Ok(());
+            }
+        }
+        let result = Box::pin(self.drive(id, &instance)).await;
+        let parent = Box::pin(self.finish_parent(id)).await;
+        result.and(parent)
+    }
+
+    /// Run the body of a control flow step as a new sub-workflow of the step
+    ///
+    /// Unlike [`WorkflowEngine::run_child`], every call starts a new child, so a step
+    /// can run its body several times, also at once. Returns the ID of the child and
+    /// its status once it stopped running. A step interrupted by a restart starts
+    /// over, so the children it left unfinished are cancelled first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if sub-workflows are nested too deeply or the child fails
+    pub async fn run_body(
+        &self,
+        parent: ParentStep,
+        spec: WorkflowSpec,
+        input: Value,
+    ) -> Result<(WorkflowId, WorkflowStatus)> {
+        let stale = match self.get_workflow(parent.workflow_id).await {
+            Some(instance) => {
+                let mut instance = instance.lock().await;
+                if instance.reattach.remove(&parent.step_id) {
+                    instance.children(parent.step_id).to_vec()
+                } else {
+                    Vec::new()
+                }
+            }
+            None => Vec::new(),
+        };
+        for child in stale {
+            if let Err(e) = self.cancel_workflow(child).await {
+                debug!("Not cancelling sub-workflow {}: {:#}", child, e);
+            }
+        }
+
+        let id = self.start_spec_child(parent, spec, input).await?;
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+        let status = instance.lock().await.status;
+        Ok((id, status))
+    }
+
+    /// Create a sub-workflow, record it under its parent step and run it
+    async fn start_child(
+        &self,
+        parent: ParentStep,
+        name: &str,
+        input: Value,
+    ) -> Result<WorkflowId> {
+        let spec = self.library.read().await.get(name).cloned();
+        let Some(spec) = spec else {
+            return Err(anyhow::anyhow!(
+                "Unknown workflow '{}', expected one of: {}",
+                name,
+                self.workflow_names().await.join(", ")
+            ));
+        };
+        self.start_spec_child(parent, spec, input).await
+    }
+
+    /// Create a sub-workflow from a workflow file, record it and run it
+    async fn start_spec_child(
+        &self,
+        parent: ParentStep,
+        spec: WorkflowSpec,
+        input: Value,
+    ) -> Result<WorkflowId> {
+        let mut depth = 1;
+        let mut ancestor = Some(parent);
+        while let Some(step) = ancestor {
+            if depth > MAX_WORKFLOW_DEPTH {
+                return Err(anyhow::anyhow!(
+                    "Sub-workflows are nested more than {} levels deep",
+                    MAX_WORKFLOW_DEPTH
+                ));
+            }
+            ancestor = match self.get_workflow(step.workflow_id).await {
+                Some(instance) => instance.lock().await.parent,
+                None => None,
+            };
+            depth += 1;
+        }
+
+        let id = self.create_instance(spec, Some(parent)).await?;
+        if let Some(instance) = self.get_workflow(parent.workflow_id).await {
+            let mut instance = instance.lock().await;
+            instance
+                .children
+                .entry(parent.step_id)
+                .or_default()
+                .push(id);
+            self.checkpoint(&instance).await;
+        }
+        self.start_workflow_with_input(id, input).await?;
+        Ok(id)
+    }
+
+    /// Latest sub-workflow of a step interrupted by a restart, if it was started
+    async fn reattach_child(&self, parent: ParentStep) -> Option<(WorkflowId, WorkflowStatus)> {
+        let id = {
+            let instance = self.get_workflow(parent.workflow_id).await?;
+            let mut instance = instance.lock().await;
+            if !instance.reattach.remove(&parent.step_id) {
+                return None;
+            }
+            *instance.children(parent.step_id).last()?
+        };
+        let status = self.get_workflow(id).await?.lock().await.status;
+        info!(
+            "Reattaching to sub-workflow {} with status {:?}",
+            id, status
+        );
+        (status != WorkflowStatus::Pending).then_some((id, status))
+    }
+
+    /// Get a workflow instance by ID
+    pub async fn get_workflow(&self, id: WorkflowId) -> Option<Arc<Mutex<WorkflowInstance>>> {
+        debug!("Getting workflow instance {}", id);
+        let instances = self.instances.read().await;
+        instances.get(&id).cloned()
+    }
+
+    /// Start a workflow and run it until it finishes or waits for input
+    ///
+    /// Steps run in dependency order, independent steps concurrently up to the
+    /// engine's parallelism limit. Each step receives the outputs of its direct
+    /// predecessors in [`StepContext::previous_outputs`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist, is not pending, or fails
+    pub async fn start_workflow(&self, id: WorkflowId) -> Result<()> {
+        self.start_workflow_with_input(id, Value::Object(serde_json::Map::new()))
+            .await
+    }
+
+    /// Start a workflow, passing `input` to every step
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist, is not pending, or fails
+    pub async fn start_workflow_with_input(&self, id: WorkflowId, input: Value) -> Result<()> {
+        info!("Starting workflow {}", id);
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        {
+            let mut instance = instance.lock().await;
+            if instance.status != WorkflowStatus::Pending {
+                return Err(anyhow::anyhow!("Workflow {} is not in pending state", id));
+            }
+
+            instance.status = WorkflowStatus::Running;
+            instance.started_at = Some(Utc::now());
+            instance.input = input;
+            instance.emit(EventKind::WorkflowStarted);
+            self.checkpoint(&instance).await;
+        }
+
+        self.drive(id, &instance).await
+    }
+
+    /// Continue a paused workflow, or a restored one that was running or waiting for input
+    ///
+    /// If the steps that were running when the workflow was paused are still
+    /// finishing, the call that ran them continues the workflow and this call
+    /// returns right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist, is in another state, or fails
+    pub async fn resume_workflow(&self, id: WorkflowId) -> Result<()> {
+        info!("Resuming workflow {}", id);
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        {
+            let mut instance = instance.lock().await;
+            if !matches!(
+                instance.status,
+                WorkflowStatus::Running | WorkflowStatus::WaitingForInput | WorkflowStatus::Paused
+            ) {
+                return Err(anyhow::anyhow!("Workflow {} cannot be resumed", id));
+            }
+            instance.status = WorkflowStatus::Running;
+            instance.emit(EventKind::WorkflowStarted);
+            self.checkpoint(&instance).await;
+            if instance.driven {
+                instance.wake.notify_one();
+                return Ok(());
+            }
+        }
+
+        let result = self.drive(id, &instance).await;
+        let children = self.resume_children(&instance).await;
+        let parent = self.finish_parent(id).await;
+        result.and(children).and(parent)
+    }
+
+    /// Resume the sub-workflows of waiting steps that are running but not driven
+    ///
+    /// This happens after a restart when the process stopped while a sub-workflow
+    /// went on after an answer; once it finishes, it settles the waiting step.
+    async fn resume_children(&self, instance: &Mutex<WorkflowInstance>) -> Result<()> {
+        let children: Vec<_> = {
+            let instance = instance.lock().await;
+            instance
+                .children
+                .iter()
+                .filter(|(step_id, _)| {
+                    instance.steps_statuses[*step_id] == StepStatus::WaitingForInput
+                })
+                .filter_map(|(_, children)| children.last().copied())
+                .collect()
+        };
+        for child in children {
+            let Some(instance) = self.get_workflow(child).await else {
+                continue;
+            };
+            let stalled = {
+                let instance = instance.lock().await;
+                instance.status == WorkflowStatus::Running && !instance.driven
+            };
+            if stalled {
+                Box::pin(self.resume_workflow(child)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Steps waiting for a human response across all workflows, oldest first
+    pub async fn pending_inputs(&self) -> Vec<PendingInput> {
+        let instances: Vec<_> = self.instances.read().await.values().cloned().collect();
+        let mut pending = Vec::new();
+        for instance in instances {
+            pending.extend(instance.lock().await.pending_inputs());
+        }
+        pending.sort_by_key(|input| input.requested_at);
+        pending
+    }
+
+    /// Steps waiting for a human response in a workflow and its running sub-workflows
+    pub async fn pending_inputs_of(&self, id: WorkflowId) -> Vec<PendingInput> {
+        let mut pending = Vec::new();
+        let mut workflows = vec![id];
+        while let Some(id) = workflows.pop() {
+            let Some(instance) = self.get_workflow(id).await else {
+                continue;
+            };
+            let instance = instance.lock().await;
+            pending.extend(instance.pending_inputs());
+            workflows.extend(
+                instance
+                    .children
+                    .iter()
+                    .filter(|(step_id, _)| {
+                        instance.steps_statuses[*step_id] == StepStatus::WaitingForInput
+                    })
+                    .filter_map(|(_, children)| children.last().copied()),
+            );
+        }
+        pending.sort_by_key(|input| input.requested_at);
+        pending
+    }
+
+    /// Answer a step waiting for input and continue the workflow
+    ///
+    /// The response must match the output schema of the step; see
+    /// [`Step::accept_human_input`]. If the workflow was paused, it runs until it
+    /// completes or waits for input again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow or step does not exist, the step is not
+    /// waiting for input, the response is invalid, or the workflow fails afterwards
+    pub async fn submit_input(
+        &self,
+        id: WorkflowId,
+        step_id: StepId,
+        response: Value,
+    ) -> Result<()> {
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        {
+            let mut instance = instance.lock().await;
+            instance.accept_input(step_id, response)?;
+            info!("Received input for step {} in workflow {}", step_id, id);
+
+            // A running workflow picks up the newly ready steps by itself
+            let paused = instance.status == WorkflowStatus::WaitingForInput;
+            if paused {
+                instance.status = WorkflowStatus::Running;
+                instance.emit(EventKind::WorkflowStarted);
+            }
+            self.checkpoint(&instance).await;
+            if !paused {
+                return Ok(());
+            }
         }
 
-        instance.status = WorkflowStatus::Cancelled;
-        instance.completed_at = Some(Utc::now());
+        let result = self.drive(id, &instance).await;
+        let parent = self.finish_parent(id).await;
+        result.and(parent)
+    }
+
+    /// Run a workflow and turn a failure into an error
+    async fn drive(&self, id: WorkflowId, instance: &Mutex<WorkflowInstance>) -> Result<()> {
+        self.run_workflow(id, instance).await;
+
+        let instance = instance.lock().await;
+        match instance.status {
+            WorkflowStatus::Failed => Err(anyhow::anyhow!(
+                "Workflow {} failed: {}",
+                id,
+                instance.error().unwrap_or("unknown error")
+            )),
+            status => {
+                info!("Workflow {} finished with status {:?}", id, status);
+                Ok(())
+            }
+        }
+    }
+
+    /// Schedule the steps of a running workflow until none can make progress
+    async fn run_workflow(&self, id: WorkflowId, instance: &Mutex<WorkflowInstance>) {
+        let mut running = JoinSet::new();
+        let mut tasks = HashMap::new();
+        let (cancel, wake) = {
+            let mut instance = instance.lock().await;
+            instance.driven = true;
+            (instance.cancel.clone(), Arc::clone(&instance.wake))
+        };
+
+        loop {
+            {
+                let mut instance = instance.lock().await;
+                // The token of a sub-workflow also fires when its parent step is cancelled
+                if cancel.is_cancelled() && instance.mark_cancelled() {
+                    self.checkpoint(&instance).await;
+                }
+                // Paused, cancelled and failed workflows let running steps finish but
+                // start no new ones
+                if instance.status == WorkflowStatus::Running {
+                    instance.skip_steps();
+                    let free = self.max_parallel_steps.saturating_sub(running.len());
+                    for step_id in instance.ready_steps().into_iter().take(free) {
+                        let (step, mut context) = instance.begin_step(step_id);
+                        context.workflows = Some(SubWorkflows::new(
+                            self.clone(),
+                            ParentStep {
+                                workflow_id: id,
+                                step_id,
+                            },
+                        ));
+                        context.tools.clone_from(&self.tools);
+                        context.model.clone_from(&self.model);
+                        let input = instance.input(step_id).cloned();
+                        let policy = instance.policy(step_id);
+                        let events = instance.events.clone();
+                        let grace_period = self.grace_period;
+                        debug!("Running step {} in workflow {}", step_id, id);
+                        let task = running.spawn(async move {
+                            match render_input(step.as_ref(), context, input.as_ref()) {
+                                Ok(context) => {
+                                    run_attempts(step, context, policy, grace_period, events, id)
+                                        .await
+                                }
+                                Err(e) => (Err(e), Vec::new()),
+                            }
+                        });
+                        tasks.insert(task.id(), step_id);
+                    }
+                    if !tasks.is_empty() {
+                        self.checkpoint(&instance).await;
+                    }
+                }
+
+                if running.is_empty() {
+                    instance.driven = false;
+                    instance.finish();
+                    self.checkpoint(&instance).await;
+                    return;
+                }
+            }
+
+            let joined = tokio::select! {
+                joined = running.join_next_with_id() => joined,
+                // Resuming a paused workflow lets it start new steps
+                () = wake.notified() => continue,
+            };
+            let (task, (result, attempts)) = match joined {
+                Some(Ok((task, outcome))) => (task, outcome),
+                Some(Err(e)) => (
+                    e.id(),
+                    (Err(anyhow::anyhow!("Step panicked: {e}")), Vec::new()),
+                ),
+                None => continue,
+            };
+            let Some(step_id) = tasks.remove(&task) else {
+                continue;
+            };
+
+            let mut instance = instance.lock().await;
+            instance.step_cancels.remove(&step_id);
+            instance.record_attempts(step_id, attempts);
+            if instance.status == WorkflowStatus::Cancelled {
+                // Steps interrupted by cancellation can run again later
+                instance.steps_statuses.insert(step_id, StepStatus::Pending);
+            } else {
+                if let Err(e) = &result {
+                    warn!("Step {} in workflow {} failed: {:#}", step_id, id, e);
+                }
+                instance.finish_step(step_id, result);
+            }
+            self.checkpoint(&instance).await;
+        }
+    }
+
+    /// Cancel a workflow
+    ///
+    /// Running steps are asked to stop through the [`CancelToken`] in their context
+    /// and dropped if they still run once the grace period is over. Sub-workflows
+    /// the workflow waits on are cancelled as well, and a step waiting on this
+    /// workflow fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist or already finished, or if
+    /// the workflow of the waiting step fails afterwards
+    pub async fn cancel_workflow(&self, id: WorkflowId) -> Result<()> {
+        info!("Cancelling workflow {}", id);
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        let children = {
+            let mut instance = instance.lock().await;
+            if !instance.mark_cancelled() {
+                return Err(anyhow::anyhow!("Workflow {} is already finished", id));
+            }
+            instance.cancel.cancel();
+            self.checkpoint(&instance).await;
+            instance.unfinished_children()
+        };
+        for child in children {
+            // A sub-workflow may finish while its parent is cancelled
+            if let Err(e) = Box::pin(self.cancel_workflow(child)).await {
+                debug!("Not cancelling sub-workflow {}: {:#}", child, e);
+            }
+        }
+        // A step waiting on this workflow fails
+        self.finish_parent(id).await
+    }
 
+    /// Cancel a single step
+    ///
+    /// A running step is asked to stop like the steps of a cancelled workflow; a step
+    /// waiting for input or for a sub-workflow stops waiting, and the sub-workflow is
+    /// cancelled. Either way the step fails without further attempts, so its failure
+    /// handler takes over if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow or step does not exist, the step is neither
+    /// running nor waiting, or the workflow fails afterwards
+    pub async fn cancel_step(&self, id: WorkflowId, step_id: StepId) -> Result<()> {
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        let (name, child) = {
+            let instance = instance.lock().await;
+            let name = instance
+                .steps
+                .get(&step_id)
+                .ok_or_else(|| anyhow::anyhow!("Step {} not found", step_id))?
+                .name()
+                .to_string();
+            match instance.step_status(step_id) {
+                Some(StepStatus::Running) => {
+                    info!("Cancelling step {} in workflow {}", name, id);
+                    if let Some(token) = instance.step_cancels.get(&step_id) {
+                        token.cancel();
+                    }
+                    return Ok(());
+                }
+                Some(StepStatus::WaitingForInput) => {
+                    (name, instance.children(step_id).last().copied())
+                }
+                _ => return Err(anyhow::anyhow!("Step {} is not running", name)),
+            }
+        };
+
+        info!("Cancelling step {} in workflow {}", name, id);
+        match child {
+            // The step fails once its sub-workflow is cancelled
+            Some(child) => Box::pin(self.cancel_workflow(child)).await,
+            None => {
+                self.fail_waiting_step(id, step_id, format!("Step {name} was cancelled"))
+                    .await
+            }
+        }
+    }
+
+    /// Pause a workflow
+    ///
+    /// Running steps finish, but no new steps start until the workflow is continued
+    /// with [`WorkflowEngine::resume_workflow`]. Steps waiting for input still accept
+    /// answers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist or is neither running nor
+    /// waiting for input
+    pub async fn pause_workflow(&self, id: WorkflowId) -> Result<()> {
+        info!("Pausing workflow {}", id);
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+
+        let mut instance = instance.lock().await;
+        if !matches!(
+            instance.status,
+            WorkflowStatus::Running | WorkflowStatus::WaitingForInput
+        ) {
+            return Err(anyhow::anyhow!("Workflow {} is not running", id));
+        }
+        instance.status = WorkflowStatus::Paused;
+        instance.emit(EventKind::WorkflowPaused);
+        self.checkpoint(&instance).await;
         Ok(())
     }
+
+    /// Cancel a workflow whenever the given token is cancelled
+    ///
+    /// Call this before starting the workflow; steps that are already running keep
+    /// the token they started with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist
+    pub async fn cancel_with(&self, id: WorkflowId, token: &CancelToken) -> Result<()> {
+        let instance = self
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} not found", id))?;
+        instance.lock().await.cancel = token.child();
+        Ok(())
+    }
+}
+
+/// Run a step until it succeeds or its policy gives up, recording every attempt
+async fn run_attempts(
+    step: Arc<dyn Step>,
+    context: StepContext,
+    policy: StepPolicy,
+    grace_period: Duration,
+    events: broadcast::Sender<WorkflowEvent>,
+    workflow_id: WorkflowId,
+) -> (Result<StepResult>, Vec<StepAttempt>) {
+    let mut attempts = Vec::new();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let started_at = Utc::now();
+        let result = run_attempt(step.as_ref(), context.clone(), &policy, grace_period).await;
+        let (status, error) = match &result {
+            Ok(result) => (result.status, result.error.clone()),
+            Err(e) => (StepStatus::Failed, Some(format!("{e:#}"))),
+        };
+        attempts.push(StepAttempt {
+            attempt,
+            started_at,
+            completed_at: Utc::now(),
+            status,
+            error: error.clone(),
+        });
+        if status != StepStatus::Failed || attempt > policy.retries || context.cancel.is_cancelled()
+        {
+            return (result, attempts);
+        }
+
+        let delay = policy.backoff.delay(attempt);
+        warn!(
+            "Attempt {} of step {} failed, retrying in {:?}",
+            attempt,
+            step.name(),
+            delay
+        );
+        let _ = events.send(WorkflowEvent::new(
+            workflow_id,
+            EventKind::StepRetrying {
+                step_id: step.id(),
+                attempt: attempt + 1,
+                error: error.unwrap_or_default(),
+                delay_secs: delay.as_secs_f64(),
+            },
+        ));
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {}
+            () = context.cancel.cancelled() => return (result, attempts),
+        }
+    }
+}
+
+/// Put the rendered input of a step into its context
+///
+/// Rendering depends only on the outputs of earlier steps, so a step whose input
+/// cannot be rendered fails without being tried.
+fn render_input(
+    step: &dyn Step,
+    mut context: StepContext,
+    input: Option<&Template>,
+) -> Result<StepContext> {
+    if let Some(input) = input {
+        context.input = input
+            .render(&context)
+            .with_context(|| format!("Invalid input of step {}", step.name()))?;
+    }
+    Ok(context)
+}
+
+/// Run a single attempt of a step, cancelling it when it exceeds the timeout
+///
+/// The input is checked against the input schema of the step before it runs, and
+/// the output of a completed step against its output schema.
+///
+/// Once the token in the context is cancelled, the step has `grace_period` to
+/// return before it is dropped.
+async fn run_attempt(
+    step: &dyn Step,
+    mut context: StepContext,
+    policy: &StepPolicy,
+    grace_period: Duration,
+) -> Result<StepResult> {
+    context.input = step.resolve_input(&context)?;
+    if let Some(input_schema) = step.input_schema() {
+        schema::check(
+            &input_schema,
+            &context.input,
+            &format!("input of step {}", step.name()),
+        )?;
+    }
+    step.validate_input(&context.input)?;
+    let cancel = context.cancel.clone();
+    let execute = async {
+        match policy.timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, step.execute(context))
+                .await
+                .map_err(|_| anyhow::anyhow!("Step timed out after {:?}", timeout))?,
+            None => step.execute(context).await,
+        }
+    };
+    tokio::pin!(execute);
+
+    let finished = tokio::select! {
+        result = &mut execute => Some(result),
+        () = cancel.cancelled() => None,
+    };
+    let result = match finished {
+        Some(result) => result,
+        None => tokio::time::timeout(grace_period, execute)
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Step {} was cancelled and did not stop within {:?}",
+                    step.name(),
+                    grace_period
+                )
+            })?,
+    };
+    let result = match result {
+        Err(e) if cancel.is_cancelled() => {
+            return Err(e.context(format!("Step {} was cancelled", step.name())))
+        }
+        result => result?,
+    };
+    if result.status == StepStatus::Completed {
+        if let Some(output_schema) = step.output_schema() {
+            schema::check(
+                &output_schema,
+                &result.output,
+                &format!("output of step {}", step.name()),
+            )?;
+        }
+    }
+    Ok(result)
 }
 
 impl Default for WorkflowEngine {
@@ -271,9 +2068,177 @@ impl Default for WorkflowEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::condition::Test;
+    use crate::policy::Backoff;
     use crate::steps::MockStep;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
     use uuid::Uuid;
 
+    type Behavior = dyn Fn(&StepContext) -> Result<StepResult> + Send + Sync;
+
+    /// A step that sleeps briefly, then runs a closure over its context
+    ///
+    /// The step stops sleeping and fails when it is cancelled, unless it is stubborn.
+    struct TestStep {
+        id: StepId,
+        name: String,
+        behavior: Box<Behavior>,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+        delay: Duration,
+        stubborn: bool,
+        schemas: (Option<Value>, Option<Value>),
+        response_schema: Option<Value>,
+    }
+
+    impl std::fmt::Debug for TestStep {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("TestStep")
+                .field("name", &self.name)
+                .finish()
+        }
+    }
+
+    impl TestStep {
+        fn new<F>(name: &str, behavior: F) -> Self
+        where
+            F: Fn(&StepContext) -> Result<StepResult> + Send + Sync + 'static,
+        {
+            Self {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+                behavior: Box::new(behavior),
+                running: Arc::new(AtomicUsize::new(0)),
+                max_running: Arc::new(AtomicUsize::new(0)),
+                delay: Duration::from_millis(20),
+                stubborn: false,
+                schemas: (None, None),
+                response_schema: None,
+            }
+        }
+
+        /// A step returning `{"name": <name>, "inputs": <number of previous outputs>}`
+        fn echo(name: &str) -> Self {
+            let owned = name.to_string();
+            Self::new(name, move |context| {
+                Ok(completed(json!({
+                    "name": owned,
+                    "inputs": context.previous_outputs.len(),
+                })))
+            })
+        }
+
+        fn slow(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+
+        fn stubborn(mut self) -> Self {
+            self.stubborn = true;
+            self
+        }
+
+        fn with_schemas(mut self, input: Option<Value>, output: Option<Value>) -> Self {
+            self.schemas = (input, output);
+            self
+        }
+
+        fn with_response_schema(mut self, response_schema: Value) -> Self {
+            self.response_schema = Some(response_schema);
+            self
+        }
+
+        fn counting(mut self, running: &Arc<AtomicUsize>, max_running: &Arc<AtomicUsize>) -> Self {
+            self.running = Arc::clone(running);
+            self.max_running = Arc::clone(max_running);
+            self
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Step for TestStep {
+        fn id(&self) -> StepId {
+            self.id
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn description(&self) -> &str {
+            "A configurable step for testing"
+        }
+
+        fn input_schema(&self) -> Option<Value> {
+            self.schemas.0.clone()
+        }
+
+        fn output_schema(&self) -> Option<Value> {
+            self.schemas.1.clone()
+        }
+
+        fn response_schema(&self) -> Option<Value> {
+            self.response_schema
+                .clone()
+                .or_else(|| self.output_schema())
+        }
+
+        async fn execute(&self, context: StepContext) -> Result<StepResult> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(now, Ordering::SeqCst);
+            let sleep = tokio::time::sleep(self.delay);
+            let stopped = if self.stubborn {
+                sleep.await;
+                false
+            } else {
+                tokio::select! {
+                    () = sleep => false,
+                    () = context.cancel.cancelled() => true,
+                }
+            };
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            if stopped {
+                return Err(anyhow::anyhow!("stopped early"));
+            }
+            (self.behavior)(&context)
+        }
+
+        fn validate_input(&self, _input: &Value) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn completed(output: Value) -> StepResult {
+        StepResult {
+            output,
+            status: StepStatus::Completed,
+            error: None,
+        }
+    }
+
+    fn definition(steps: Vec<TestStep>, dependencies: &[(usize, usize)]) -> WorkflowDefinition {
+        let ids: Vec<_> = steps.iter().map(|step| step.id).collect();
+        WorkflowDefinition {
+            id: Uuid::new_v4(),
+            name: "Test Workflow".to_string(),
+            description: "A test workflow".to_string(),
+            steps: steps
+                .into_iter()
+                .map(|step| Box::new(step) as Box<dyn Step>)
+                .collect(),
+            dependencies: dependencies
+                .iter()
+                .map(|&(from, to)| (ids[from], ids[to]))
+                .collect(),
+            policies: HashMap::new(),
+            conditions: Vec::new(),
+            inputs: HashMap::new(),
+        }
+    }
+
     fn create_test_workflow() -> WorkflowDefinition {
         let step1 = Box::new(MockStep::new(Uuid::new_v4()));
         let step2 = Box::new(MockStep::new(Uuid::new_v4()));
@@ -286,6 +2251,9 @@ mod tests {
             description: "A test workflow".to_string(),
             steps: vec![step1, step2],
             dependencies: vec![(step1_id, step2_id)],
+            policies: HashMap::new(),
+            conditions: Vec::new(),
+            inputs: HashMap::new(),
         }
     }
 
@@ -296,6 +2264,16 @@ mod tests {
         assert!(instance.is_ok());
     }
 
+    #[test]
+    fn test_cyclic_workflow_is_rejected() {
+        let mut definition = create_test_workflow();
+        let (first, second) = definition.dependencies[0];
+        definition.dependencies.push((second, first));
+
+        let error = WorkflowInstance::new(definition).unwrap_err();
+        assert!(error.to_string().contains("Circular dependency"));
+    }
+
     #[tokio::test]
     async fn test_workflow_engine_creation() {
         let engine = WorkflowEngine::new();
@@ -345,4 +2323,905 @@ mod tests {
         let instance = engine.get_workflow(id).await.unwrap();
         assert_eq!(instance.lock().await.status(), WorkflowStatus::Cancelled);
     }
+
+    /// Wait until a step of a workflow has the given status
+    async fn wait_for_step(
+        engine: &WorkflowEngine,
+        id: WorkflowId,
+        step_id: StepId,
+        status: StepStatus,
+    ) {
+        let instance = engine.get_workflow(id).await.unwrap();
+        while instance.lock().await.step_status(step_id) != Some(status) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_running_steps() {
+        let steps = vec![
+            TestStep::echo("build").slow(Duration::from_secs(60)),
+            TestStep::echo("test"),
+        ];
+        let build = steps[0].id;
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1)]))
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.start_workflow(id).await }
+        });
+
+        wait_for_step(&engine, id, build, StepStatus::Running).await;
+        engine.cancel_workflow(id).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert!(engine.cancel_workflow(id).await.is_err());
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Cancelled);
+        assert_eq!(instance.step_status(build), Some(StepStatus::Pending));
+        assert_eq!(
+            instance.attempts(build)[0].error.as_deref(),
+            Some("Step build was cancelled: stopped early")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_drops_steps_after_grace_period() {
+        let steps = vec![TestStep::echo("build")
+            .slow(Duration::from_secs(60))
+            .stubborn()];
+        let build = steps[0].id;
+        let engine = WorkflowEngine::new().with_grace_period(Duration::from_millis(10));
+        let id = engine
+            .create_workflow(definition(steps, &[]))
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.start_workflow(id).await }
+        });
+
+        wait_for_step(&engine, id, build, StepStatus::Running).await;
+        engine.cancel_workflow(id).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Cancelled);
+        assert!(instance.attempts(build)[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("did not stop within 10ms"));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_step() {
+        let steps = vec![
+            TestStep::echo("deploy").slow(Duration::from_secs(60)),
+            TestStep::echo("rollback"),
+            TestStep::echo("notify"),
+        ];
+        let (deploy, rollback, notify) = (steps[0].id, steps[1].id, steps[2].id);
+        let mut workflow = definition(steps, &[]);
+        workflow.policies.insert(
+            deploy,
+            StepPolicy::default()
+                .with_retries(3)
+                .with_on_failure(rollback),
+        );
+
+        let engine = WorkflowEngine::new().with_max_parallel_steps(1);
+        let id = engine.create_workflow(workflow).await.unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.start_workflow(id).await }
+        });
+
+        wait_for_step(&engine, id, deploy, StepStatus::Running).await;
+        let error = engine.cancel_step(id, notify).await.unwrap_err();
+        assert_eq!(error.to_string(), "Step notify is not running");
+        engine.cancel_step(id, deploy).await.unwrap();
+        run.await.unwrap().unwrap();
+
+        // The step is not retried, and its failure handler takes over
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.attempts(deploy).len(), 1);
+        assert_eq!(instance.step_status(rollback), Some(StepStatus::Completed));
+        assert_eq!(instance.step_status(notify), Some(StepStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let steps = vec![
+            TestStep::echo("build").slow(Duration::from_millis(50)),
+            TestStep::echo("test"),
+        ];
+        let (build, test) = (steps[0].id, steps[1].id);
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1)]))
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.start_workflow(id).await }
+        });
+
+        // The running step finishes, but the next one waits
+        wait_for_step(&engine, id, build, StepStatus::Running).await;
+        engine.pause_workflow(id).await.unwrap();
+        run.await.unwrap().unwrap();
+        let instance = engine.get_workflow(id).await.unwrap();
+        {
+            let instance = instance.lock().await;
+            assert_eq!(instance.status(), WorkflowStatus::Paused);
+            assert_eq!(instance.step_status(build), Some(StepStatus::Completed));
+            assert_eq!(instance.step_status(test), Some(StepStatus::Pending));
+            assert_eq!(instance.completed_at(), None);
+        }
+        assert!(engine.pause_workflow(id).await.is_err());
+
+        engine.resume_workflow(id).await.unwrap();
+        assert_eq!(instance.lock().await.status(), WorkflowStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_resume_while_steps_are_running() {
+        let steps = vec![
+            TestStep::echo("build").slow(Duration::from_millis(50)),
+            TestStep::echo("test"),
+        ];
+        let build = steps[0].id;
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1)]))
+            .await
+            .unwrap();
+        let run = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.start_workflow(id).await }
+        });
+
+        // The call that started the workflow carries on
+        wait_for_step(&engine, id, build, StepStatus::Running).await;
+        engine.pause_workflow(id).await.unwrap();
+        engine.resume_workflow(id).await.unwrap();
+        run.await.unwrap().unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        assert_eq!(instance.lock().await.status(), WorkflowStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_steps_receive_predecessor_outputs() {
+        // a -> b, a -> c, (b, c) -> d
+        let d = TestStep::new("d", |context| {
+            let names: HashSet<_> = context
+                .previous_outputs
+                .values()
+                .map(|output| output["name"].as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(names, HashSet::from(["b".to_string(), "c".to_string()]));
+            Ok(completed(
+                json!({ "joined": context.previous_outputs.len() }),
+            ))
+        });
+        let steps = vec![
+            TestStep::echo("a"),
+            TestStep::echo("b"),
+            TestStep::echo("c"),
+            d,
+        ];
+        let ids: Vec<_> = steps.iter().map(|step| step.id).collect();
+
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1), (0, 2), (1, 3), (2, 3)]))
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.step_output(ids[0]).unwrap()["inputs"], 0);
+        assert_eq!(instance.step_output(ids[1]).unwrap()["inputs"], 1);
+        assert_eq!(instance.step_output(ids[3]).unwrap()["joined"], 2);
+        assert!(instance.completed_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_parallelism_limit() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let steps = (0..6)
+            .map(|i| TestStep::echo(&format!("step{i}")).counting(&running, &max_running))
+            .collect();
+
+        let engine = WorkflowEngine::new().with_max_parallel_steps(2);
+        let id = engine
+            .create_workflow(definition(steps, &[]))
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_step_failure_fails_workflow() {
+        let steps = vec![
+            TestStep::new("compile", |_| Err(anyhow::anyhow!("linker not found"))),
+            TestStep::echo("test"),
+        ];
+        let ids: Vec<_> = steps.iter().map(|step| step.id).collect();
+
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1)]))
+            .await
+            .unwrap();
+        let error = engine.start_workflow(id).await.unwrap_err();
+        assert!(error.to_string().contains("linker not found"));
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Failed);
+        assert_eq!(instance.step_status(ids[0]), Some(StepStatus::Failed));
+        assert_eq!(instance.step_error(ids[0]), Some("linker not found"));
+        assert_eq!(instance.step_status(ids[1]), Some(StepStatus::Pending));
+        assert!(instance.error().unwrap().contains("compile"));
+    }
+
+    #[tokio::test]
+    async fn test_inputs_and_outputs_match_schemas() {
+        let output = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": { "name": { "type": "string" }, "inputs": { "type": "integer" } }
+        });
+        for (input_schema, output_schema, expected) in [
+            (Some(json!({"type": "object"})), Some(output), None),
+            (
+                Some(json!({"type": "object", "required": ["branch"]})),
+                None,
+                Some("Invalid input of step check:\n  - /: \"branch\" is a required property"),
+            ),
+            (
+                None,
+                Some(json!({"properties": { "inputs": { "type": "string" } }})),
+                Some("Invalid output of step check:\n  - /inputs: 0 is not of type \"string\""),
+            ),
+        ] {
+            let executions = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&executions);
+            let step = TestStep::new("check", move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(completed(json!({"name": "check", "inputs": 0})))
+            })
+            .with_schemas(input_schema, output_schema);
+            let step_id = step.id;
+
+            let engine = WorkflowEngine::new();
+            let id = engine
+                .create_workflow(definition(vec![step], &[]))
+                .await
+                .unwrap();
+            let result = engine.start_workflow(id).await;
+            let instance = engine.get_workflow(id).await.unwrap();
+            let instance = instance.lock().await;
+            assert_eq!(instance.step_error(step_id), expected);
+            assert_eq!(result.is_ok(), expected.is_none());
+
+            // A step never runs on an input that does not match its schema
+            let ran = !expected.is_some_and(|error| error.starts_with("Invalid input"));
+            assert_eq!(executions.load(Ordering::SeqCst), usize::from(ran));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_steps_are_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let flaky = TestStep::new("flaky", move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                anyhow::bail!("connection reset");
+            }
+            Ok(completed(json!("done")))
+        });
+        let flaky_id = flaky.id;
+        let mut workflow = definition(vec![flaky], &[]);
+        let backoff = Backoff {
+            initial_secs: 0.01,
+            ..Backoff::default()
+        };
+        workflow.policies.insert(
+            flaky_id,
+            StepPolicy::default().with_retries(2).with_backoff(backoff),
+        );
+
+        let engine = WorkflowEngine::new();
+        let mut events = engine.subscribe();
+        let id = engine.create_workflow(workflow).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        let attempts = instance.attempts(flaky_id);
+        let statuses: Vec<_> = attempts.iter().map(|attempt| attempt.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                StepStatus::Failed,
+                StepStatus::Failed,
+                StepStatus::Completed
+            ]
+        );
+        assert_eq!(attempts[0].error.as_deref(), Some("connection reset"));
+        assert_eq!(attempts[2].attempt, 3);
+        assert_eq!(instance.snapshot().steps[&flaky_id].attempts.len(), 3);
+
+        let mut retries = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let EventKind::StepRetrying { attempt, .. } = event.kind {
+                retries.push(attempt);
+            }
+        }
+        assert_eq!(retries, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_steps_time_out() {
+        let slow = TestStep::echo("slow");
+        let slow_id = slow.id;
+        let mut workflow = definition(vec![slow], &[]);
+        workflow.policies.insert(
+            slow_id,
+            StepPolicy::default().with_timeout(Duration::from_millis(1)),
+        );
+
+        let engine = WorkflowEngine::new();
+        let id = engine.create_workflow(workflow).await.unwrap();
+        let error = engine.start_workflow(id).await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.step_status(slow_id), Some(StepStatus::Failed));
+        assert_eq!(instance.attempts(slow_id).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failure_handler_takes_over() {
+        let steps = vec![
+            TestStep::new("deploy", |_| Err(anyhow::anyhow!("cluster unreachable"))),
+            TestStep::new("rollback", |context| {
+                let failure = context.previous_outputs.values().next().unwrap();
+                Ok(completed(json!({ "rolled_back": failure["error"] })))
+            }),
+            TestStep::echo("notify"),
+            TestStep::new("unused", |_| Ok(completed(json!(null)))),
+            TestStep::echo("lint"),
+        ];
+        let ids: Vec<_> = steps.iter().map(|step| step.id).collect();
+        let mut workflow = definition(steps, &[(0, 2)]);
+        workflow
+            .policies
+            .insert(ids[0], StepPolicy::default().with_on_failure(ids[1]));
+        workflow
+            .policies
+            .insert(ids[4], StepPolicy::default().with_on_failure(ids[3]));
+
+        let engine = WorkflowEngine::new();
+        let id = engine.create_workflow(workflow).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.step_status(ids[0]), Some(StepStatus::Completed));
+        assert_eq!(
+            instance.step_output(ids[0]),
+            Some(&json!({ "rolled_back": "cluster unreachable" }))
+        );
+        assert_eq!(instance.step_status(ids[2]), Some(StepStatus::Completed));
+        assert_eq!(instance.step_status(ids[3]), Some(StepStatus::Skipped));
+        assert_eq!(
+            instance.snapshot().steps[&ids[1]].fallback_for,
+            Some(ids[0])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conditions_skip_steps() {
+        let steps = vec![
+            TestStep::new("test", |_| Ok(completed(json!({ "passed": false })))),
+            TestStep::echo("fix"),
+            TestStep::echo("celebrate"),
+            TestStep::echo("announce"),
+            TestStep::echo("report"),
+        ];
+        let ids: Vec<_> = steps.iter().map(|step| step.id).collect();
+        let mut workflow = definition(steps, &[(0, 1), (0, 2), (2, 3), (1, 4), (2, 4)]);
+        workflow.conditions = vec![
+            (
+                ids[0],
+                ids[1],
+                Condition::new("/passed", Test::Equals(json!(false))),
+            ),
+            (
+                ids[0],
+                ids[2],
+                Condition::new("/passed", Test::Equals(json!(true))),
+            ),
+        ];
+
+        let engine = WorkflowEngine::new();
+        let mut events = engine.subscribe();
+        let id = engine.create_workflow(workflow).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        let statuses: Vec<_> = ids.iter().map(|id| instance.step_status(*id)).collect();
+        assert_eq!(
+            statuses,
+            [
+                Some(StepStatus::Completed),
+                Some(StepStatus::Completed),
+                Some(StepStatus::Skipped),
+                Some(StepStatus::Skipped),
+                Some(StepStatus::Completed),
+            ]
+        );
+        // The report only sees the output of the branch that ran
+        assert_eq!(instance.step_output(ids[4]).unwrap()["inputs"], 1);
+
+        let mut reasons = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let EventKind::StepSkipped { step_id, reason } = event.kind {
+                reasons.push((step_id, reason));
+            }
+        }
+        assert_eq!(
+            reasons,
+            [
+                (
+                    ids[2],
+                    "condition on step test not met: /passed equals true".to_string()
+                ),
+                (ids[3], "all of its dependencies were skipped".to_string()),
+            ]
+        );
+    }
+
+    /// A registry with an `echo` step type that counts its executions
+    fn echo_registry(executions: &Arc<AtomicUsize>) -> StepRegistry {
+        let executions = Arc::clone(executions);
+        let mut registry = StepRegistry::new();
+        registry.register("echo", move |config| {
+            let executions = Arc::clone(&executions);
+            let key = config.key.clone();
+            let mut step = TestStep::new(&config.name, move |context| {
+                executions.fetch_add(1, Ordering::SeqCst);
+                Ok(completed(json!({
+                    "name": key,
+                    "inputs": context.previous_outputs.len(),
+                })))
+            });
+            step.id = config.id;
+            Ok(Box::new(step))
+        });
+        registry
+    }
+
+    fn echo_spec() -> WorkflowSpec {
+        WorkflowSpec::parse(
+            "name: pipeline\nsteps:\n  - {id: build, type: echo}\n  - {id: test, type: echo, depends_on: [build]}\n",
+            crate::spec::SpecFormat::Yaml,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_completed_workflow_is_checkpointed() {
+        let dir = tempfile::tempdir().unwrap();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let engine = WorkflowEngine::new()
+            .with_registry(echo_registry(&executions))
+            .with_store(WorkflowStore::new(dir.path()));
+
+        let id = engine.create_workflow_from_spec(echo_spec()).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let snapshot = WorkflowStore::new(dir.path()).load(id).await.unwrap();
+        assert_eq!(snapshot.status, WorkflowStatus::Completed);
+        assert_eq!(snapshot.spec, Some(echo_spec()));
+        let test = &snapshot.steps[&crate::spec::step_id(id, "test")];
+        assert_eq!(test.status, StepStatus::Completed);
+        assert_eq!(test.output.as_ref().unwrap()["inputs"], 1);
+        assert!(test.started_at.is_some() && test.completed_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_step_inputs_are_rendered() {
+        let mut registry = echo_registry(&Arc::new(AtomicUsize::new(0)));
+        registry.register("input", |config| {
+            let mut step =
+                TestStep::new(&config.name, |context| Ok(completed(context.input.clone())));
+            step.id = config.id;
+            Ok(Box::new(step))
+        });
+        let engine = WorkflowEngine::new().with_registry(registry);
+        let spec = WorkflowSpec::parse(
+            r#"
+name: pipeline
+steps:
+  - {id: build, type: echo}
+  - id: report
+    type: input
+    depends_on: [build]
+    input:
+      title: '{{ steps.build.output.name | upper }} on {{ input.branch | default("main") }}'
+      inputs: "{{ steps.build.output.inputs }}"
+  - id: broken
+    type: input
+    depends_on: [build]
+    input: {files: "{{ steps.build.output.files | length }}"}
+"#,
+            crate::spec::SpecFormat::Yaml,
+        )
+        .unwrap();
+
+        let id = engine.create_workflow_from_spec(spec).await.unwrap();
+        let _ = engine.start_workflow(id).await;
+        let workflow = engine.get_workflow(id).await.unwrap();
+        let instance = workflow.lock().await;
+
+        let report = crate::spec::step_id(id, "report");
+        assert_eq!(
+            instance.step_output(report).unwrap(),
+            &json!({"title": "BUILD on main", "inputs": 0})
+        );
+        let broken = crate::spec::step_id(id, "broken");
+        assert_eq!(instance.step_status(broken), Some(StepStatus::Failed));
+        assert_eq!(
+            instance.step_error(broken).unwrap(),
+            "Invalid input of step broken: At /files: \
+             Cannot resolve {{ steps.build.output.files | length }}: \
+             steps.build.output.files | length has no value"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let build = crate::spec::step_id(Uuid::nil(), "build");
+        let test = crate::spec::step_id(Uuid::nil(), "test");
+
+        // The process died while `test` was running
+        let mut snapshot = WorkflowSnapshot {
+            id: Uuid::nil(),
+            name: "pipeline".to_string(),
+            description: String::new(),
+            spec: Some(echo_spec()),
+            parent: None,
+            status: WorkflowStatus::Running,
+            error: None,
+            input: json!({}),
+            global: HashMap::new(),
+            steps: HashMap::new(),
+            created_at: Utc::now(),
+            started_at: Some(Utc::now()),
+            completed_at: None,
+        };
+        snapshot.steps.insert(
+            build,
+            StepSnapshot {
+                status: StepStatus::Completed,
+                output: Some(json!({"name": "build", "inputs": 0})),
+                error: None,
+                started_at: Some(Utc::now()),
+                completed_at: Some(Utc::now()),
+                attempts: Vec::new(),
+                fallback_for: None,
+                children: Vec::new(),
+            },
+        );
+        snapshot.steps.insert(
+            test,
+            StepSnapshot {
+                status: StepStatus::Running,
+                output: None,
+                error: None,
+                started_at: Some(Utc::now()),
+                completed_at: None,
+                attempts: Vec::new(),
+                fallback_for: None,
+                children: Vec::new(),
+            },
+        );
+        let store = WorkflowStore::new(dir.path());
+        store.save(&snapshot).await.unwrap();
+
+        // A workflow assembled in code cannot be rebuilt
+        let mut orphan = snapshot.clone();
+        orphan.id = Uuid::new_v4();
+        orphan.spec = None;
+        store.save(&orphan).await.unwrap();
+
+        let engine = WorkflowEngine::new()
+            .with_registry(echo_registry(&executions))
+            .with_store(store);
+        assert_eq!(engine.restore().await.unwrap(), [Uuid::nil()]);
+
+        let instance = engine.get_workflow(orphan.id).await.unwrap();
+        assert_eq!(instance.lock().await.status(), WorkflowStatus::Failed);
+        assert!(engine.resume_workflow(orphan.id).await.is_err());
+
+        engine.resume_workflow(Uuid::nil()).await.unwrap();
+        assert_eq!(executions.load(Ordering::SeqCst), 1);
+
+        let instance = engine.get_workflow(Uuid::nil()).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.step_output(test).unwrap()["inputs"], 1);
+        assert!(instance.step_times(test).completed_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_accepted_input_matches_output_schema() {
+        let ask = TestStep::new("ask", |_| {
+            Ok(StepResult {
+                output: json!({"prompt": "Which board?"}),
+                status: StepStatus::WaitingForInput,
+                error: None,
+            })
+        })
+        .with_schemas(None, Some(json!({"type": "object", "required": ["board"]})))
+        // A response can be valid and still not make a valid output
+        .with_response_schema(json!({"type": "object"}));
+        let ask_id = ask.id;
+
+        let engine = WorkflowEngine::new();
+        let id = engine
+            .create_workflow(definition(vec![ask], &[]))
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let error = engine
+            .submit_input(id, ask_id, json!({"chip": "nrf52"}))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid output of step ask:\n  - /: \"board\" is a required property"
+        );
+        assert_eq!(engine.pending_inputs().await.len(), 1);
+
+        engine
+            .submit_input(id, ask_id, json!({"board": "qemu_x86"}))
+            .await
+            .unwrap();
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.step_output(ask_id).unwrap()["board"], "qemu_x86");
+    }
+
+    #[tokio::test]
+    async fn test_human_input_pauses_and_resumes() {
+        let ask = crate::steps::HumanInputStep::new(
+            Uuid::new_v4(),
+            "ask".to_string(),
+            String::new(),
+            "Which board?".to_string(),
+        );
+        let ask_id = ask.id();
+        let build = TestStep::new("build", |context| {
+            let answer = context.previous_outputs.values().next().unwrap();
+            Ok(completed(json!({ "board": answer["human_input"] })))
+        });
+        let build_id = build.id;
+        let definition = WorkflowDefinition {
+            id: Uuid::new_v4(),
+            name: "Test Workflow".to_string(),
+            description: String::new(),
+            steps: vec![Box::new(ask), Box::new(build)],
+            dependencies: vec![(ask_id, build_id)],
+            policies: HashMap::new(),
+            conditions: Vec::new(),
+            inputs: HashMap::new(),
+        };
+
+        let engine = WorkflowEngine::new();
+        let id = engine.create_workflow(definition).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].workflow_id, id);
+        assert_eq!(pending[0].step_id, ask_id);
+        assert_eq!(pending[0].prompt, "Which board?");
+        assert!(pending[0].requested_at.is_some());
+        {
+            let instance = engine.get_workflow(id).await.unwrap();
+            let instance = instance.lock().await;
+            assert_eq!(instance.status(), WorkflowStatus::WaitingForInput);
+            assert_eq!(
+                instance.step_status(ask_id),
+                Some(StepStatus::WaitingForInput)
+            );
+            assert_eq!(instance.step_status(build_id), Some(StepStatus::Pending));
+            assert!(instance.completed_at().is_none());
+        }
+
+        let error = engine
+            .submit_input(id, ask_id, json!({ "human_input": 3 }))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Invalid response"));
+        assert_eq!(engine.pending_inputs().await.len(), 1);
+
+        engine
+            .submit_input(id, ask_id, json!("qemu_x86"))
+            .await
+            .unwrap();
+        assert!(engine.pending_inputs().await.is_empty());
+        assert!(engine
+            .submit_input(id, ask_id, json!("again"))
+            .await
+            .is_err());
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.step_output(build_id).unwrap()["board"], "qemu_x86");
+    }
+
+    #[tokio::test]
+    async fn test_rejection_reworks_the_reviewed_step() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let producer_id = Uuid::new_v4();
+        let counter = Arc::clone(&runs);
+        let mut producer = TestStep::new("generate", move |context| {
+            let run = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let feedback = crate::steps::feedback_for(context, producer_id).cloned();
+            Ok(completed(
+                json!({ "diff": format!("v{run}"), "feedback": feedback }),
+            ))
+        });
+        producer.id = producer_id;
+        let gate = crate::steps::ApprovalStep::new(
+            Uuid::new_v4(),
+            "review".to_string(),
+            String::new(),
+            "Apply?".to_string(),
+        );
+        let gate_id = gate.id();
+        let apply = TestStep::new("apply", |context| {
+            let review = context.previous_outputs.values().next().unwrap();
+            Ok(completed(json!({ "applied": review["output"]["diff"] })))
+        });
+        let apply_id = apply.id;
+        let definition = WorkflowDefinition {
+            id: Uuid::new_v4(),
+            name: "Test Workflow".to_string(),
+            description: String::new(),
+            steps: vec![Box::new(producer), Box::new(gate), Box::new(apply)],
+            dependencies: vec![(producer_id, gate_id), (gate_id, apply_id)],
+            policies: HashMap::new(),
+            conditions: Vec::new(),
+            inputs: HashMap::new(),
+        };
+
+        let engine = WorkflowEngine::new();
+        let id = engine.create_workflow(definition).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending[0].request["proposal"]["diff"], "v1");
+
+        engine
+            .submit_input(
+                id,
+                gate_id,
+                json!({ "decision": "reject", "feedback": "Add a test" }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        let proposal = &pending[0].request["proposal"];
+        assert_eq!(proposal["diff"], "v2");
+        assert_eq!(proposal["feedback"]["feedback"], "Add a test");
+        assert_eq!(proposal["feedback"]["rejected_output"]["diff"], "v1");
+
+        engine
+            .submit_input(
+                id,
+                gate_id,
+                json!({ "decision": "edit", "output": { "diff": "v2 with test" } }),
+            )
+            .await
+            .unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(apply_id).unwrap()["applied"],
+            "v2 with test"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_are_published() {
+        let compile = TestStep::new("compile", |context| {
+            context.progress.report(json!({ "percent": 50 }));
+            Ok(completed(json!({ "ok": true })))
+        });
+        let compile_id = compile.id;
+        let steps = vec![
+            compile,
+            TestStep::new("test", |_| Err(anyhow::anyhow!("1 test failed"))),
+        ];
+
+        let engine = WorkflowEngine::new();
+        let mut events = engine.subscribe();
+        let id = engine
+            .create_workflow(definition(steps, &[(0, 1)]))
+            .await
+            .unwrap();
+        assert!(engine.start_workflow(id).await.is_err());
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.workflow_id, id);
+            kinds.push(event.kind);
+        }
+        let names: Vec<_> = kinds
+            .iter()
+            .map(|kind| serde_json::to_value(kind).unwrap()["event"].clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "workflow_created",
+                "workflow_started",
+                "step_started",
+                "step_progress",
+                "step_completed",
+                "step_started",
+                "step_failed",
+                "workflow_failed",
+            ]
+        );
+        assert_eq!(
+            kinds[3],
+            EventKind::StepProgress {
+                step_id: compile_id,
+                payload: json!({ "percent": 50 }),
+            }
+        );
+    }
 }
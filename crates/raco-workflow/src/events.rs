@@ -0,0 +1,226 @@
+//! Workflow events
+//!
+//! This module defines the events the workflow engine publishes whenever a workflow
+//! or one of its steps changes state. Subscribe with
+//! [`WorkflowEngine::subscribe`](crate::engine::WorkflowEngine::subscribe).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::{StepId, WorkflowId};
+
+/// Number of events kept for subscribers that fall behind
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// A state change of a workflow or step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowEvent {
+    /// Workflow the event belongs to
+    pub workflow_id: WorkflowId,
+
+    /// Time of the change
+    pub timestamp: DateTime<Utc>,
+
+    /// What happened
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl WorkflowEvent {
+    /// Create an event with the current time
+    #[must_use]
+    pub fn new(workflow_id: WorkflowId, kind: EventKind) -> Self {
+        Self {
+            workflow_id,
+            timestamp: Utc::now(),
+            kind,
+        }
+    }
+
+    /// Check if the event ends a run of the workflow
+    #[must_use]
+    pub const fn is_final(&self) -> bool {
+        matches!(
+            self.kind,
+            EventKind::WorkflowCompleted
+                | EventKind::WorkflowFailed { .. }
+                | EventKind::WorkflowCancelled
+                | EventKind::WorkflowWaitingForInput
+        )
+    }
+}
+
+/// Kinds of workflow events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A workflow instance was created
+    WorkflowCreated {
+        /// Workflow name
+        name: String,
+    },
+
+    /// A workflow started or resumed running
+    WorkflowStarted,
+
+    /// All steps of a workflow completed
+    WorkflowCompleted,
+
+    /// A workflow failed
+    WorkflowFailed {
+        /// Reason
+        error: String,
+    },
+
+    /// A workflow was cancelled
+    WorkflowCancelled,
+
+    /// A workflow was paused; running steps finish, but no new ones start
+    WorkflowPaused,
+
+    /// A workflow paused until a human answers
+    WorkflowWaitingForInput,
+
+    /// A step started running
+    StepStarted {
+        /// Step ID
+        step_id: StepId,
+        /// Step name
+        name: String,
+    },
+
+    /// A running step reported progress
+    StepProgress {
+        /// Step ID
+        step_id: StepId,
+        /// Step-specific progress data
+        payload: Value,
+    },
+
+    /// An attempt of a step failed and the step will run again
+    StepRetrying {
+        /// Step ID
+        step_id: StepId,
+        /// Number of the next attempt, counting from 1
+        attempt: u32,
+        /// Reason the previous attempt failed
+        error: String,
+        /// Delay before the next attempt, in seconds
+        delay_secs: f64,
+    },
+
+    /// A step was skipped because its conditions did not hold
+    StepSkipped {
+        /// Step ID
+        step_id: StepId,
+        /// Why the step was skipped
+        reason: String,
+    },
+
+    /// A step completed
+    StepCompleted {
+        /// Step ID
+        step_id: StepId,
+        /// Output
+        output: Value,
+    },
+
+    /// A step failed
+    StepFailed {
+        /// Step ID
+        step_id: StepId,
+        /// Reason
+        error: String,
+    },
+
+    /// A step is waiting for a human response
+    StepWaitingForInput {
+        /// Step ID
+        step_id: StepId,
+        /// Request shown to the human
+        request: Value,
+    },
+}
+
+/// Handle a running step uses to report progress
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    /// Channel, workflow and step the reports belong to; reports are dropped without one
+    target: Option<(broadcast::Sender<WorkflowEvent>, WorkflowId, StepId)>,
+}
+
+impl Progress {
+    /// Create a handle publishing on the given channel
+    #[must_use]
+    pub const fn new(
+        events: broadcast::Sender<WorkflowEvent>,
+        workflow_id: WorkflowId,
+        step_id: StepId,
+    ) -> Self {
+        Self {
+            target: Some((events, workflow_id, step_id)),
+        }
+    }
+
+    /// Publish a progress report
+    pub fn report(&self, payload: Value) {
+        if let Some((events, workflow_id, step_id)) = &self.target {
+            // Nobody may be listening
+            let _ = events.send(WorkflowEvent::new(
+                *workflow_id,
+                EventKind::StepProgress {
+                    step_id: *step_id,
+                    payload,
+                },
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_event_serialization() {
+        let step_id = Uuid::new_v4();
+        let event = WorkflowEvent::new(
+            Uuid::new_v4(),
+            EventKind::StepFailed {
+                step_id,
+                error: "linker not found".to_string(),
+            },
+        );
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "step_failed");
+        assert_eq!(json["step_id"], step_id.to_string());
+        assert!(json["timestamp"].is_string());
+        assert_eq!(
+            serde_json::from_value::<WorkflowEvent>(json).unwrap(),
+            event
+        );
+        assert!(!event.is_final());
+    }
+
+    #[test]
+    fn test_progress() {
+        let (events, mut receiver) = broadcast::channel(EVENT_CAPACITY);
+        let step_id = Uuid::new_v4();
+        Progress::new(events, Uuid::new_v4(), step_id).report(serde_json::json!({"percent": 50}));
+        Progress::default().report(serde_json::json!({"percent": 100}));
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            event.kind,
+            EventKind::StepProgress {
+                step_id,
+                payload: serde_json::json!({"percent": 50}),
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
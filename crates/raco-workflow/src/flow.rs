@@ -0,0 +1,768 @@
+//! Control flow steps
+//!
+//! This module provides steps that run a group of steps, their body, several times:
+//! [`MapStep`] once for every item of an array, [`LoopStep`] again and again while a
+//! condition holds. Each run of the body is a sub-workflow of the step.
+//!
+//! ```yaml
+//! - id: fix_each
+//!   type: map
+//!   depends_on: [test]
+//!   params:
+//!     items: { step: test, path: /failures }
+//!     max_parallel: 2
+//!     steps:
+//!       - id: fix
+//!         type: code_generation
+//!         params:
+//!           template: Fix the failing test
+//! - id: until_green
+//!   type: loop
+//!   params:
+//!     max_iterations: 5
+//!     while: { step: test, path: /passed, equals: false }
+//!     steps:
+//!       - id: fix
+//!         type: code_generation
+//!         params:
+//!           template: Fix the build
+//!       - id: test
+//!         type: cargo_test
+//!         depends_on: [fix]
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::condition::Condition;
+use crate::registry::StepRegistry;
+use crate::spec::WorkflowSpec;
+use crate::steps::{Step, StepContext, StepResult};
+use crate::{StepId, StepStatus, WorkflowStatus};
+
+/// Default number of items a map step processes at the same time
+pub const DEFAULT_MAX_PARALLEL_ITEMS: usize = 4;
+
+/// Default number of iterations after which a loop step gives up
+pub const DEFAULT_MAX_ITERATIONS: u32 = 10;
+
+/// Steps a control flow step runs as a unit
+#[derive(Debug, Clone)]
+pub struct Body {
+    /// The steps, described as a workflow
+    spec: WorkflowSpec,
+}
+
+impl Body {
+    /// Create a body from a workflow description
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the description cannot be instantiated with the registry
+    pub fn new(spec: WorkflowSpec, registry: StepRegistry) -> Result<Self> {
+        if spec.steps.is_empty() {
+            bail!("{} has no steps", spec.name);
+        }
+        let definition = registry.instantiate(&spec)?;
+        if let Some(diagnostic) = definition
+            .validate()
+            .into_iter()
+            .find(|diagnostic| diagnostic.is_error())
+        {
+            bail!("Invalid {}: {}", spec.name, diagnostic.message);
+        }
+        Ok(Self { spec })
+    }
+
+    /// Check if the body has a step with the given ID
+    #[must_use]
+    pub fn has_step(&self, id: &str) -> bool {
+        self.spec.step(id).is_some()
+    }
+
+    /// Run the body to completion
+    ///
+    /// Every step receives `input` as its workflow input. Returns the outputs of the
+    /// completed steps by step ID. The body runs as a sub-workflow of the step running
+    /// it, on the same engine: its steps share the engine's tools, language model,
+    /// workflows and limits, publish events, and are cancelled along with the step.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the step does not run inside a workflow engine, a step
+    /// fails, the run is cancelled, or a step waits for human input, which a body
+    /// cannot receive
+    pub async fn run(&self, input: Value, parent: &StepContext) -> Result<Map<String, Value>> {
+        let workflows = parent
+            .workflows
+            .as_ref()
+            .ok_or_else(|| anyhow!("{} can only run inside a workflow engine", self.spec.name))?;
+        let (id, status) = workflows.run_body(self.spec.clone(), input).await?;
+        match status {
+            WorkflowStatus::Completed => workflows.outputs(id).await,
+            WorkflowStatus::Cancelled => bail!("{} was cancelled", self.spec.name),
+            status => {
+                // Nothing answers a body, so it would wait forever
+                if status == WorkflowStatus::WaitingForInput {
+                    workflows.cancel(id).await?;
+                }
+                bail!(
+                    "{} stopped with status {:?}; steps inside it cannot wait for input",
+                    self.spec.name,
+                    status
+                )
+            }
+        }
+    }
+}
+
+/// A step that runs its body once for every item of an array
+///
+/// The array is taken from the output of a preceding step. Each run receives
+/// `{"item": <item>, "index": <index>, "input": <workflow input>}` as input. The
+/// step outputs `{"results": [...]}` with the step outputs of every run, in item
+/// order.
+#[derive(Debug)]
+pub struct MapStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Step whose output holds the items
+    items_step: StepId,
+
+    /// JSON pointer to the items in the output of `items_step`
+    items_path: String,
+
+    /// Steps run for every item
+    body: Body,
+
+    /// Number of items processed at the same time
+    max_parallel: usize,
+}
+
+impl MapStep {
+    /// Create a map step over the array at `items_path` in the output of `items_step`
+    #[must_use]
+    pub const fn new(
+        id: StepId,
+        name: String,
+        description: String,
+        items_step: StepId,
+        items_path: String,
+        body: Body,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            items_step,
+            items_path,
+            body,
+            max_parallel: DEFAULT_MAX_PARALLEL_ITEMS,
+        }
+    }
+
+    /// Process up to `max_parallel` items at the same time
+    #[must_use]
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = max_parallel.max(1);
+        self
+    }
+
+    fn items(&self, context: &StepContext) -> Result<Vec<Value>> {
+        let output = context
+            .previous_outputs
+            .get(&self.items_step)
+            .ok_or_else(|| anyhow!("Step {} has no output to take items from", self.items_step))?;
+        match output.pointer(&self.items_path) {
+            Some(Value::Array(items)) => Ok(items.clone()),
+            Some(other) => bail!(
+                "Expected an array at '{}' of the output of step {}, found {}",
+                self.items_path,
+                self.items_step,
+                other
+            ),
+            None => bail!(
+                "The output of step {} has no value at '{}'",
+                self.items_step,
+                self.items_path
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl Step for MapStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<Value> {
+        None
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        Some(json!({
+            "type": "object",
+            "required": ["results"],
+            "properties": {
+                "results": { "type": "array", "items": { "type": "object" } }
+            }
+        }))
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let items = self.items(&context)?;
+        let total = items.len();
+
+        let mut runs = stream::iter(items.into_iter().enumerate().map(|(index, item)| {
+            let input = json!({ "item": item, "index": index, "input": context.input });
+            self.body.run(input, &context)
+        }))
+        .buffered(self.max_parallel);
+
+        let mut results = Vec::with_capacity(total);
+        let mut failures = Vec::new();
+        while let Some(result) = runs.next().await {
+            match result {
+                Ok(outputs) => results.push(Value::Object(outputs)),
+                Err(e) => {
+                    failures.push(format!("item {}: {:#}", results.len(), e));
+                    results.push(Value::Null);
+                }
+            }
+            context
+                .progress
+                .report(json!({ "completed": results.len(), "total": total }));
+        }
+
+        if !failures.is_empty() {
+            bail!(
+                "{} of {} items failed:\n  - {}",
+                failures.len(),
+                total,
+                failures.join("\n  - ")
+            );
+        }
+        Ok(StepResult {
+            output: json!({ "results": results }),
+            status: StepStatus::Completed,
+            error: None,
+        })
+    }
+
+    fn validate_input(&self, _input: &Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Condition under which a loop runs its body again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoopCondition {
+    /// Body step whose output is tested
+    pub step: String,
+
+    /// Test of the output
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+/// A step that runs its body again while a condition holds, up to a limit
+///
+/// The condition is checked after every run, so the body runs at least once. Each
+/// run receives `{"iteration": <number>, "previous": <outputs of the previous run>,
+/// "input": <workflow input>}` as input, counting iterations from 1. The step outputs
+/// `{"iterations": <number>, "outputs": <outputs of the last run>}`, and fails if
+/// the condition still holds after the last allowed iteration.
+#[derive(Debug)]
+pub struct LoopStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Steps run in every iteration
+    body: Body,
+
+    /// Condition under which the body runs again
+    condition: LoopCondition,
+
+    /// Number of iterations after which the step gives up
+    max_iterations: u32,
+}
+
+impl LoopStep {
+    /// Create a loop step
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the condition refers to a step that is not part of the body
+    pub fn new(
+        id: StepId,
+        name: String,
+        description: String,
+        body: Body,
+        condition: LoopCondition,
+    ) -> Result<Self> {
+        if !body.has_step(&condition.step) {
+            bail!(
+                "Loop {} tests step '{}', which is not part of its body",
+                name,
+                condition.step
+            );
+        }
+        Ok(Self {
+            id,
+            name,
+            description,
+            body,
+            condition,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+        })
+    }
+
+    /// Give up after `max_iterations` iterations
+    #[must_use]
+    pub fn with_max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl Step for LoopStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<Value> {
+        None
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        Some(json!({
+            "type": "object",
+            "required": ["iterations", "outputs"],
+            "properties": {
+                "iterations": { "type": "integer", "minimum": 1 },
+                "outputs": { "type": "object" }
+            }
+        }))
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let mut previous = Value::Null;
+        for iteration in 1..=self.max_iterations {
+            let input = json!({
+                "iteration": iteration,
+                "previous": previous,
+                "input": context.input,
+            });
+            let outputs = self
+                .body
+                .run(input, &context)
+                .await
+                .with_context(|| format!("Iteration {iteration} failed"))?;
+            let again = self
+                .condition
+                .condition
+                .holds(outputs.get(&self.condition.step).unwrap_or(&Value::Null));
+            previous = Value::Object(outputs);
+            context.progress.report(json!({
+                "iteration": iteration,
+                "max_iterations": self.max_iterations,
+            }));
+
+            if !again {
+                return Ok(StepResult {
+                    output: json!({ "iterations": iteration, "outputs": previous }),
+                    status: StepStatus::Completed,
+                    error: None,
+                });
+            }
+        }
+
+        bail!(
+            "{} of step '{}' still held after {} iterations",
+            self.condition.condition,
+            self.condition.step,
+            self.max_iterations
+        )
+    }
+
+    fn validate_input(&self, _input: &Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::WorkflowEngine;
+    use crate::events::EventKind;
+    use crate::spec::{self, SpecFormat};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A step that sleeps briefly, counting concurrent runs, then runs a closure over its input
+    struct FnStep<F> {
+        id: StepId,
+        name: String,
+        run: F,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    impl<F> FnStep<F> {
+        fn new(config: crate::registry::StepConfig, run: F) -> Self {
+            Self {
+                id: config.id,
+                name: config.name,
+                run,
+                running: Arc::default(),
+                max_running: Arc::default(),
+            }
+        }
+    }
+
+    impl<F> std::fmt::Debug for FnStep<F> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("FnStep").field("name", &self.name).finish()
+        }
+    }
+
+    #[async_trait]
+    impl<F> Step for FnStep<F>
+    where
+        F: Fn(&Value) -> Value + Send + Sync,
+    {
+        fn id(&self) -> StepId {
+            self.id
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn description(&self) -> &str {
+            "A step running a closure"
+        }
+
+        fn input_schema(&self) -> Option<Value> {
+            None
+        }
+
+        fn output_schema(&self) -> Option<Value> {
+            None
+        }
+
+        async fn execute(&self, context: StepContext) -> Result<StepResult> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(StepResult {
+                output: (self.run)(&context.input),
+                status: StepStatus::Completed,
+                error: None,
+            })
+        }
+
+        fn validate_input(&self, _input: &Value) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Builtins plus `list`, which outputs three failures, `double`, which doubles
+    /// its item, and `attempt`, which passes from the third iteration on
+    fn registry(running: &Arc<AtomicUsize>, max_running: &Arc<AtomicUsize>) -> StepRegistry {
+        let mut registry = StepRegistry::with_builtins();
+        registry.register("list", |config| {
+            Ok(Box::new(FnStep::new(
+                config,
+                |_: &Value| json!({ "failures": [1, 2, 3] }),
+            )))
+        });
+        let (running, max_running) = (Arc::clone(running), Arc::clone(max_running));
+        registry.register("double", move |config| {
+            let mut step = FnStep::new(config, |input: &Value| {
+                json!(input["item"].as_i64().unwrap() * 2)
+            });
+            step.running = Arc::clone(&running);
+            step.max_running = Arc::clone(&max_running);
+            Ok(Box::new(step))
+        });
+        registry.register("attempt", |config| {
+            Ok(Box::new(FnStep::new(
+                config,
+                |input: &Value| json!({ "passed": input["iteration"].as_u64() >= Some(3) }),
+            )))
+        });
+        registry
+    }
+
+    async fn run(registry: StepRegistry, text: &str) -> (WorkflowEngine, crate::WorkflowId) {
+        let engine = WorkflowEngine::new().with_registry(registry);
+        let spec = WorkflowSpec::parse(text, SpecFormat::Yaml).unwrap();
+        let id = engine.create_workflow_from_spec(spec).await.unwrap();
+        let _ = engine.start_workflow(id).await;
+        (engine, id)
+    }
+
+    #[tokio::test]
+    async fn test_map_over_items() {
+        let (running, max_running) = Default::default();
+        let text = r"
+name: fan-out
+steps:
+  - id: test
+    type: list
+  - id: fix_each
+    type: map
+    depends_on: [test]
+    params:
+      items: { step: test, path: /failures }
+      max_parallel: 2
+      steps:
+        - { id: fix, type: double }
+";
+        let (engine, id) = run(registry(&running, &max_running), text).await;
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(spec::step_id(id, "fix_each")),
+            Some(&json!({ "results": [{ "fix": 2 }, { "fix": 4 }, { "fix": 6 }] }))
+        );
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bodies_run_as_sub_workflows() {
+        let (running, max_running) = Default::default();
+        let engine = WorkflowEngine::new().with_registry(registry(&running, &max_running));
+        engine
+            .register_workflow(
+                WorkflowSpec::parse(
+                    "name: fix\nsteps:\n  - { id: fix, type: double }\n",
+                    SpecFormat::Yaml,
+                )
+                .unwrap(),
+            )
+            .await;
+        let mut events = engine.subscribe();
+        let text = r"
+name: fan-out
+steps:
+  - id: test
+    type: list
+  - id: fix_each
+    type: map
+    depends_on: [test]
+    params:
+      items: { step: test, path: /failures }
+      steps:
+        - { id: run, type: workflow, params: { workflow: fix } }
+";
+        let spec = WorkflowSpec::parse(text, SpecFormat::Yaml).unwrap();
+        let id = engine.create_workflow_from_spec(spec).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let fix_each = spec::step_id(id, "fix_each");
+        let bodies = {
+            let instance = engine.get_workflow(id).await.unwrap();
+            let instance = instance.lock().await;
+            assert_eq!(
+                instance.step_output(fix_each),
+                Some(&json!({ "results": [
+                    { "run": { "fix": 2 } },
+                    { "run": { "fix": 4 } },
+                    { "run": { "fix": 6 } }
+                ] }))
+            );
+            instance.children(fix_each).to_vec()
+        };
+        assert_eq!(bodies.len(), 3);
+        for body in &bodies {
+            let body = engine.get_workflow(*body).await.unwrap();
+            let body = body.lock().await;
+            assert_eq!(body.status(), WorkflowStatus::Completed);
+            assert_eq!(body.parent().map(|parent| parent.step_id), Some(fix_each));
+        }
+
+        // Subscribers see the steps inside the bodies complete
+        let mut completed = std::collections::HashSet::new();
+        while let Ok(event) = events.try_recv() {
+            if matches!(event.kind, EventKind::StepCompleted { .. }) {
+                completed.insert(event.workflow_id);
+            }
+        }
+        assert!(bodies.iter().all(|body| completed.contains(body)));
+    }
+
+    #[tokio::test]
+    async fn test_bodies_cannot_wait_for_input() {
+        let (running, max_running) = Default::default();
+        let text = r"
+name: ask-each
+steps:
+  - id: test
+    type: list
+  - id: ask_each
+    type: map
+    depends_on: [test]
+    params:
+      items: { step: test, path: /failures }
+      max_parallel: 1
+      steps:
+        - { id: ask, type: human_input, params: { prompt: Fix it? } }
+";
+        let (engine, id) = run(registry(&running, &max_running), text).await;
+
+        // Nothing is left waiting for an answer that cannot arrive
+        assert!(engine.pending_inputs().await.is_empty());
+        let ask_each = spec::step_id(id, "ask_each");
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Failed);
+        assert!(instance
+            .step_error(ask_each)
+            .unwrap()
+            .contains("steps inside it cannot wait for input"));
+        for body in instance.children(ask_each) {
+            let body = engine.get_workflow(*body).await.unwrap();
+            assert_eq!(body.lock().await.status(), WorkflowStatus::Cancelled);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_map_requires_an_array() {
+        let (running, max_running) = Default::default();
+        let text = r"
+name: fan-out
+steps:
+  - id: test
+    type: list
+  - id: fix_each
+    type: map
+    depends_on: [test]
+    params:
+      items: { step: test }
+      steps:
+        - { id: fix, type: double }
+";
+        let (engine, id) = run(registry(&running, &max_running), text).await;
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Failed);
+        assert!(instance
+            .step_error(spec::step_id(id, "fix_each"))
+            .unwrap()
+            .starts_with("Expected an array at ''"));
+    }
+
+    #[tokio::test]
+    async fn test_loop_until_condition_fails() {
+        let (running, max_running) = Default::default();
+        let text = r"
+name: until-green
+steps:
+  - id: retry
+    type: loop
+    params:
+      max_iterations: 5
+      while: { step: check, path: /passed, equals: false }
+      steps:
+        - { id: check, type: attempt }
+";
+        let (engine, id) = run(registry(&running, &max_running), text).await;
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(spec::step_id(id, "retry")),
+            Some(&json!({ "iterations": 3, "outputs": { "check": { "passed": true } } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loop_gives_up() {
+        let (running, max_running) = Default::default();
+        let text = r"
+name: until-green
+steps:
+  - id: retry
+    type: loop
+    params:
+      max_iterations: 2
+      while: { step: check, path: /passed, equals: false }
+      steps:
+        - { id: check, type: attempt }
+";
+        let (engine, id) = run(registry(&running, &max_running), text).await;
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Failed);
+        assert_eq!(
+            instance.step_error(spec::step_id(id, "retry")),
+            Some("/passed equals false of step 'check' still held after 2 iterations")
+        );
+    }
+
+    #[test]
+    fn test_loop_condition_must_name_a_body_step() {
+        let (running, max_running) = Default::default();
+        let spec = WorkflowSpec::parse(
+            r"
+name: until-green
+steps:
+  - id: retry
+    type: loop
+    params:
+      while: { step: missing, truthy: false }
+      steps:
+        - { id: check, type: attempt }
+",
+            SpecFormat::Yaml,
+        )
+        .unwrap();
+        let error = registry(&running, &max_running)
+            .instantiate(&spec)
+            .unwrap_err();
+        assert!(error.to_string().contains("not part of its body"));
+    }
+}
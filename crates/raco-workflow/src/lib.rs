@@ -2,9 +2,25 @@
 //!
 //! This module provides workflow management functionality.
 
+pub mod agent;
+pub mod cancel;
+pub mod condition;
 pub mod engine;
+pub mod events;
+pub mod flow;
+pub mod mcp;
+pub mod model;
+pub mod persistence;
+pub mod policy;
+pub mod registry;
+pub mod schema;
+pub mod spec;
 pub mod steps;
+pub mod subworkflow;
+pub mod template;
+pub mod validation;
 
+use serde::{Deserialize, Serialize};
 use tracing::info;
 use uuid::Uuid;
 
@@ -31,7 +47,8 @@ pub type WorkflowId = Uuid;
 pub type StepId = Uuid;
 
 /// The status of a workflow
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WorkflowStatus {
     /// Workflow is pending execution
     Pending,
@@ -39,6 +56,8 @@ pub enum WorkflowStatus {
     Running,
     /// Workflow is paused, waiting for human input
     WaitingForInput,
+    /// Workflow was paused by a user and starts no new steps until resumed
+    Paused,
     /// Workflow has completed successfully
     Completed,
     /// Workflow has failed
@@ -48,7 +67,8 @@ pub enum WorkflowStatus {
 }
 
 /// The status of a workflow step
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum StepStatus {
     /// Step is pending execution
     Pending,
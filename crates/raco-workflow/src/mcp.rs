@@ -0,0 +1,469 @@
+//! Steps that call MCP tools
+//!
+//! Steps reach the tools of MCP servers through a [`ToolProvider`], which the
+//! engine hands them in [`StepContext::tools`]. [`ServerRegistry`] provides the
+//! tools of the servers RACO manages.
+//!
+//! [`McpToolStep`] calls a single tool. Its arguments are a [`Template`] over the
+//! context of the step:
+//!
+//! ```yaml
+//! - id: status
+//!   type: mcp_tool
+//!   depends_on: [checkout]
+//!   params:
+//!     server: git
+//!     tool: status
+//!     arguments:
+//!       path: "{{ steps.checkout.output.dir }}"
+//!       verbose: true
+//!     outputs:
+//!       branch: /branch
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use raco_mcp::server::ServerRegistry;
+use raco_mcp::tools::{ToolEntry, NAMESPACE_SEPARATOR};
+use serde_json::{json, Map, Value};
+
+use crate::schema;
+use crate::steps::{Step, StepContext, StepResult};
+use crate::template::Template;
+use crate::{StepId, StepStatus};
+
+/// Source of the tools steps may call
+#[async_trait]
+pub trait ToolProvider: Debug + Send + Sync {
+    /// All tools, sorted by qualified name
+    async fn list_tools(&self) -> Vec<ToolEntry>;
+
+    /// Resolve a tool name, bare or qualified as `server/tool`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no server provides the tool or the name is ambiguous
+    async fn resolve_tool(&self, name: &str) -> Result<ToolEntry>;
+
+    /// Call a tool and return the result of the `tools/call` request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tool cannot be resolved or the request fails
+    async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value>;
+}
+
+#[async_trait]
+impl ToolProvider for ServerRegistry {
+    async fn list_tools(&self) -> Vec<ToolEntry> {
+        self.tools().list().await
+    }
+
+    async fn resolve_tool(&self, name: &str) -> Result<ToolEntry> {
+        Ok(Self::resolve_tool(self, name).await?)
+    }
+
+    async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value> {
+        Ok(Self::call_tool(self, name, arguments).await?)
+    }
+}
+
+/// Text content of the result of a `tools/call` request
+fn content_text(result: &Value) -> String {
+    result
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|content| content["type"] == "text")
+        .filter_map(|content| content["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Describe the result of a `tools/call` request as text, e.g. for a model
+///
+/// Returns the text content, or the structured content if there is no text, and
+/// whether the tool reported an error.
+#[must_use]
+pub fn result_text(result: &Value) -> (String, bool) {
+    let mut text = content_text(result);
+    if text.is_empty() {
+        if let Some(structured) = result.get("structuredContent").filter(|v| !v.is_null()) {
+            text = structured.to_string();
+        }
+    }
+    (text, result["isError"] == true)
+}
+
+/// Turn the result of a `tools/call` request into a step output
+///
+/// The structured content of the result is the output. Results with only text
+/// content output the text parsed as a JSON object, or `{"text": <text>}` if it is
+/// not one.
+///
+/// # Errors
+///
+/// Returns an error with the text of the result if the tool reported an error
+pub fn tool_output(tool: &str, result: &Value) -> Result<Value> {
+    let text = content_text(result);
+
+    if result["isError"] == true {
+        bail!(
+            "Tool {} failed: {}",
+            tool,
+            if text.is_empty() { "no details" } else { &text }
+        );
+    }
+    if let Some(structured) = result.get("structuredContent").filter(|v| !v.is_null()) {
+        return Ok(structured.clone());
+    }
+    Ok(serde_json::from_str(&text)
+        .ok()
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({ "text": text })))
+}
+
+/// A step that calls a tool of an MCP server
+///
+/// The rendered arguments are the input of the step and are checked against the
+/// input schema of the tool. Without output mappings the step outputs the result of
+/// the tool as described in [`tool_output`].
+#[derive(Debug)]
+pub struct McpToolStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Tool to call, qualified with its server
+    tool: String,
+
+    /// Arguments of the tool
+    arguments: Template,
+
+    /// Output of this step, by key, as JSON pointers into the tool output
+    outputs: BTreeMap<String, String>,
+
+    /// Input schema of the tool, once the step has resolved it
+    schema: RwLock<Option<Value>>,
+}
+
+impl McpToolStep {
+    /// Create a step calling `tool` on the server named `server`
+    #[must_use]
+    pub fn new(
+        id: StepId,
+        name: String,
+        description: String,
+        server: &str,
+        tool: &str,
+        arguments: Template,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            tool: format!("{server}{NAMESPACE_SEPARATOR}{tool}"),
+            arguments,
+            outputs: BTreeMap::new(),
+            schema: RwLock::new(None),
+        }
+    }
+
+    /// Output the value at `path` of the tool output under `key`
+    #[must_use]
+    pub fn with_output(mut self, key: impl Into<String>, path: impl Into<String>) -> Self {
+        self.outputs.insert(key.into(), path.into());
+        self
+    }
+
+    fn output(&self, result: Value) -> Result<Value> {
+        if self.outputs.is_empty() {
+            return Ok(result);
+        }
+
+        let mut output = Map::new();
+        for (key, path) in &self.outputs {
+            let value = result.pointer(path).ok_or_else(|| {
+                anyhow!(
+                    "Cannot take '{}' from the result of tool {}: no value at '{}'",
+                    key,
+                    self.tool,
+                    path
+                )
+            })?;
+            output.insert(key.clone(), value.clone());
+        }
+        Ok(Value::Object(output))
+    }
+}
+
+#[async_trait]
+impl Step for McpToolStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<Value> {
+        self.schema.read().ok()?.clone()
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        None
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let tools = context.tools.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Step {} calls tool {}, but no MCP servers are available",
+                self.name,
+                self.tool
+            )
+        })?;
+
+        let entry = tools.resolve_tool(&self.tool).await?;
+        if let Ok(mut schema) = self.schema.write() {
+            *schema = Some(entry.tool.input_schema).filter(|schema| !schema.is_null());
+        }
+        self.validate_input(&context.input)?;
+
+        let result = tools.call_tool(&self.tool, context.input).await?;
+        let output = self.output(tool_output(&self.tool, &result)?)?;
+        Ok(StepResult {
+            output,
+            status: StepStatus::Completed,
+            error: None,
+        })
+    }
+
+    fn validate_input(&self, input: &Value) -> Result<()> {
+        match self.input_schema() {
+            Some(input_schema) => schema::check(
+                &input_schema,
+                input,
+                &format!("arguments for tool {}", self.tool),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn resolve_input(&self, context: &StepContext) -> Result<Value> {
+        self.arguments.render(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::engine::WorkflowEngine;
+    use crate::events::Progress;
+    use crate::spec::{self, SpecFormat, WorkflowSpec};
+    use crate::WorkflowStatus;
+    use raco_mcp::tools::Tool;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    /// Provider of a single `git/status` tool that echoes its arguments
+    #[derive(Debug, Default)]
+    struct FakeTools {
+        calls: Mutex<Vec<(String, Value)>>,
+    }
+
+    #[async_trait]
+    impl ToolProvider for FakeTools {
+        async fn list_tools(&self) -> Vec<ToolEntry> {
+            vec![ToolEntry {
+                server_id: Uuid::nil(),
+                server_name: "git".to_string(),
+                tool: Tool {
+                    name: "status".to_string(),
+                    description: None,
+                    input_schema: json!({
+                        "type": "object",
+                        "required": ["path"],
+                        "properties": { "path": { "type": "string" } }
+                    }),
+                },
+            }]
+        }
+
+        async fn resolve_tool(&self, name: &str) -> Result<ToolEntry> {
+            self.list_tools()
+                .await
+                .into_iter()
+                .find(|entry| entry.qualified_name() == name)
+                .ok_or_else(|| anyhow!("Unknown tool: {}", name))
+        }
+
+        async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((name.to_string(), arguments.clone()));
+            if arguments["path"] == "missing" {
+                return Ok(json!({
+                    "content": [{ "type": "text", "text": "No such directory" }],
+                    "isError": true
+                }));
+            }
+            Ok(json!({
+                "content": [{ "type": "text", "text": "On branch main" }],
+                "structuredContent": {
+                    "branch": "main",
+                    "path": arguments["path"],
+                    "files": ["Cargo.toml", "README.md"]
+                }
+            }))
+        }
+    }
+
+    fn step(arguments: Value) -> McpToolStep {
+        let template =
+            Template::new(arguments, &|name| Ok(spec::step_id(Uuid::nil(), name))).unwrap();
+        McpToolStep::new(
+            Uuid::new_v4(),
+            "Status".to_string(),
+            String::new(),
+            "git",
+            "status",
+            template,
+        )
+    }
+
+    async fn call(step: &McpToolStep, tools: Option<Arc<FakeTools>>) -> Result<StepResult> {
+        let mut context = StepContext {
+            input: json!({ "path": "src" }),
+            previous_outputs: HashMap::new(),
+            global: HashMap::new(),
+            progress: Progress::default(),
+            workflows: None,
+            tools: tools.map(|tools| tools as Arc<dyn ToolProvider>),
+            model: None,
+            cancel: CancelToken::default(),
+        };
+        context.input = step.resolve_input(&context)?;
+        step.validate_input(&context.input)?;
+        step.execute(context).await
+    }
+
+    #[test]
+    fn test_tool_output() {
+        let text = |text: &str| json!({ "content": [{ "type": "text", "text": text }] });
+
+        assert_eq!(
+            tool_output("t", &text(r#"{"files": 2}"#)).unwrap(),
+            json!({ "files": 2 })
+        );
+        assert_eq!(
+            tool_output("t", &text("[1, 2]")).unwrap(),
+            json!({ "text": "[1, 2]" })
+        );
+        assert_eq!(
+            tool_output("t", &json!({ "isError": true }))
+                .unwrap_err()
+                .to_string(),
+            "Tool t failed: no details"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tool_call() {
+        let tools = Arc::new(FakeTools::default());
+
+        let status =
+            step(json!({ "path": "{{ input.path }}/lib" })).with_output("branch", "/branch");
+        let result = call(&status, Some(Arc::clone(&tools))).await.unwrap();
+        assert_eq!(result.output, json!({ "branch": "main" }));
+        assert_eq!(
+            tools.calls.lock().unwrap().as_slice(),
+            [("git/status".to_string(), json!({ "path": "src/lib" }))]
+        );
+        assert!(status.input_schema().is_some());
+
+        let error = call(&step(json!({ "path": "missing" })), Some(tools))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Tool git/status failed: No such directory"
+        );
+
+        let error = call(&status, None).await.unwrap_err();
+        assert!(error.to_string().contains("no MCP servers are available"));
+    }
+
+    #[tokio::test]
+    async fn test_arguments_follow_the_tool_schema() {
+        let tools = Arc::new(FakeTools::default());
+        let status = step(json!({ "path": 12 }));
+
+        let error = call(&status, Some(Arc::clone(&tools))).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid arguments for tool git/status:\n  - /path: "));
+        assert!(tools.calls.lock().unwrap().is_empty());
+
+        // The schema is known from now on, so the input is rejected up front
+        assert!(status.validate_input(&json!({})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_workflow_steps_call_tools() {
+        let text = r#"
+name: status
+steps:
+  - id: status
+    type: mcp_tool
+    params:
+      server: git
+      tool: status
+      arguments: { path: "{{ input.path }}" }
+      outputs: { files: /files }
+  - id: each
+    type: map
+    depends_on: [status]
+    params:
+      items: { step: status, path: /files }
+      steps:
+        - id: file
+          type: mcp_tool
+          params: { server: git, tool: status, arguments: { path: "{{ input.item }}" } }
+"#;
+        let tools = Arc::new(FakeTools::default());
+        let engine = WorkflowEngine::new().with_tools(Arc::clone(&tools) as Arc<dyn ToolProvider>);
+        let spec = WorkflowSpec::parse(text, SpecFormat::Yaml).unwrap();
+        let id = engine.create_workflow_from_spec(spec).await.unwrap();
+        engine
+            .start_workflow_with_input(id, json!({ "path": "src" }))
+            .await
+            .unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(spec::step_id(id, "each")).unwrap()["results"][1]["file"]["path"],
+            "README.md"
+        );
+        assert_eq!(tools.calls.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,1055 @@
+//! Language model providers
+//!
+//! Steps that talk to a language model go through a [`ModelProvider`]. The
+//! engine hands its default provider to every step in [`StepContext::model`];
+//! steps may also bring their own, described in the workflow file by a
+//! [`ModelConfig`]:
+//!
+//! ```yaml
+//! params:
+//!   model:
+//!     provider: anthropic       # openai, anthropic or ollama
+//!     model: claude-3-5-sonnet-latest
+//!     api_key_env: ANTHROPIC_API_KEY
+//!     timeout_secs: 120         # defaults to 300
+//! ```
+//!
+//! The `openai` provider speaks the chat completions API, which llama.cpp and most
+//! other local servers offer as well; point `base_url` at them.
+//!
+//! [`StepContext::model`]: crate::steps::StepContext::model
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Default number of tokens a model may generate per request
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// A request for a completion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    /// Instructions that frame the conversation
+    pub system: Option<String>,
+
+    /// The message to complete
+    pub prompt: String,
+
+    /// Maximum number of tokens to generate
+    pub max_tokens: u32,
+}
+
+impl CompletionRequest {
+    /// Create a request for a completion of `prompt`
+    #[must_use]
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            system: None,
+            prompt: prompt.into(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+        }
+    }
+
+    /// Frame the conversation with the given instructions
+    #[must_use]
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    /// Limit the number of tokens to generate
+    #[must_use]
+    pub const fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+/// Tokens a request consumed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    /// Tokens of the prompt
+    pub input_tokens: u64,
+
+    /// Tokens the model generated
+    pub output_tokens: u64,
+}
+
+/// The answer of a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    /// Generated text
+    pub text: String,
+
+    /// Tokens the request consumed
+    pub usage: TokenUsage,
+}
+
+impl TokenUsage {
+    /// Tokens consumed in total
+    #[must_use]
+    pub const fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// A tool the model may call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    /// Name the model calls the tool by
+    pub name: String,
+
+    /// What the tool does
+    pub description: String,
+
+    /// JSON Schema of the arguments
+    pub input_schema: Value,
+}
+
+/// A tool call the model asked for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// ID the result of the call refers to
+    pub id: String,
+
+    /// Name of the tool
+    pub name: String,
+
+    /// Arguments of the call
+    pub arguments: Value,
+
+    /// Why the arguments could not be read, in which case `arguments` holds
+    /// them as the model sent them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A message of a conversation with a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum Message {
+    /// Message to the model
+    User {
+        /// Text of the message
+        content: String,
+    },
+    /// Answer of the model
+    Assistant {
+        /// Text of the answer
+        content: String,
+        /// Tools the model asked to call
+        tool_calls: Vec<ToolCall>,
+    },
+    /// Result of a tool call
+    Tool {
+        /// ID of the call
+        call_id: String,
+        /// Result of the call, as text
+        content: String,
+        /// Whether the call failed
+        is_error: bool,
+    },
+}
+
+/// A request to continue a conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatRequest {
+    /// Instructions that frame the conversation
+    pub system: Option<String>,
+
+    /// The conversation so far
+    pub messages: Vec<Message>,
+
+    /// Tools the model may call
+    pub tools: Vec<ToolDefinition>,
+
+    /// Maximum number of tokens to generate
+    pub max_tokens: u32,
+}
+
+/// The next message of a conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatResponse {
+    /// Text of the answer
+    pub content: String,
+
+    /// Tools the model asked to call; none for a final answer
+    pub tool_calls: Vec<ToolCall>,
+
+    /// Tokens the request consumed
+    pub usage: TokenUsage,
+}
+
+/// A language model steps can talk to
+#[async_trait]
+pub trait ModelProvider: Debug + Send + Sync {
+    /// Name of the model, for logs and step outputs
+    fn model(&self) -> &str;
+
+    /// Ask the model for the next message of a conversation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model cannot be reached or rejects the request
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse>;
+
+    /// Ask the model to complete a single prompt
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model cannot be reached or rejects the request
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let response = self
+            .chat(&ChatRequest {
+                system: request.system.clone(),
+                messages: vec![Message::User {
+                    content: request.prompt.clone(),
+                }],
+                tools: Vec::new(),
+                max_tokens: request.max_tokens,
+            })
+            .await?;
+        Ok(Completion {
+            text: response.content,
+            usage: response.usage,
+        })
+    }
+}
+
+/// Kind of API a model is served with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    /// OpenAI chat completions API, also served by llama.cpp
+    Openai,
+    /// Anthropic messages API
+    Anthropic,
+    /// Ollama chat API
+    Ollama,
+}
+
+/// Time to wait for a model response when the configuration sets none; local
+/// models can take minutes for a long answer
+const DEFAULT_MODEL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Description of a model provider in a workflow file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    /// API the model is served with
+    pub provider: ProviderKind,
+
+    /// Name of the model
+    pub model: String,
+
+    /// Address of the API; defaults to the public endpoint of the provider
+    #[serde(default)]
+    pub base_url: Option<String>,
+
+    /// Environment variable holding the API key, read for every request
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+
+    /// Seconds to wait for a response, including its body (defaults to 300)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl ModelConfig {
+    /// Create the provider this configuration describes
+    #[must_use]
+    pub fn provider(&self) -> Arc<dyn ModelProvider> {
+        let api = HttpApi {
+            client: reqwest::Client::new(),
+            timeout: self
+                .timeout_secs
+                .map_or(DEFAULT_MODEL_TIMEOUT, Duration::from_secs),
+            base_url: self
+                .base_url
+                .clone()
+                .unwrap_or_else(|| self.default_base_url().to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key_env: self
+                .api_key_env
+                .clone()
+                .or_else(|| self.default_api_key_env().map(str::to_string)),
+            model: self.model.clone(),
+        };
+        match self.provider {
+            ProviderKind::Openai => Arc::new(OpenAiProvider { api }),
+            ProviderKind::Anthropic => Arc::new(AnthropicProvider { api }),
+            ProviderKind::Ollama => Arc::new(OllamaProvider { api }),
+        }
+    }
+
+    const fn default_base_url(&self) -> &'static str {
+        match self.provider {
+            ProviderKind::Openai => "https://api.openai.com/v1",
+            ProviderKind::Anthropic => "https://api.anthropic.com",
+            ProviderKind::Ollama => "http://localhost:11434",
+        }
+    }
+
+    const fn default_api_key_env(&self) -> Option<&'static str> {
+        match self.provider {
+            ProviderKind::Openai => Some("OPENAI_API_KEY"),
+            ProviderKind::Anthropic => Some("ANTHROPIC_API_KEY"),
+            ProviderKind::Ollama => None,
+        }
+    }
+}
+
+/// Connection to a model served over HTTP
+#[derive(Debug, Clone)]
+struct HttpApi {
+    client: reqwest::Client,
+    timeout: Duration,
+    base_url: String,
+    api_key_env: Option<String>,
+    model: String,
+}
+
+impl HttpApi {
+    /// API key from the environment; servers without authentication need none
+    fn api_key(&self) -> Option<String> {
+        self.api_key_env
+            .as_ref()
+            .and_then(|name| std::env::var(name).ok())
+            .filter(|key| !key.is_empty())
+    }
+
+    /// Post a JSON body with the given headers and return the JSON response
+    async fn post(&self, path: &str, headers: Vec<(&str, String)>, body: Value) -> Result<Value> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self.client.post(&url).timeout(self.timeout).json(&body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach model at {url}"))?;
+
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .with_context(|| format!("Failed to read the response of {url}"))?;
+        if !status.is_success() {
+            bail!(
+                "Model request to {} failed with status {}: {}",
+                url,
+                status,
+                text
+            );
+        }
+        serde_json::from_str(&text).with_context(|| format!("Invalid response from {url}"))
+    }
+}
+
+fn tokens(value: &Value) -> u64 {
+    value.as_u64().unwrap_or_default()
+}
+
+/// Arguments of a tool call; some models send them as a JSON string
+fn arguments(value: &Value) -> Result<Value> {
+    match value {
+        Value::String(text) => serde_json::from_str(text)
+            .with_context(|| format!("Arguments are not valid JSON: {text}")),
+        Value::Null => Ok(json!({})),
+        value => Ok(value.clone()),
+    }
+}
+
+/// A tool call that keeps arguments the model got wrong along with the reason
+fn tool_call(id: String, name: String, raw_arguments: &Value) -> ToolCall {
+    let (arguments, error) = match arguments(raw_arguments) {
+        Ok(arguments) => (arguments, None),
+        Err(e) => (raw_arguments.clone(), Some(format!("{e:#}"))),
+    };
+    ToolCall {
+        id,
+        name,
+        arguments,
+        error,
+    }
+}
+
+/// Model served with the OpenAI chat completions API
+#[derive(Debug)]
+pub struct OpenAiProvider {
+    api: HttpApi,
+}
+
+impl OpenAiProvider {
+    fn messages(request: &ChatRequest) -> Vec<Value> {
+        let system = request
+            .system
+            .iter()
+            .map(|system| json!({ "role": "system", "content": system }));
+        let messages = request.messages.iter().map(|message| match message {
+            Message::User { content } => json!({ "role": "user", "content": content }),
+            Message::Assistant {
+                content,
+                tool_calls,
+            } if tool_calls.is_empty() => json!({ "role": "assistant", "content": content }),
+            Message::Assistant {
+                content,
+                tool_calls,
+            } => json!({
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls.iter().map(|call| json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": { "name": call.name, "arguments": match &call.arguments {
+                        // Unreadable arguments go back as the model sent them
+                        Value::String(text) if call.error.is_some() => text.clone(),
+                        arguments => arguments.to_string(),
+                    } },
+                })).collect::<Vec<_>>(),
+            }),
+            Message::Tool {
+                call_id, content, ..
+            } => json!({ "role": "tool", "tool_call_id": call_id, "content": content }),
+        });
+        system.chain(messages).collect()
+    }
+}
+
+/// Tools in the function format of the OpenAI and Ollama APIs
+fn functions(tools: &[ToolDefinition]) -> Vec<Value> {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.input_schema,
+                },
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ModelProvider for OpenAiProvider {
+    fn model(&self) -> &str {
+        &self.api.model
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let headers = self
+            .api
+            .api_key()
+            .map(|key| ("authorization", format!("Bearer {key}")))
+            .into_iter()
+            .collect();
+        let mut body = json!({
+            "model": self.api.model,
+            "messages": Self::messages(request),
+            "max_tokens": request.max_tokens,
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = json!(functions(&request.tools));
+        }
+        let response = self.api.post("/chat/completions", headers, body).await?;
+
+        let message = response["choices"][0]
+            .get("message")
+            .ok_or_else(|| anyhow!("Response of model {} has no message", self.api.model))?;
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|call| {
+                tool_call(
+                    call["id"].as_str().unwrap_or_default().to_string(),
+                    call["function"]["name"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    &call["function"]["arguments"],
+                )
+            })
+            .collect();
+        Ok(ChatResponse {
+            content: message["content"].as_str().unwrap_or_default().to_string(),
+            tool_calls,
+            usage: TokenUsage {
+                input_tokens: tokens(&response["usage"]["prompt_tokens"]),
+                output_tokens: tokens(&response["usage"]["completion_tokens"]),
+            },
+        })
+    }
+}
+
+/// Model served with the Anthropic messages API
+#[derive(Debug)]
+pub struct AnthropicProvider {
+    api: HttpApi,
+}
+
+/// Version of the Anthropic API the provider speaks
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+impl AnthropicProvider {
+    /// Messages of a request; results of parallel tool calls share one message
+    fn messages(request: &ChatRequest) -> Vec<Value> {
+        let mut messages: Vec<Value> = Vec::new();
+        for message in &request.messages {
+            match message {
+                Message::User { content } => {
+                    messages.push(json!({ "role": "user", "content": content }));
+                }
+                Message::Assistant {
+                    content,
+                    tool_calls,
+                } => {
+                    let text =
+                        (!content.is_empty()).then(|| json!({ "type": "text", "text": content }));
+                    let calls = tool_calls.iter().map(|call| {
+                        json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.name,
+                            "input": call.arguments,
+                        })
+                    });
+                    let blocks: Vec<_> = text.into_iter().chain(calls).collect();
+                    messages.push(json!({ "role": "assistant", "content": blocks }));
+                }
+                Message::Tool {
+                    call_id,
+                    content,
+                    is_error,
+                } => {
+                    let block = json!({
+                        "type": "tool_result",
+                        "tool_use_id": call_id,
+                        "content": content,
+                        "is_error": is_error,
+                    });
+                    match messages.last_mut() {
+                        Some(last) if last["role"] == "user" && last["content"].is_array() => {
+                            if let Some(blocks) = last["content"].as_array_mut() {
+                                blocks.push(block);
+                            }
+                        }
+                        _ => messages.push(json!({ "role": "user", "content": [block] })),
+                    }
+                }
+            }
+        }
+        messages
+    }
+}
+
+#[async_trait]
+impl ModelProvider for AnthropicProvider {
+    fn model(&self) -> &str {
+        &self.api.model
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let mut headers = vec![("anthropic-version", ANTHROPIC_VERSION.to_string())];
+        if let Some(key) = self.api.api_key() {
+            headers.push(("x-api-key", key));
+        }
+        let mut body = json!({
+            "model": self.api.model,
+            "max_tokens": request.max_tokens,
+            "messages": Self::messages(request),
+        });
+        if let Some(system) = &request.system {
+            body["system"] = json!(system);
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
+        }
+        let response = self.api.post("/v1/messages", headers, body).await?;
+
+        let blocks = response["content"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Response of model {} has no content", self.api.model))?;
+        let content = blocks
+            .iter()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect::<String>();
+        let tool_calls = blocks
+            .iter()
+            .filter(|block| block["type"] == "tool_use")
+            .map(|block| {
+                tool_call(
+                    block["id"].as_str().unwrap_or_default().to_string(),
+                    block["name"].as_str().unwrap_or_default().to_string(),
+                    &block["input"],
+                )
+            })
+            .collect();
+        Ok(ChatResponse {
+            content,
+            tool_calls,
+            usage: TokenUsage {
+                input_tokens: tokens(&response["usage"]["input_tokens"]),
+                output_tokens: tokens(&response["usage"]["output_tokens"]),
+            },
+        })
+    }
+}
+
+/// Model served by Ollama
+#[derive(Debug)]
+pub struct OllamaProvider {
+    api: HttpApi,
+}
+
+impl OllamaProvider {
+    fn messages(request: &ChatRequest) -> Vec<Value> {
+        let system = request
+            .system
+            .iter()
+            .map(|system| json!({ "role": "system", "content": system }));
+        let messages = request.messages.iter().map(|message| match message {
+            Message::User { content } => json!({ "role": "user", "content": content }),
+            Message::Assistant {
+                content,
+                tool_calls,
+            } => json!({
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls.iter().map(|call| json!({
+                    "function": { "name": call.name, "arguments": call.arguments },
+                })).collect::<Vec<_>>(),
+            }),
+            Message::Tool { content, .. } => json!({ "role": "tool", "content": content }),
+        });
+        system.chain(messages).collect()
+    }
+}
+
+#[async_trait]
+impl ModelProvider for OllamaProvider {
+    fn model(&self) -> &str {
+        &self.api.model
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let mut body = json!({
+            "model": self.api.model,
+            "messages": Self::messages(request),
+            "stream": false,
+            "options": { "num_predict": request.max_tokens },
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = json!(functions(&request.tools));
+        }
+        let response = self.api.post("/api/chat", Vec::new(), body).await?;
+
+        let message = response
+            .get("message")
+            .ok_or_else(|| anyhow!("Response of model {} has no message", self.api.model))?;
+        // Ollama does not identify tool calls, so they are numbered
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, call)| {
+                tool_call(
+                    format!("call_{index}"),
+                    call["function"]["name"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    &call["function"]["arguments"],
+                )
+            })
+            .collect();
+        Ok(ChatResponse {
+            content: message["content"].as_str().unwrap_or_default().to_string(),
+            tool_calls,
+            usage: TokenUsage {
+                input_tokens: tokens(&response["prompt_eval_count"]),
+                output_tokens: tokens(&response["eval_count"]),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::Mutex;
+
+    /// Requests the stub server received, with their headers
+    type Requests = Arc<Mutex<Vec<(HeaderMap, Value)>>>;
+
+    /// Serve canned answers of all supported APIs on a local port
+    async fn stub_server() -> (String, Requests) {
+        let answer = |answer: Value| {
+            post(
+                move |State(requests): State<Requests>,
+                      headers: HeaderMap,
+                      Json(body): Json<Value>| async move {
+                    requests.lock().unwrap().push((headers, body));
+                    Json(answer)
+                },
+            )
+        };
+        let requests = Requests::default();
+        let app = Router::new()
+            .route(
+                "/v1/chat/completions",
+                answer(json!({
+                    "choices": [{ "message": { "role": "assistant", "content": "fn a() {}" } }],
+                    "usage": { "prompt_tokens": 12, "completion_tokens": 5 }
+                })),
+            )
+            .route(
+                "/v1/messages",
+                answer(json!({
+                    "content": [{ "type": "text", "text": "fn b() {}" }],
+                    "usage": { "input_tokens": 20, "output_tokens": 7 }
+                })),
+            )
+            .route(
+                "/api/chat",
+                answer(json!({
+                    "message": { "role": "assistant", "content": "fn c() {}" },
+                    "prompt_eval_count": 30,
+                    "eval_count": 9
+                })),
+            )
+            .route(
+                "/tools/v1/chat/completions",
+                answer(json!({
+                    "choices": [{ "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": { "name": "git__status", "arguments": "{\"path\":\"src\"}" }
+                        }]
+                    } }]
+                })),
+            )
+            .route(
+                "/tools/v1/messages",
+                answer(json!({
+                    "content": [
+                        { "type": "text", "text": "Let me look" },
+                        { "type": "tool_use", "id": "toolu_1", "name": "git__status", "input": { "path": "src" } }
+                    ]
+                })),
+            )
+            .route(
+                "/tools/api/chat",
+                answer(json!({
+                    "message": {
+                        "role": "assistant",
+                        "content": "",
+                        "tool_calls": [{ "function": { "name": "git__status", "arguments": { "path": "src" } } }]
+                    }
+                })),
+            )
+            .route(
+                "/broken/v1/chat/completions",
+                answer(json!({
+                    "choices": [{ "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": { "name": "git__status", "arguments": "{\"path\":" }
+                        }]
+                    } }]
+                })),
+            )
+            .route(
+                "/slow/chat/completions",
+                post(|| async {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    "{}"
+                }),
+            )
+            .route(
+                "/busy/chat/completions",
+                post(|| async { (StatusCode::TOO_MANY_REQUESTS, "Slow down") }),
+            )
+            .with_state(Arc::clone(&requests));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener).unwrap();
+        tokio::spawn(server.serve(app.into_make_service()));
+        (url, requests)
+    }
+
+    fn provider(config: Value) -> Arc<dyn ModelProvider> {
+        serde_json::from_value::<ModelConfig>(config)
+            .unwrap()
+            .provider()
+    }
+
+    #[tokio::test]
+    async fn test_providers() {
+        let (url, requests) = stub_server().await;
+        std::env::set_var("RACO_TEST_MODEL_KEY", "secret");
+        let request = CompletionRequest::new("Write a function")
+            .with_system("Answer with code")
+            .with_max_tokens(100);
+
+        let openai = provider(json!({
+            "provider": "openai",
+            "model": "qwen",
+            "base_url": format!("{url}/v1/"),
+            "api_key_env": "RACO_TEST_MODEL_KEY"
+        }));
+        assert_eq!(openai.model(), "qwen");
+        let completion = openai.complete(&request).await.unwrap();
+        assert_eq!(completion.text, "fn a() {}");
+        assert_eq!(
+            completion.usage,
+            TokenUsage {
+                input_tokens: 12,
+                output_tokens: 5
+            }
+        );
+
+        let anthropic = provider(json!({
+            "provider": "anthropic",
+            "model": "claude",
+            "base_url": url,
+            "api_key_env": "RACO_TEST_MODEL_KEY"
+        }));
+        let completion = anthropic.complete(&request).await.unwrap();
+        assert_eq!(completion.text, "fn b() {}");
+        assert_eq!(completion.usage.input_tokens, 20);
+
+        let ollama = provider(json!({ "provider": "ollama", "model": "llama", "base_url": url }));
+        let completion = ollama.complete(&request).await.unwrap();
+        assert_eq!(completion.text, "fn c() {}");
+        assert_eq!(completion.usage.output_tokens, 9);
+
+        let requests = requests.lock().unwrap();
+        let (headers, body) = &requests[0];
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(body["model"], "qwen");
+        assert_eq!(body["max_tokens"], 100);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "Write a function");
+
+        let (headers, body) = &requests[1];
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["anthropic-version"], ANTHROPIC_VERSION);
+        assert_eq!(body["system"], "Answer with code");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+
+        let (headers, body) = &requests[2];
+        assert!(headers.get("authorization").is_none());
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["options"]["num_predict"], 100);
+    }
+
+    #[tokio::test]
+    async fn test_tool_use() {
+        let (url, requests) = stub_server().await;
+        let call = ToolCall {
+            id: "call_0".to_string(),
+            name: "git__log".to_string(),
+            arguments: json!({ "limit": 1 }),
+            error: None,
+        };
+        let request = ChatRequest {
+            system: None,
+            messages: vec![
+                Message::User {
+                    content: "Check the repository".to_string(),
+                },
+                Message::Assistant {
+                    content: String::new(),
+                    tool_calls: vec![
+                        call.clone(),
+                        ToolCall {
+                            id: "call_1".to_string(),
+                            ..call
+                        },
+                    ],
+                },
+                Message::Tool {
+                    call_id: "call_0".to_string(),
+                    content: "Initial commit".to_string(),
+                    is_error: false,
+                },
+                Message::Tool {
+                    call_id: "call_1".to_string(),
+                    content: "No such branch".to_string(),
+                    is_error: true,
+                },
+            ],
+            tools: vec![ToolDefinition {
+                name: "git__status".to_string(),
+                description: "Show the status".to_string(),
+                input_schema: json!({ "type": "object" }),
+            }],
+            max_tokens: 100,
+        };
+        let status = ToolCall {
+            id: String::new(),
+            name: "git__status".to_string(),
+            arguments: json!({ "path": "src" }),
+            error: None,
+        };
+
+        for (index, (provider_kind, base_url, id)) in [
+            ("openai", format!("{url}/tools/v1"), "call_1"),
+            ("anthropic", format!("{url}/tools"), "toolu_1"),
+            ("ollama", format!("{url}/tools"), "call_0"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let model =
+                provider(json!({ "provider": provider_kind, "model": "m", "base_url": base_url }));
+            let response = model.chat(&request).await.unwrap();
+            assert_eq!(
+                response.tool_calls,
+                [ToolCall {
+                    id: id.to_string(),
+                    ..status.clone()
+                }],
+                "{provider_kind}"
+            );
+            let body = &requests.lock().unwrap()[index].1;
+            assert_eq!(
+                body["messages"].as_array().unwrap().len(),
+                if provider_kind == "anthropic" { 3 } else { 4 }
+            );
+        }
+
+        let requests = requests.lock().unwrap();
+        let openai = &requests[0].1;
+        assert_eq!(openai["tools"][0]["function"]["name"], "git__status");
+        assert_eq!(openai["messages"][1]["tool_calls"][1]["id"], "call_1");
+        assert_eq!(
+            openai["messages"][1]["tool_calls"][0]["function"]["arguments"],
+            r#"{"limit":1}"#
+        );
+        assert_eq!(openai["messages"][3]["tool_call_id"], "call_1");
+
+        let anthropic = &requests[1].1;
+        assert_eq!(
+            anthropic["tools"][0]["input_schema"],
+            json!({ "type": "object" })
+        );
+        assert_eq!(anthropic["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(
+            anthropic["messages"][2]["content"][1]["tool_use_id"],
+            "call_1"
+        );
+        assert_eq!(anthropic["messages"][2]["content"][1]["is_error"], true);
+
+        let ollama = &requests[2].1;
+        assert_eq!(
+            ollama["messages"][1]["tool_calls"][0]["function"]["arguments"],
+            json!({ "limit": 1 })
+        );
+        assert_eq!(ollama["messages"][3]["role"], "tool");
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_tool_arguments() {
+        let (url, requests) = stub_server().await;
+        let model = provider(
+            json!({ "provider": "openai", "model": "m", "base_url": format!("{url}/broken/v1") }),
+        );
+        let mut request = ChatRequest {
+            system: None,
+            messages: vec![Message::User {
+                content: "Check the repository".to_string(),
+            }],
+            tools: Vec::new(),
+            max_tokens: 100,
+        };
+        let response = model.chat(&request).await.unwrap();
+        let call = &response.tool_calls[0];
+        assert_eq!(call.arguments, json!("{\"path\":"));
+        assert!(call
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("Arguments are not valid JSON: {\"path\":: EOF"));
+
+        // The model sees its arguments as it sent them
+        request.messages.push(Message::Assistant {
+            content: String::new(),
+            tool_calls: response.tool_calls,
+        });
+        model.chat(&request).await.unwrap();
+        let body = &requests.lock().unwrap()[1].1;
+        assert_eq!(
+            body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+            "{\"path\":"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests() {
+        let (url, _) = stub_server().await;
+
+        let busy = provider(
+            json!({ "provider": "openai", "model": "m", "base_url": format!("{url}/busy") }),
+        );
+        let error = busy
+            .complete(&CompletionRequest::new("x"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Model request to {url}/busy/chat/completions failed with status 429 Too Many Requests: Slow down")
+        );
+
+        let missing = provider(
+            json!({ "provider": "ollama", "model": "m", "base_url": format!("{url}/missing") }),
+        );
+        let error = missing
+            .complete(&CompletionRequest::new("x"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("failed with status 404"));
+
+        let slow = provider(json!({
+            "provider": "openai",
+            "model": "m",
+            "base_url": format!("{url}/slow"),
+            "timeout_secs": 1
+        }));
+        let error = slow
+            .complete(&CompletionRequest::new("x"))
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("timed out"), "{error:#}");
+    }
+}
@@ -0,0 +1,390 @@
+//! Workflow persistence
+//!
+//! This module checkpoints workflow instances to disk so they survive a restart.
+//! Each instance is stored as one JSON file named after its ID, next to a JSON Lines
+//! file holding the history of its events.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::events::WorkflowEvent;
+use crate::policy::StepAttempt;
+use crate::spec::WorkflowSpec;
+use crate::subworkflow::ParentStep;
+use crate::{StepId, StepStatus, WorkflowId, WorkflowStatus};
+
+/// Name of the directory holding checkpoints inside the data directory
+pub const WORKFLOWS_DIR: &str = "workflows";
+
+/// Saved state of a step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepSnapshot {
+    /// Status
+    pub status: StepStatus,
+
+    /// Output, once the step finished
+    #[serde(default)]
+    pub output: Option<Value>,
+
+    /// Error message, if the step failed
+    #[serde(default)]
+    pub error: Option<String>,
+
+    /// Start time
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+
+    /// Completion time
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+
+    /// Attempts made to run the step
+    #[serde(default)]
+    pub attempts: Vec<StepAttempt>,
+
+    /// Failed step this step stands in for, if it is a failure handler in use
+    #[serde(default)]
+    pub fallback_for: Option<StepId>,
+
+    /// Sub-workflows the step started, oldest first
+    #[serde(default)]
+    pub children: Vec<WorkflowId>,
+}
+
+/// Saved state of a workflow instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowSnapshot {
+    /// Workflow ID
+    pub id: WorkflowId,
+
+    /// Workflow name
+    pub name: String,
+
+    /// Workflow description
+    #[serde(default)]
+    pub description: String,
+
+    /// Workflow file the instance was created from; needed to resume it
+    #[serde(default)]
+    pub spec: Option<WorkflowSpec>,
+
+    /// Step of another workflow this instance runs for, if it is a sub-workflow
+    #[serde(default)]
+    pub parent: Option<ParentStep>,
+
+    /// Workflow status
+    pub status: WorkflowStatus,
+
+    /// Reason the workflow failed
+    #[serde(default)]
+    pub error: Option<String>,
+
+    /// Input passed to every step
+    #[serde(default)]
+    pub input: Value,
+
+    /// Context shared by all steps
+    #[serde(default)]
+    pub global: HashMap<String, Value>,
+
+    /// State of each step
+    pub steps: HashMap<StepId, StepSnapshot>,
+
+    /// Creation time
+    pub created_at: DateTime<Utc>,
+
+    /// Start time
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+
+    /// Completion time
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Directory of workflow checkpoints
+#[derive(Debug, Clone)]
+pub struct WorkflowStore {
+    /// Directory holding one file per workflow
+    dir: PathBuf,
+}
+
+impl WorkflowStore {
+    /// Create a store in the given directory
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Create a store in the `workflows` directory of the RACO data directory
+    pub fn in_data_dir<P: AsRef<Path>>(data_dir: P) -> Self {
+        Self::new(data_dir.as_ref().join(WORKFLOWS_DIR))
+    }
+
+    /// Directory of the store
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, id: WorkflowId) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    fn events_path(&self, id: WorkflowId) -> PathBuf {
+        self.dir.join(format!("{id}.events.jsonl"))
+    }
+
+    /// Save a snapshot, replacing the previous one of the same workflow
+    ///
+    /// The snapshot is written to a temporary file first, so a crash never leaves a
+    /// truncated checkpoint behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be written
+    pub async fn save(&self, snapshot: &WorkflowSnapshot) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        let path = self.path(snapshot.id);
+        let temp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec_pretty(snapshot)?;
+        tokio::fs::write(&temp, json)
+            .await
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        tokio::fs::rename(&temp, &path)
+            .await
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Load the snapshot of a workflow
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be read or parsed
+    pub async fn load(&self, id: WorkflowId) -> Result<WorkflowSnapshot> {
+        let path = self.path(id);
+        let json = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Corrupt checkpoint {}", path.display()))
+    }
+
+    /// Load all snapshots, skipping unreadable files
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory exists but cannot be listed
+    pub async fn load_all(&self) -> Result<Vec<WorkflowSnapshot>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to list {}", self.dir.display()))
+            }
+        };
+
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let snapshot = tokio::fs::read(&path)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice::<WorkflowSnapshot>(&json)?));
+            match snapshot {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => warn!("Skipping checkpoint {}: {}", path.display(), e),
+            }
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.created_at);
+        Ok(snapshots)
+    }
+
+    /// Append an event to the history of its workflow
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be written
+    pub async fn append_event(&self, event: &WorkflowEvent) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        let path = self.events_path(event.workflow_id);
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(&line)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Load the history of a workflow, oldest event first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing history cannot be read or parsed
+    pub async fn load_events(&self, id: WorkflowId) -> Result<Vec<WorkflowEvent>> {
+        let path = self.events_path(id);
+        let text = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Corrupt event in {}", path.display()))
+            })
+            .collect()
+    }
+
+    /// Record events in the histories of their workflows until the channel closes
+    ///
+    /// Pass a receiver from
+    /// [`WorkflowEngine::subscribe`](crate::engine::WorkflowEngine::subscribe); the
+    /// returned task finishes after the engine is dropped and all events are written.
+    pub fn record(&self, mut events: broadcast::Receiver<WorkflowEvent>) -> JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Err(e) = store.append_event(&event).await {
+                            warn!("Failed to record workflow event: {:#}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Workflow history is missing {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Delete the snapshot and history of a workflow
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing file cannot be deleted
+    pub async fn remove(&self, id: WorkflowId) -> Result<()> {
+        for path in [self.path(id), self.events_path(id)] {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use uuid::Uuid;
+
+    fn snapshot() -> WorkflowSnapshot {
+        let step = Uuid::new_v4();
+        WorkflowSnapshot {
+            id: Uuid::new_v4(),
+            name: "build".to_string(),
+            description: String::new(),
+            spec: None,
+            parent: None,
+            status: WorkflowStatus::Running,
+            error: None,
+            input: serde_json::json!({"crate": "raco-core"}),
+            global: HashMap::new(),
+            steps: HashMap::from([(
+                step,
+                StepSnapshot {
+                    status: StepStatus::Completed,
+                    output: Some(serde_json::json!({"ok": true})),
+                    error: None,
+                    started_at: Some(Utc::now()),
+                    completed_at: Some(Utc::now()),
+                    attempts: Vec::new(),
+                    fallback_for: None,
+                    children: Vec::new(),
+                },
+            )]),
+            created_at: Utc::now(),
+            started_at: Some(Utc::now()),
+            completed_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WorkflowStore::in_data_dir(dir.path());
+        assert!(store.load_all().await.unwrap().is_empty());
+
+        let snapshot = snapshot();
+        store.save(&snapshot).await.unwrap();
+        store.save(&snapshot).await.unwrap();
+        assert_eq!(store.load(snapshot.id).await.unwrap(), snapshot);
+
+        std::fs::write(store.dir().join("garbage.json"), "{").unwrap();
+        assert_eq!(
+            store.load_all().await.unwrap(),
+            std::slice::from_ref(&snapshot)
+        );
+
+        store.remove(snapshot.id).await.unwrap();
+        store.remove(snapshot.id).await.unwrap();
+        assert!(store.load(snapshot.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WorkflowStore::new(dir.path());
+        let id = Uuid::new_v4();
+
+        let (sender, receiver) = broadcast::channel(16);
+        let recorder = store.record(receiver);
+        sender
+            .send(WorkflowEvent::new(id, EventKind::WorkflowStarted))
+            .unwrap();
+        sender
+            .send(WorkflowEvent::new(id, EventKind::WorkflowCompleted))
+            .unwrap();
+        drop(sender);
+        recorder.await.unwrap();
+
+        let events = store.load_events(id).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].kind, EventKind::WorkflowCompleted);
+        assert!(store.load_all().await.unwrap().is_empty());
+
+        store.remove(id).await.unwrap();
+        assert!(store.load_events(id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Step execution policies
+//!
+//! This module describes how the engine runs a step: how often it retries after a
+//! failure, how long it waits in between, how long an attempt may take and which
+//! step takes over when all attempts fail.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{StepId, StepStatus};
+
+/// Delay between attempts of a step, growing exponentially
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Backoff {
+    /// Delay before the first retry, in seconds
+    pub initial_secs: f64,
+
+    /// Factor the delay grows by with every retry
+    pub multiplier: f64,
+
+    /// Upper bound of the delay, in seconds
+    pub max_secs: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_secs: 1.0,
+            multiplier: 2.0,
+            max_secs: 60.0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given retry, counting from 1
+    #[must_use]
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let secs = (self.initial_secs * self.multiplier.powi(exponent)).min(self.max_secs);
+        Duration::try_from_secs_f64(secs).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// How the engine runs a step
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StepPolicy {
+    /// Number of times a failed step is tried again
+    pub retries: u32,
+
+    /// Delay between attempts
+    pub backoff: Backoff,
+
+    /// Time an attempt may take before it is cancelled, in seconds
+    pub timeout_secs: Option<f64>,
+
+    /// Step that runs in place of this step once all attempts failed
+    ///
+    /// The fallback step receives the failure as the output of this step. If it
+    /// completes, its output becomes the output of this step and the workflow goes on.
+    pub on_failure: Option<StepId>,
+}
+
+impl StepPolicy {
+    /// Retry a failed step up to `retries` times
+    #[must_use]
+    pub const fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait between attempts according to the given backoff
+    #[must_use]
+    pub const fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Cancel attempts that take longer than `timeout`
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_secs = Some(timeout.as_secs_f64());
+        self
+    }
+
+    /// Run the given step when all attempts failed
+    #[must_use]
+    pub const fn with_on_failure(mut self, step: StepId) -> Self {
+        self.on_failure = Some(step);
+        self
+    }
+
+    /// Time an attempt may take
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    }
+}
+
+/// Record of one attempt to run a step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepAttempt {
+    /// Number of the attempt, counting from 1
+    pub attempt: u32,
+
+    /// Start time
+    pub started_at: DateTime<Utc>,
+
+    /// Completion time
+    pub completed_at: DateTime<Utc>,
+
+    /// Status the attempt ended with
+    pub status: StepStatus,
+
+    /// Reason the attempt failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            initial_secs: 0.5,
+            multiplier: 2.0,
+            max_secs: 3.0,
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(500));
+        assert_eq!(backoff.delay(2), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(2));
+        assert_eq!(backoff.delay(4), Duration::from_secs(3));
+        assert_eq!(backoff.delay(100), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_policy_from_yaml() {
+        let policy: StepPolicy =
+            serde_yaml::from_str("retries: 2\ntimeout_secs: 1.5\nbackoff: {initial_secs: 0.1}\n")
+                .unwrap();
+        assert_eq!(policy.retries, 2);
+        assert_eq!(policy.timeout(), Some(Duration::from_millis(1500)));
+        assert!((policy.backoff.multiplier - 2.0).abs() < f64::EPSILON);
+        assert_eq!(policy.backoff.delay(1), Duration::from_millis(100));
+        assert!(StepPolicy::default().timeout().is_none());
+    }
+}
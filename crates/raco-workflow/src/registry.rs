@@ -0,0 +1,679 @@
+//! Step type registry
+//!
+//! This module maps the step types used in workflow files to constructors of
+//! [`Step`] implementations.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::agent::AgentStep;
+use crate::engine::WorkflowDefinition;
+use crate::flow::{Body, LoopStep, MapStep};
+use crate::mcp::McpToolStep;
+use crate::model::ModelConfig;
+use crate::policy::StepPolicy;
+use crate::spec::{self, StepSpec, WorkflowSpec};
+use crate::steps::{ApprovalStep, CodeGenerationStep, HumanInputStep, Step};
+use crate::subworkflow::{InputMapping, OutputMapping, WorkflowStep};
+use crate::template::Template;
+use crate::{StepId, WorkflowId};
+
+/// Everything a step constructor needs to know about the step
+#[derive(Debug, Clone)]
+pub struct StepConfig {
+    /// ID of the workflow the step belongs to
+    pub workflow_id: WorkflowId,
+
+    /// ID of the step
+    pub id: StepId,
+
+    /// Name the workflow file uses for the step
+    pub key: String,
+
+    /// Display name
+    pub name: String,
+
+    /// Description
+    pub description: String,
+
+    /// Names of the steps that must finish first
+    pub depends_on: Vec<String>,
+
+    /// Type-specific parameters
+    pub params: Value,
+
+    /// Registry the step is instantiated with, for steps that contain steps
+    pub registry: StepRegistry,
+}
+
+impl StepConfig {
+    /// Get the ID of a step this step depends on
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the step is not listed in `depends_on`
+    pub fn dependency(&self, name: &str) -> Result<StepId> {
+        if !self.depends_on.iter().any(|dependency| dependency == name) {
+            bail!(
+                "Step {} refers to '{}', which must be listed in depends_on",
+                self.key,
+                name
+            );
+        }
+        Ok(spec::step_id(self.workflow_id, name))
+    }
+
+    /// Get the steps a control flow step runs, from the `steps` parameter
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameter is missing or the steps are invalid
+    pub fn body(&self) -> Result<Body> {
+        let spec = WorkflowSpec {
+            name: format!("Body of step {}", self.key),
+            description: self.description.clone(),
+            steps: self.param("steps")?,
+        };
+        Body::new(spec, self.registry.clone())
+    }
+
+    /// Get a required parameter
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameter is missing or has the wrong type
+    pub fn param<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        self.optional_param(name)?
+            .ok_or_else(|| anyhow!("Step {} is missing parameter '{}'", self.key, name))
+    }
+
+    /// Get an optional parameter
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameter has the wrong type
+    pub fn optional_param<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        match self.params.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => serde_json::from_value(value.clone())
+                .map(Some)
+                .with_context(|| format!("Invalid parameter '{}' of step {}", name, self.key)),
+        }
+    }
+}
+
+/// Source of the items of a map step
+#[derive(Debug, Deserialize)]
+struct ItemsParam {
+    /// Step whose output holds the items
+    step: String,
+
+    /// JSON pointer to the items
+    #[serde(default)]
+    path: String,
+}
+
+/// Value passed into a sub-workflow
+#[derive(Debug, Deserialize)]
+struct InputParam {
+    /// Step whose output holds the value; the workflow input if missing
+    #[serde(default)]
+    step: Option<String>,
+
+    /// JSON pointer to the value
+    #[serde(default)]
+    path: String,
+}
+
+/// Constructor of a step type
+pub type StepFactory = dyn Fn(StepConfig) -> Result<Box<dyn Step>> + Send + Sync;
+
+/// Registry of the step types available to workflow files
+#[derive(Clone, Default)]
+pub struct StepRegistry {
+    /// Constructors by step type
+    factories: HashMap<String, Arc<StepFactory>>,
+}
+
+impl fmt::Debug for StepRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StepRegistry")
+            .field("step_types", &self.step_types())
+            .finish()
+    }
+}
+
+impl StepRegistry {
+    /// Create an empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry with the step types that ship with RACO
+    #[must_use]
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("human_input", |config| {
+            let prompt = config.param("prompt")?;
+            Ok(Box::new(HumanInputStep::new(
+                config.id,
+                config.name,
+                config.description,
+                prompt,
+            )))
+        });
+        registry.register("approval", |config| {
+            let prompt = config
+                .optional_param("prompt")?
+                .unwrap_or_else(|| "Approve this output?".to_string());
+            let review = config
+                .optional_param::<String>("review")?
+                .map(|name| config.dependency(&name))
+                .transpose()?;
+            let max_rejections = config.optional_param("max_rejections")?;
+
+            let mut step = ApprovalStep::new(config.id, config.name, config.description, prompt);
+            if let Some(review) = review {
+                step = step.with_review(review);
+            }
+            if let Some(max_rejections) = max_rejections {
+                step = step.with_max_rejections(max_rejections);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("map", |config| {
+            let items: ItemsParam = config.param("items")?;
+            let step = MapStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                config.dependency(&items.step)?,
+                items.path,
+                config.body()?,
+            );
+            Ok(Box::new(match config.optional_param("max_parallel")? {
+                Some(max_parallel) => step.with_max_parallel(max_parallel),
+                None => step,
+            }))
+        });
+        registry.register("loop", |config| {
+            let step = LoopStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                config.body()?,
+                config.param("while")?,
+            )?;
+            Ok(Box::new(match config.optional_param("max_iterations")? {
+                Some(max_iterations) => step.with_max_iterations(max_iterations),
+                None => step,
+            }))
+        });
+        registry.register("workflow", |config| {
+            let mut step = WorkflowStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                config.param("workflow")?,
+            );
+            let inputs: BTreeMap<String, InputParam> =
+                config.optional_param("inputs")?.unwrap_or_default();
+            for (key, input) in inputs {
+                let step_id = input
+                    .step
+                    .map(|name| config.dependency(&name))
+                    .transpose()?;
+                step = step.with_input(
+                    key,
+                    InputMapping {
+                        step: step_id,
+                        path: input.path,
+                    },
+                );
+            }
+            let outputs: BTreeMap<String, OutputMapping> =
+                config.optional_param("outputs")?.unwrap_or_default();
+            for (key, output) in outputs {
+                step = step.with_output(key, output);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("mcp_tool", |config| {
+            let server: String = config.param("server")?;
+            let tool: String = config.param("tool")?;
+            let arguments = config
+                .optional_param("arguments")?
+                .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+            let arguments = Template::new(arguments, &|name| config.dependency(name))
+                .with_context(|| format!("Invalid parameter 'arguments' of step {}", config.key))?;
+            let outputs: BTreeMap<String, String> =
+                config.optional_param("outputs")?.unwrap_or_default();
+
+            let mut step = McpToolStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                &server,
+                &tool,
+                arguments,
+            );
+            for (key, path) in outputs {
+                step = step.with_output(key, path);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("agent", |config| {
+            let prompt = Template::new(config.param("prompt")?, &|name| config.dependency(name))
+                .with_context(|| format!("Invalid parameter 'prompt' of step {}", config.key))?;
+            let model: Option<ModelConfig> = config.optional_param("model")?;
+            let servers = config.optional_param("servers")?;
+            let max_iterations = config.optional_param("max_iterations")?;
+            let token_budget = config.optional_param("token_budget")?;
+            let max_tokens = config.optional_param("max_tokens")?;
+
+            let mut step = AgentStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                prompt,
+            );
+            if let Some(model) = model {
+                step = step.with_model(model.provider());
+            }
+            if let Some(servers) = servers {
+                step = step.with_servers(servers);
+            }
+            if let Some(max_iterations) = max_iterations {
+                step = step.with_max_iterations(max_iterations);
+            }
+            if let Some(token_budget) = token_budget {
+                step = step.with_token_budget(token_budget);
+            }
+            if let Some(max_tokens) = max_tokens {
+                step = step.with_max_tokens(max_tokens);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("code_generation", |config| {
+            let template =
+                Template::new(config.param("template")?, &|name| config.dependency(name))
+                    .with_context(|| {
+                        format!("Invalid parameter 'template' of step {}", config.key)
+                    })?;
+            let model: Option<ModelConfig> = config.optional_param("model")?;
+            let max_tokens = config.optional_param("max_tokens")?;
+
+            let mut step = CodeGenerationStep::new(
+                config.id,
+                config.name.clone(),
+                config.description.clone(),
+                template,
+            );
+            if let Some(model) = model {
+                step = step.with_model(model.provider());
+            }
+            if let Some(max_tokens) = max_tokens {
+                step = step.with_max_tokens(max_tokens);
+            }
+            Ok(Box::new(step))
+        });
+        registry
+    }
+
+    /// Register a step type, replacing any previous constructor of that type
+    pub fn register<F>(&mut self, step_type: &str, factory: F)
+    where
+        F: Fn(StepConfig) -> Result<Box<dyn Step>> + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(step_type.to_string(), Arc::new(factory));
+    }
+
+    /// Registered step types, sorted
+    #[must_use]
+    pub fn step_types(&self) -> Vec<&str> {
+        let mut types: Vec<_> = self.factories.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        types
+    }
+
+    /// Instantiate a workflow file under a new workflow ID
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a step has an unknown type or invalid parameters, two
+    /// steps share an ID, or a step depends on a step that does not exist
+    pub fn instantiate(&self, spec: &WorkflowSpec) -> Result<WorkflowDefinition> {
+        self.instantiate_with_id(spec, Uuid::new_v4())
+    }
+
+    /// Instantiate a workflow file under the given workflow ID
+    ///
+    /// Step IDs are derived with [`spec::step_id`], so they are the same every time a
+    /// file is instantiated under the same workflow ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a step has an unknown type or invalid parameters, two
+    /// steps share an ID, or a step depends on a step that does not exist
+    pub fn instantiate_with_id(
+        &self,
+        spec: &WorkflowSpec,
+        id: WorkflowId,
+    ) -> Result<WorkflowDefinition> {
+        let mut ids = BTreeMap::new();
+        for step in &spec.steps {
+            if ids
+                .insert(step.id.as_str(), spec::step_id(id, &step.id))
+                .is_some()
+            {
+                bail!(
+                    "Several steps of workflow {} use the ID '{}'",
+                    spec.name,
+                    step.id
+                );
+            }
+        }
+
+        let mut steps = Vec::new();
+        let mut dependencies = Vec::new();
+        let mut policies = HashMap::new();
+        let mut conditions = Vec::new();
+        let mut inputs = HashMap::new();
+        for step in &spec.steps {
+            let step_id = ids[step.id.as_str()];
+            for dependency in &step.depends_on {
+                let from = ids.get(dependency.as_str()).ok_or_else(|| {
+                    anyhow!(
+                        "Step '{}' depends on '{}', which is not a step of workflow {}",
+                        step.id,
+                        dependency,
+                        spec.name
+                    )
+                })?;
+                dependencies.push((*from, step_id));
+            }
+            for (dependency, condition) in &step.when {
+                if !step.depends_on.contains(dependency) {
+                    bail!(
+                        "Step '{}' has a condition on '{}', which must be listed in depends_on",
+                        step.id,
+                        dependency
+                    );
+                }
+                conditions.push((ids[dependency.as_str()], step_id, condition.clone()));
+            }
+            if step.has_policy() {
+                policies.insert(step_id, Self::create_policy(step, &ids, &spec.name)?);
+            }
+            if let Some(input) = &step.input {
+                let input = Template::new(input.clone(), &|name| {
+                    if step.depends_on.iter().any(|dependency| dependency == name) {
+                        Ok(ids[name])
+                    } else {
+                        bail!(
+                            "Step '{}' refers to '{}', which must be listed in depends_on",
+                            step.id,
+                            name
+                        )
+                    }
+                })
+                .with_context(|| format!("Invalid input of step '{}'", step.id))?;
+                inputs.insert(step_id, input);
+            }
+            steps.push(self.create_step(step, id, step_id)?);
+        }
+
+        Ok(WorkflowDefinition {
+            id,
+            name: spec.name.clone(),
+            description: spec.description.clone(),
+            steps,
+            dependencies,
+            policies,
+            conditions,
+            inputs,
+        })
+    }
+
+    fn create_policy(
+        step: &StepSpec,
+        ids: &BTreeMap<&str, StepId>,
+        workflow: &str,
+    ) -> Result<StepPolicy> {
+        let on_failure = step
+            .on_failure
+            .as_deref()
+            .map(|handler| {
+                ids.get(handler).copied().ok_or_else(|| {
+                    anyhow!(
+                        "Step '{}' falls back to '{}', which is not a step of workflow {}",
+                        step.id,
+                        handler,
+                        workflow
+                    )
+                })
+            })
+            .transpose()?;
+
+        Ok(StepPolicy {
+            retries: step.retries,
+            backoff: step.backoff.unwrap_or_default(),
+            timeout_secs: step.timeout_secs,
+            on_failure,
+        })
+    }
+
+    fn create_step(
+        &self,
+        step: &StepSpec,
+        workflow_id: WorkflowId,
+        id: StepId,
+    ) -> Result<Box<dyn Step>> {
+        let factory = self.factories.get(&step.step_type).ok_or_else(|| {
+            anyhow!(
+                "Step '{}' has unknown type '{}', expected one of: {}",
+                step.id,
+                step.step_type,
+                self.step_types().join(", ")
+            )
+        })?;
+
+        factory(StepConfig {
+            workflow_id,
+            id,
+            key: step.id.clone(),
+            name: step.name.clone().unwrap_or_else(|| step.id.clone()),
+            description: step.description.clone().unwrap_or_default(),
+            depends_on: step.depends_on.clone(),
+            params: step.params.clone(),
+            registry: self.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::SpecFormat;
+
+    const WORKFLOW: &str = r#"
+name: fix-build
+steps:
+  - id: describe
+    type: human_input
+    params:
+      prompt: What is broken?
+  - id: fix
+    type: code_generation
+    depends_on: [describe]
+    params:
+      template: Fix the following problem
+"#;
+
+    fn spec(text: &str) -> WorkflowSpec {
+        WorkflowSpec::parse(text, SpecFormat::Yaml).unwrap()
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let registry = StepRegistry::with_builtins();
+        let id = Uuid::new_v4();
+        let definition = registry.instantiate_with_id(&spec(WORKFLOW), id).unwrap();
+
+        assert_eq!(definition.id, id);
+        assert_eq!(definition.name, "fix-build");
+        assert_eq!(definition.steps.len(), 2);
+        assert_eq!(definition.steps[0].id(), spec::step_id(id, "describe"));
+        assert_eq!(definition.steps[1].name(), "fix");
+        assert_eq!(
+            definition.steps[0].human_input_prompt().as_deref(),
+            Some("What is broken?")
+        );
+        assert_eq!(
+            definition.dependencies,
+            [(spec::step_id(id, "describe"), spec::step_id(id, "fix"))]
+        );
+        assert!(definition.validate().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_type_and_reference() {
+        let registry = StepRegistry::with_builtins();
+
+        let error = registry
+            .instantiate(&spec("name: w\nsteps:\n  - {id: a, type: teleport}\n"))
+            .unwrap_err();
+        assert!(error.to_string().contains("unknown type 'teleport'"));
+        assert!(error.to_string().contains(
+            "agent, approval, code_generation, human_input, loop, map, mcp_tool, workflow"
+        ));
+
+        let error = registry
+            .instantiate(&spec(
+                "name: w\nsteps:\n  - {id: a, type: human_input, depends_on: [b], params: {prompt: x}}\n",
+            ))
+            .unwrap_err();
+        assert!(error.to_string().contains("depends on 'b'"));
+
+        let error = registry
+            .instantiate(&spec(
+                "name: w\nsteps:\n  - {id: a, type: human_input, params: {prompt: x}}\n  - {id: a, type: human_input, params: {prompt: y}}\n",
+            ))
+            .unwrap_err();
+        assert!(error.to_string().contains("use the ID 'a'"));
+    }
+
+    #[test]
+    fn test_missing_parameter() {
+        let registry = StepRegistry::with_builtins();
+        let error = registry
+            .instantiate(&spec("name: w\nsteps:\n  - {id: ask, type: human_input}\n"))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Step ask is missing parameter 'prompt'");
+    }
+
+    #[test]
+    fn test_approval_must_review_a_dependency() {
+        let registry = StepRegistry::with_builtins();
+        let workflow =
+            "name: w\nsteps:\n  - {id: fix, type: code_generation, params: {template: x}}\n";
+
+        let text = format!("{workflow}  - {{id: gate, type: approval, depends_on: [fix], params: {{review: fix}}}}\n");
+        assert!(registry.instantiate(&spec(&text)).is_ok());
+
+        let text = format!("{workflow}  - {{id: gate, type: approval, params: {{review: fix}}}}\n");
+        let error = registry.instantiate(&spec(&text)).unwrap_err();
+        assert!(error.to_string().contains("must be listed in depends_on"));
+    }
+
+    #[test]
+    fn test_step_policies() {
+        let registry = StepRegistry::with_builtins();
+        let id = Uuid::new_v4();
+        let text = "name: w\nsteps:\n  - {id: fix, type: code_generation, params: {template: x}, retries: 2, timeout_secs: 30, on_failure: ask}\n  - {id: ask, type: human_input, params: {prompt: x}}\n";
+        let definition = registry.instantiate_with_id(&spec(text), id).unwrap();
+
+        assert_eq!(definition.policies.len(), 1);
+        let policy = &definition.policies[&spec::step_id(id, "fix")];
+        assert_eq!(policy.retries, 2);
+        assert_eq!(policy.timeout(), Some(std::time::Duration::from_secs(30)));
+        assert_eq!(policy.on_failure, Some(spec::step_id(id, "ask")));
+        assert!(definition.validate().is_empty());
+
+        let text = "name: w\nsteps:\n  - {id: fix, type: code_generation, params: {template: x}, on_failure: ask}\n";
+        let error = registry.instantiate(&spec(text)).unwrap_err();
+        assert!(error.to_string().contains("falls back to 'ask'"));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let registry = StepRegistry::with_builtins();
+        let id = Uuid::new_v4();
+        let text = format!(
+            "{WORKFLOW}    when:\n      describe: {{path: /human_input, not_equals: ''}}\n"
+        );
+        let definition = registry.instantiate_with_id(&spec(&text), id).unwrap();
+        assert_eq!(definition.conditions.len(), 1);
+        let (from, to, condition) = &definition.conditions[0];
+        assert_eq!(*from, spec::step_id(id, "describe"));
+        assert_eq!(*to, spec::step_id(id, "fix"));
+        assert_eq!(condition.to_string(), "/human_input does not equal \"\"");
+
+        let text = "name: w\nsteps:\n  - {id: a, type: human_input, params: {prompt: x}}\n  - {id: b, type: human_input, params: {prompt: x}, when: {a: {truthy: true}}}\n";
+        let error = registry.instantiate(&spec(text)).unwrap_err();
+        assert!(error.to_string().contains("condition on 'a'"));
+    }
+
+    #[test]
+    fn test_step_inputs() {
+        let registry = StepRegistry::with_builtins();
+        let id = Uuid::new_v4();
+        let text = format!(
+            "{WORKFLOW}    input:\n      problem: \"{{{{ steps.describe.output.human_input | trim }}}}\"\n"
+        );
+        let definition = registry.instantiate_with_id(&spec(&text), id).unwrap();
+        assert_eq!(definition.inputs.len(), 1);
+        assert!(definition.inputs.contains_key(&spec::step_id(id, "fix")));
+
+        let text = "name: w\nsteps:\n  - {id: a, type: human_input, params: {prompt: x}}\n  - {id: b, type: human_input, params: {prompt: x}, input: '{{ steps.a.output }}'}\n";
+        let error = registry.instantiate(&spec(text)).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Invalid input of step 'b': Invalid expression {{ steps.a.output }}: \
+             Step 'b' refers to 'a', which must be listed in depends_on"
+        );
+    }
+
+    #[test]
+    fn test_custom_step_type() {
+        let mut registry = StepRegistry::new();
+        registry.register("prompt", |config| {
+            let prompt = config
+                .optional_param::<String>("text")?
+                .unwrap_or_else(|| "Continue?".to_string());
+            Ok(Box::new(HumanInputStep::new(
+                config.id,
+                config.name,
+                config.description,
+                prompt,
+            )))
+        });
+
+        let definition = registry
+            .instantiate(&spec("name: w\nsteps:\n  - {id: ask, type: prompt}\n"))
+            .unwrap();
+        assert_eq!(
+            definition.steps[0].human_input_prompt().as_deref(),
+            Some("Continue?")
+        );
+    }
+}
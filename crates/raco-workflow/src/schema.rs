@@ -0,0 +1,75 @@
+//! JSON Schema checks
+//!
+//! This module validates the data exchanged with steps against the schemas the
+//! steps declare.
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+
+/// Describe every way a value violates a schema
+///
+/// Each entry names the location of the offending value as a JSON pointer, followed
+/// by the reason, e.g. `/errors/0/line: "3" is not of type "integer"`.
+///
+/// # Errors
+///
+/// Returns an error if the schema itself is invalid
+pub fn violations(schema: &Value, value: &Value) -> Result<Vec<String>> {
+    let validator =
+        jsonschema::validator_for(schema).map_err(|e| anyhow!("Invalid schema: {e}"))?;
+
+    Ok(validator
+        .iter_errors(value)
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            let path = if path.is_empty() { "/" } else { &path };
+            format!("{path}: {error}")
+        })
+        .collect())
+}
+
+/// Check a value against a schema
+///
+/// # Errors
+///
+/// Returns an error listing all violations, introduced by `subject`
+pub fn check(schema: &Value, value: &Value, subject: &str) -> Result<()> {
+    let violations = violations(schema, value)?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+    bail!("Invalid {}:\n  - {}", subject, violations.join("\n  - "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_violations() {
+        let schema = json!({
+            "type": "object",
+            "required": ["file"],
+            "properties": {
+                "file": { "type": "string" },
+                "line": { "type": "integer" }
+            }
+        });
+
+        assert!(check(&schema, &json!({"file": "main.rs", "line": 3}), "output").is_ok());
+
+        let violations = violations(&schema, &json!({"line": "3"})).unwrap();
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().any(|v| v.starts_with("/: ")));
+        assert!(violations.iter().any(|v| v.starts_with("/line: ")));
+
+        let error = check(&schema, &json!([]), "output").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid output:\n  - /: "));
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!(violations(&json!({"type": 12}), &json!(null)).is_err());
+    }
+}
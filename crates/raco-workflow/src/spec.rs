@@ -0,0 +1,268 @@
+//! Declarative workflow files
+//!
+//! This module reads workflows described in YAML or TOML. Steps are referred to by
+//! name and instantiated through a [`StepRegistry`](crate::registry::StepRegistry).
+//!
+//! ```yaml
+//! name: fix-build
+//! description: Reproduce and fix a build failure
+//! steps:
+//!   - id: describe
+//!     type: human_input
+//!     params:
+//!       prompt: What is broken?
+//!   - id: fix
+//!     type: code_generation
+//!     depends_on: [describe]
+//!     when:
+//!       describe: { path: /human_input, not_equals: "" }
+//!     input:
+//!       problem: "{{ steps.describe.output.human_input | trim }}"
+//!     params:
+//!       template: Fix the following problem
+//!     retries: 2
+//!     timeout_secs: 300
+//!     on_failure: escalate
+//!   - id: escalate
+//!     type: human_input
+//!     params:
+//!       prompt: The fix failed, what should we do?
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::condition::Condition;
+use crate::policy::Backoff;
+use crate::{StepId, WorkflowId};
+
+/// A workflow as described in a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowSpec {
+    /// Name of the workflow
+    pub name: String,
+
+    /// Description of the workflow
+    #[serde(default)]
+    pub description: String,
+
+    /// Steps of the workflow
+    #[serde(default)]
+    pub steps: Vec<StepSpec>,
+}
+
+/// A step as described in a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepSpec {
+    /// Name other steps use to refer to this step
+    pub id: String,
+
+    /// Step type, as registered in the step registry
+    #[serde(rename = "type")]
+    pub step_type: String,
+
+    /// Display name (defaults to the ID)
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Description of the step
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// IDs of the steps that must finish first
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Conditions on the outputs of dependencies, by dependency ID
+    #[serde(default)]
+    pub when: BTreeMap<String, Condition>,
+
+    /// Input of the step, a template over the workflow input and the outputs of its
+    /// dependencies; see [`crate::template`]. Steps without one receive the
+    /// workflow input.
+    #[serde(default)]
+    pub input: Option<Value>,
+
+    /// Type-specific parameters
+    #[serde(default)]
+    pub params: Value,
+
+    /// Number of times the step is tried again after a failure
+    #[serde(default)]
+    pub retries: u32,
+
+    /// Delay between attempts
+    #[serde(default)]
+    pub backoff: Option<Backoff>,
+
+    /// Time an attempt may take before it is cancelled, in seconds
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
+
+    /// ID of the step that runs in place of this step once all attempts failed
+    #[serde(default)]
+    pub on_failure: Option<String>,
+}
+
+impl StepSpec {
+    /// Check if the step sets any execution policy
+    #[must_use]
+    pub const fn has_policy(&self) -> bool {
+        self.retries > 0
+            || self.backoff.is_some()
+            || self.timeout_secs.is_some()
+            || self.on_failure.is_some()
+    }
+}
+
+/// Format of a workflow file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecFormat {
+    /// YAML
+    Yaml,
+
+    /// TOML
+    Toml,
+}
+
+impl SpecFormat {
+    /// Format of a file, judged by its extension
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+}
+
+impl WorkflowSpec {
+    /// Parse a workflow in the given format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text is not a valid workflow description
+    pub fn parse(text: &str, format: SpecFormat) -> Result<Self> {
+        match format {
+            SpecFormat::Yaml => serde_yaml::from_str(text).context("Invalid YAML workflow"),
+            SpecFormat::Toml => toml::from_str(text).context("Invalid TOML workflow"),
+        }
+    }
+
+    /// Read a workflow file, choosing the format by extension
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, has an unknown extension or is
+    /// not a valid workflow description
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = SpecFormat::from_path(path).ok_or_else(|| {
+            anyhow!(
+                "Unknown workflow format of {}, use .yaml, .yml or .toml",
+                path.display()
+            )
+        })?;
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text, format).with_context(|| format!("In {}", path.display()))
+    }
+
+    /// Find a step by its ID
+    #[must_use]
+    pub fn step(&self, id: &str) -> Option<&StepSpec> {
+        self.steps.iter().find(|step| step.id == id)
+    }
+}
+
+/// ID of a named step in an instantiated workflow
+///
+/// The ID is derived from the workflow ID and the step name, so instantiating the
+/// same file under the same workflow ID always yields the same step IDs.
+#[must_use]
+pub fn step_id(workflow_id: WorkflowId, name: &str) -> StepId {
+    Uuid::new_v5(&workflow_id, name.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+name: fix-build
+description: Reproduce and fix a build failure
+steps:
+  - id: describe
+    type: human_input
+    params:
+      prompt: What is broken?
+  - id: fix
+    type: code_generation
+    name: Fix the build
+    depends_on: [describe]
+    params:
+      template: Fix the following problem
+"#;
+
+    const TOML: &str = r#"
+name = "fix-build"
+description = "Reproduce and fix a build failure"
+
+[[steps]]
+id = "describe"
+type = "human_input"
+params = { prompt = "What is broken?" }
+
+[[steps]]
+id = "fix"
+type = "code_generation"
+name = "Fix the build"
+depends_on = ["describe"]
+
+[steps.params]
+template = "Fix the following problem"
+"#;
+
+    #[test]
+    fn test_yaml_and_toml_are_equivalent() {
+        let yaml = WorkflowSpec::parse(YAML, SpecFormat::Yaml).unwrap();
+        let toml = WorkflowSpec::parse(TOML, SpecFormat::Toml).unwrap();
+        assert_eq!(yaml, toml);
+
+        let fix = yaml.step("fix").unwrap();
+        assert_eq!(fix.step_type, "code_generation");
+        assert_eq!(fix.depends_on, ["describe"]);
+        assert_eq!(fix.params["template"], "Fix the following problem");
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workflow.yml");
+        std::fs::write(&path, YAML).unwrap();
+        assert_eq!(WorkflowSpec::from_file(&path).unwrap().steps.len(), 2);
+
+        let path = dir.path().join("workflow.json");
+        std::fs::write(&path, "{}").unwrap();
+        assert!(WorkflowSpec::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_invalid_file_is_rejected() {
+        assert!(WorkflowSpec::parse("steps: [{type: x}]", SpecFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_step_ids_are_stable() {
+        let workflow = Uuid::new_v4();
+        assert_eq!(step_id(workflow, "fix"), step_id(workflow, "fix"));
+        assert_ne!(step_id(workflow, "fix"), step_id(workflow, "test"));
+        assert_ne!(step_id(workflow, "fix"), step_id(Uuid::new_v4(), "fix"));
+    }
+}
@@ -3,13 +3,23 @@
 //! This module provides the workflow step trait and implementations.
 
 use std::fmt::Debug;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use serde_json;
 #[cfg(test)]
 use uuid::Uuid;
 
+use crate::cancel::CancelToken;
+use crate::events::Progress;
+use crate::mcp::ToolProvider;
+#[cfg(test)]
+use crate::model::{ChatRequest, ChatResponse, Message, TokenUsage};
+use crate::model::{CompletionRequest, ModelProvider, DEFAULT_MAX_TOKENS};
+use crate::schema;
+use crate::subworkflow::SubWorkflows;
+use crate::template::Template;
 use crate::StepId;
 use crate::StepStatus;
 
@@ -24,6 +34,43 @@ pub struct StepContext {
 
     /// Global workflow context
     pub global: std::collections::HashMap<String, serde_json::Value>,
+
+    /// Handle for reporting progress while the step runs
+    pub progress: Progress,
+
+    /// Handle for running sub-workflows; set when the step runs in a workflow engine
+    pub workflows: Option<SubWorkflows>,
+
+    /// Tools of the connected MCP servers; set when the engine has any
+    pub tools: Option<Arc<dyn ToolProvider>>,
+
+    /// Default language model; set when the engine has one
+    pub model: Option<Arc<dyn ModelProvider>>,
+
+    /// Signalled when the step should stop; see [`crate::cancel`]
+    pub cancel: CancelToken,
+}
+
+/// Key of [`StepContext::global`] holding review feedback
+///
+/// The value is an object mapping the ID of each rejected step to the feedback it
+/// received; see [`feedback_for`].
+pub const FEEDBACK_KEY: &str = "feedback";
+
+/// Feedback a reviewer left on an earlier output of a step, if any
+#[must_use]
+pub fn feedback_for(context: &StepContext, step: StepId) -> Option<&serde_json::Value> {
+    context.global.get(FEEDBACK_KEY)?.get(step.to_string())
+}
+
+/// Request to run a step again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rework {
+    /// Step to run again, together with every step between it and the requester
+    pub step: StepId,
+
+    /// Feedback for the step, stored under [`FEEDBACK_KEY`]
+    pub feedback: serde_json::Value,
 }
 
 /// Result of step execution
@@ -52,9 +99,13 @@ pub trait Step: Debug + Send + Sync {
     fn description(&self) -> &str;
 
     /// Get the expected input schema for this step
+    ///
+    /// The engine checks the input against it before every attempt.
     fn input_schema(&self) -> Option<serde_json::Value>;
 
     /// Get the expected output schema for this step
+    ///
+    /// The engine checks the output of every completed attempt against it.
     fn output_schema(&self) -> Option<serde_json::Value>;
 
     /// Execute the step
@@ -62,12 +113,27 @@ pub trait Step: Debug + Send + Sync {
 
     /// Validate step input
     ///
+    /// The engine calls this after checking the input against
+    /// [`Step::input_schema`], for requirements a schema cannot express.
+    ///
     /// # Errors
     ///
     /// Returns an error if the input does not meet the step's requirements
-    /// or if the input schema validation fails
     fn validate_input(&self, input: &serde_json::Value) -> Result<()>;
 
+    /// Build the input the step works on from its context
+    ///
+    /// The engine replaces the input in the context with the result and checks it
+    /// against [`Step::input_schema`] and with [`Step::validate_input`] before
+    /// every attempt. The default keeps the input as it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input cannot be built from the context
+    fn resolve_input(&self, context: &StepContext) -> Result<serde_json::Value> {
+        Ok(context.input.clone())
+    }
+
     /// Check if this step requires human input
     fn requires_human_input(&self) -> bool {
         false
@@ -77,6 +143,35 @@ pub trait Step: Debug + Send + Sync {
     fn human_input_prompt(&self) -> Option<String> {
         None
     }
+
+    /// Get the schema a human response must match
+    fn response_schema(&self) -> Option<serde_json::Value> {
+        self.output_schema()
+    }
+
+    /// Turn a human response into the output of a step waiting for input
+    ///
+    /// `request` is the output the step reported while suspending. The default
+    /// accepts the response as the output if it matches the response schema.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response does not match the response schema
+    fn accept_human_input(
+        &self,
+        _request: &serde_json::Value,
+        response: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        if let Some(response_schema) = self.response_schema() {
+            schema::check(&response_schema, &response, "response")?;
+        }
+        Ok(response)
+    }
+
+    /// Ask the engine to run an earlier step again after this step completed
+    fn rework(&self, _output: &serde_json::Value) -> Option<Rework> {
+        None
+    }
 }
 
 /// A simple human input step
@@ -128,6 +223,7 @@ impl Step for HumanInputStep {
     fn output_schema(&self) -> Option<serde_json::Value> {
         Some(serde_json::json!({
             "type": "object",
+            "required": ["human_input"],
             "properties": {
                 "human_input": { "type": "string" }
             }
@@ -135,11 +231,10 @@ impl Step for HumanInputStep {
     }
 
     async fn execute(&self, _context: StepContext) -> Result<StepResult> {
-        // In a real implementation, this would wait for human input
-        // For now, we'll just return a dummy result
+        // The engine suspends the step until someone answers the prompt
         Ok(StepResult {
-            output: serde_json::json!({"human_input": "dummy response"}),
-            status: StepStatus::Completed,
+            output: serde_json::json!({"prompt": self.prompt}),
+            status: StepStatus::WaitingForInput,
             error: None,
         })
     }
@@ -156,9 +251,259 @@ impl Step for HumanInputStep {
     fn human_input_prompt(&self) -> Option<String> {
         Some(self.prompt.clone())
     }
+
+    fn accept_human_input(
+        &self,
+        _request: &serde_json::Value,
+        response: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        // A plain answer is shorthand for `{"human_input": answer}`
+        let response = match response {
+            serde_json::Value::String(answer) => serde_json::json!({"human_input": answer}),
+            response => response,
+        };
+        schema::check(
+            &self.output_schema().unwrap_or_default(),
+            &response,
+            "response",
+        )?;
+        Ok(response)
+    }
+}
+
+/// Default number of times an approval gate lets a reviewer reject an output
+pub const DEFAULT_MAX_REJECTIONS: u64 = 3;
+
+/// A gate that lets a human approve, edit or reject the output of a previous step
+///
+/// Approving passes the output on unchanged and editing replaces it; downstream
+/// steps find the result under `output`. Rejecting runs the reviewed step again
+/// with the reviewer's feedback, see [`feedback_for`].
+#[derive(Debug)]
+pub struct ApprovalStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Question shown with the output
+    prompt: String,
+
+    /// Step whose output is reviewed (defaults to the only predecessor)
+    review: Option<StepId>,
+
+    /// Number of rejections before the reviewer must approve or edit
+    max_rejections: u64,
+}
+
+impl ApprovalStep {
+    /// Create a new approval gate
+    pub fn new(id: StepId, name: String, description: String, prompt: String) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            prompt,
+            review: None,
+            max_rejections: DEFAULT_MAX_REJECTIONS,
+        }
+    }
+
+    /// Review the output of the given predecessor
+    #[must_use]
+    pub const fn with_review(mut self, step: StepId) -> Self {
+        self.review = Some(step);
+        self
+    }
+
+    /// Limit how often the output can be rejected
+    #[must_use]
+    pub const fn with_max_rejections(mut self, max_rejections: u64) -> Self {
+        self.max_rejections = max_rejections;
+        self
+    }
+
+    /// Step reviewed in the given context
+    fn reviewed_step(&self, context: &StepContext) -> Result<StepId> {
+        if let Some(step) = self.review {
+            return Ok(step);
+        }
+        let mut predecessors = context.previous_outputs.keys();
+        match (predecessors.next(), predecessors.next()) {
+            (Some(step), None) => Ok(*step),
+            (None, _) => bail!("Step {} has no previous step to review", self.name),
+            (Some(_), Some(_)) => bail!(
+                "Step {} follows several steps; choose the one to review",
+                self.name
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl Step for ApprovalStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn output_schema(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "type": "object",
+            "required": ["decision"],
+            "properties": {
+                "decision": { "enum": ["approved", "edited", "rejected"] },
+                "output": {},
+                "feedback": { "type": "string" }
+            }
+        }))
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let step = self.reviewed_step(&context)?;
+        let proposal = context.previous_outputs.get(&step).ok_or_else(|| {
+            anyhow!(
+                "Step {} has no output from step {} to review",
+                self.name,
+                step
+            )
+        })?;
+        let rejections = feedback_for(&context, step)
+            .and_then(|feedback| feedback["rejections"].as_u64())
+            .unwrap_or(0);
+
+        Ok(StepResult {
+            output: serde_json::json!({
+                "prompt": self.prompt,
+                "step": step,
+                "proposal": proposal,
+                "rejections": rejections,
+            }),
+            status: StepStatus::WaitingForInput,
+            error: None,
+        })
+    }
+
+    fn validate_input(&self, _input: &serde_json::Value) -> Result<()> {
+        Ok(())
+    }
+
+    fn requires_human_input(&self) -> bool {
+        true
+    }
+
+    fn human_input_prompt(&self) -> Option<String> {
+        Some(self.prompt.clone())
+    }
+
+    fn response_schema(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "type": "object",
+            "required": ["decision"],
+            "properties": {
+                "decision": { "enum": ["approve", "edit", "reject"] },
+                "output": {},
+                "feedback": { "type": "string" }
+            }
+        }))
+    }
+
+    fn accept_human_input(
+        &self,
+        request: &serde_json::Value,
+        response: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        // A plain answer is shorthand for `{"decision": answer}`
+        let response = match response {
+            serde_json::Value::String(decision) => serde_json::json!({"decision": decision}),
+            response => response,
+        };
+        schema::check(
+            &self.response_schema().unwrap_or_default(),
+            &response,
+            "response",
+        )?;
+
+        let mut output = match response["decision"].as_str() {
+            Some("approve") => serde_json::json!({
+                "decision": "approved",
+                "output": request["proposal"],
+            }),
+            Some("edit") => {
+                let output = response
+                    .get("output")
+                    .ok_or_else(|| anyhow!("An edit must include the edited output"))?;
+                serde_json::json!({
+                    "decision": "edited",
+                    "output": output,
+                })
+            }
+            _ => {
+                let rejections = request["rejections"].as_u64().unwrap_or(0) + 1;
+                if rejections > self.max_rejections {
+                    bail!(
+                        "The output was already rejected {} times; approve or edit it instead",
+                        self.max_rejections
+                    );
+                }
+                serde_json::json!({
+                    "decision": "rejected",
+                    "step": request["step"],
+                    "proposal": request["proposal"],
+                    "rejections": rejections,
+                })
+            }
+        };
+        // Feedback is optional, so it is left out rather than set to null
+        if let Some(feedback) = response.get("feedback") {
+            output["feedback"] = feedback.clone();
+        }
+        Ok(output)
+    }
+
+    fn rework(&self, output: &serde_json::Value) -> Option<Rework> {
+        if output["decision"] != "rejected" {
+            return None;
+        }
+        let step = serde_json::from_value(output["step"].clone()).ok()?;
+        Some(Rework {
+            step,
+            feedback: serde_json::json!({
+                "reviewer": self.name,
+                "feedback": output["feedback"],
+                "rejected_output": output["proposal"],
+                "rejections": output["rejections"],
+            }),
+        })
+    }
 }
 
-/// A simple code generation step
+/// Instructions a code generation step gives the model
+pub const CODE_GENERATION_SYSTEM_PROMPT: &str = "You are an expert software engineer. \
+Answer with the requested code in a single fenced code block.";
+
+/// A step that asks a language model for code
+///
+/// The template is rendered with the context of the step (see [`crate::template`])
+/// and, if a reviewer rejected an earlier answer, followed by their feedback. The
+/// step uses its own model if it has one and the model of the engine otherwise.
+/// It outputs the first fenced code block of the answer, or the whole answer if it
+/// has none, together with the model name and token usage.
 #[derive(Debug)]
 pub struct CodeGenerationStep {
     /// Step ID
@@ -170,21 +515,72 @@ pub struct CodeGenerationStep {
     /// Step description
     description: String,
 
-    /// Template for code generation
-    #[allow(dead_code)]
-    template: String,
+    /// Template for the prompt
+    template: Template,
+
+    /// Model to use instead of the model of the engine
+    model: Option<Arc<dyn ModelProvider>>,
+
+    /// Maximum number of tokens to generate
+    max_tokens: u32,
 }
 
 impl CodeGenerationStep {
     /// Create a new code generation step
-    pub fn new(id: StepId, name: String, description: String, template: String) -> Self {
+    pub const fn new(id: StepId, name: String, description: String, template: Template) -> Self {
         Self {
             id,
             name,
             description,
             template,
+            model: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
         }
     }
+
+    /// Use the given model instead of the model of the engine
+    #[must_use]
+    pub fn with_model(mut self, model: Arc<dyn ModelProvider>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Limit the number of tokens the model may generate
+    #[must_use]
+    pub const fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    fn prompt(&self, context: &StepContext) -> Result<String> {
+        let mut prompt = match self.template.render(context)? {
+            serde_json::Value::String(prompt) => prompt,
+            prompt => prompt.to_string(),
+        };
+        if let Some(feedback) = feedback_for(context, self.id) {
+            if let Some(rejected) = feedback["rejected_output"]["generated_code"].as_str() {
+                prompt.push_str("\n\nYour previous answer was:\n```\n");
+                prompt.push_str(rejected);
+                prompt.push_str("\n```");
+            }
+            prompt.push_str("\n\nA reviewer rejected it with this feedback:\n");
+            match &feedback["feedback"] {
+                serde_json::Value::String(text) => prompt.push_str(text),
+                other => prompt.push_str(&other.to_string()),
+            }
+        }
+        Ok(prompt)
+    }
+}
+
+/// Take the first fenced code block out of a model answer
+fn extract_code(text: &str) -> &str {
+    text.split_once("```")
+        .and_then(|(_, rest)| rest.split_once('\n'))
+        .map_or(text, |(_, block)| {
+            block.split_once("```").map_or(block, |(code, _)| code)
+        })
+        .trim_end()
 }
 
 #[async_trait]
@@ -213,17 +609,44 @@ impl Step for CodeGenerationStep {
     fn output_schema(&self) -> Option<serde_json::Value> {
         Some(serde_json::json!({
             "type": "object",
+            "required": ["generated_code", "usage"],
             "properties": {
-                "generated_code": { "type": "string" }
+                "generated_code": { "type": "string" },
+                "model": { "type": "string" },
+                "usage": {
+                    "type": "object",
+                    "properties": {
+                        "input_tokens": { "type": "integer" },
+                        "output_tokens": { "type": "integer" }
+                    }
+                }
             }
         }))
     }
 
-    async fn execute(&self, _context: StepContext) -> Result<StepResult> {
-        // In a real implementation, this would generate code based on the template
-        // For now, we'll just return a dummy result
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let model = self
+            .model
+            .as_ref()
+            .or(context.model.as_ref())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Step {} needs a language model; set its 'model' parameter or give the engine one",
+                    self.name
+                )
+            })?;
+
+        let request = CompletionRequest::new(self.prompt(&context)?)
+            .with_system(CODE_GENERATION_SYSTEM_PROMPT)
+            .with_max_tokens(self.max_tokens);
+        let completion = model.complete(&request).await?;
+
         Ok(StepResult {
-            output: serde_json::json!({"generated_code": "// Generated code\nfn main() {\n    println!(\"Hello, world!\");\n}"}),
+            output: serde_json::json!({
+                "generated_code": extract_code(&completion.text),
+                "model": model.model(),
+                "usage": completion.usage,
+            }),
             status: StepStatus::Completed,
             error: None,
         })
@@ -318,13 +741,204 @@ mod tests {
             input: serde_json::json!({}),
             previous_outputs: std::collections::HashMap::new(),
             global: std::collections::HashMap::new(),
+            progress: Progress::default(),
+            workflows: None,
+            tools: None,
+            model: None,
+            cancel: CancelToken::default(),
         };
 
         let result = step.execute(context).await;
         assert!(result.is_ok());
 
         let result = result.unwrap();
-        assert_eq!(result.status, StepStatus::Completed);
+        assert_eq!(result.status, StepStatus::WaitingForInput);
+        assert_eq!(result.output["prompt"], "Please provide input");
         assert!(result.error.is_none());
     }
+
+    #[test]
+    fn test_accept_human_input() {
+        let step = HumanInputStep::new(
+            Uuid::new_v4(),
+            "Test".to_string(),
+            "Test description".to_string(),
+            "Please provide input".to_string(),
+        );
+
+        let request = serde_json::json!({});
+        assert_eq!(
+            step.accept_human_input(&request, serde_json::json!("yes"))
+                .unwrap(),
+            serde_json::json!({"human_input": "yes"})
+        );
+        let error = step
+            .accept_human_input(&request, serde_json::json!({"human_input": 42}))
+            .unwrap_err();
+        assert!(error.to_string().contains("/human_input"));
+    }
+
+    #[tokio::test]
+    async fn test_approval_step() {
+        let producer = Uuid::new_v4();
+        let step = ApprovalStep::new(
+            Uuid::new_v4(),
+            "review".to_string(),
+            String::new(),
+            "Apply this diff?".to_string(),
+        )
+        .with_max_rejections(1);
+
+        let context = StepContext {
+            input: serde_json::json!({}),
+            previous_outputs: std::collections::HashMap::from([(
+                producer,
+                serde_json::json!({"diff": "+fn main() {}"}),
+            )]),
+            global: std::collections::HashMap::new(),
+            progress: Progress::default(),
+            workflows: None,
+            tools: None,
+            model: None,
+            cancel: CancelToken::default(),
+        };
+        let result = step.execute(context).await.unwrap();
+        assert_eq!(result.status, StepStatus::WaitingForInput);
+        assert_eq!(result.output["proposal"]["diff"], "+fn main() {}");
+        let request = result.output;
+
+        let approved = step
+            .accept_human_input(&request, serde_json::json!("approve"))
+            .unwrap();
+        assert_eq!(approved["output"]["diff"], "+fn main() {}");
+        assert!(approved.get("feedback").is_none());
+        let output_schema = step.output_schema().unwrap();
+        schema::check(&output_schema, &approved, "output").unwrap();
+        assert!(step.rework(&approved).is_none());
+
+        let edited = step
+            .accept_human_input(
+                &request,
+                serde_json::json!({"decision": "edit", "output": {"diff": ""}}),
+            )
+            .unwrap();
+        assert_eq!(edited["output"]["diff"], "");
+        schema::check(&output_schema, &edited, "output").unwrap();
+        assert!(step
+            .accept_human_input(&request, serde_json::json!("edit"))
+            .is_err());
+        assert!(step
+            .accept_human_input(&request, serde_json::json!("maybe"))
+            .is_err());
+
+        let rejected = step
+            .accept_human_input(
+                &request,
+                serde_json::json!({"decision": "reject", "feedback": "Missing tests"}),
+            )
+            .unwrap();
+        schema::check(&output_schema, &rejected, "output").unwrap();
+        let rework = step.rework(&rejected).unwrap();
+        assert_eq!(rework.step, producer);
+        assert_eq!(rework.feedback["feedback"], "Missing tests");
+        assert_eq!(rework.feedback["rejections"], 1);
+
+        let mut request = request;
+        request["rejections"] = serde_json::json!(1);
+        assert!(step
+            .accept_human_input(&request, serde_json::json!("reject"))
+            .is_err());
+    }
+
+    /// Model that answers every prompt with the prompt in a code block
+    #[derive(Debug, Default)]
+    struct EchoModel {
+        requests: std::sync::Mutex<Vec<ChatRequest>>,
+    }
+
+    #[async_trait]
+    impl ModelProvider for EchoModel {
+        fn model(&self) -> &str {
+            "echo"
+        }
+
+        async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+            self.requests.lock().unwrap().push(request.clone());
+            let prompt = match &request.messages[0] {
+                Message::User { content } => content,
+                message => panic!("Unexpected message {message:?}"),
+            };
+            Ok(ChatResponse {
+                content: format!("Here you go:\n```rust\n{prompt}\n```\nDone."),
+                tool_calls: Vec::new(),
+                usage: TokenUsage {
+                    input_tokens: 10,
+                    output_tokens: 4,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_code_generation() {
+        let template = Template::new(serde_json::json!("Fix {{ input.file }}"), &|_| {
+            bail!("No steps")
+        })
+        .unwrap();
+        let id = Uuid::new_v4();
+        let step = CodeGenerationStep::new(id, "Fix".to_string(), String::new(), template)
+            .with_max_tokens(200);
+
+        let mut context = StepContext {
+            input: serde_json::json!({"file": "main.rs"}),
+            previous_outputs: std::collections::HashMap::new(),
+            global: std::collections::HashMap::new(),
+            progress: Progress::default(),
+            workflows: None,
+            tools: None,
+            model: None,
+            cancel: CancelToken::default(),
+        };
+        let error = step.execute(context.clone()).await.unwrap_err();
+        assert!(error.to_string().contains("needs a language model"));
+
+        let model = Arc::new(EchoModel::default());
+        context.model = Some(Arc::clone(&model) as Arc<dyn ModelProvider>);
+        let result = step.execute(context.clone()).await.unwrap();
+        assert_eq!(
+            result.output,
+            serde_json::json!({
+                "generated_code": "Fix main.rs",
+                "model": "echo",
+                "usage": { "input_tokens": 10, "output_tokens": 4 }
+            })
+        );
+        assert!(schema::check(&step.output_schema().unwrap(), &result.output, "output").is_ok());
+
+        context.global.insert(
+            FEEDBACK_KEY.to_string(),
+            serde_json::json!({ id.to_string(): {
+                "feedback": "Keep the old name",
+                "rejected_output": { "generated_code": "fn renamed() {}" }
+            }}),
+        );
+        step.execute(context).await.unwrap();
+        let requests = model.requests.lock().unwrap();
+        assert_eq!(requests[1].max_tokens, 200);
+        assert_eq!(
+            requests[1].messages[0],
+            Message::User {
+                content: "Fix main.rs\n\nYour previous answer was:\n```\nfn renamed() {}\n```\n\n\
+             A reviewer rejected it with this feedback:\nKeep the old name"
+                    .to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_extract_code() {
+        assert_eq!(extract_code("fn main() {}\n"), "fn main() {}");
+        assert_eq!(extract_code("```\nfn a() {}\n```"), "fn a() {}");
+        assert_eq!(extract_code("Unclosed:\n```rust\nfn a() {}\n"), "fn a() {}");
+    }
 }
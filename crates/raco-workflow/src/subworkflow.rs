@@ -0,0 +1,683 @@
+//! Sub-workflows
+//!
+//! This module lets a step run another workflow, registered with
+//! [`WorkflowEngine::register_workflow`], as a child instance of its own workflow.
+//!
+//! ```yaml
+//! - id: review
+//!   type: workflow
+//!   depends_on: [build]
+//!   params:
+//!     workflow: review
+//!     inputs:
+//!       diff: { step: build, path: /diff }
+//!       branch: { path: /branch }
+//!     outputs:
+//!       approved: { step: decide, path: /decision }
+//! ```
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::engine::WorkflowEngine;
+use crate::spec::WorkflowSpec;
+use crate::steps::{Step, StepContext, StepResult};
+use crate::{StepId, StepStatus, WorkflowId, WorkflowStatus};
+
+/// Number of sub-workflow levels a workflow may nest
+pub const MAX_WORKFLOW_DEPTH: usize = 8;
+
+/// Step of a workflow that started a sub-workflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParentStep {
+    /// Parent workflow
+    pub workflow_id: WorkflowId,
+
+    /// Step of the parent workflow
+    pub step_id: StepId,
+}
+
+/// Handle a running step uses to start sub-workflows
+#[derive(Debug, Clone)]
+pub struct SubWorkflows {
+    /// Engine running the step
+    engine: WorkflowEngine,
+
+    /// The running step
+    parent: ParentStep,
+}
+
+impl SubWorkflows {
+    /// Create a handle for the given step
+    #[must_use]
+    pub const fn new(engine: WorkflowEngine, parent: ParentStep) -> Self {
+        Self { engine, parent }
+    }
+
+    /// Get the step the handle belongs to
+    #[must_use]
+    pub const fn parent(&self) -> ParentStep {
+        self.parent
+    }
+
+    /// Run a registered workflow until it completes or waits for input
+    ///
+    /// Returns the ID of the sub-workflow and its status; see
+    /// [`WorkflowEngine::run_child`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow is unknown, fails or is cancelled
+    pub async fn run(&self, name: &str, input: Value) -> Result<(WorkflowId, WorkflowStatus)> {
+        self.engine.run_child(self.parent, name, input).await
+    }
+
+    /// Run the body of a control flow step until it stops running
+    ///
+    /// Returns the ID of the sub-workflow and its status; see
+    /// [`WorkflowEngine::run_body`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if sub-workflows are nested too deeply or the body fails
+    pub async fn run_body(
+        &self,
+        spec: WorkflowSpec,
+        input: Value,
+    ) -> Result<(WorkflowId, WorkflowStatus)> {
+        self.engine.run_body(self.parent, spec, input).await
+    }
+
+    /// Cancel a sub-workflow
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist or already finished
+    pub async fn cancel(&self, id: WorkflowId) -> Result<()> {
+        self.engine.cancel_workflow(id).await
+    }
+
+    /// Get the outputs of the completed steps of a sub-workflow by step ID
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow does not exist
+    pub async fn outputs(&self, id: WorkflowId) -> Result<Map<String, Value>> {
+        let instance = self
+            .engine
+            .get_workflow(id)
+            .await
+            .ok_or_else(|| anyhow!("Workflow {} not found", id))?;
+        let instance = instance.lock().await;
+        Ok(instance.named_outputs())
+    }
+}
+
+/// Value passed into a sub-workflow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMapping {
+    /// Preceding step whose output holds the value; the workflow input if `None`
+    pub step: Option<StepId>,
+
+    /// JSON pointer to the value
+    pub path: String,
+}
+
+/// Value taken out of a sub-workflow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputMapping {
+    /// Step of the sub-workflow whose output holds the value
+    pub step: String,
+
+    /// JSON pointer to the value
+    #[serde(default)]
+    pub path: String,
+}
+
+/// A step that runs a registered workflow as a sub-workflow
+///
+/// Without input mappings the sub-workflow receives the workflow input of this
+/// step. Without output mappings the step outputs the outputs of all completed
+/// steps of the sub-workflow by step ID.
+///
+/// While the sub-workflow waits for input, so does this step. Its request names the
+/// sub-workflow, and the engine answers it with the outputs of the sub-workflow
+/// once that completes.
+#[derive(Debug)]
+pub struct WorkflowStep {
+    /// Step ID
+    id: StepId,
+
+    /// Step name
+    name: String,
+
+    /// Step description
+    description: String,
+
+    /// Name of the workflow to run
+    workflow: String,
+
+    /// Input of the sub-workflow, by key
+    inputs: BTreeMap<String, InputMapping>,
+
+    /// Output of this step, by key
+    outputs: BTreeMap<String, OutputMapping>,
+}
+
+impl WorkflowStep {
+    /// Create a step running the workflow registered under `workflow`
+    #[must_use]
+    pub const fn new(id: StepId, name: String, description: String, workflow: String) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            workflow,
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Pass a value into the sub-workflow under `key`
+    #[must_use]
+    pub fn with_input(mut self, key: impl Into<String>, mapping: InputMapping) -> Self {
+        self.inputs.insert(key.into(), mapping);
+        self
+    }
+
+    /// Output a value of the sub-workflow under `key`
+    #[must_use]
+    pub fn with_output(mut self, key: impl Into<String>, mapping: OutputMapping) -> Self {
+        self.outputs.insert(key.into(), mapping);
+        self
+    }
+
+    fn child_input(&self, context: &StepContext) -> Result<Value> {
+        if self.inputs.is_empty() {
+            return Ok(context.input.clone());
+        }
+
+        let mut input = Map::new();
+        for (key, mapping) in &self.inputs {
+            let (source, description) = match mapping.step {
+                Some(step) => (
+                    context.previous_outputs.get(&step),
+                    format!("the output of step {step}"),
+                ),
+                None => (Some(&context.input), "the workflow input".to_string()),
+            };
+            let value = source
+                .and_then(|source| source.pointer(&mapping.path))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Cannot pass '{}' to workflow {}: {} has no value at '{}'",
+                        key,
+                        self.workflow,
+                        description,
+                        mapping.path
+                    )
+                })?;
+            input.insert(key.clone(), value.clone());
+        }
+        Ok(Value::Object(input))
+    }
+
+    fn output(&self, child: WorkflowId, outputs: Map<String, Value>) -> Result<Value> {
+        if self.outputs.is_empty() {
+            return Ok(Value::Object(outputs));
+        }
+
+        let mut output = Map::new();
+        for (key, mapping) in &self.outputs {
+            let value = outputs
+                .get(&mapping.step)
+                .and_then(|source| source.pointer(&mapping.path))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Cannot take '{}' from workflow {} ({}): step '{}' has no value at '{}'",
+                        key,
+                        self.workflow,
+                        child,
+                        mapping.step,
+                        mapping.path
+                    )
+                })?;
+            output.insert(key.clone(), value.clone());
+        }
+        Ok(Value::Object(output))
+    }
+}
+
+#[async_trait]
+impl Step for WorkflowStep {
+    fn id(&self) -> StepId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn input_schema(&self) -> Option<Value> {
+        None
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        Some(serde_json::json!({ "type": "object" }))
+    }
+
+    async fn execute(&self, context: StepContext) -> Result<StepResult> {
+        let workflows = context.workflows.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Step {} can only run workflow {} inside a workflow engine",
+                self.name,
+                self.workflow
+            )
+        })?;
+
+        let input = self.child_input(&context)?;
+        let (child, status) = workflows.run(&self.workflow, input).await?;
+        if status == WorkflowStatus::WaitingForInput {
+            return Ok(StepResult {
+                output: serde_json::json!({
+                    "workflow": self.workflow,
+                    "workflow_id": child,
+                }),
+                status: StepStatus::WaitingForInput,
+                error: None,
+            });
+        }
+
+        let output = self.output(child, workflows.outputs(child).await?)?;
+        Ok(StepResult {
+            output,
+            status: StepStatus::Completed,
+            error: None,
+        })
+    }
+
+    fn accept_human_input(&self, request: &Value, response: Value) -> Result<Value> {
+        let child = request
+            .get("workflow_id")
+            .and_then(Value::as_str)
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| anyhow!("Step {} is not waiting for a sub-workflow", self.name))?;
+        let Value::Object(outputs) = response else {
+            return Err(anyhow!(
+                "Expected the outputs of workflow {} ({}) as an object",
+                self.workflow,
+                child
+            ));
+        };
+        self.output(child, outputs)
+    }
+
+    fn validate_input(&self, _input: &Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::WorkflowStore;
+    use crate::registry::StepRegistry;
+    use crate::spec::{self, SpecFormat, WorkflowSpec};
+    use crate::WorkflowStatus;
+    use serde_json::json;
+
+    /// A step returning its workflow input, or never finishing if it is parked
+    #[derive(Debug)]
+    struct EchoStep {
+        id: StepId,
+        name: String,
+        parked: bool,
+    }
+
+    #[async_trait]
+    impl Step for EchoStep {
+        fn id(&self) -> StepId {
+            self.id
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn description(&self) -> &str {
+            "Returns its input"
+        }
+
+        fn input_schema(&self) -> Option<Value> {
+            None
+        }
+
+        fn output_schema(&self) -> Option<Value> {
+            None
+        }
+
+        async fn execute(&self, context: StepContext) -> Result<StepResult> {
+            if self.parked {
+                std::future::pending::<()>().await;
+            }
+            Ok(StepResult {
+                output: context.input,
+                status: StepStatus::Completed,
+                error: None,
+            })
+        }
+
+        fn validate_input(&self, _input: &Value) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse(text: &str) -> WorkflowSpec {
+        WorkflowSpec::parse(text, SpecFormat::Yaml).unwrap()
+    }
+
+    async fn engine() -> WorkflowEngine {
+        engine_with(false).await
+    }
+
+    /// Test engine whose `echo` steps never finish if `parked` is set
+    async fn engine_with(parked: bool) -> WorkflowEngine {
+        let mut registry = StepRegistry::with_builtins();
+        registry.register("echo", move |config| {
+            Ok(Box::new(EchoStep {
+                id: config.id,
+                name: config.name,
+                parked,
+            }))
+        });
+        let engine = WorkflowEngine::new().with_registry(registry);
+        engine
+            .register_workflow(parse("name: lint\nsteps:\n  - {id: check, type: echo}\n"))
+            .await;
+        engine
+            .register_workflow(parse(
+                "name: ask\nsteps:\n  - {id: question, type: human_input, params: {prompt: Ship it?}}\n",
+            ))
+            .await;
+        engine
+            .register_workflow(parse(
+                "name: forever\nsteps:\n  - {id: again, type: workflow, params: {workflow: forever}}\n",
+            ))
+            .await;
+        engine
+    }
+
+    const PARENT: &str = r"
+name: release
+steps:
+  - id: build
+    type: echo
+  - id: lint
+    type: workflow
+    depends_on: [build]
+    params:
+      workflow: lint
+      inputs:
+        files: { step: build, path: /files }
+        strict: { path: /strict }
+      outputs:
+        checked: { step: check, path: /files/0 }
+";
+
+    #[tokio::test]
+    async fn test_sub_workflow() {
+        let engine = engine().await;
+        let id = engine
+            .create_workflow_from_spec(parse(PARENT))
+            .await
+            .unwrap();
+        engine
+            .start_workflow_with_input(id, json!({ "files": ["main.rs"], "strict": true }))
+            .await
+            .unwrap();
+
+        let lint = spec::step_id(id, "lint");
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(lint),
+            Some(&json!({ "checked": "main.rs" }))
+        );
+
+        let children = instance.children(lint);
+        assert_eq!(children.len(), 1);
+        let child = engine.get_workflow(children[0]).await.unwrap();
+        let child = child.lock().await;
+        assert_eq!(
+            child.parent(),
+            Some(ParentStep {
+                workflow_id: id,
+                step_id: lint,
+            })
+        );
+        assert_eq!(
+            child.named_outputs()["check"],
+            json!({ "files": ["main.rs"], "strict": true })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflow_waits_for_input() {
+        let engine = engine().await;
+        let id = engine
+            .create_workflow_from_spec(parse(
+                "name: ship\nsteps:\n  - {id: confirm, type: workflow, params: {workflow: ask}}\n",
+            ))
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+        {
+            let instance = engine.get_workflow(id).await.unwrap();
+            assert_eq!(
+                instance.lock().await.status(),
+                WorkflowStatus::WaitingForInput
+            );
+        }
+
+        // Only the question of the sub-workflow is listed
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        assert_ne!(pending[0].workflow_id, id);
+        assert_eq!(pending[0].prompt, "Ship it?");
+        assert_eq!(engine.pending_inputs_of(id).await, pending);
+        engine
+            .submit_input(pending[0].workflow_id, pending[0].step_id, json!("yes"))
+            .await
+            .unwrap();
+
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(
+            instance.step_output(spec::step_id(id, "confirm")),
+            Some(&json!({ "question": { "human_input": "yes" } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_sub_workflow_outputs_fail_the_step() {
+        let engine = engine().await;
+        let id = engine
+            .create_workflow_from_spec(parse(
+                "name: ship\nsteps:\n  - id: confirm\n    type: workflow\n    params:\n      workflow: ask\n      outputs:\n        answer: { step: question, path: /missing }\n",
+            ))
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        let error = engine
+            .submit_input(pending[0].workflow_id, pending[0].step_id, json!("yes"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Step confirm failed"));
+
+        // The parent step fails instead of waiting for outputs that never fit
+        assert!(engine.pending_inputs().await.is_empty());
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Failed);
+        assert!(instance
+            .step_error(spec::step_id(id, "confirm"))
+            .unwrap()
+            .contains("step 'question' has no value at '/missing'"));
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflow_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WorkflowStore::new(dir.path());
+        let spec = parse(
+            "name: ship\nsteps:\n  - {id: confirm, type: workflow, params: {workflow: ask}}\n",
+        );
+
+        // The first process stops while the sub-workflow waits for an answer
+        let first = engine().await.with_store(store.clone());
+        let id = first.create_workflow_from_spec(spec).await.unwrap();
+        first.start_workflow(id).await.unwrap();
+        drop(first);
+
+        let engine = engine().await.with_store(store);
+        assert_eq!(engine.restore().await.unwrap(), [id]);
+        let pending = engine.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        engine
+            .submit_input(pending[0].workflow_id, pending[0].step_id, json!("yes"))
+            .await
+            .unwrap();
+
+        let confirm = spec::step_id(id, "confirm");
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.children(confirm), [pending[0].workflow_id]);
+        assert_eq!(
+            instance.step_output(confirm),
+            Some(&json!({ "question": { "human_input": "yes" } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answered_sub_workflow_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WorkflowStore::new(dir.path());
+        let review = parse(
+            "name: review\nsteps:\n  - {id: question, type: human_input, params: {prompt: Ship it?}}\n  - {id: notify, type: echo, depends_on: [question]}\n",
+        );
+        let spec = parse(
+            "name: ship\nsteps:\n  - {id: confirm, type: workflow, params: {workflow: review}}\n",
+        );
+
+        // The first process stops while the sub-workflow runs on after the answer
+        let first = engine_with(true).await.with_store(store.clone());
+        first.register_workflow(review.clone()).await;
+        let id = first.create_workflow_from_spec(spec).await.unwrap();
+        first.start_workflow(id).await.unwrap();
+        let pending = first.pending_inputs().await;
+        assert_eq!(pending.len(), 1);
+        let answer = first.submit_input(pending[0].workflow_id, pending[0].step_id, json!("yes"));
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), answer)
+                .await
+                .is_err()
+        );
+        drop(first);
+
+        // Resuming the parent resumes the sub-workflow, which then settles the parent
+        let engine = engine().await.with_store(store);
+        engine.register_workflow(review).await;
+        assert_eq!(engine.restore().await.unwrap(), [id]);
+        engine.resume_workflow(id).await.unwrap();
+
+        let confirm = spec::step_id(id, "confirm");
+        let instance = engine.get_workflow(id).await.unwrap();
+        let instance = instance.lock().await;
+        assert_eq!(instance.status(), WorkflowStatus::Completed);
+        assert_eq!(instance.children(confirm), [pending[0].workflow_id]);
+        assert_eq!(
+            instance.step_output(confirm).unwrap()["question"],
+            json!({ "human_input": "yes" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelling_sub_workflows() {
+        let engine = engine().await;
+        let spec = parse(
+            "name: ship\nsteps:\n  - {id: confirm, type: workflow, params: {workflow: ask}}\n",
+        );
+        let status = |id| {
+            let engine = engine.clone();
+            async move {
+                let instance = engine.get_workflow(id).await.unwrap();
+                let status = instance.lock().await.status();
+                status
+            }
+        };
+
+        // Cancelling the parent cancels the sub-workflow it waits on
+        let id = engine
+            .create_workflow_from_spec(spec.clone())
+            .await
+            .unwrap();
+        engine.start_workflow(id).await.unwrap();
+        let child = engine.pending_inputs_of(id).await[0].workflow_id;
+        engine.cancel_workflow(id).await.unwrap();
+        assert_eq!(status(child).await, WorkflowStatus::Cancelled);
+        assert!(engine.pending_inputs().await.is_empty());
+
+        // Cancelling the waiting step fails it
+        let id = engine.create_workflow_from_spec(spec).await.unwrap();
+        engine.start_workflow(id).await.unwrap();
+        let child = engine.pending_inputs_of(id).await[0].workflow_id;
+        let error = engine
+            .cancel_step(id, spec::step_id(id, "confirm"))
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains(&format!("Workflow {child} was cancelled")));
+        assert_eq!(status(child).await, WorkflowStatus::Cancelled);
+        assert_eq!(status(id).await, WorkflowStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_and_recursive_workflows() {
+        let engine = engine().await;
+
+        let id = engine
+            .create_workflow_from_spec(parse(
+                "name: w\nsteps:\n  - {id: run, type: workflow, params: {workflow: deploy}}\n",
+            ))
+            .await
+            .unwrap();
+        let error = engine.start_workflow(id).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Unknown workflow 'deploy', expected one of: ask, forever, lint"));
+
+        let id = engine
+            .create_workflow_from_spec(parse(
+                "name: w\nsteps:\n  - {id: run, type: workflow, params: {workflow: forever}}\n",
+            ))
+            .await
+            .unwrap();
+        let error = engine.start_workflow(id).await.unwrap_err();
+        assert!(error.to_string().contains("nested more than 8 levels deep"));
+    }
+}
@@ -0,0 +1,734 @@
+//! Templates over the context of a step
+//!
+//! A template is a JSON value whose strings may contain `{{ expression }}`
+//! placeholders. An expression names a value in the [`StepContext`] of the step:
+//!
+//! - `input.<path>` — the input of the step
+//! - `steps.<step>.output.<path>` — the output of a step the step depends on
+//! - `global.<key>.<path>` — the shared workflow context
+//!
+//! A path is a sequence of `.field` and `[index]` lookups, e.g.
+//! `steps.build.output.errors[0].file`. A string that consists of a single
+//! placeholder is replaced by the value itself, whatever its type; placeholders
+//! inside longer strings are replaced by their text.
+//!
+//! The value can be passed through filters, separated by `|`:
+//!
+//! | Filter           | Result                                                   |
+//! |------------------|----------------------------------------------------------|
+//! | `default(value)` | `value` if the lookup has no value or is null            |
+//! | `length`         | number of characters, items or fields                   |
+//! | `first`, `last`  | first or last item of an array                           |
+//! | `keys`           | field names of an object                                 |
+//! | `join(sep)`      | items of an array as text, separated by `sep`            |
+//! | `upper`, `lower` | text in upper or lower case                              |
+//! | `trim`           | text without leading and trailing whitespace            |
+//! | `json`           | the value as JSON text                                   |
+//!
+//! Filter arguments are JSON values, e.g. `{{ input.files | join(", ") }}`.
+//! Expressions only read the context; there is no way to call out of a template.
+
+use std::fmt;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::steps::StepContext;
+use crate::StepId;
+
+/// A compiled template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// A value without placeholders
+    Value(Value),
+
+    /// A string that is a single placeholder
+    Expression(Expression),
+
+    /// A string with placeholders among text
+    Text(Vec<Segment>),
+
+    Array(Vec<Node>),
+
+    Object(Vec<(String, Node)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Expression(Expression),
+}
+
+/// A lookup of a value in the context of a step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    /// The expression as written
+    source: String,
+
+    root: Root,
+
+    path: Vec<Key>,
+
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Root {
+    Input,
+    Step { name: String, id: StepId },
+    Global(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Field(String),
+    Index(usize),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Field(field) => write!(f, ".{field}"),
+            Self::Index(index) => write!(f, "[{index}]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Default(Value),
+    Length,
+    First,
+    Last,
+    Keys,
+    Join(String),
+    Upper,
+    Lower,
+    Trim,
+    Json,
+}
+
+/// Names of the filters, for error messages
+const FILTERS: &str = "default, first, join, json, keys, last, length, lower, trim, upper";
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default(value) => write!(f, "default({value})"),
+            Self::Length => write!(f, "length"),
+            Self::First => write!(f, "first"),
+            Self::Last => write!(f, "last"),
+            Self::Keys => write!(f, "keys"),
+            Self::Join(separator) => write!(f, "join({})", Value::String(separator.clone())),
+            Self::Upper => write!(f, "upper"),
+            Self::Lower => write!(f, "lower"),
+            Self::Trim => write!(f, "trim"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl Filter {
+    fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let (name, args) = match text.split_once('(') {
+            Some((name, rest)) => {
+                let name = name.trim();
+                let args = rest
+                    .trim_end()
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow!("Unclosed arguments of filter '{}'", name))?;
+                let args: Vec<Value> = serde_json::from_str(&format!("[{args}]"))
+                    .map_err(|_| anyhow!("Invalid arguments of filter '{}': {}", name, args))?;
+                (name, args)
+            }
+            None => (text, Vec::new()),
+        };
+
+        Ok(match (name, args.as_slice()) {
+            ("default", [value]) => Self::Default(value.clone()),
+            ("join", [Value::String(separator)]) => Self::Join(separator.clone()),
+            ("join", [_]) => bail!("Filter 'join' takes a string"),
+            ("default" | "join", _) => bail!("Filter '{}' takes one argument", name),
+            ("length", []) => Self::Length,
+            ("first", []) => Self::First,
+            ("last", []) => Self::Last,
+            ("keys", []) => Self::Keys,
+            ("upper", []) => Self::Upper,
+            ("lower", []) => Self::Lower,
+            ("trim", []) => Self::Trim,
+            ("json", []) => Self::Json,
+            ("length" | "first" | "last" | "keys" | "upper" | "lower" | "trim" | "json", _) => {
+                bail!("Filter '{}' takes no arguments", name)
+            }
+            _ => bail!("Unknown filter '{}'; filters are {}", name, FILTERS),
+        })
+    }
+
+    /// Apply the filter to a value, which is `None` if the lookup found nothing
+    fn apply(&self, value: Option<Value>) -> Result<Option<Value>> {
+        let value = match (self, value) {
+            (Self::Default(default), None | Some(Value::Null)) => return Ok(Some(default.clone())),
+            (_, None) => return Ok(None),
+            (_, Some(value)) => value,
+        };
+
+        Ok(match (self, value) {
+            (Self::Default(_), value) => Some(value),
+            (Self::Length, Value::String(text)) => Some(text.chars().count().into()),
+            (Self::Length, Value::Array(items)) => Some(items.len().into()),
+            (Self::Length, Value::Object(fields)) => Some(fields.len().into()),
+            (Self::First, Value::Array(items)) => items.into_iter().next(),
+            (Self::Last, Value::Array(items)) => items.into_iter().next_back(),
+            (Self::Keys, Value::Object(fields)) => Some(fields.keys().cloned().collect()),
+            (Self::Join(separator), Value::Array(items)) => Some(Value::String(
+                items.iter().map(text).collect::<Vec<_>>().join(separator),
+            )),
+            (Self::Upper, Value::String(text)) => Some(Value::String(text.to_uppercase())),
+            (Self::Lower, Value::String(text)) => Some(Value::String(text.to_lowercase())),
+            (Self::Trim, Value::String(text)) => Some(Value::String(text.trim().to_string())),
+            (Self::Json, value) => Some(Value::String(value.to_string())),
+            (filter, value) => bail!("filter '{}' cannot be applied to {}", filter, kind(&value)),
+        })
+    }
+}
+
+/// Text of a value in an interpolated string
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Name of the type of a value, for error messages
+const fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Location of a value inside a template, as a JSON pointer
+fn pointer(at: &str, key: &str) -> String {
+    format!("{}/{}", at, key.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Name a location in an error, unless it is the whole template
+fn locate<T>(result: Result<T>, at: &str) -> Result<T> {
+    if at.is_empty() {
+        result
+    } else {
+        result.with_context(|| format!("At {at}"))
+    }
+}
+
+impl Template {
+    /// Compile a template
+    ///
+    /// `step` maps the name of a step an expression refers to onto its ID, and
+    /// fails for steps the template may not refer to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid expression and its location
+    pub fn new(value: Value, step: &dyn Fn(&str) -> Result<StepId>) -> Result<Self> {
+        Ok(Self {
+            root: Node::compile(value, step, "")?,
+        })
+    }
+
+    /// Create a template that renders to the given value as it is
+    #[must_use]
+    pub const fn constant(value: Value) -> Self {
+        Self {
+            root: Node::Value(value),
+        }
+    }
+
+    /// Check if the template has no placeholders
+    #[must_use]
+    pub const fn is_constant(&self) -> bool {
+        matches!(self.root, Node::Value(_))
+    }
+
+    /// Render the template with the context of a step
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first expression that cannot be resolved and its
+    /// location
+    pub fn render(&self, context: &StepContext) -> Result<Value> {
+        self.root.render(context, "")
+    }
+}
+
+impl Node {
+    fn compile(value: Value, step: &dyn Fn(&str) -> Result<StepId>, at: &str) -> Result<Self> {
+        match value {
+            Value::String(text) => locate(Self::compile_text(text, step), at),
+            Value::Array(items) => {
+                let nodes = items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        Self::compile(item, step, &pointer(at, &index.to_string()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(if nodes.iter().all(|node| matches!(node, Self::Value(_))) {
+                    Self::Value(Value::Array(
+                        nodes.into_iter().map(Self::into_value).collect(),
+                    ))
+                } else {
+                    Self::Array(nodes)
+                })
+            }
+            Value::Object(fields) => {
+                let nodes = fields
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let node = Self::compile(value, step, &pointer(at, &key))?;
+                        Ok((key, node))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(
+                    if nodes.iter().all(|(_, node)| matches!(node, Self::Value(_))) {
+                        Self::Value(Value::Object(
+                            nodes
+                                .into_iter()
+                                .map(|(key, node)| (key, node.into_value()))
+                                .collect(),
+                        ))
+                    } else {
+                        Self::Object(nodes)
+                    },
+                )
+            }
+            value => Ok(Self::Value(value)),
+        }
+    }
+
+    fn compile_text(text: String, step: &dyn Fn(&str) -> Result<StepId>) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| anyhow!("Unclosed placeholder in '{}'", text))?;
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let source = rest[start + 2..start + end].trim();
+            segments.push(Segment::Expression(Expression::parse(source, step)?));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(match segments.as_slice() {
+            [] | [Segment::Text(_)] => Self::Value(Value::String(text)),
+            [Segment::Expression(_)] => match segments.remove(0) {
+                Segment::Expression(expression) => Self::Expression(expression),
+                Segment::Text(_) => unreachable!(),
+            },
+            _ => Self::Text(segments),
+        })
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Self::Value(value) => value,
+            _ => unreachable!("only constant nodes are turned into values"),
+        }
+    }
+
+    fn render(&self, context: &StepContext, at: &str) -> Result<Value> {
+        match self {
+            Self::Value(value) => Ok(value.clone()),
+            Self::Expression(expression) => locate(expression.evaluate(context), at),
+            Self::Text(segments) => {
+                let mut rendered = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Text(part) => rendered.push_str(part),
+                        Segment::Expression(expression) => {
+                            rendered.push_str(&text(&locate(expression.evaluate(context), at)?));
+                        }
+                    }
+                }
+                Ok(Value::String(rendered))
+            }
+            Self::Array(nodes) => nodes
+                .iter()
+                .enumerate()
+                .map(|(index, node)| node.render(context, &pointer(at, &index.to_string())))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array),
+            Self::Object(nodes) => nodes
+                .iter()
+                .map(|(key, node)| Ok((key.clone(), node.render(context, &pointer(at, key))?)))
+                .collect::<Result<Map<_, _>>>()
+                .map(Value::Object),
+        }
+    }
+}
+
+impl Expression {
+    /// Parse an expression
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expression is malformed or refers to a step `step`
+    /// rejects
+    pub fn parse(source: &str, step: &dyn Fn(&str) -> Result<StepId>) -> Result<Self> {
+        Self::parse_path(source, step)
+            .with_context(|| format!("Invalid expression {{{{ {source} }}}}"))
+    }
+
+    fn parse_path(source: &str, step: &dyn Fn(&str) -> Result<StepId>) -> Result<Self> {
+        let mut parts = split_filters(source).into_iter();
+        let path = parts.next().unwrap_or_default().trim();
+        let filters = parts.map(Filter::parse).collect::<Result<Vec<_>>>()?;
+
+        let mut keys = parse_keys(path)?.into_iter();
+        let root = match keys.next() {
+            Some(Key::Field(name)) if name == "input" => Root::Input,
+            Some(Key::Field(name)) if name == "global" => match keys.next() {
+                Some(Key::Field(key)) => Root::Global(key),
+                _ => bail!("Expected the name of a global value after 'global'"),
+            },
+            Some(Key::Field(name)) if name == "steps" => {
+                let name = match keys.next() {
+                    Some(Key::Field(name)) => name,
+                    _ => bail!("Expected the name of a step after 'steps'"),
+                };
+                match keys.next() {
+                    Some(Key::Field(output)) if output == "output" => {}
+                    _ => bail!("Expected 'output' after 'steps.{}'", name),
+                }
+                let id = step(&name)?;
+                Root::Step { name, id }
+            }
+            _ => bail!("Expressions start with 'input', 'steps' or 'global'"),
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            root,
+            path: keys.collect(),
+            filters,
+        })
+    }
+
+    /// Look up the value in the context of a step and apply the filters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the part of the expression that has no value or the
+    /// filter that failed
+    pub fn evaluate(&self, context: &StepContext) -> Result<Value> {
+        let (mut value, mut resolved) = match &self.root {
+            Root::Input => (Some(&context.input), "input".to_string()),
+            Root::Step { name, id } => (
+                context.previous_outputs.get(id),
+                format!("steps.{name}.output"),
+            ),
+            Root::Global(key) => (context.global.get(key), format!("global.{key}")),
+        };
+
+        for key in &self.path {
+            let Some(current) = value else { break };
+            value = match key {
+                Key::Field(field) => current.get(field),
+                Key::Index(index) => current.get(index),
+            };
+            resolved.push_str(&key.to_string());
+        }
+
+        let mut value = value.cloned();
+        for filter in &self.filters {
+            value = filter
+                .apply(value)
+                .with_context(|| format!("Cannot resolve {{{{ {} }}}}", self.source))?;
+            resolved.push_str(&format!(" | {filter}"));
+        }
+
+        value.ok_or_else(|| {
+            anyhow!(
+                "Cannot resolve {{{{ {} }}}}: {} has no value",
+                self.source,
+                resolved
+            )
+        })
+    }
+}
+
+/// Split an expression at the `|` characters outside of string literals
+fn split_filters(source: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in source.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '|' if !in_string => {
+                parts.push(&source[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+/// Split a path into its keys
+fn parse_keys(source: &str) -> Result<Vec<Key>> {
+    let mut keys = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut expect_field = true;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' if !expect_field => {
+                chars.next();
+                expect_field = true;
+            }
+            '[' => {
+                chars.next();
+                let index: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let index = index
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid index [{}]", index))?;
+                keys.push(Key::Index(index));
+                expect_field = false;
+            }
+            c if expect_field && is_name_char(c) => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !is_name_char(c) {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                keys.push(Key::Field(name));
+                expect_field = false;
+            }
+            c => bail!("Unexpected '{}'", c),
+        }
+    }
+    if expect_field {
+        bail!("Expected a name at the end");
+    }
+    Ok(keys)
+}
+
+const fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::events::Progress;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn build_id() -> StepId {
+        Uuid::from_u128(1)
+    }
+
+    fn steps(name: &str) -> Result<StepId> {
+        if name == "build" {
+            Ok(build_id())
+        } else {
+            bail!("Unknown step {}", name)
+        }
+    }
+
+    fn context() -> StepContext {
+        StepContext {
+            input: json!({"path": "src", "verbose": true}),
+            previous_outputs: HashMap::from([(
+                build_id(),
+                json!({"errors": [{"file": "main.rs", "line": 3}]}),
+            )]),
+            global: HashMap::from([("branch".to_string(), json!("main"))]),
+            progress: Progress::default(),
+            workflows: None,
+            tools: None,
+            model: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Template::new(
+            json!({
+                "path": "{{ input.path }}",
+                "verbose": "{{input.verbose}}",
+                "error": "{{ steps.build.output.errors[0] }}",
+                "message": "{{ steps.build.output.errors[0].file }}:{{ steps.build.output.errors[0].line }} on {{ global.branch }}",
+                "limit": 10
+            }),
+            &steps,
+        )
+        .unwrap();
+        assert!(!template.is_constant());
+
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            json!({
+                "path": "src",
+                "verbose": true,
+                "error": {"file": "main.rs", "line": 3},
+                "message": "main.rs:3 on main",
+                "limit": 10
+            })
+        );
+        assert!(Template::new(json!({"a": ["b", 1]}), &steps)
+            .unwrap()
+            .is_constant());
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for (source, reason) in [
+            (
+                "{{ output.path }}",
+                "start with 'input', 'steps' or 'global'",
+            ),
+            (
+                "{{ steps.build.errors }}",
+                "Expected 'output' after 'steps.build'",
+            ),
+            ("{{ steps.test.output }}", "Unknown step test"),
+            ("{{ input.errors[x] }}", "Invalid index [x]"),
+            ("{{ input. }}", "Expected a name"),
+            ("{{ input.path", "Unclosed placeholder"),
+        ] {
+            let error = Template::new(json!(source), &steps).unwrap_err();
+            assert!(format!("{error:#}").contains(reason), "{source}: {error:#}");
+        }
+    }
+
+    #[test]
+    fn test_unresolved_expressions() {
+        let template =
+            Template::new(json!("{{ steps.build.output.errors[1].file }}"), &steps).unwrap();
+        let error = template.render(&context()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot resolve {{ steps.build.output.errors[1].file }}: \
+             steps.build.output.errors[1] has no value"
+        );
+
+        let mut context = context();
+        context.previous_outputs.clear();
+        let error = template.render(&context).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("steps.build.output has no value"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let context = StepContext {
+            input: json!({
+                "files": ["a.rs", "b.rs"],
+                "name": "  Raco ",
+                "options": {"fast": true},
+                "missing": null
+            }),
+            ..context()
+        };
+        for (source, expected) in [
+            ("{{ input.files | length }}", json!(2)),
+            ("{{ input.name | length }}", json!(7)),
+            ("{{ input.files | first }}", json!("a.rs")),
+            ("{{ input.files | last | upper }}", json!("B.RS")),
+            ("{{ input.files | join(\" | \") }}", json!("a.rs | b.rs")),
+            ("{{ input.name | trim | lower }}", json!("raco")),
+            ("{{ input.options | keys }}", json!(["fast"])),
+            ("{{ input.options | json }}", json!("{\"fast\":true}")),
+            ("{{ input.missing | default(1) }}", json!(1)),
+            ("{{ input.absent.field | default([]) }}", json!([])),
+            ("{{ input.files | default([]) | length }}", json!(2)),
+            ("{{ input.files | length }} files", json!("2 files")),
+        ] {
+            let template = Template::new(json!(source), &steps).unwrap();
+            assert_eq!(template.render(&context).unwrap(), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        for (source, reason) in [
+            ("{{ input.path | reverse }}", "Unknown filter 'reverse'"),
+            (
+                "{{ input.path | length(1) }}",
+                "Filter 'length' takes no arguments",
+            ),
+            (
+                "{{ input.path | default }}",
+                "Filter 'default' takes one argument",
+            ),
+            ("{{ input.path | join(1) }}", "Filter 'join' takes a string"),
+            (
+                "{{ input.path | join(\",\" }}",
+                "Unclosed arguments of filter 'join'",
+            ),
+        ] {
+            let error = Template::new(json!(source), &steps).unwrap_err();
+            assert!(format!("{error:#}").contains(reason), "{source}: {error:#}");
+        }
+
+        let template = Template::new(json!("{{ input.path | keys }}"), &steps).unwrap();
+        assert_eq!(
+            format!("{:#}", template.render(&context()).unwrap_err()),
+            "Cannot resolve {{ input.path | keys }}: filter 'keys' cannot be applied to a string"
+        );
+        let template = Template::new(json!("{{ input.absent | first }}"), &steps).unwrap();
+        assert_eq!(
+            template.render(&context()).unwrap_err().to_string(),
+            "Cannot resolve {{ input.absent | first }}: input.absent | first has no value"
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let error = Template::new(
+            json!({"report": {"files": ["{{ input.path }}", "{{ input.path | size }}"]}}),
+            &steps,
+        )
+        .unwrap_err();
+        let message = format!("{error:#}");
+        assert!(
+            message.starts_with("At /report/files/1: Invalid expression {{ input.path | size }}"),
+            "{message}"
+        );
+
+        let template = Template::new(json!({"a/b": "{{ global.user }}"}), &steps).unwrap();
+        assert_eq!(
+            format!("{:#}", template.render(&context()).unwrap_err()),
+            "At /a~1b: Cannot resolve {{ global.user }}: global.user has no value"
+        );
+    }
+}
@@ -0,0 +1,582 @@
+//! Workflow validation
+//!
+//! This module checks a workflow definition before it runs and reports problems as
+//! structured diagnostics.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::engine::WorkflowDefinition;
+use crate::StepId;
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The workflow cannot run
+    Error,
+
+    /// The workflow can run but probably does not do what was intended
+    Warning,
+}
+
+/// What a diagnostic is about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// Several steps share an ID
+    DuplicateStepId {
+        /// The shared ID
+        step: StepId,
+    },
+
+    /// A dependency refers to a step that does not exist
+    UnknownStep {
+        /// The missing step
+        step: StepId,
+    },
+
+    /// Steps depend on each other in a circle
+    Cycle {
+        /// Steps along the cycle, starting and ending with the same step
+        path: Vec<StepId>,
+    },
+
+    /// A step can never run because it depends on a cycle
+    UnreachableStep {
+        /// The step
+        step: StepId,
+    },
+
+    /// A step falls back to a step that cannot serve as its failure handler
+    InvalidFailureHandler {
+        /// Step whose policy names the handler
+        step: StepId,
+
+        /// The handler
+        handler: StepId,
+    },
+
+    /// A condition is attached to something other than a dependency, or cannot be evaluated
+    InvalidCondition {
+        /// Step whose output the condition tests
+        from: StepId,
+
+        /// Step the condition guards
+        to: StepId,
+    },
+
+    /// The output of a step does not fit the input of a dependent step
+    SchemaMismatch {
+        /// Producing step
+        from: StepId,
+
+        /// Consuming step
+        to: StepId,
+
+        /// Property that does not fit, if the mismatch is not at the top level
+        property: Option<String>,
+    },
+}
+
+/// A problem found in a workflow definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Severity
+    pub severity: Severity,
+
+    /// What the problem is about
+    #[serde(flatten)]
+    pub kind: DiagnosticKind,
+
+    /// Human readable description
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(kind: DiagnosticKind, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            kind,
+            message,
+        }
+    }
+
+    fn warning(kind: DiagnosticKind, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            kind,
+            message,
+        }
+    }
+
+    /// Whether the diagnostic prevents the workflow from running
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}", self.message)
+    }
+}
+
+impl WorkflowDefinition {
+    /// Check the definition for duplicate step IDs, dangling or circular dependencies,
+    /// steps that can never run and schema mismatches between connected steps
+    #[must_use]
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validate(self)
+    }
+}
+
+/// Validate a workflow definition
+#[must_use]
+pub fn validate(definition: &WorkflowDefinition) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    // Step order and names by ID
+    let mut order = Vec::new();
+    let mut names = HashMap::new();
+    let mut reported = HashSet::new();
+    for step in &definition.steps {
+        let id = step.id();
+        if names.insert(id, step.name()).is_some() {
+            if reported.insert(id) {
+                diagnostics.push(Diagnostic::error(
+                    DiagnosticKind::DuplicateStepId { step: id },
+                    format!("Several steps use the ID {id}"),
+                ));
+            }
+        } else {
+            order.push(id);
+        }
+    }
+    let name = |id: &StepId| {
+        names
+            .get(id)
+            .map_or_else(|| id.to_string(), |n| format!("'{n}'"))
+    };
+
+    let mut successors: HashMap<StepId, Vec<StepId>> = HashMap::new();
+    let mut has_predecessor = HashSet::new();
+    let mut reported = HashSet::new();
+    for (from, to) in &definition.dependencies {
+        let mut known = true;
+        for id in [from, to] {
+            if !names.contains_key(id) {
+                known = false;
+                if reported.insert(*id) {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticKind::UnknownStep { step: *id },
+                        format!("A dependency refers to step {id}, which does not exist"),
+                    ));
+                }
+            }
+        }
+        if known {
+            successors.entry(*from).or_default().push(*to);
+            has_predecessor.insert(*to);
+        }
+    }
+
+    // Failure handlers only run in place of a failed step, so they stand apart
+    let mut policy_steps: Vec<_> = definition.policies.keys().collect();
+    policy_steps.sort_by_key(|id| order.iter().position(|o| o == *id));
+    for id in policy_steps {
+        if !names.contains_key(id) {
+            if reported.insert(*id) {
+                diagnostics.push(Diagnostic::error(
+                    DiagnosticKind::UnknownStep { step: *id },
+                    format!("A step policy refers to step {id}, which does not exist"),
+                ));
+            }
+            continue;
+        }
+        let Some(handler) = definition.policies[id].on_failure else {
+            continue;
+        };
+        let problem = if !names.contains_key(&handler) {
+            Some("does not exist")
+        } else if handler == *id {
+            Some("is the failing step itself")
+        } else if has_predecessor.contains(&handler) || successors.contains_key(&handler) {
+            Some("has dependencies")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticKind::InvalidFailureHandler { step: *id, handler },
+                format!(
+                    "Step {} falls back to step {}, which {problem}",
+                    name(id),
+                    name(&handler)
+                ),
+            ));
+        }
+    }
+
+    for (from, to, condition) in &definition.conditions {
+        let problem = if !successors.get(from).is_some_and(|next| next.contains(to)) {
+            Some("which is not a dependency".to_string())
+        } else if !condition.has_valid_path() {
+            Some(format!(
+                "whose path '{}' is not a JSON pointer",
+                condition.path
+            ))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticKind::InvalidCondition {
+                    from: *from,
+                    to: *to,
+                },
+                format!(
+                    "Step {} has a condition on step {}, {problem}",
+                    name(to),
+                    name(from)
+                ),
+            ));
+        }
+    }
+
+    let cycles = find_cycles(&order, &successors);
+    for path in &cycles {
+        let description = path.iter().map(&name).collect::<Vec<_>>().join(" -> ");
+        diagnostics.push(Diagnostic::error(
+            DiagnosticKind::Cycle { path: path.clone() },
+            format!("Circular dependency: {description}"),
+        ));
+    }
+
+    // Steps are reachable from the steps without dependencies
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<_> = order
+        .iter()
+        .filter(|id| !has_predecessor.contains(*id))
+        .copied()
+        .collect();
+    while let Some(id) = stack.pop() {
+        if reachable.insert(id) {
+            stack.extend(successors.get(&id).into_iter().flatten().copied());
+        }
+    }
+    let in_cycle: HashSet<_> = cycles.iter().flatten().collect();
+    for id in &order {
+        if !reachable.contains(id) && !in_cycle.contains(id) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticKind::UnreachableStep { step: *id },
+                format!(
+                    "Step {} can never run because it depends on a circular dependency",
+                    name(id)
+                ),
+            ));
+        }
+    }
+
+    let schemas: HashMap<_, _> = definition
+        .steps
+        .iter()
+        .map(|step| (step.id(), (step.output_schema(), step.input_schema())))
+        .collect();
+    for (from, to) in &definition.dependencies {
+        let (Some((Some(output), _)), Some((_, Some(input)))) =
+            (schemas.get(from), schemas.get(to))
+        else {
+            continue;
+        };
+        for property in schema_mismatches(output, input) {
+            let message = match &property {
+                Some(property) => format!(
+                    "Property '{property}' has different types in the output of {} and the input of {}",
+                    name(from),
+                    name(to)
+                ),
+                None => format!(
+                    "The output of {} has a different type than the input of {}",
+                    name(from),
+                    name(to)
+                ),
+            };
+            diagnostics.push(Diagnostic::warning(
+                DiagnosticKind::SchemaMismatch {
+                    from: *from,
+                    to: *to,
+                    property,
+                },
+                message,
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Find the cycles of a graph, one closed path per back edge of a depth-first search
+fn find_cycles(order: &[StepId], successors: &HashMap<StepId, Vec<StepId>>) -> Vec<Vec<StepId>> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Mark {
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        id: StepId,
+        successors: &HashMap<StepId, Vec<StepId>>,
+        marks: &mut HashMap<StepId, Mark>,
+        stack: &mut Vec<StepId>,
+        cycles: &mut Vec<Vec<StepId>>,
+    ) {
+        marks.insert(id, Mark::Visiting);
+        stack.push(id);
+        for next in successors.get(&id).into_iter().flatten() {
+            match marks.get(next) {
+                Some(Mark::Visiting) => {
+                    let start = stack.iter().position(|step| step == next).unwrap_or(0);
+                    let mut path = stack[start..].to_vec();
+                    path.push(*next);
+                    cycles.push(path);
+                }
+                Some(Mark::Done) => {}
+                None => visit(*next, successors, marks, stack, cycles),
+            }
+        }
+        stack.pop();
+        marks.insert(id, Mark::Done);
+    }
+
+    let mut marks = HashMap::new();
+    let mut cycles = Vec::new();
+    for id in order {
+        if !marks.contains_key(id) {
+            visit(*id, successors, &mut marks, &mut Vec::new(), &mut cycles);
+        }
+    }
+    cycles
+}
+
+/// Places where an output schema contradicts an input schema
+///
+/// Returns `None` for a mismatch of the top-level types and the property name for
+/// properties declared with different types.
+fn schema_mismatches(output: &Value, input: &Value) -> Vec<Option<String>> {
+    let (Some(output_type), Some(input_type)) = (output.get("type"), input.get("type")) else {
+        return Vec::new();
+    };
+    if output_type != input_type {
+        return vec![None];
+    }
+
+    let (Some(outputs), Some(inputs)) = (
+        output.get("properties").and_then(Value::as_object),
+        input.get("properties").and_then(Value::as_object),
+    ) else {
+        return Vec::new();
+    };
+    let mut mismatches: Vec<_> = inputs
+        .iter()
+        .filter_map(|(property, input)| {
+            let output_type = outputs.get(property)?.get("type")?;
+            (Some(output_type) != input.get("type")).then(|| Some(property.clone()))
+        })
+        .collect();
+    mismatches.sort();
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::{Condition, Test};
+    use crate::policy::StepPolicy;
+    use crate::steps::{CodeGenerationStep, MockStep, Step};
+    use crate::template::Template;
+    use uuid::Uuid;
+
+    fn definition(
+        steps: Vec<Box<dyn Step>>,
+        dependencies: Vec<(StepId, StepId)>,
+    ) -> WorkflowDefinition {
+        WorkflowDefinition {
+            id: Uuid::new_v4(),
+            name: "Test Workflow".to_string(),
+            description: "A test workflow".to_string(),
+            steps,
+            dependencies,
+            policies: HashMap::new(),
+            conditions: Vec::new(),
+            inputs: HashMap::new(),
+        }
+    }
+
+    fn mock_steps(count: usize) -> (Vec<Box<dyn Step>>, Vec<StepId>) {
+        let ids: Vec<_> = (0..count).map(|_| Uuid::new_v4()).collect();
+        let steps = ids
+            .iter()
+            .map(|id| Box::new(MockStep::new(*id)) as Box<dyn Step>)
+            .collect();
+        (steps, ids)
+    }
+
+    #[test]
+    fn test_valid_workflow() {
+        let (steps, ids) = mock_steps(3);
+        let definition = definition(steps, vec![(ids[0], ids[1]), (ids[1], ids[2])]);
+        assert!(definition.validate().is_empty());
+    }
+
+    #[test]
+    fn test_cycle_is_reported_with_path() {
+        // a -> b -> c -> b, c -> d
+        let (steps, ids) = mock_steps(4);
+        let definition = definition(
+            steps,
+            vec![
+                (ids[0], ids[1]),
+                (ids[1], ids[2]),
+                (ids[2], ids[1]),
+                (ids[2], ids[3]),
+            ],
+        );
+
+        let diagnostics = definition.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(
+            diagnostics[0].kind,
+            DiagnosticKind::Cycle {
+                path: vec![ids[1], ids[2], ids[1]]
+            }
+        );
+        assert!(diagnostics[0]
+            .to_string()
+            .starts_with("error: Circular dependency: 'Mock Step' -> "));
+    }
+
+    #[test]
+    fn test_steps_behind_a_cycle_are_unreachable() {
+        // a <-> b, b -> c
+        let (steps, ids) = mock_steps(3);
+        let definition = definition(
+            steps,
+            vec![(ids[0], ids[1]), (ids[1], ids[0]), (ids[1], ids[2])],
+        );
+
+        let kinds: Vec<_> = definition.validate().into_iter().map(|d| d.kind).collect();
+        assert!(kinds.contains(&DiagnosticKind::UnreachableStep { step: ids[2] }));
+        assert!(matches!(kinds[0], DiagnosticKind::Cycle { .. }));
+    }
+
+    #[test]
+    fn test_duplicate_and_unknown_steps() {
+        let id = Uuid::new_v4();
+        let unknown = Uuid::new_v4();
+        let definition = definition(
+            vec![Box::new(MockStep::new(id)), Box::new(MockStep::new(id))],
+            vec![(id, unknown)],
+        );
+
+        let kinds: Vec<_> = definition.validate().into_iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                DiagnosticKind::DuplicateStepId { step: id },
+                DiagnosticKind::UnknownStep { step: unknown }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failure_handlers() {
+        let (steps, ids) = mock_steps(4);
+        let mut definition = definition(steps, vec![(ids[0], ids[1])]);
+        definition.policies = HashMap::from([
+            (ids[0], StepPolicy::default().with_on_failure(ids[3])),
+            (ids[2], StepPolicy::default().with_on_failure(ids[1])),
+        ]);
+
+        let diagnostics = definition.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].kind,
+            DiagnosticKind::InvalidFailureHandler {
+                step: ids[2],
+                handler: ids[1],
+            }
+        );
+        assert!(diagnostics[0].message.ends_with("which has dependencies"));
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        let (steps, ids) = mock_steps(3);
+        let mut definition = definition(steps, vec![(ids[0], ids[1])]);
+        definition.conditions = vec![
+            (ids[0], ids[1], Condition::new("/ok", Test::Exists(true))),
+            (ids[0], ids[2], Condition::new("/ok", Test::Exists(true))),
+            (ids[0], ids[1], Condition::new("ok", Test::Exists(true))),
+        ];
+
+        let diagnostics = definition.validate();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].kind,
+            DiagnosticKind::InvalidCondition {
+                from: ids[0],
+                to: ids[2],
+            }
+        );
+        assert!(diagnostics[0]
+            .message
+            .ends_with("which is not a dependency"));
+        assert!(diagnostics[1].message.contains("is not a JSON pointer"));
+    }
+
+    #[test]
+    fn test_schema_mismatch() {
+        let first = CodeGenerationStep::new(
+            Uuid::new_v4(),
+            "Generate".to_string(),
+            "Generate code".to_string(),
+            Template::constant(serde_json::Value::String(String::new())),
+        );
+        let second = CodeGenerationStep::new(
+            Uuid::new_v4(),
+            "Refine".to_string(),
+            "Refine code".to_string(),
+            Template::constant(serde_json::Value::String(String::new())),
+        );
+        let dependencies = vec![(first.id(), second.id())];
+        let definition = definition(vec![Box::new(first), Box::new(second)], dependencies);
+
+        // Both schemas are objects without shared properties
+        assert!(definition.validate().is_empty());
+
+        assert_eq!(
+            schema_mismatches(
+                &serde_json::json!({"type": "object", "properties": {"code": {"type": "string"}}}),
+                &serde_json::json!({"type": "object", "properties": {"code": {"type": "array"}}}),
+            ),
+            [Some("code".to_string())]
+        );
+        assert_eq!(
+            schema_mismatches(
+                &serde_json::json!({"type": "string"}),
+                &serde_json::json!({"type": "object"}),
+            ),
+            [None]
+        );
+    }
+}